@@ -0,0 +1,99 @@
+//! Compares calling into a contract with a fresh instance for every call against reusing
+//! the warm instances kept in the runtime instance pool.
+//!
+//! Requires `CARGO_TARGET_DIR` to be set, since the test contract is built on the fly.
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Arc,
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use freenet::dev_tool::{
+    ContractRuntimeInterface, ContractStore, DelegateStore, MasterKey, Runtime, SecretsStore,
+};
+use freenet_stdlib::prelude::*;
+
+const TEST_CONTRACT: &str = "test_contract_1";
+const POOL_SIZES: [usize; 2] = [0, 4];
+
+fn test_contract() -> ContractContainer {
+    const WASM_TARGET: &str = "wasm32-unknown-unknown";
+    let contract_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .ancestors()
+        .nth(2)
+        .unwrap()
+        .join("tests")
+        .join(TEST_CONTRACT.replace('_', "-"));
+    let target = std::env::var("CARGO_TARGET_DIR").expect("CARGO_TARGET_DIR should be set");
+    let status = Command::new("cargo")
+        .args(["build", "--release", "--target", WASM_TARGET])
+        .current_dir(&contract_path)
+        .status()
+        .expect("failed building the test contract");
+    assert!(status.success());
+    let code = std::fs::read(
+        Path::new(&target)
+            .join(WASM_TARGET)
+            .join("release")
+            .join(TEST_CONTRACT)
+            .with_extension("wasm"),
+    )
+    .expect("test contract not found");
+    ContractContainer::Wasm(ContractWasmAPIVersion::V1(WrappedContract::new(
+        Arc::new(ContractCode::from(code)),
+        Parameters::from(vec![]),
+    )))
+}
+
+fn runtime(dir: &Path, pool_size: usize, contract: &ContractContainer) -> Runtime {
+    let mut contract_store = ContractStore::new(dir.join("contracts"), 10_000_000).unwrap();
+    let delegate_store = DelegateStore::new(dir.join("delegates"), 10_000).unwrap();
+    let secret_store =
+        SecretsStore::new(dir.join("secrets"), MasterKey::from_passphrase(b"bench")).unwrap();
+    contract_store.store_contract(contract.clone()).unwrap();
+    Runtime::build(contract_store, delegate_store, secret_store, false)
+        .unwrap()
+        .with_instance_pool(pool_size)
+}
+
+fn contract_calls(c: &mut Criterion) {
+    let contract = test_contract();
+    let key = contract.key();
+    let params = Parameters::from(vec![]);
+    let state = WrappedState::new(vec![1, 2, 3, 4]);
+    let update_state = WrappedState::new(vec![5, 2, 3]);
+    let update = [UpdateData::Delta(StateDelta::from(vec![4]))];
+
+    let mut group = c.benchmark_group("contract_calls");
+    for pool_size in POOL_SIZES {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut runtime = runtime(temp_dir.path(), pool_size, &contract);
+        group.bench_with_input(
+            BenchmarkId::new("validate_state", pool_size),
+            &pool_size,
+            |b, _| {
+                b.iter(|| {
+                    runtime
+                        .validate_state(&key, &params, &state, &Default::default())
+                        .unwrap()
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("update_state", pool_size),
+            &pool_size,
+            |b, _| {
+                b.iter(|| {
+                    runtime
+                        .update_state(&key, &params, &update_state, &update)
+                        .unwrap()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, contract_calls);
+criterion_main!(benches);
@@ -2,6 +2,7 @@
 
 use freenet_stdlib::client_api::ClientRequest;
 use freenet_stdlib::client_api::{ClientError, ContractResponse, HostResponse};
+use freenet_stdlib::prelude::ContractInstanceId;
 use futures::future::BoxFuture;
 use std::fmt::Debug;
 use std::fmt::Display;
@@ -99,6 +100,8 @@ pub struct OpenRequest<'a> {
     pub request: Box<ClientRequest<'a>>,
     pub notification_channel: Option<UnboundedSender<HostResult>>,
     pub token: Option<AuthToken>,
+    /// Contract instance the client was attested for, if any.
+    pub attested_contract: Option<ContractInstanceId>,
 }
 
 impl Display for OpenRequest<'_> {
@@ -125,6 +128,7 @@ impl<'a> OpenRequest<'a> {
             request,
             notification_channel: None,
             token: None,
+            attested_contract: None,
         }
     }
 
@@ -137,6 +141,11 @@ impl<'a> OpenRequest<'a> {
         self.token = token;
         self
     }
+
+    pub fn with_attested_contract(mut self, contract: Option<ContractInstanceId>) -> Self {
+        self.attested_contract = contract;
+        self
+    }
 }
 
 pub trait ClientEventsProxy {
@@ -273,6 +282,7 @@ pub(crate) mod test {
                                     .into(),
                                 notification_channel: None,
                                 token: None,
+                                attested_contract: None,
                             };
                             return Ok(res.into_owned());
                         } else if pk == self.id {
@@ -284,6 +294,7 @@ pub(crate) mod test {
                                     .into(),
                                 notification_channel: None,
                                 token: None,
+                                attested_contract: None,
                             };
                             return Ok(res.into_owned());
                         }
@@ -88,6 +88,7 @@ impl<const N: usize> super::ClientEventsProxy for ClientEventsCombinator<N> {
                             request,
                             notification_channel,
                             token,
+                            attested_contract,
                         }) => {
                             tracing::debug!(
                                 "received request; internal_id={external}; req={request}"
@@ -107,6 +108,7 @@ impl<const N: usize> super::ClientEventsProxy for ClientEventsCombinator<N> {
                                 request,
                                 notification_channel,
                                 token,
+                                attested_contract,
                             })
                         }
                         err @ Err(_) => err,
@@ -162,9 +164,9 @@ async fn client_fn(
             }
             client_msg = client.recv() => {
                 match client_msg {
-                    Ok(OpenRequest { client_id,  request, notification_channel, token }) => {
+                    Ok(OpenRequest { client_id,  request, notification_channel, token, attested_contract }) => {
                         tracing::debug!("received msg @ combinator from external id {client_id}, msg: {request}");
-                        if tx_host.send(Ok(OpenRequest { client_id,  request, notification_channel, token })).await.is_err() {
+                        if tx_host.send(Ok(OpenRequest { client_id,  request, notification_channel, token, attested_contract })).await.is_err() {
                             break;
                         }
                     }
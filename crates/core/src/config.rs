@@ -4,7 +4,7 @@ use std::{
     future::Future,
     io::Read,
     net::{IpAddr, Ipv4Addr, SocketAddr},
-    path::PathBuf,
+    path::{Path, PathBuf},
     pin::Pin,
     str::FromStr,
     sync::atomic::AtomicBool,
@@ -16,7 +16,9 @@ use libp2p::{identity, PeerId};
 use once_cell::sync::Lazy;
 use tokio::runtime::Runtime;
 
+use crate::contract::storages::StorageBackend;
 use crate::local_node::OperationMode;
+use crate::wasm_runtime::MasterKey;
 
 /// Default maximum number of connections for the peer.
 pub const DEFAULT_MAX_CONNECTIONS: usize = 20;
@@ -53,7 +55,12 @@ pub struct Config {
     pub bootstrap_id: Option<PeerId>,
     pub local_peer_keypair: identity::Keypair,
     pub log_level: tracing::log::LevelFilter,
+    /// Backend used for contract states unless overridden by the node configuration.
+    pub storage_backend: StorageBackend,
     config_paths: ConfigPaths,
+    secrets_master_key: MasterKey,
+    /// file the master key was loaded from, if it was kept in plaintext
+    secrets_master_key_file: Option<PathBuf>,
     local_mode: AtomicBool,
 
     #[cfg(feature = "websocket")]
@@ -103,6 +110,7 @@ pub struct ConfigPaths {
     secrets_dir: PathBuf,
     db_dir: PathBuf,
     event_log: PathBuf,
+    peer_book: PathBuf,
 }
 
 impl ConfigPaths {
@@ -158,6 +166,7 @@ impl ConfigPaths {
             secrets_dir,
             db_dir,
             event_log,
+            peer_book: app_data_dir.join("_PEER_BOOK"),
         })
     }
 }
@@ -202,6 +211,24 @@ impl Config {
         }
     }
 
+    /// Master key protecting the delegate ciphers persisted by the secrets store.
+    ///
+    /// Derived from the `secrets_passphrase` setting if present, otherwise from the keypair in
+    /// the `local_peer_key_file`. Without either, a random key is generated the first time and
+    /// stored **in plaintext** in the `MASTER_KEY` file of the secrets directory, next to the
+    /// ciphers it protects, so it only keeps secrets readable by the node across restarts and
+    /// offers no protection at rest: set a passphrase for that. The key can be changed with
+    /// `fdev execute rotate-secrets-key`.
+    pub fn secrets_master_key(&self) -> MasterKey {
+        self.secrets_master_key.clone()
+    }
+
+    /// File the master key was loaded from when it is kept in plaintext, see
+    /// [`Self::secrets_master_key`].
+    pub fn secrets_master_key_file(&self) -> Option<&Path> {
+        self.secrets_master_key_file.as_deref()
+    }
+
     pub fn event_log(&self) -> PathBuf {
         if self.local_mode.load(std::sync::atomic::Ordering::SeqCst) {
             let mut local_file = self.config_paths.event_log.clone();
@@ -212,6 +239,10 @@ impl Config {
         }
     }
 
+    pub fn peer_book(&self) -> PathBuf {
+        self.config_paths.peer_book.to_owned()
+    }
+
     pub fn conf() -> &'static Config {
         CONFIG.get_or_init(|| match Config::load_conf() {
             Ok(config) => config,
@@ -258,6 +289,30 @@ impl Config {
         let config_paths = ConfigPaths::new(data_dir)?;
 
         let local_mode = settings.get_string("network_mode").is_err();
+        let mut secrets_master_key_file = None;
+        let secrets_master_key = match (
+            settings.get_string("secrets_passphrase"),
+            &local_peer_keypair,
+        ) {
+            (Ok(passphrase), _) => MasterKey::from_passphrase(passphrase.as_bytes()),
+            (Err(_), Some(keypair)) => MasterKey::from_keypair(keypair),
+            (Err(_), None) => {
+                // the peer keypair is ephemeral, the key can't be derived from it
+                let key_file = config_paths.secrets_dir.join("MASTER_KEY");
+                tracing::warn!(
+                    "no secrets passphrase set, delegate secrets are protected by a key stored \
+                     in plaintext at {key_file:?}"
+                );
+                let key = MasterKey::load_or_create(&key_file)?;
+                secrets_master_key_file = Some(key_file);
+                key
+            }
+        };
+        let storage_backend = match settings.get_string("storage_backend") {
+            Ok(backend) => <StorageBackend as clap::ValueEnum>::from_str(&backend, true)
+                .map_err(|_err| std::io::ErrorKind::InvalidInput)?,
+            Err(_) => StorageBackend::default(),
+        };
 
         Ok(Config {
             bootstrap_ip,
@@ -266,7 +321,10 @@ impl Config {
             local_peer_keypair: local_peer_keypair
                 .unwrap_or_else(identity::Keypair::generate_ed25519),
             log_level,
+            storage_backend,
             config_paths,
+            secrets_master_key,
+            secrets_master_key_file,
             local_mode: AtomicBool::new(local_mode),
             #[cfg(feature = "websocket")]
             ws: WebSocketApiConfig::from_config(&settings),
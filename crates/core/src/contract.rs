@@ -2,6 +2,8 @@
 //!
 //! Internally uses the wasm_runtime module to execute contract and/or delegate instructions.
 
+use std::collections::HashMap;
+
 use either::Either;
 use freenet_stdlib::prelude::*;
 
@@ -16,22 +18,63 @@ pub(crate) use executor::{
 pub(crate) use handler::{
     client_responses_channel, contract_handler_channel, in_memory::MemoryContractHandler,
     ClientResponsesReceiver, ClientResponsesSender, ContractHandler, ContractHandlerChannel,
-    ContractHandlerEvent, NetworkContractHandler, SenderHalve, StoreResponse, WaitingResolution,
+    ContractHandlerEvent, ExecutionUsage, NetworkContractHandler, SenderHalve, StoreResponse,
+    WaitingResolution,
 };
 
+pub(crate) use executor::DelegateOutcome;
 pub use executor::{Executor, ExecutorError, OperationMode};
+pub use storages::StorageBackend;
 
 use executor::ContractExecutor;
+use handler::EventId;
 use tracing::Instrument;
 
 pub(crate) async fn contract_handling<'a, CH>(mut contract_handler: CH) -> Result<(), ContractError>
 where
     CH: ContractHandler + Send + 'static,
 {
+    // delegates waiting on a network operation, and the event they will answer to once resumed
+    let mut suspended_delegates = HashMap::new();
     loop {
-        let (id, event) = contract_handler.channel().recv_from_sender().await?;
+        let next = {
+            let (channel, executor) = contract_handler.channel_and_executor();
+            tokio::select! {
+                event = channel.recv_from_sender() => Either::Left(event?),
+                transaction = executor.delegate_ready_to_resume() => Either::Right(transaction),
+            }
+        };
+        let (id, event) = match next {
+            Either::Left(event) => event,
+            Either::Right(transaction) => {
+                let outcome = contract_handler
+                    .executor()
+                    .resume_delegate(transaction)
+                    .instrument(tracing::info_span!("resume_delegate", %transaction))
+                    .await;
+                let Some(id) = suspended_delegates.remove(&transaction) else {
+                    tracing::debug!(%transaction, "resumed delegate not waited on by any event");
+                    continue;
+                };
+                let response = match outcome {
+                    Ok(DelegateOutcome::Suspended(transaction)) => {
+                        suspended_delegates.insert(transaction, id);
+                        continue;
+                    }
+                    Ok(DelegateOutcome::Completed(response)) => Ok(response),
+                    Err(err) => Err(err),
+                };
+                send_response(
+                    &mut contract_handler,
+                    id,
+                    ContractHandlerEvent::DelegateResponse { response },
+                )
+                .await?;
+                continue;
+            }
+        };
         tracing::debug!(%event, "Got contract handling event");
-        match event {
+        let response = match event {
             ContractHandlerEvent::GetQuery {
                 key,
                 fetch_contract,
@@ -44,40 +87,20 @@ where
                 {
                     Ok((state, contract)) => {
                         tracing::debug!(with_contract = %fetch_contract, has_contract = %contract.is_some(), "Fetched contract {key}");
-                        contract_handler
-                            .channel()
-                            .send_to_sender(
-                                id,
-                                ContractHandlerEvent::GetResponse {
-                                    key,
-                                    response: Ok(StoreResponse {
-                                        state: Some(state),
-                                        contract,
-                                    }),
-                                },
-                            )
-                            .await
-                            .map_err(|error| {
-                                tracing::debug!(%error, "shutting down contract handler");
-                                error
-                            })?;
+                        ContractHandlerEvent::GetResponse {
+                            key,
+                            response: Ok(StoreResponse {
+                                state: Some(state),
+                                contract,
+                            }),
+                        }
                     }
                     Err(err) => {
                         tracing::warn!("Error while executing get contract query: {err}");
-                        contract_handler
-                            .channel()
-                            .send_to_sender(
-                                id,
-                                ContractHandlerEvent::GetResponse {
-                                    key,
-                                    response: Err(err),
-                                },
-                            )
-                            .await
-                            .map_err(|error| {
-                                tracing::debug!(%error, "shutting down contract handler");
-                                error
-                            })?;
+                        ContractHandlerEvent::GetResponse {
+                            key,
+                            response: Err(err),
+                        }
                     }
                 }
             }
@@ -97,25 +120,92 @@ where
                     )
                     .instrument(tracing::info_span!("upsert_contract_state", %key))
                     .await;
-                contract_handler
-                    .channel()
-                    .send_to_sender(
-                        id,
-                        ContractHandlerEvent::PutResponse {
-                            new_value: put_result.map_err(Into::into),
-                        },
-                    )
+                ContractHandlerEvent::PutResponse {
+                    new_value: put_result.map_err(Into::into),
+                }
+            }
+            ContractHandlerEvent::UpdateQuery {
+                key,
+                data,
+                related_contracts,
+            } => {
+                let executor = contract_handler.executor();
+                let update_result = match executor
+                    .upsert_contract_state(key.clone(), data, related_contracts, None)
+                    .instrument(tracing::info_span!("upsert_contract_state", %key))
                     .await
-                    .map_err(|error| {
-                        tracing::debug!(%error, "shutting down contract handler");
-                        error
-                    })?;
+                {
+                    Ok(new_state) => executor
+                        .summarize_contract_state(&key, &new_state)
+                        .await
+                        .map(|summary| (new_state, summary)),
+                    Err(err) => Err(err),
+                };
+                ContractHandlerEvent::UpdateResponse {
+                    key,
+                    new_value: update_result,
+                }
+            }
+            ContractHandlerEvent::DelegateRequest {
+                req,
+                attested_contract,
+            } => {
+                let outcome = contract_handler
+                    .executor()
+                    .execute_delegate_request(req, attested_contract.as_ref())
+                    .instrument(tracing::info_span!("execute_delegate_request"))
+                    .await;
+                let response = match outcome {
+                    Ok(DelegateOutcome::Suspended(transaction)) => {
+                        // answered once the operation completes, meanwhile keep handling events
+                        suspended_delegates.insert(transaction, id);
+                        continue;
+                    }
+                    Ok(DelegateOutcome::Completed(response)) => Ok(response),
+                    Err(err) => Err(err),
+                };
+                ContractHandlerEvent::DelegateResponse { response }
             }
             _ => unreachable!(),
-        }
+        };
+        send_response(&mut contract_handler, id, response).await?;
     }
 }
 
+/// Sends back the response to the event, together with the resources spent handling it.
+async fn send_response<CH>(
+    contract_handler: &mut CH,
+    id: EventId,
+    response: ContractHandlerEvent,
+) -> Result<(), ContractError>
+where
+    CH: ContractHandler,
+{
+    let storage = match &response {
+        ContractHandlerEvent::PutResponse {
+            new_value: Ok(state),
+        }
+        | ContractHandlerEvent::UpdateResponse {
+            new_value: Ok((state, _)),
+            ..
+        } => state.size(),
+        _ => 0,
+    };
+    let usage = ExecutionUsage {
+        fuel: contract_handler.executor().take_fuel_usage(),
+        storage,
+        wasm_memory: contract_handler.executor().take_wasm_memory_usage(),
+    };
+    contract_handler
+        .channel()
+        .send_to_sender(id, response, usage)
+        .await
+        .map_err(|error| {
+            tracing::debug!(%error, "shutting down contract handler");
+            error
+        })
+}
+
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum ContractError {
     #[error("handler channel dropped")]
@@ -27,8 +27,8 @@ use crate::node::OpManager;
 use crate::operations::get::GetResult;
 use crate::operations::{OpEnum, OpError};
 use crate::wasm_runtime::{
-    ContractRuntimeInterface, ContractStore, DelegateRuntimeInterface, DelegateStore, Runtime,
-    SecretsStore, StateStore, StateStoreError,
+    ContractRuntimeInterface, ContractStore, DelegateRuntimeInterface, DelegateStore,
+    ExecutionLimits, Runtime, SecretsStore, StateStore, StateStoreError,
 };
 use crate::{
     client_events::{ClientId, HostResult},
@@ -37,13 +37,17 @@ use crate::{
     DynError,
 };
 
-use super::storages::Storage;
+use super::storages::{Storage, StorageBackend};
 
 pub(super) mod mock_runtime;
 pub(super) mod runtime;
 
 #[derive(Debug)]
-pub struct ExecutorError(Either<Box<RequestError>, DynError>);
+pub struct ExecutorError {
+    inner: Either<Box<RequestError>, DynError>,
+    /// The request was rejected by the contract's own validation.
+    invalid: bool,
+}
 
 enum InnerOpError {
     Upsert(ContractKey),
@@ -54,16 +58,30 @@ impl std::error::Error for ExecutorError {}
 
 impl ExecutorError {
     pub fn other(error: impl Into<DynError>) -> Self {
-        Self(Either::Right(error.into()))
+        Self {
+            inner: Either::Right(error.into()),
+            invalid: false,
+        }
     }
 
     /// Call this when an unreachable path is reached but need to avoid panics.
     fn internal_error() -> Self {
-        ExecutorError(Either::Right("internal error".into()))
+        ExecutorError::other("internal error")
     }
 
     fn request(error: impl Into<RequestError>) -> Self {
-        Self(Either::Left(Box::new(error.into())))
+        Self {
+            inner: Either::Left(Box::new(error.into())),
+            invalid: false,
+        }
+    }
+
+    /// A request error caused by a state or delta which is not valid for the contract.
+    fn invalid(error: impl Into<RequestError>) -> Self {
+        Self {
+            inner: Either::Left(Box::new(error.into())),
+            invalid: true,
+        }
     }
 
     fn execution(
@@ -126,11 +144,16 @@ impl ExecutorError {
     }
 
     pub fn is_request(&self) -> bool {
-        matches!(self.0, Either::Left(_))
+        matches!(self.inner, Either::Left(_))
+    }
+
+    /// Whether the state or delta in the request failed the contract validation.
+    pub fn is_invalid(&self) -> bool {
+        self.invalid
     }
 
     pub fn unwrap_request(self) -> RequestError {
-        match self.0 {
+        match self.inner {
             Either::Left(err) => *err,
             Either::Right(_) => panic!(),
         }
@@ -139,13 +162,13 @@ impl ExecutorError {
 
 impl From<RequestError> for ExecutorError {
     fn from(value: RequestError) -> Self {
-        Self(Either::Left(Box::new(value)))
+        Self::request(value)
     }
 }
 
 impl Display for ExecutorError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match &self.0 {
+        match &self.inner {
             Either::Left(l) => write!(f, "{}", &**l),
             Either::Right(r) => write!(f, "{}", &**r),
         }
@@ -154,7 +177,10 @@ impl Display for ExecutorError {
 
 impl From<Box<RequestError>> for ExecutorError {
     fn from(value: Box<RequestError>) -> Self {
-        Self(Either::Left(value))
+        Self {
+            inner: Either::Left(value),
+            invalid: false,
+        }
     }
 }
 
@@ -208,26 +234,39 @@ enum CallbackError {
     Err(#[from] ExecutorError),
     #[error(transparent)]
     Conversion(#[from] OpError),
-    #[error("missing result")]
-    MissingResult,
+    #[error("timed out waiting for the operation result")]
+    Timeout,
 }
 
 impl ExecutorToEventLoopChannel<ExecutorHalve> {
-    async fn send_to_event_loop<Op, T>(&mut self, message: T) -> Result<Transaction, DynError>
+    /// Starts the operation for `message`, on behalf of `delegate` if any, so its traffic is
+    /// attributed to the delegate.
+    async fn send_to_event_loop<Op, T>(
+        &mut self,
+        message: T,
+        delegate: Option<&DelegateKey>,
+    ) -> Result<Transaction, DynError>
     where
         T: ComposeNetworkMessage<Op>,
         Op: Operation + Send + 'static,
     {
         let op = message.initiate_op(&self.op_manager);
         let tx = *op.id();
+        if let Some(delegate) = delegate {
+            self.op_manager
+                .ring
+                .delegate_transaction(tx, delegate.clone());
+        }
         self.end.waiting_for_op_tx.send(tx).await.map_err(|e| {
             tracing::debug!("failed to send request to executor, channel closed");
+            self.op_manager.ring.delegate_transaction_done(&tx);
             e
         })?;
         <T as ComposeNetworkMessage<Op>>::resume_op(op, &self.op_manager)
             .await
             .map_err(|e| {
                 tracing::debug!("failed to resume operation: {e}");
+                self.op_manager.ring.delegate_transaction_done(&tx);
                 e
             })?;
         Ok(tx)
@@ -237,19 +276,29 @@ impl ExecutorToEventLoopChannel<ExecutorHalve> {
     where
         Op: Operation + TryFrom<OpEnum, Error = OpError>,
     {
+        // results which were never claimed are of no use once their transaction expired
+        self.end.completed.retain(|tx, _| !tx.timed_out());
         if let Some(result) = self.end.completed.remove(&transaction) {
             return result.try_into().map_err(CallbackError::Conversion);
         }
-        let op_result = self
-            .end
-            .response_for_rx
-            .recv()
+        let response_for_rx = &mut self.end.response_for_rx;
+        let completed = &mut self.end.completed;
+        let wait_for_result = async {
+            loop {
+                let op_result = response_for_rx
+                    .recv()
+                    .await
+                    .ok_or_else(|| ExecutorError::other("channel closed"))?;
+                if op_result.id() == &transaction {
+                    break Ok::<_, CallbackError>(op_result);
+                }
+                // a result for an other request, keep it until it is asked for
+                completed.insert(*op_result.id(), op_result);
+            }
+        };
+        let op_result = tokio::time::timeout(crate::config::OPERATION_TTL, wait_for_result)
             .await
-            .ok_or_else(|| ExecutorError::other("channel closed"))?;
-        if op_result.id() != &transaction {
-            self.end.completed.insert(*op_result.id(), op_result);
-            return Err(CallbackError::MissingResult);
-        }
+            .map_err(|_| CallbackError::Timeout)??;
         op_result.try_into().map_err(CallbackError::Conversion)
     }
 }
@@ -361,7 +410,6 @@ impl ComposeNetworkMessage<operations::subscribe::SubscribeOp> for SubscribeCont
     }
 }
 
-#[allow(unused)]
 struct PutContract {
     contract: ContractContainer,
     state: WrappedState,
@@ -399,7 +447,11 @@ struct UpdateContract {
 impl ComposeNetworkMessage<operations::update::UpdateOp> for UpdateContract {
     fn initiate_op(self, op_manager: &OpManager) -> operations::update::UpdateOp {
         let UpdateContract { key, new_state } = self;
-        operations::update::start_op(key, new_state, op_manager.ring.max_hops_to_live)
+        operations::update::start_op(
+            key,
+            Either::Left(new_state),
+            op_manager.ring.max_hops_to_live,
+        )
     }
 
     async fn resume_op(
@@ -426,6 +478,99 @@ pub(crate) trait ContractExecutor: Send + 'static {
         related_contracts: RelatedContracts<'static>,
         code: Option<ContractContainer>,
     ) -> Result<WrappedState, ExecutorError>;
+
+    async fn summarize_contract_state(
+        &mut self,
+        key: &ContractKey,
+        state: &WrappedState,
+    ) -> Result<StateSummary<'static>, ExecutorError>;
+
+    async fn execute_delegate_request(
+        &mut self,
+        req: DelegateRequest<'_>,
+        attested_contract: Option<&ContractInstanceId>,
+    ) -> Result<DelegateOutcome, ExecutorError>;
+
+    /// Waits until one of the suspended delegates can be resumed, because the operation it is
+    /// waiting on completed or timed out. Never completes while no delegate is suspended.
+    ///
+    /// Cancel safe, so it can be raced against incoming events.
+    async fn delegate_ready_to_resume(&mut self) -> Transaction;
+
+    async fn resume_delegate(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<DelegateOutcome, ExecutorError>;
+
+    /// Takes the bytes of WASM instance memory used since the last time it was taken.
+    fn take_wasm_memory_usage(&mut self) -> usize;
+
+    /// Takes the fuel spent running WASM code since the last time it was taken.
+    fn take_fuel_usage(&mut self) -> u64;
+}
+
+/// Result of executing a delegate request.
+#[derive(Debug)]
+pub(crate) enum DelegateOutcome {
+    Completed(HostResponse),
+    /// The delegate is waiting on the network operation, it will be resumed once it completes.
+    Suspended(Transaction),
+}
+
+/// A contract request made by a delegate which is served through a network operation.
+enum DelegateContractRequest {
+    Get {
+        contract_id: ContractInstanceId,
+        context: DelegateContext,
+    },
+    Put {
+        contract_id: ContractInstanceId,
+        context: DelegateContext,
+    },
+}
+
+impl DelegateContractRequest {
+    /// The message for the delegate with the result of the request, the state in case of a get.
+    fn response(self, result: Result<Option<WrappedState>, String>) -> InboundDelegateMsg<'static> {
+        match self {
+            DelegateContractRequest::Get {
+                contract_id,
+                context,
+            } => InboundDelegateMsg::GetContractResponse(GetContractResponse {
+                contract_id,
+                state: result.ok().flatten(),
+                context,
+            }),
+            DelegateContractRequest::Put {
+                contract_id,
+                context,
+            } => InboundDelegateMsg::PutContractResponse(PutContractResponse {
+                contract_id,
+                result: result.map(|_| ()),
+                context,
+            }),
+        }
+    }
+}
+
+/// The state required to resume the execution of a delegate once the network operation it is
+/// waiting on completes.
+struct DelegateContinuation {
+    key: DelegateKey,
+    params: Parameters<'static>,
+    attested_contract: Option<ContractInstanceId>,
+    request: DelegateContractRequest,
+    /// Messages for the application produced before the request.
+    ready: Vec<OutboundDelegateMsg>,
+    /// Messages the delegate queued after the request, processed once it gets the response.
+    pending: Vec<OutboundDelegateMsg>,
+    suspended_at: Instant,
+}
+
+impl DelegateContinuation {
+    fn expired(&self) -> bool {
+        self.suspended_at.elapsed() >= crate::config::OPERATION_TTL
+    }
 }
 
 /// A WASM executor which will run any contracts, delegates, etc. registered.
@@ -447,6 +592,8 @@ pub struct Executor<R = Runtime> {
     subscriber_summaries: HashMap<ContractKey, HashMap<ClientId, Option<StateSummary<'static>>>>,
     /// Attested contract instances for a given delegate.
     delegate_attested_ids: HashMap<DelegateKey, Vec<ContractInstanceId>>,
+    /// Delegate executions parked while waiting for the result of a network operation.
+    suspended_delegates: HashMap<Transaction, DelegateContinuation>,
 
     event_loop_channel: Option<ExecutorToEventLoopChannel<ExecutorHalve>>,
 }
@@ -472,6 +619,7 @@ impl<R> Executor<R> {
             update_notifications: HashMap::default(),
             subscriber_summaries: HashMap::default(),
             delegate_attested_ids: HashMap::default(),
+            suspended_delegates: HashMap::default(),
             event_loop_channel,
         })
     }
@@ -496,33 +644,82 @@ impl<R> Executor<R> {
         let static_conf = crate::config::Config::conf();
 
         let db_path = crate::config::Config::conf().db_dir();
-        let state_store =
-            StateStore::new(Storage::new(Some(&db_path)).await?, MAX_MEM_CACHE).unwrap();
+        let backend = config
+            .storage_backend
+            .unwrap_or(static_conf.storage_backend);
+        let mut state_store =
+            StateStore::new(Storage::new(backend, Some(&db_path)).await?, MAX_MEM_CACHE).unwrap();
+        if let Some(max_versions) = config.state_history {
+            state_store = state_store.with_history(max_versions);
+        }
 
         let contract_dir = config
             .node_data_dir
             .as_ref()
             .map(|d| d.join("contracts"))
             .unwrap_or_else(|| static_conf.contracts_dir());
-        let contract_store = ContractStore::new(contract_dir, MAX_SIZE)?;
+        let mut contract_store = ContractStore::new(contract_dir, MAX_SIZE)?;
 
         let delegate_dir = config
             .node_data_dir
             .as_ref()
             .map(|d| d.join("delegates"))
             .unwrap_or_else(|| static_conf.delegates_dir());
-        let delegate_store = DelegateStore::new(delegate_dir, MAX_SIZE)?;
+        let mut delegate_store = DelegateStore::new(delegate_dir, MAX_SIZE)?;
+
+        if let Some(quota) = config.max_code_disk_usage {
+            contract_store = contract_store.with_disk_quota(quota);
+            delegate_store = delegate_store.with_disk_quota(quota);
+        }
 
         let secrets_dir = config
             .node_data_dir
             .as_ref()
             .map(|d| d.join("secrets"))
             .unwrap_or_else(|| static_conf.secrets_dir());
-        let secret_store = SecretsStore::new(secrets_dir)?;
+        let secret_store = SecretsStore::new(secrets_dir, static_conf.secrets_master_key())?;
 
         Ok((contract_store, delegate_store, secret_store, state_store))
     }
 
+    /// Waits for the result of the operation of a suspended delegate, or for the first of them
+    /// to time out.
+    async fn suspended_delegate_ready(&mut self) -> Transaction {
+        let Some(ch) = &mut self.event_loop_channel else {
+            return std::future::pending().await;
+        };
+        loop {
+            if let Some(tx) = self
+                .suspended_delegates
+                .iter()
+                .find(|(tx, continuation)| {
+                    ch.end.completed.contains_key(*tx) || continuation.expired()
+                })
+                .map(|(tx, _)| *tx)
+            {
+                return tx;
+            }
+            let Some(deadline) = self
+                .suspended_delegates
+                .values()
+                .map(|continuation| continuation.suspended_at + crate::config::OPERATION_TTL)
+                .min()
+            else {
+                return std::future::pending().await;
+            };
+            tokio::select! {
+                result = ch.end.response_for_rx.recv() => {
+                    let Some(result) = result else {
+                        tracing::debug!("executor channel closed");
+                        return std::future::pending().await;
+                    };
+                    ch.end.completed.insert(*result.id(), result);
+                }
+                _ = tokio::time::sleep_until(deadline.into()) => {}
+            }
+        }
+    }
+
     async fn op_request<Op, M>(&mut self, request: M) -> Result<Op::Result, ExecutorError>
     where
         Op: Operation + Send + TryFrom<OpEnum, Error = OpError> + 'static,
@@ -533,26 +730,24 @@ impl<R> Executor<R> {
             return Err(ExecutorError::other("missing event loop channel"));
         };
         let transaction = ch
-            .send_to_event_loop(request)
+            .send_to_event_loop(request, None)
             .await
             .map_err(ExecutorError::other)?;
-        // FIXME: must add a way to suspend a request while waiting for result and resume upon getting
-        // an answer back so we don't block the executor itself.
-        // otherwise it may be possible to end up in a deadlock waiting for a tree of contract
-        // dependencies to be resolved
-        let result = loop {
-            match ch.receive_op_result::<Op>(transaction).await {
-                Ok(result) => break result,
-                Err(CallbackError::MissingResult) => {
-                    tokio::time::sleep(Duration::from_secs(10)).await;
-                    continue;
-                }
-                Err(CallbackError::Conversion(err)) => {
-                    tracing::error!("expect message of one type but got an other: {err}");
-                    return Err(ExecutorError::other(err));
-                }
-                Err(CallbackError::Err(other)) => return Err(other),
+        // FIXME: contract requests still block the executor while waiting for the result (up to
+        // the operation TTL), it may be possible to end up in a deadlock waiting for a tree of
+        // contract dependencies to be resolved; delegates are suspended instead, see
+        // `Executor::suspend_delegate`
+        let result = match ch.receive_op_result::<Op>(transaction).await {
+            Ok(result) => result,
+            Err(CallbackError::Timeout) => {
+                tracing::warn!(%transaction, "timed out waiting for network operation result");
+                return Err(ExecutorError::other(CallbackError::Timeout));
+            }
+            Err(CallbackError::Conversion(err)) => {
+                tracing::error!("expect message of one type but got an other: {err}");
+                return Err(ExecutorError::other(err));
             }
+            Err(CallbackError::Err(other)) => return Err(other),
         };
         let result = <Op::Result>::try_from(result).map_err(|err| {
             tracing::debug!("didn't get result back: {err}");
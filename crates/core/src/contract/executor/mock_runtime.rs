@@ -15,10 +15,11 @@ impl Executor<MockRuntime> {
         std::fs::create_dir_all(&contracts_data_dir).expect("directory created");
         let contract_store = ContractStore::new(contracts_data_dir, u16::MAX as i64)?;
 
-        let db_path = data_dir.join("db");
-        std::fs::create_dir_all(&db_path).expect("directory created");
-        let state_store =
-            StateStore::new(Storage::new(Some(&db_path)).await?, u16::MAX as u32).unwrap();
+        let state_store = StateStore::new(
+            Storage::new(StorageBackend::InMemory, None).await?,
+            u16::MAX as u32,
+        )
+        .unwrap();
 
         let executor = Executor::new(
             state_store,
@@ -102,17 +103,71 @@ impl ContractExecutor for Executor<MockRuntime> {
                     .map_err(ExecutorError::other)?;
                 return Ok(incoming_state);
             }
-            // (Either::Left(_), None) => {
-            //     return Err(ExecutorError::request(RequestError::from(
-            //         StdContractError::Get {
-            //             key: key.clone(),
-            //             cause: "Missing contract or parameters".into(),
-            //         },
-            //     )));
-            // }
+            (update, None) => {
+                // the mock runtime does not run any contract code, so deltas are just taken
+                // as the new value for the state
+                let Some(params) = self
+                    .state_store
+                    .get_params(&key)
+                    .await
+                    .map_err(ExecutorError::other)?
+                else {
+                    return Err(ExecutorError::request(StdContractError::MissingContract {
+                        key: key.into(),
+                    }));
+                };
+                let new_state = match update {
+                    Either::Left(state) => state,
+                    Either::Right(delta) => WrappedState::new(delta.to_vec()),
+                };
+                self.state_store
+                    .store(key, new_state.clone(), params)
+                    .await
+                    .map_err(ExecutorError::other)?;
+                Ok(new_state)
+            }
             (update, contract) => unreachable!("{update:?}, {contract:?}"),
         }
     }
+
+    async fn summarize_contract_state(
+        &mut self,
+        _key: &ContractKey,
+        state: &WrappedState,
+    ) -> Result<StateSummary<'static>, ExecutorError> {
+        Ok(StateSummary::from(state.as_ref().to_vec()))
+    }
+
+    async fn execute_delegate_request(
+        &mut self,
+        _req: DelegateRequest<'_>,
+        _attested_contract: Option<&ContractInstanceId>,
+    ) -> Result<DelegateOutcome, ExecutorError> {
+        Err(ExecutorError::other(
+            "delegates are not supported by the mock runtime",
+        ))
+    }
+
+    async fn delegate_ready_to_resume(&mut self) -> Transaction {
+        std::future::pending().await
+    }
+
+    async fn resume_delegate(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<DelegateOutcome, ExecutorError> {
+        Err(ExecutorError::other(format!(
+            "no delegate waiting on transaction {transaction}"
+        )))
+    }
+
+    fn take_wasm_memory_usage(&mut self) -> usize {
+        0
+    }
+
+    fn take_fuel_usage(&mut self) -> u64 {
+        0
+    }
 }
 
 #[cfg(test)]
@@ -126,7 +181,11 @@ mod test {
         const MAX_MEM_CACHE: u32 = 10_000_000;
         let tmp_dir = tempfile::tempdir()?;
         let contract_store = ContractStore::new(tmp_dir.path().join("executor-test"), MAX_SIZE)?;
-        let state_store = StateStore::new(Storage::new(None).await?, MAX_MEM_CACHE).unwrap();
+        let state_store = StateStore::new(
+            Storage::new(StorageBackend::InMemory, None).await?,
+            MAX_MEM_CACHE,
+        )
+        .unwrap();
         let mut counter = 0;
         Executor::new(
             state_store,
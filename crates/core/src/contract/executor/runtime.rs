@@ -24,10 +24,13 @@ impl ContractExecutor for Executor<Runtime> {
     }
 
     async fn store_contract(&mut self, contract: ContractContainer) -> Result<(), ExecutorError> {
+        let key = contract.key();
         self.runtime
             .contract_store
             .store_contract(contract)
-            .map_err(ExecutorError::other)
+            .map_err(ExecutorError::other)?;
+        self.evict_unused_contracts(&key);
+        Ok(())
     }
 
     async fn upsert_contract_state(
@@ -86,7 +89,7 @@ impl ContractExecutor for Executor<Runtime> {
                 match result {
                     ValidateResult::Valid => {}
                     ValidateResult::Invalid => {
-                        return Err(ExecutorError::request(StdContractError::invalid_put(key)));
+                        return Err(ExecutorError::invalid(StdContractError::invalid_put(key)));
                     }
                     ValidateResult::RequestRelated(mut related) => {
                         if let Some(key) = related.pop() {
@@ -112,7 +115,7 @@ impl ContractExecutor for Executor<Runtime> {
                         ExecutorError::other(err)
                     })?;
                 if !valid {
-                    return Err(ExecutorError::request(StdContractError::invalid_update(
+                    return Err(ExecutorError::invalid(StdContractError::invalid_update(
                         key,
                     )));
                 }
@@ -128,7 +131,7 @@ impl ContractExecutor for Executor<Runtime> {
                     key: key.into(),
                 }));
             }
-            Err(StateStoreError::Any(err)) => return Err(ExecutorError::other(err)),
+            Err(err) => return Err(ExecutorError::other(err)),
         };
 
         for (id, state) in related_contracts
@@ -158,6 +161,54 @@ impl ContractExecutor for Executor<Runtime> {
         };
         Ok(updated_state)
     }
+
+    async fn summarize_contract_state(
+        &mut self,
+        key: &ContractKey,
+        state: &WrappedState,
+    ) -> Result<StateSummary<'static>, ExecutorError> {
+        let params = self
+            .state_store
+            .get_params(key)
+            .await
+            .map_err(ExecutorError::other)?
+            .ok_or_else(|| {
+                ExecutorError::request(StdContractError::Update {
+                    key: key.clone(),
+                    cause: "missing contract parameters".into(),
+                })
+            })?;
+        self.runtime
+            .summarize_state(key, &params, state)
+            .map_err(ExecutorError::other)
+    }
+
+    async fn execute_delegate_request(
+        &mut self,
+        req: DelegateRequest<'_>,
+        attested_contract: Option<&ContractInstanceId>,
+    ) -> Result<DelegateOutcome, ExecutorError> {
+        self.execute_delegate(req, attested_contract).await
+    }
+
+    async fn delegate_ready_to_resume(&mut self) -> Transaction {
+        self.suspended_delegate_ready().await
+    }
+
+    async fn resume_delegate(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<DelegateOutcome, ExecutorError> {
+        Executor::resume_delegate(self, transaction).await
+    }
+
+    fn take_wasm_memory_usage(&mut self) -> usize {
+        self.runtime.take_memory_usage()
+    }
+
+    fn take_fuel_usage(&mut self) -> u64 {
+        self.runtime.take_fuel_usage()
+    }
 }
 
 impl Executor<Runtime> {
@@ -167,7 +218,19 @@ impl Executor<Runtime> {
     ) -> Result<Self, DynError> {
         let (contract_store, delegate_store, secret_store, state_store) =
             Self::get_stores(&config).await?;
-        let rt = Runtime::build(contract_store, delegate_store, secret_store, false).unwrap();
+        let mut limits = ExecutionLimits::default();
+        if let Some(max_fuel) = config.max_fuel {
+            limits.max_fuel = max_fuel;
+        }
+        if let Some(max_execution_time) = config.max_execution_time {
+            limits.max_execution_time = Duration::from_millis(max_execution_time);
+        }
+        let mut rt = Runtime::build(contract_store, delegate_store, secret_store, false)
+            .unwrap()
+            .with_limits(limits);
+        if let Some(size) = config.instance_pool_size {
+            rt = rt.with_instance_pool(size);
+        }
         Executor::new(
             state_store,
             || {
@@ -181,6 +244,35 @@ impl Executor<Runtime> {
         .await
     }
 
+    /// An executor for a network node, keeping its stores under `data_dir`.
+    #[cfg(test)]
+    pub(crate) async fn new_test(
+        data_dir: &std::path::Path,
+        event_loop_channel: ExecutorToEventLoopChannel<ExecutorHalve>,
+    ) -> Result<Self, DynError> {
+        const MAX_SIZE: i64 = 10 * 1024 * 1024;
+        let contract_store = ContractStore::new(data_dir.join("contracts"), MAX_SIZE)?;
+        let delegate_store = DelegateStore::new(data_dir.join("delegates"), MAX_SIZE)?;
+        let secret_store = SecretsStore::new(
+            data_dir.join("secrets"),
+            crate::wasm_runtime::MasterKey::from_passphrase(b"test"),
+        )?;
+        let state_store = StateStore::new(
+            Storage::new(StorageBackend::InMemory, None).await?,
+            u16::MAX as u32,
+        )
+        .unwrap();
+        let rt = Runtime::build(contract_store, delegate_store, secret_store, false).unwrap();
+        Executor::new(
+            state_store,
+            || Ok(()),
+            OperationMode::Network,
+            rt,
+            Some(event_loop_channel),
+        )
+        .await
+    }
+
     pub fn register_contract_notifier(
         &mut self,
         key: ContractKey,
@@ -235,7 +327,7 @@ impl Executor<Runtime> {
             )
             .await
         {
-            match err.0 {
+            match err.inner {
                 Either::Left(err) => tracing::error!("req error: {err}"),
                 Either::Right(err) => tracing::error!("other error: {err}"),
             }
@@ -250,7 +342,7 @@ impl Executor<Runtime> {
     ) -> Response {
         match req {
             ClientRequest::ContractOp(op) => self.contract_requests(op, id, updates).await,
-            ClientRequest::DelegateOp(op) => self.delegate_request(op, None),
+            ClientRequest::DelegateOp(op) => self.delegate_request(op, None).await,
             ClientRequest::Disconnect { cause } => {
                 if let Some(cause) = cause {
                     tracing::info!("disconnecting cause: {cause}");
@@ -302,21 +394,38 @@ impl Executor<Runtime> {
         }
     }
 
-    pub fn delegate_request(
+    pub async fn delegate_request(
         &mut self,
         req: DelegateRequest<'_>,
         attestaded_contract: Option<&ContractInstanceId>,
     ) -> Response {
-        match req {
+        match self.execute_delegate(req, attestaded_contract).await? {
+            DelegateOutcome::Completed(response) => Ok(response),
+            DelegateOutcome::Suspended(transaction) => {
+                // only executors driven by a node's contract handler get to resume delegates
+                self.suspended_delegates.remove(&transaction);
+                Err(ExecutorError::other(
+                    "delegate requested a network operation outside of a node",
+                ))
+            }
+        }
+    }
+
+    /// Executes a delegate request, suspending the delegate if it needs to wait on a network
+    /// operation.
+    pub(crate) async fn execute_delegate(
+        &mut self,
+        req: DelegateRequest<'_>,
+        attestaded_contract: Option<&ContractInstanceId>,
+    ) -> Result<DelegateOutcome, ExecutorError> {
+        let response = match req {
             DelegateRequest::RegisterDelegate {
                 delegate,
                 cipher,
                 nonce,
             } => {
-                use chacha20poly1305::{KeyInit, XChaCha20Poly1305};
                 let key = delegate.key().clone();
-                let arr = GenericArray::from_slice(&cipher);
-                let cipher = XChaCha20Poly1305::new(arr);
+                let cipher = GenericArray::from_slice(&cipher).to_owned();
                 let nonce = GenericArray::from_slice(&nonce).to_owned();
                 tracing::debug!("registering delegate `{key}");
                 if let Some(contract) = attestaded_contract {
@@ -378,18 +487,28 @@ impl Executor<Runtime> {
                     self.delegate_attested_ids
                         .get(&key)
                         .and_then(|contracts| contracts.iter().find(|c| *c == contract))
+                        .copied()
                 });
                 match self.runtime.inbound_app_message(
                     &key,
                     &params,
-                    attested.map(|c| c.as_bytes()),
+                    attested.as_ref().map(|c| c.as_bytes()),
                     inbound
                         .into_iter()
                         .map(InboundDelegateMsg::into_owned)
                         .collect(),
                 ) {
-                    Ok(values) => Ok(HostResponse::DelegateResponse { key, values }),
-
+                    Ok(values) => {
+                        return self
+                            .serve_delegate_contract_requests(
+                                key,
+                                params.into_owned(),
+                                attested,
+                                Vec::new(),
+                                values,
+                            )
+                            .await;
+                    }
                     Err(err) => {
                         tracing::error!("failed executing delegate `{key}`: {err}");
                         Err(ExecutorError::other(format!(
@@ -399,6 +518,225 @@ impl Executor<Runtime> {
                 }
             }
             _ => Err(ExecutorError::other("not supported")),
+        };
+        response.map(DelegateOutcome::Completed)
+    }
+
+    /// Serves the contract requests made by a delegate, feeding it back each response until it
+    /// doesn't request anything else, or suspending it if a request has to go through the network.
+    async fn serve_delegate_contract_requests(
+        &mut self,
+        key: DelegateKey,
+        params: Parameters<'static>,
+        attested_contract: Option<ContractInstanceId>,
+        mut ready: Vec<OutboundDelegateMsg>,
+        mut values: Vec<OutboundDelegateMsg>,
+    ) -> Result<DelegateOutcome, ExecutorError> {
+        loop {
+            let Some(pos) = values.iter().position(is_contract_request) else {
+                ready.extend(values);
+                return Ok(DelegateOutcome::Completed(HostResponse::DelegateResponse {
+                    key,
+                    values: ready,
+                }));
+            };
+            let mut pending = values.split_off(pos);
+            ready.append(&mut values);
+            let response = match self
+                .delegate_contract_request(&key, pending.remove(0))
+                .await
+            {
+                Either::Left(response) => response,
+                Either::Right((transaction, request)) => {
+                    self.suspend_delegate(
+                        transaction,
+                        DelegateContinuation {
+                            key,
+                            params,
+                            attested_contract,
+                            request,
+                            ready,
+                            pending,
+                            suspended_at: Instant::now(),
+                        },
+                    );
+                    return Ok(DelegateOutcome::Suspended(transaction));
+                }
+            };
+            values = self
+                .runtime
+                .inbound_contract_response(
+                    &key,
+                    &params,
+                    attested_contract.as_ref().map(|c| c.as_bytes()),
+                    response,
+                    pending,
+                )
+                .map_err(|err| {
+                    tracing::error!("failed executing delegate `{key}`: {err}");
+                    ExecutorError::other(format!("uncontrolled error while executing `{key}`"))
+                })?;
+        }
+    }
+
+    /// Performs a GET or PUT on behalf of a delegate. Returns the response for the delegate if
+    /// it could be served locally, otherwise the transaction of the network operation started.
+    async fn delegate_contract_request(
+        &mut self,
+        delegate: &DelegateKey,
+        request: OutboundDelegateMsg,
+    ) -> Either<InboundDelegateMsg<'static>, (Transaction, DelegateContractRequest)> {
+        let (request, sent) = match request {
+            OutboundDelegateMsg::GetContractRequest(GetContractRequest {
+                contract_id,
+                context,
+                ..
+            }) => {
+                let request = DelegateContractRequest::Get {
+                    contract_id,
+                    context,
+                };
+                let key = ContractKey::from(contract_id);
+                match (
+                    self.state_store.get(&key).await,
+                    &mut self.event_loop_channel,
+                ) {
+                    (Ok(state), _) => return Either::Left(request.response(Ok(Some(state)))),
+                    (Err(_), None) => return Either::Left(request.response(Ok(None))),
+                    (Err(_), Some(ch)) => {
+                        let get = GetContract {
+                            key,
+                            fetch_contract: false,
+                        };
+                        (
+                            request,
+                            ch.send_to_event_loop::<operations::get::GetOp, _>(get, Some(delegate))
+                                .await,
+                        )
+                    }
+                }
+            }
+            OutboundDelegateMsg::PutContractRequest(PutContractRequest {
+                contract,
+                state,
+                related_contracts,
+                context,
+                ..
+            }) => {
+                let request = DelegateContractRequest::Put {
+                    contract_id: *contract.key().id(),
+                    context,
+                };
+                let Some(ch) = &mut self.event_loop_channel else {
+                    let result = self
+                        .perform_contract_put(contract, state, related_contracts)
+                        .await
+                        .map(|_| None)
+                        .map_err(|err| err.to_string());
+                    return Either::Left(request.response(result));
+                };
+                let put = PutContract {
+                    contract,
+                    state,
+                    related_contracts,
+                };
+                (
+                    request,
+                    ch.send_to_event_loop::<operations::put::PutOp, _>(put, Some(delegate))
+                        .await,
+                )
+            }
+            _ => unreachable!("only contract requests are served for delegates"),
+        };
+        match sent {
+            Ok(transaction) => Either::Right((transaction, request)),
+            Err(err) => {
+                tracing::debug!("failed starting operation for delegate: {err}");
+                Either::Left(request.response(Err(err.to_string())))
+            }
+        }
+    }
+
+    /// Park a delegate execution until the network operation identified by `transaction`
+    /// completes, so the executor can keep serving other requests in the meantime.
+    fn suspend_delegate(&mut self, transaction: Transaction, continuation: DelegateContinuation) {
+        tracing::debug!(%transaction, delegate = %continuation.key, "suspending delegate");
+        self.suspended_delegates.insert(transaction, continuation);
+    }
+
+    /// Resume a delegate previously parked with [`Self::suspend_delegate`], feeding it the
+    /// result of the operation, or a failure if it timed out, with the saved context restored.
+    pub(crate) async fn resume_delegate(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<DelegateOutcome, ExecutorError> {
+        let Some(continuation) = self.suspended_delegates.remove(&transaction) else {
+            return Err(ExecutorError::other(format!(
+                "no delegate waiting on transaction {transaction}"
+            )));
+        };
+        let DelegateContinuation {
+            key,
+            params,
+            attested_contract,
+            request,
+            ready,
+            pending,
+            ..
+        } = continuation;
+        let result = self.event_loop_channel.as_mut().and_then(|ch| {
+            ch.op_manager.ring.delegate_transaction_done(&transaction);
+            ch.end.completed.remove(&transaction)
+        });
+        let result = match (result, &request) {
+            (None, _) => {
+                tracing::warn!(%transaction, delegate = %key, "delegate timed out waiting on operation");
+                Err(CallbackError::Timeout.to_string())
+            }
+            (Some(op), DelegateContractRequest::Get { .. }) => operations::get::GetOp::try_from(op)
+                .and_then(operations::get::GetResult::try_from)
+                .map(|result| Some(result.state))
+                .map_err(|err| err.to_string()),
+            (Some(op), DelegateContractRequest::Put { .. }) => operations::put::PutOp::try_from(op)
+                .and_then(operations::put::PutResult::try_from)
+                .map(|_| None)
+                .map_err(|err| err.to_string()),
+        };
+        let values = self
+            .runtime
+            .inbound_contract_response(
+                &key,
+                &params,
+                attested_contract.as_ref().map(|c| c.as_bytes()),
+                request.response(result),
+                pending,
+            )
+            .map_err(|err| {
+                tracing::error!("failed executing delegate `{key}`: {err}");
+                ExecutorError::other(format!("uncontrolled error while executing `{key}`"))
+            })?;
+        self.serve_delegate_contract_requests(key, params, attested_contract, ready, values)
+            .await
+    }
+
+    /// Frees disk space if the contract store went over its quota, keeping the contracts which
+    /// are in use: the one just stored, those this peer is seeding or relaying updates for,
+    /// and those clients are subscribed to.
+    fn evict_unused_contracts(&mut self, just_stored: &ContractKey) {
+        let ring = self
+            .event_loop_channel
+            .as_ref()
+            .map(|ch| ch.op_manager.ring.clone());
+        let update_notifications = &self.update_notifications;
+        let keep = |key: &ContractKey| {
+            key == just_stored
+                || update_notifications.contains_key(key)
+                || ring.as_ref().is_some_and(|ring| {
+                    ring.is_seeding_contract(key) || ring.subscribers_of(key).is_some()
+                })
+        };
+        if let Err(err) = self.runtime.evict_unused_contracts(keep) {
+            tracing::warn!("failed evicting unused contracts: {err}");
         }
     }
 
@@ -423,6 +761,7 @@ impl Executor<Runtime> {
 
         self.verify_and_store_contract(state.clone(), contract, related_contracts)
             .await?;
+        self.evict_unused_contracts(&key);
 
         self.send_update_notification(&key, &params, &state)
             .await
@@ -938,6 +1277,19 @@ impl Executor<Runtime> {
     feature = "network-mode",
     all(not(feature = "local-mode"), not(feature = "network-mode"))
 ))]
+fn is_contract_request(msg: &OutboundDelegateMsg) -> bool {
+    matches!(
+        msg,
+        OutboundDelegateMsg::GetContractRequest(GetContractRequest {
+            processed: false,
+            ..
+        }) | OutboundDelegateMsg::PutContractRequest(PutContractRequest {
+            processed: false,
+            ..
+        })
+    )
+}
+
 impl Executor<Runtime> {
     async fn subscribe(&mut self, key: ContractKey) -> Result<(), ExecutorError> {
         #[cfg(any(
@@ -3,6 +3,7 @@ use std::hash::Hash;
 use std::sync::atomic::{AtomicU64, Ordering::SeqCst};
 use std::time::Duration;
 
+use either::Either;
 use freenet_stdlib::client_api::{ClientError, ClientRequest, HostResponse};
 use freenet_stdlib::prelude::*;
 use futures::{future::BoxFuture, FutureExt};
@@ -75,6 +76,14 @@ pub(crate) trait ContractHandler {
     ) -> BoxFuture<'a, Result<HostResponse, DynError>>;
 
     fn executor(&mut self) -> &mut Self::ContractExecutor;
+
+    /// Both the channel and the executor, to wait on events from either at the same time.
+    fn channel_and_executor(
+        &mut self,
+    ) -> (
+        &mut ContractHandlerChannel<ContractHandlerHalve>,
+        &mut Self::ContractExecutor,
+    );
 }
 
 pub(crate) struct NetworkContractHandler<R = Runtime> {
@@ -124,6 +133,28 @@ impl ContractHandler for NetworkContractHandler<Runtime> {
     fn executor(&mut self) -> &mut Self::ContractExecutor {
         &mut self.executor
     }
+
+    fn channel_and_executor(
+        &mut self,
+    ) -> (
+        &mut ContractHandlerChannel<ContractHandlerHalve>,
+        &mut Self::ContractExecutor,
+    ) {
+        (&mut self.channel, &mut self.executor)
+    }
+}
+
+#[cfg(test)]
+impl NetworkContractHandler<Runtime> {
+    /// A handler running the WASM runtime, keeping its stores under `data_dir`.
+    pub(crate) async fn build_test(
+        channel: ContractHandlerChannel<ContractHandlerHalve>,
+        executor_request_sender: ExecutorToEventLoopChannel<ExecutorHalve>,
+        data_dir: &std::path::Path,
+    ) -> Result<Self, DynError> {
+        let executor = Executor::new_test(data_dir, executor_request_sender).await?;
+        Ok(Self { executor, channel })
+    }
 }
 
 #[cfg(test)]
@@ -169,6 +200,15 @@ impl ContractHandler for NetworkContractHandler<super::MockRuntime> {
     fn executor(&mut self) -> &mut Self::ContractExecutor {
         &mut self.executor
     }
+
+    fn channel_and_executor(
+        &mut self,
+    ) -> (
+        &mut ContractHandlerChannel<ContractHandlerHalve>,
+        &mut Self::ContractExecutor,
+    ) {
+        (&mut self.channel, &mut self.executor)
+    }
 }
 
 #[derive(Eq)]
@@ -196,7 +236,10 @@ pub(crate) struct ContractHandlerChannel<End: sealed::ChannelHalve> {
 
 pub(crate) struct ContractHandlerHalve {
     event_receiver: mpsc::UnboundedReceiver<InternalCHEvent>,
-    waiting_response: BTreeMap<u64, tokio::sync::oneshot::Sender<(EventId, ContractHandlerEvent)>>,
+    waiting_response: BTreeMap<
+        u64,
+        tokio::sync::oneshot::Sender<(EventId, ContractHandlerEvent, ExecutionUsage)>,
+    >,
 }
 
 pub(crate) struct SenderHalve {
@@ -266,11 +309,12 @@ impl ContractHandlerChannel<SenderHalve> {
     // kind of event and can be optimized on a case basis
     const CH_EV_RESPONSE_TIME_OUT: Duration = Duration::from_secs(300);
 
-    /// Send an event to the contract handler and receive a response event if successful.
+    /// Send an event to the contract handler and receive a response event if successful,
+    /// together with the resources spent handling it.
     pub async fn send_to_handler(
         &self,
         ev: ContractHandlerEvent,
-    ) -> Result<ContractHandlerEvent, ContractError> {
+    ) -> Result<(ContractHandlerEvent, ExecutionUsage), ContractError> {
         let id = EV_ID.fetch_add(1, SeqCst);
         let (result, result_receiver) = tokio::sync::oneshot::channel();
         self.end
@@ -278,7 +322,7 @@ impl ContractHandlerChannel<SenderHalve> {
             .send(InternalCHEvent { ev, id, result })
             .map_err(|err| ContractError::ChannelDropped(Box::new(err.0.ev)))?;
         match tokio::time::timeout(Self::CH_EV_RESPONSE_TIME_OUT, result_receiver).await {
-            Ok(Ok((_, res))) => Ok(res),
+            Ok(Ok((_, res, usage))) => Ok((res, usage)),
             Ok(Err(_)) | Err(_) => Err(ContractError::NoEvHandlerResponse),
         }
     }
@@ -301,10 +345,11 @@ impl ContractHandlerChannel<ContractHandlerHalve> {
         &mut self,
         id: EventId,
         ev: ContractHandlerEvent,
+        usage: ExecutionUsage,
     ) -> Result<(), ContractError> {
         if let Some(response) = self.end.waiting_response.remove(&id.id) {
             response
-                .send((id, ev))
+                .send((id, ev, usage))
                 .map_err(|_| ContractError::NoEvHandlerResponse)
         } else {
             Err(ContractError::NoEvHandlerResponse)
@@ -322,6 +367,18 @@ impl ContractHandlerChannel<ContractHandlerHalve> {
     }
 }
 
+/// Resources spent by the contract handler while handling an event.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct ExecutionUsage {
+    /// Fuel spent running WASM code, roughly the number of instructions executed. Unlike
+    /// the time taken it doesn't depend on what else the node was doing meanwhile.
+    pub fuel: u64,
+    /// Bytes of state stored.
+    pub storage: usize,
+    /// Bytes of WASM instance memory used.
+    pub wasm_memory: usize,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub(crate) struct StoreResponse {
     pub state: Option<WrappedState>,
@@ -332,7 +389,7 @@ struct InternalCHEvent {
     ev: ContractHandlerEvent,
     id: u64,
     // client_id: Option<ClientId>,
-    result: tokio::sync::oneshot::Sender<(EventId, ContractHandlerEvent)>,
+    result: tokio::sync::oneshot::Sender<(EventId, ContractHandlerEvent, ExecutionUsage)>,
 }
 
 #[derive(Debug)]
@@ -358,6 +415,26 @@ pub(crate) enum ContractHandlerEvent {
         key: ContractKey,
         response: Result<StoreResponse, ExecutorError>,
     },
+    /// Apply an update (either a full new state or a delta) to an already stored contract
+    UpdateQuery {
+        key: ContractKey,
+        data: Either<WrappedState, StateDelta<'static>>,
+        related_contracts: RelatedContracts<'static>,
+    },
+    /// The response to an update query, containing the merged state and its summary
+    UpdateResponse {
+        key: ContractKey,
+        new_value: Result<(WrappedState, StateSummary<'static>), ExecutorError>,
+    },
+    /// Execute a delegate operation requested by a client
+    DelegateRequest {
+        req: DelegateRequest<'static>,
+        attested_contract: Option<ContractInstanceId>,
+    },
+    /// The response to a delegate request
+    DelegateResponse {
+        response: Result<HostResponse, ExecutorError>,
+    },
 }
 
 impl std::fmt::Display for ContractHandlerEvent {
@@ -399,6 +476,23 @@ impl std::fmt::Display for ContractHandlerEvent {
                     write!(f, "get query failed {{ {key} }}",)
                 }
             },
+            ContractHandlerEvent::UpdateQuery { key, data, .. } => match data {
+                Either::Left(_) => write!(f, "update query {{ {key}, state }}"),
+                Either::Right(_) => write!(f, "update query {{ {key}, delta }}"),
+            },
+            ContractHandlerEvent::UpdateResponse { key, new_value } => match new_value {
+                Ok((v, _)) => {
+                    write!(f, "update query response {{ {key}, {v} }}",)
+                }
+                Err(e) => {
+                    write!(f, "update query failed {{ {key}, {e} }}",)
+                }
+            },
+            ContractHandlerEvent::DelegateRequest { .. } => write!(f, "delegate request"),
+            ContractHandlerEvent::DelegateResponse { response } => match response {
+                Ok(_) => write!(f, "delegate response"),
+                Err(e) => write!(f, "delegate request failed {{ {e} }}"),
+            },
         }
     }
 }
@@ -447,14 +541,19 @@ pub mod test {
                 ContractHandlerEvent::PutResponse {
                     new_value: Ok(vec![0, 7].into()),
                 },
+                ExecutionUsage {
+                    storage: 2,
+                    ..Default::default()
+                },
             ),
         )
         .await??;
-        let ContractHandlerEvent::PutResponse { new_value } = h.await?? else {
+        let (ContractHandlerEvent::PutResponse { new_value }, usage) = h.await?? else {
             anyhow::bail!("invalid event!");
         };
         let new_value = new_value.map_err(|e| anyhow::anyhow!(e))?;
         assert_eq!(new_value.as_ref(), &[0, 7]);
+        assert_eq!(usage.storage, 2);
 
         Ok(())
     }
@@ -529,6 +628,15 @@ pub(super) mod in_memory {
         fn executor(&mut self) -> &mut Self::ContractExecutor {
             &mut self.runtime
         }
+
+        fn channel_and_executor(
+            &mut self,
+        ) -> (
+            &mut ContractHandlerChannel<ContractHandlerHalve>,
+            &mut Self::ContractExecutor,
+        ) {
+            (&mut self.channel, &mut self.runtime)
+        }
     }
 
     #[test]
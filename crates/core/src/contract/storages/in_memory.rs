@@ -0,0 +1,129 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    time::SystemTime,
+};
+
+use freenet_stdlib::prelude::*;
+
+use crate::wasm_runtime::{StateStorage, StateVersion};
+
+/// Ephemeral storage keeping everything in memory, all data is lost when dropped.
+#[derive(Default)]
+pub struct InMemoryStorage {
+    states: HashMap<ContractKey, WrappedState>,
+    params: HashMap<ContractKey, Parameters<'static>>,
+    history: HashMap<ContractKey, VecDeque<(StateVersion, WrappedState)>>,
+}
+
+impl InMemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait::async_trait]
+impl StateStorage for InMemoryStorage {
+    type Error = Infallible;
+
+    async fn store(&mut self, key: ContractKey, state: WrappedState) -> Result<(), Self::Error> {
+        self.states.insert(key, state);
+        Ok(())
+    }
+
+    async fn get(&self, key: &ContractKey) -> Result<Option<WrappedState>, Self::Error> {
+        Ok(self.states.get(key).cloned())
+    }
+
+    async fn store_params(
+        &mut self,
+        key: ContractKey,
+        params: Parameters<'static>,
+    ) -> Result<(), Self::Error> {
+        self.params.insert(key, params);
+        Ok(())
+    }
+
+    async fn get_params<'a>(
+        &'a self,
+        key: &'a ContractKey,
+    ) -> Result<Option<Parameters<'static>>, Self::Error> {
+        Ok(self.params.get(key).cloned())
+    }
+
+    async fn store_version(
+        &mut self,
+        key: ContractKey,
+        state: WrappedState,
+        max_versions: usize,
+    ) -> Result<u64, Self::Error> {
+        let history = self.history.entry(key).or_default();
+        let version = history.back().map(|(v, _)| v.version).unwrap_or_default() + 1;
+        history.push_back((
+            StateVersion {
+                version,
+                timestamp: SystemTime::now(),
+                size: state.size(),
+            },
+            state,
+        ));
+        while history.len() > max_versions {
+            history.pop_front();
+        }
+        Ok(version)
+    }
+
+    async fn get_version(
+        &self,
+        key: &ContractKey,
+        version: u64,
+    ) -> Result<Option<WrappedState>, Self::Error> {
+        Ok(self.history.get(key).and_then(|history| {
+            history
+                .iter()
+                .find(|(v, _)| v.version == version)
+                .map(|(_, state)| state.clone())
+        }))
+    }
+
+    async fn list_versions(&self, key: &ContractKey) -> Result<Vec<StateVersion>, Self::Error> {
+        Ok(self
+            .history
+            .get(key)
+            .map(|history| history.iter().map(|(v, _)| *v).collect())
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::wasm_runtime::StateStore;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn history_rollback() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = StateStore::new(InMemoryStorage::new(), 10_000)?.with_history(2);
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        store
+            .store(
+                key.clone(),
+                WrappedState::new(vec![1]),
+                Parameters::from(vec![]),
+            )
+            .await?;
+        store.update(&key, WrappedState::new(vec![2])).await?;
+        store.update(&key, WrappedState::new(vec![3])).await?;
+
+        let versions = store.list_versions(&key).await?;
+        assert_eq!(
+            versions.iter().map(|v| v.version).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert!(store.get_version(&key, 1).await.is_err());
+
+        store.rollback(&key, 2).await?;
+        assert_eq!(store.get(&key).await?.as_ref(), &[2]);
+        assert_eq!(store.list_versions(&key).await?.last().unwrap().version, 4);
+        Ok(())
+    }
+}
@@ -1,15 +1,166 @@
+use std::path::Path;
+
+use freenet_stdlib::prelude::*;
+
+use crate::{
+    wasm_runtime::{StateStorage, StateVersion},
+    DynError,
+};
+
+pub mod in_memory;
+pub use in_memory::InMemoryStorage;
+
 #[cfg(feature = "sqlite")]
 pub mod sqlite;
 #[cfg(feature = "sqlite")]
 pub use sqlite::Pool as SqlitePool;
 
-#[cfg(feature = "sqlite")]
-pub type Storage = SqlitePool;
-
 #[cfg(feature = "rocks_db")]
 pub mod rocks_db;
-#[cfg(all(feature = "rocks_db", not(feature = "sqlite")))]
+#[cfg(feature = "rocks_db")]
 use self::rocks_db::RocksDb;
 
-#[cfg(all(feature = "rocks_db", not(feature = "sqlite")))]
-pub type Storage = RocksDb;
+/// Backend used to persist contract states, selected at runtime.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StorageBackend {
+    /// Keep states in memory only, they are lost when the node stops.
+    InMemory,
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+    #[cfg(feature = "rocks_db")]
+    RocksDb,
+}
+
+impl Default for StorageBackend {
+    fn default() -> Self {
+        #[cfg(feature = "sqlite")]
+        {
+            StorageBackend::Sqlite
+        }
+        #[cfg(all(feature = "rocks_db", not(feature = "sqlite")))]
+        {
+            StorageBackend::RocksDb
+        }
+        #[cfg(not(any(feature = "sqlite", feature = "rocks_db")))]
+        {
+            StorageBackend::InMemory
+        }
+    }
+}
+
+/// State storage for any of the backends enabled at compile time.
+pub enum Storage {
+    InMemory(InMemoryStorage),
+    #[cfg(feature = "sqlite")]
+    Sqlite(SqlitePool),
+    #[cfg(feature = "rocks_db")]
+    RocksDb(RocksDb),
+}
+
+impl Storage {
+    /// Opens the storage for the given backend. Persistent backends are kept in `db_dir`,
+    /// sqlite falls back to an in-memory database if it is not provided.
+    pub async fn new(backend: StorageBackend, db_dir: Option<&Path>) -> Result<Self, DynError> {
+        match backend {
+            StorageBackend::InMemory => Ok(Storage::InMemory(InMemoryStorage::new())),
+            #[cfg(feature = "sqlite")]
+            StorageBackend::Sqlite => Ok(Storage::Sqlite(SqlitePool::new(db_dir).await?)),
+            #[cfg(feature = "rocks_db")]
+            StorageBackend::RocksDb => {
+                let db_dir = db_dir.ok_or("a database directory is required for rocksdb")?;
+                Ok(Storage::RocksDb(RocksDb::new(db_dir).await?))
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl StateStorage for Storage {
+    type Error = DynError;
+
+    async fn store(&mut self, key: ContractKey, state: WrappedState) -> Result<(), Self::Error> {
+        match self {
+            Storage::InMemory(s) => Ok(s.store(key, state).await?),
+            #[cfg(feature = "sqlite")]
+            Storage::Sqlite(s) => Ok(s.store(key, state).await?),
+            #[cfg(feature = "rocks_db")]
+            Storage::RocksDb(s) => Ok(s.store(key, state).await?),
+        }
+    }
+
+    async fn store_params(
+        &mut self,
+        key: ContractKey,
+        params: Parameters<'static>,
+    ) -> Result<(), Self::Error> {
+        match self {
+            Storage::InMemory(s) => Ok(s.store_params(key, params).await?),
+            #[cfg(feature = "sqlite")]
+            Storage::Sqlite(s) => Ok(s.store_params(key, params).await?),
+            #[cfg(feature = "rocks_db")]
+            Storage::RocksDb(s) => Ok(s.store_params(key, params).await?),
+        }
+    }
+
+    async fn get(&self, key: &ContractKey) -> Result<Option<WrappedState>, Self::Error> {
+        match self {
+            Storage::InMemory(s) => Ok(s.get(key).await?),
+            #[cfg(feature = "sqlite")]
+            Storage::Sqlite(s) => Ok(s.get(key).await?),
+            #[cfg(feature = "rocks_db")]
+            Storage::RocksDb(s) => Ok(s.get(key).await?),
+        }
+    }
+
+    async fn get_params<'a>(
+        &'a self,
+        key: &'a ContractKey,
+    ) -> Result<Option<Parameters<'static>>, Self::Error> {
+        match self {
+            Storage::InMemory(s) => Ok(s.get_params(key).await?),
+            #[cfg(feature = "sqlite")]
+            Storage::Sqlite(s) => Ok(s.get_params(key).await?),
+            #[cfg(feature = "rocks_db")]
+            Storage::RocksDb(s) => Ok(s.get_params(key).await?),
+        }
+    }
+
+    async fn store_version(
+        &mut self,
+        key: ContractKey,
+        state: WrappedState,
+        max_versions: usize,
+    ) -> Result<u64, Self::Error> {
+        match self {
+            Storage::InMemory(s) => Ok(s.store_version(key, state, max_versions).await?),
+            #[cfg(feature = "sqlite")]
+            Storage::Sqlite(s) => Ok(s.store_version(key, state, max_versions).await?),
+            #[cfg(feature = "rocks_db")]
+            Storage::RocksDb(s) => Ok(s.store_version(key, state, max_versions).await?),
+        }
+    }
+
+    async fn get_version(
+        &self,
+        key: &ContractKey,
+        version: u64,
+    ) -> Result<Option<WrappedState>, Self::Error> {
+        match self {
+            Storage::InMemory(s) => Ok(s.get_version(key, version).await?),
+            #[cfg(feature = "sqlite")]
+            Storage::Sqlite(s) => Ok(s.get_version(key, version).await?),
+            #[cfg(feature = "rocks_db")]
+            Storage::RocksDb(s) => Ok(s.get_version(key, version).await?),
+        }
+    }
+
+    async fn list_versions(&self, key: &ContractKey) -> Result<Vec<StateVersion>, Self::Error> {
+        match self {
+            Storage::InMemory(s) => Ok(s.list_versions(key).await?),
+            #[cfg(feature = "sqlite")]
+            Storage::Sqlite(s) => Ok(s.list_versions(key).await?),
+            #[cfg(feature = "rocks_db")]
+            Storage::RocksDb(s) => Ok(s.list_versions(key).await?),
+        }
+    }
+}
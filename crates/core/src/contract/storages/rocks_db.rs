@@ -1,15 +1,15 @@
 use std::path::Path;
+use std::time::{Duration, SystemTime};
 
 use freenet_stdlib::prelude::*;
-use rocksdb::{Options, DB};
+use rocksdb::{Direction, IteratorMode, Options, WriteBatch, DB};
 
 use crate::contract::ContractKey;
-use crate::wasm_runtime::StateStorage;
+use crate::wasm_runtime::{StateStorage, StateVersion};
 
 pub struct RocksDb(DB);
 
 impl RocksDb {
-    #[cfg_attr(feature = "sqlite", allow(unused))]
     pub async fn new(db_path: &Path) -> Result<Self, rocksdb::Error> {
         tracing::info!("loading contract store from {db_path:?}");
 
@@ -26,6 +26,27 @@ impl RocksDb {
 impl RocksDb {
     const STATE_SUFFIX: &'static [u8] = "_key".as_bytes();
     const PARAMS_SUFFIX: &'static [u8] = "_params".as_bytes();
+    const HISTORY_SUFFIX: &'static [u8] = "_history".as_bytes();
+    const HISTORY_HEAD_SUFFIX: &'static [u8] = "_history_head".as_bytes();
+
+    fn history_key(key: &ContractKey, version: u64) -> Vec<u8> {
+        [
+            key.as_bytes(),
+            RocksDb::HISTORY_SUFFIX,
+            version.to_be_bytes().as_slice(),
+        ]
+        .concat()
+    }
+
+    /// Decodes a history entry, encoded as the timestamp in millis followed by the state.
+    fn decode_history_entry(value: &[u8]) -> (SystemTime, &[u8]) {
+        let (timestamp, state) = value.split_at(std::mem::size_of::<u64>());
+        let millis = u64::from_be_bytes(timestamp.try_into().expect("8 bytes"));
+        (
+            SystemTime::UNIX_EPOCH + Duration::from_millis(millis),
+            state,
+        )
+    }
 }
 
 #[async_trait::async_trait]
@@ -95,4 +116,152 @@ impl StateStorage for RocksDb {
             }
         }
     }
+
+    async fn store_version(
+        &mut self,
+        key: ContractKey,
+        state: WrappedState,
+        max_versions: usize,
+    ) -> Result<u64, Self::Error> {
+        let head_key = [key.as_bytes(), RocksDb::HISTORY_HEAD_SUFFIX].concat();
+        let version = self
+            .0
+            .get(&head_key)?
+            .and_then(|head| Some(u64::from_be_bytes(head.as_slice().try_into().ok()?)))
+            .unwrap_or_default()
+            + 1;
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        let mut batch = WriteBatch::default();
+        batch.put(
+            RocksDb::history_key(&key, version),
+            [timestamp.to_be_bytes().as_slice(), state.as_ref()].concat(),
+        );
+        batch.put(&head_key, version.to_be_bytes());
+        if let Some(oldest_kept) = version.checked_sub(max_versions as u64) {
+            // versions are only ever added at the head, so older ones are in a contiguous range
+            batch.delete_range(
+                RocksDb::history_key(&key, 0),
+                RocksDb::history_key(&key, oldest_kept + 1),
+            );
+        }
+        self.0.write(batch)?;
+        Ok(version)
+    }
+
+    async fn get_version(
+        &self,
+        key: &ContractKey,
+        version: u64,
+    ) -> Result<Option<WrappedState>, Self::Error> {
+        Ok(self
+            .0
+            .get(RocksDb::history_key(key, version))?
+            .map(|value| WrappedState::new(RocksDb::decode_history_entry(&value).1.to_vec())))
+    }
+
+    async fn list_versions(&self, key: &ContractKey) -> Result<Vec<StateVersion>, Self::Error> {
+        let prefix = [key.as_bytes(), RocksDb::HISTORY_SUFFIX].concat();
+        let mut versions = vec![];
+        for entry in self
+            .0
+            .iterator(IteratorMode::From(&prefix, Direction::Forward))
+        {
+            let (entry_key, value) = entry?;
+            let Some(version) = entry_key.strip_prefix(prefix.as_slice()) else {
+                break;
+            };
+            let Ok(version) = <[u8; 8]>::try_from(version) else {
+                // not an history entry (e.g. the history head)
+                continue;
+            };
+            let (timestamp, state) = RocksDb::decode_history_entry(&value);
+            versions.push(StateVersion {
+                version: u64::from_be_bytes(version),
+                timestamp,
+                size: state.len(),
+            });
+        }
+        Ok(versions)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::wasm_runtime::StateStore;
+
+    async fn storage() -> Result<(tempfile::TempDir, RocksDb), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let storage = RocksDb::new(dir.path()).await?;
+        Ok((dir, storage))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn history_versions() -> Result<(), Box<dyn std::error::Error>> {
+        let (_dir, mut storage) = storage().await?;
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let other = ContractKey::from(ContractInstanceId::new([2; 32]));
+        for state in 1..=3u8 {
+            let version = storage
+                .store_version(key.clone(), WrappedState::new(vec![state; 4]), 2)
+                .await?;
+            assert_eq!(version, state as u64);
+        }
+        assert_eq!(
+            storage
+                .store_version(other.clone(), WrappedState::new(vec![9]), 2)
+                .await?,
+            1
+        );
+
+        // only the last 2 versions are kept
+        assert!(storage.get_version(&key, 1).await?.is_none());
+        assert_eq!(
+            storage.get_version(&key, 3).await?.unwrap().as_ref(),
+            &[3; 4]
+        );
+        let versions = storage.list_versions(&key).await?;
+        assert_eq!(
+            versions
+                .iter()
+                .map(|v| (v.version, v.size))
+                .collect::<Vec<_>>(),
+            vec![(2, 4), (3, 4)]
+        );
+        assert!(versions[0].timestamp <= versions[1].timestamp);
+        assert_eq!(storage.list_versions(&other).await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn history_rollback() -> Result<(), Box<dyn std::error::Error>> {
+        let (_dir, storage) = storage().await?;
+        let mut store = StateStore::new(storage, 10_000)?.with_history(2);
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        store
+            .store(
+                key.clone(),
+                WrappedState::new(vec![1]),
+                Parameters::from(vec![]),
+            )
+            .await?;
+        store.update(&key, WrappedState::new(vec![2])).await?;
+        store.update(&key, WrappedState::new(vec![3])).await?;
+
+        let versions = store.list_versions(&key).await?;
+        assert_eq!(
+            versions.iter().map(|v| v.version).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert!(store.get_version(&key, 1).await.is_err());
+
+        store.rollback(&key, 2).await?;
+        assert_eq!(store.get(&key).await?.as_ref(), &[2]);
+        assert_eq!(store.list_versions(&key).await?.last().unwrap().version, 4);
+        Ok(())
+    }
 }
@@ -1,4 +1,8 @@
-use std::{path::Path, str::FromStr};
+use std::{
+    path::Path,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
 
 use freenet_stdlib::prelude::*;
 use sqlx::{
@@ -8,7 +12,7 @@ use sqlx::{
 
 use crate::{
     contract::ContractKey,
-    wasm_runtime::{ContractError, StateStorage, StateStoreError},
+    wasm_runtime::{ContractError, StateStorage, StateStoreError, StateVersion},
 };
 
 async fn create_contracts_table(pool: &SqlitePool) -> Result<(), SqlDbError> {
@@ -21,6 +25,17 @@ async fn create_contracts_table(pool: &SqlitePool) -> Result<(), SqlDbError> {
     )
     .execute(pool)
     .await?;
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS state_history (
+            contract        BLOB,
+            version         INTEGER,
+            timestamp       INTEGER,
+            state           BLOB,
+            PRIMARY KEY (contract, version)
+        )",
+    )
+    .execute(pool)
+    .await?;
     Ok(())
 }
 
@@ -113,6 +128,75 @@ impl StateStorage for Pool {
             Err(_) => Err(SqlDbError::ContractNotFound),
         }
     }
+
+    async fn store_version(
+        &mut self,
+        key: ContractKey,
+        state: WrappedState,
+        max_versions: usize,
+    ) -> Result<u64, Self::Error> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+        let mut tx = self.0.begin().await?;
+        let version: i64 = sqlx::query(
+            "SELECT COALESCE(MAX(version), 0) + 1 AS version FROM state_history WHERE contract = ?",
+        )
+        .bind(key.as_bytes())
+        .map(|row: SqliteRow| row.get("version"))
+        .fetch_one(&mut *tx)
+        .await?;
+        sqlx::query(
+            "INSERT INTO state_history (contract, version, timestamp, state)
+                     VALUES ($1, $2, $3, $4)",
+        )
+        .bind(key.as_bytes())
+        .bind(version)
+        .bind(timestamp)
+        .bind(state.as_ref())
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM state_history WHERE contract = ? AND version <= ?")
+            .bind(key.as_bytes())
+            .bind(version - max_versions as i64)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(version as u64)
+    }
+
+    async fn get_version(
+        &self,
+        key: &ContractKey,
+        version: u64,
+    ) -> Result<Option<WrappedState>, Self::Error> {
+        let state =
+            sqlx::query("SELECT state FROM state_history WHERE contract = ? AND version = ?")
+                .bind(key.as_bytes())
+                .bind(version as i64)
+                .map(|row: SqliteRow| WrappedState::new(row.get("state")))
+                .fetch_optional(&self.0)
+                .await?;
+        Ok(state)
+    }
+
+    async fn list_versions(&self, key: &ContractKey) -> Result<Vec<StateVersion>, Self::Error> {
+        let versions = sqlx::query(
+            "SELECT version, timestamp, LENGTH(state) AS size FROM state_history
+                     WHERE contract = ? ORDER BY version",
+        )
+        .bind(key.as_bytes())
+        .map(|row: SqliteRow| StateVersion {
+            version: row.get::<i64, _>("version") as u64,
+            timestamp: SystemTime::UNIX_EPOCH
+                + Duration::from_millis(row.get::<i64, _>("timestamp") as u64),
+            size: row.get::<i64, _>("size") as usize,
+        })
+        .fetch_all(&self.0)
+        .await?;
+        Ok(versions)
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -128,3 +212,80 @@ pub enum SqlDbError {
     #[error(transparent)]
     StateStore(#[from] StateStoreError),
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::wasm_runtime::StateStore;
+
+    async fn storage() -> Result<(tempfile::TempDir, Pool), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let storage = Pool::new(Some(dir.path())).await?;
+        Ok((dir, storage))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn history_versions() -> Result<(), Box<dyn std::error::Error>> {
+        let (_dir, mut storage) = storage().await?;
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let other = ContractKey::from(ContractInstanceId::new([2; 32]));
+        for state in 1..=3u8 {
+            let version = storage
+                .store_version(key.clone(), WrappedState::new(vec![state; 4]), 2)
+                .await?;
+            assert_eq!(version, state as u64);
+        }
+        assert_eq!(
+            storage
+                .store_version(other.clone(), WrappedState::new(vec![9]), 2)
+                .await?,
+            1
+        );
+
+        // only the last 2 versions are kept
+        assert!(storage.get_version(&key, 1).await?.is_none());
+        assert_eq!(
+            storage.get_version(&key, 3).await?.unwrap().as_ref(),
+            &[3; 4]
+        );
+        let versions = storage.list_versions(&key).await?;
+        assert_eq!(
+            versions
+                .iter()
+                .map(|v| (v.version, v.size))
+                .collect::<Vec<_>>(),
+            vec![(2, 4), (3, 4)]
+        );
+        assert!(versions[0].timestamp <= versions[1].timestamp);
+        assert_eq!(storage.list_versions(&other).await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn history_rollback() -> Result<(), Box<dyn std::error::Error>> {
+        let (_dir, storage) = storage().await?;
+        let mut store = StateStore::new(storage, 10_000)?.with_history(2);
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        store
+            .store(
+                key.clone(),
+                WrappedState::new(vec![1]),
+                Parameters::from(vec![]),
+            )
+            .await?;
+        store.update(&key, WrappedState::new(vec![2])).await?;
+        store.update(&key, WrappedState::new(vec![3])).await?;
+
+        let versions = store.list_versions(&key).await?;
+        assert_eq!(
+            versions.iter().map(|v| v.version).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert!(store.get_version(&key, 1).await.is_err());
+
+        store.rollback(&key, 2).await?;
+        assert_eq!(store.get(&key).await?.as_ref(), &[2]);
+        assert_eq!(store.list_versions(&key).await?.last().unwrap().version, 4);
+        Ok(())
+    }
+}
@@ -41,15 +41,26 @@ pub mod local_node {
 pub mod dev_tool {
     use super::*;
     pub use crate::config::Config;
+    pub use crate::tracing::{Property, Violation};
     pub use client_events::{test::MemoryEventsGen, ClientEventsProxy, ClientId, OpenRequest};
-    pub use contract::{storages::Storage, Executor, OperationMode};
+    pub use contract::{
+        storages::{Storage, StorageBackend},
+        Executor, OperationMode,
+    };
     pub use flatbuffers;
     pub use node::{
-        testing_impl::{EventChain, NodeLabel, SimNetwork, SimPeer},
-        InitPeerNode, InterProcessConnManager, NodeConfig, PeerCliConfig, PeerId,
+        testing_impl::{
+            replay, Divergence, EventChain, Fault, LinkConditions, NodeLabel, ReplayReport,
+            SimNetwork, SimPeer,
+        },
+        InitPeerNode, InterProcessConnManager, NodeConfig, PeerCliConfig, PeerId, PeerOutput,
+        PeerOutputMsg,
     };
     pub use ring::Location;
-    pub use wasm_runtime::{ContractStore, DelegateStore, Runtime, SecretsStore, StateStore};
+    pub use wasm_runtime::{
+        ContractRuntimeInterface, ContractStore, DelegateStore, ExecutionLimits, MasterKey,
+        Runtime, SecretsStore, StateStore, StateVersion,
+    };
 }
 
 #[cfg(test)]
@@ -6,7 +6,7 @@ use std::{
     time::{Duration, SystemTime},
 };
 
-use freenet_stdlib::prelude::ContractKey;
+use freenet_stdlib::prelude::{ContractInstanceId, ContractKey};
 use serde::{Deserialize, Serialize};
 use ulid::Ulid;
 
@@ -57,6 +57,25 @@ impl Transaction {
         }
     }
 
+    /// A transaction id no honest peer would send, as its type is not a known one.
+    pub(crate) fn malformed() -> Self {
+        Self {
+            id: Ulid(Ulid::new().0 | 0xFF),
+        }
+    }
+
+    /// The type of the transaction, if the id received is a valid one.
+    pub(crate) fn checked_transaction_type(&self) -> Option<TransactionType> {
+        match (self.id.0 & 0xFFu128) as u8 {
+            0 => Some(TransactionType::Connect),
+            1 => Some(TransactionType::Put),
+            2 => Some(TransactionType::Get),
+            3 => Some(TransactionType::Subscribe),
+            4 => Some(TransactionType::Update),
+            _ => None,
+        }
+    }
+
     pub fn timed_out(&self) -> bool {
         self.elapsed() >= crate::config::OPERATION_TTL
     }
@@ -85,6 +104,11 @@ impl Transaction {
         }
     }
 
+    /// A new transaction of the same type as this one.
+    pub(crate) fn renewed(&self) -> Self {
+        Self::update(self.transaction_type(), Ulid::new())
+    }
+
     /// Generate a random transaction which has the implicit TTL cutoff.
     ///
     /// This will allow, for example, to compare against any older transactions,
@@ -242,11 +266,16 @@ pub(crate) enum NetMessage {
 pub(crate) trait InnerMessage: Into<NetMessage> {
     fn id(&self) -> &Transaction;
 
+    fn id_mut(&mut self) -> &mut Transaction;
+
     fn target(&self) -> Option<&PeerKeyLocation>;
 
     fn terminal(&self) -> bool;
 
     fn requested_location(&self) -> Option<Location>;
+
+    /// The contract this message is about, if any.
+    fn requested_contract(&self) -> Option<ContractInstanceId>;
 }
 
 /// Internal node events emitted to the event loop.
@@ -304,6 +333,19 @@ impl NetMessage {
         }
     }
 
+    pub(crate) fn id_mut(&mut self) -> &mut Transaction {
+        use NetMessage::*;
+        match self {
+            Connect(op) => op.id_mut(),
+            Put(op) => op.id_mut(),
+            Get(op) => op.id_mut(),
+            Subscribe(op) => op.id_mut(),
+            Update(op) => op.id_mut(),
+            Aborted(tx) => tx,
+            Unsubscribed { transaction, .. } => transaction,
+        }
+    }
+
     pub fn target(&self) -> Option<&PeerKeyLocation> {
         use NetMessage::*;
         match self {
@@ -317,6 +359,24 @@ impl NetMessage {
         }
     }
 
+    /// Whether the message is consistent on its own, regardless of the state of the operation
+    /// it belongs to: the transaction id is valid and of the same type as the message.
+    pub fn is_well_formed(&self) -> bool {
+        use NetMessage::*;
+        let expected = match self {
+            Connect(_) => TransactionType::Connect,
+            Put(_) => TransactionType::Put,
+            Get(_) => TransactionType::Get,
+            Subscribe(_) => TransactionType::Subscribe,
+            Update(_) => TransactionType::Update,
+            Aborted(tx)
+            | Unsubscribed {
+                transaction: tx, ..
+            } => return tx.checked_transaction_type().is_some(),
+        };
+        self.id().checked_transaction_type() == Some(expected)
+    }
+
     /// Is the last expected message for this chain of messages.
     pub fn terminal(&self) -> bool {
         use NetMessage::*;
@@ -344,6 +404,19 @@ impl NetMessage {
         }
     }
 
+    pub fn requested_contract(&self) -> Option<ContractInstanceId> {
+        use NetMessage::*;
+        match self {
+            Connect(op) => op.requested_contract(),
+            Put(op) => op.requested_contract(),
+            Get(op) => op.requested_contract(),
+            Subscribe(op) => op.requested_contract(),
+            Update(op) => op.requested_contract(),
+            Aborted(_) => None,
+            Unsubscribed { key, .. } => Some(*key.id()),
+        }
+    }
+
     pub fn track_stats(&self) -> bool {
         use NetMessage::*;
         !matches!(self, Connect(_) | Subscribe(_) | Aborted(_))
@@ -20,7 +20,7 @@ use std::{
 
 use either::Either;
 use freenet_stdlib::{
-    client_api::{ClientRequest, ContractRequest, ErrorKind},
+    client_api::{ClientRequest, ContractError as StdContractError, ContractRequest, ErrorKind},
     prelude::ContractKey,
 };
 use libp2p::{identity, multiaddr::Protocol, Multiaddr, PeerId as Libp2pPeerId};
@@ -34,12 +34,13 @@ use crate::{
     config::GlobalExecutor,
     contract::{
         Callback, ClientResponsesReceiver, ClientResponsesSender, ContractError,
-        ExecutorToEventLoopChannel, NetworkContractHandler, OperationMode,
+        ContractHandlerEvent, ExecutorToEventLoopChannel, NetworkContractHandler, OperationMode,
+        StorageBackend,
     },
     message::{NetMessage, NodeEvent, Transaction, TransactionType},
     operations::{
         connect::{self, ConnectOp},
-        get, put, subscribe, OpEnum, OpError, OpOutcome,
+        get, put, subscribe, update, OpEnum, OpError, OpOutcome,
     },
     ring::{Location, PeerKeyLocation},
     router::{RouteEvent, RouteOutcome},
@@ -48,7 +49,7 @@ use crate::{
 };
 
 use crate::operations::handle_op_request;
-pub use network_bridge::inter_process::InterProcessConnManager;
+pub use network_bridge::inter_process::{InterProcessConnManager, PeerOutput, PeerOutputMsg};
 pub(crate) use network_bridge::{ConnectionError, EventLoopNotificationsSender, NetworkBridge};
 
 use crate::topology::rate::Rate;
@@ -74,6 +75,45 @@ pub struct PeerCliConfig {
     /// Port to expose api on
     #[arg(long, short, default_value_t = 50509)]
     pub port: u16,
+
+    /// Max disk space in bytes to be used by each of the contract and delegate stores,
+    /// least recently used code is evicted when over it.
+    #[arg(long)]
+    pub max_code_disk_usage: Option<u64>,
+
+    /// Number of past states kept for each contract, disabled by default.
+    #[arg(long)]
+    pub state_history: Option<usize>,
+
+    /// Backend used to store contract states, overrides the `storage_backend` setting.
+    #[arg(long, value_enum)]
+    pub storage_backend: Option<StorageBackend>,
+
+    /// Fuel available for each call into a contract or delegate, roughly the number
+    /// of instructions it may execute.
+    #[arg(long)]
+    pub max_fuel: Option<u64>,
+
+    /// Wall-clock time in milliseconds available for each call into a contract or delegate.
+    #[arg(long)]
+    pub max_execution_time: Option<u64>,
+
+    /// Number of warm instances kept for each contract or delegate, 0 disables pooling.
+    #[arg(long)]
+    pub instance_pool_size: Option<usize>,
+
+    /// Fuel per second the node may spend running contract code for other peers, once over
+    /// it no new peers are accepted and the most demanding ones are dropped.
+    #[arg(long)]
+    pub max_execution_fuel_rate: Option<f64>,
+
+    /// Bytes of contract state per second the node may store for other peers.
+    #[arg(long)]
+    pub max_storage_rate: Option<f64>,
+
+    /// Bytes of WASM instance memory per second the node may use for other peers.
+    #[arg(long)]
+    pub max_wasm_memory_rate: Option<f64>,
 }
 
 pub struct Node(NodeP2P);
@@ -95,7 +135,7 @@ impl Node {
 ///
 /// If both are provided but also additional peers are added via the [`Self::add_gateway()`] method, this node will
 /// be listening but also try to connect to an existing peer.
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct NodeConfig {
     /// public identifier for the peer
     pub peer_id: PeerId,
@@ -119,6 +159,15 @@ pub struct NodeConfig {
     pub(crate) min_number_conn: Option<usize>,
     pub(crate) max_upstream_bandwidth: Option<Rate>,
     pub(crate) max_downstream_bandwidth: Option<Rate>,
+    pub(crate) max_execution_fuel: Option<Rate>,
+    pub(crate) max_storage: Option<Rate>,
+    pub(crate) max_wasm_memory: Option<Rate>,
+    /// File where all the traffic of the node is recorded, if any.
+    pub(crate) capture_file: Option<PathBuf>,
+    /// File where the peers known by this node are persisted across restarts, if any.
+    pub(crate) peer_book: Option<PathBuf>,
+    /// Directory for the files of this node, instead of the one in the global configuration.
+    pub(crate) data_dir: Option<PathBuf>,
 }
 
 impl NodeConfig {
@@ -138,6 +187,12 @@ impl NodeConfig {
             min_number_conn: None,
             max_upstream_bandwidth: None,
             max_downstream_bandwidth: None,
+            max_execution_fuel: None,
+            max_storage: None,
+            max_wasm_memory: None,
+            capture_file: None,
+            peer_book: None,
+            data_dir: None,
         }
     }
 
@@ -161,6 +216,27 @@ impl NodeConfig {
         self
     }
 
+    /// Fuel per second spent running contract code on behalf of other peers above which the
+    /// node sheds connections.
+    pub fn max_execution_fuel(&mut self, fuel_per_sec: f64) -> &mut Self {
+        self.max_execution_fuel = Some(Rate::new_per_second(fuel_per_sec));
+        self
+    }
+
+    /// Bytes of contract state per second stored on behalf of other peers above which the
+    /// node sheds connections.
+    pub fn max_storage(&mut self, bytes_per_sec: f64) -> &mut Self {
+        self.max_storage = Some(Rate::new_per_second(bytes_per_sec));
+        self
+    }
+
+    /// Bytes of WASM instance memory per second used on behalf of other peers above which the
+    /// node sheds connections.
+    pub fn max_wasm_memory(&mut self, bytes_per_sec: f64) -> &mut Self {
+        self.max_wasm_memory = Some(Rate::new_per_second(bytes_per_sec));
+        self
+    }
+
     pub fn with_port(&mut self, port: u16) -> &mut Self {
         self.local_port = Some(port);
         self
@@ -183,6 +259,54 @@ impl NodeConfig {
         self
     }
 
+    /// Records every message and client request handled by the node into `path`,
+    /// so the session can be replayed later on.
+    pub fn capture_messages(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.capture_file = Some(path.into());
+        self
+    }
+
+    /// Persists the peers this node connects to into `path`, so they can be tried first
+    /// when joining the ring again after a restart.
+    pub fn with_peer_book(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.peer_book = Some(path.into());
+        self
+    }
+
+    /// Keeps the files of this node, like its event log, in `path` instead of the data
+    /// directory of the global configuration; for running several nodes in the same process
+    /// or host.
+    pub fn with_data_dir(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.data_dir = Some(path.into());
+        self
+    }
+
+    /// Keeps the peer book in the data directory of this node if it has one, so nodes sharing
+    /// a host don't rejoin through each other's peers; unless a file was already set.
+    pub(crate) fn default_peer_book(&mut self) -> &mut Self {
+        if self.peer_book.is_none() {
+            let peer_book = match &self.data_dir {
+                Some(data_dir) => data_dir.join("_PEER_BOOK"),
+                None => Config::conf().peer_book(),
+            };
+            self.peer_book = Some(peer_book);
+        }
+        self
+    }
+
+    /// File where the network events of this node are logged.
+    pub(crate) fn event_log(&self) -> std::io::Result<PathBuf> {
+        let Some(data_dir) = &self.data_dir else {
+            return Ok(Config::conf().event_log());
+        };
+        std::fs::create_dir_all(data_dir)?;
+        let event_log = data_dir.join("_EVENT_LOG");
+        if !event_log.exists() {
+            std::fs::write(&event_log, [])?;
+        }
+        Ok(event_log)
+    }
+
     /// Connection info for an already existing peer. Required in case this is not a gateway node.
     pub fn add_gateway(&mut self, peer: InitPeerNode) -> &mut Self {
         self.remote_nodes.push(peer);
@@ -191,25 +315,32 @@ impl NodeConfig {
 
     /// Builds a node using the default backend connection manager.
     pub async fn build<const CLIENTS: usize>(
-        self,
+        mut self,
         config: PeerCliConfig,
         clients: [BoxedClient; CLIENTS],
         private_key: identity::Keypair,
     ) -> Result<Node, anyhow::Error> {
+        let event_log = self.event_log()?;
+        self.default_peer_book();
+        // limits set on the command line only apply if not set in the node configuration
+        let rate = |value: Option<f64>| value.map(Rate::new_per_second);
+        self.max_execution_fuel = self
+            .max_execution_fuel
+            .or(rate(config.max_execution_fuel_rate));
+        self.max_storage = self.max_storage.or(rate(config.max_storage_rate));
+        self.max_wasm_memory = self.max_wasm_memory.or(rate(config.max_wasm_memory_rate));
         let event_register = {
             #[cfg(feature = "trace-ot")]
             {
                 use super::tracing::{CombinedRegister, OTEventRegister};
                 CombinedRegister::new([
-                    Box::new(EventRegister::new(
-                        crate::config::Config::conf().event_log(),
-                    )),
+                    Box::new(EventRegister::new(event_log)),
                     Box::new(OTEventRegister::new()),
                 ])
             }
             #[cfg(not(feature = "trace-ot"))]
             {
-                EventRegister::new(crate::config::Config::conf().event_log())
+                EventRegister::new(event_log)
             }
         };
         let node = NodeP2P::build::<NetworkContractHandler, CLIENTS, _>(
@@ -319,6 +450,7 @@ async fn client_event_handling<ClientEv>(
     op_manager: Arc<OpManager>,
     mut client_events: ClientEv,
     mut client_responses: ClientResponsesReceiver,
+    cli_response_sender: ClientResponsesSender,
     node_controller: tokio::sync::mpsc::Sender<NodeEvent>,
 ) where
     ClientEv: ClientEventsProxy + Send + 'static,
@@ -345,7 +477,7 @@ async fn client_event_handling<ClientEv>(
                     node_controller.send(NodeEvent::Disconnect { cause: cause.clone() }).await.ok();
                     break;
                 }
-                process_open_request(req, op_manager.clone()).await;
+                process_open_request(req, op_manager.clone(), cli_response_sender.clone()).await;
             }
             res = client_responses.recv() => {
                 if let Some((cli_id, res)) = res {
@@ -363,10 +495,18 @@ async fn client_event_handling<ClientEv>(
 }
 
 #[inline]
-async fn process_open_request(request: OpenRequest<'static>, op_manager: Arc<OpManager>) {
+async fn process_open_request(
+    request: OpenRequest<'static>,
+    op_manager: Arc<OpManager>,
+    cli_response_sender: ClientResponsesSender,
+) {
+    if let Some(capture) = &op_manager.capture {
+        capture.client_request(request.client_id, &request.request);
+    }
     // this will indirectly start actions on the local contract executor
     let fut = async move {
         let client_id = request.client_id;
+        let attested_contract = request.attested_contract;
 
         // fixme: communicate back errors in this loop to the client somehow
         match *request.request {
@@ -395,15 +535,34 @@ async fn process_open_request(request: OpenRequest<'static>, op_manager: Arc<OpM
                         tracing::error!("{}", err);
                     }
                 }
-                ContractRequest::Update {
-                    key: _key,
-                    data: _delta,
-                } => {
-                    // FIXME: perform updates
+                ContractRequest::Update { key, data } => {
+                    // Initialize an update op.
                     tracing::debug!(
                         this_peer = %op_manager.ring.peer_key,
                         "Received update from user event",
                     );
+                    let Some(update) = update::client_update_value(data) else {
+                        tracing::error!(%key, "Unsupported update data, related contract updates are not supported");
+                        let error = ErrorKind::RequestError(
+                            StdContractError::Update {
+                                key,
+                                cause: "related contract updates are not supported".into(),
+                            }
+                            .into(),
+                        );
+                        if cli_response_sender
+                            .send((client_id, Err(error.into())))
+                            .is_err()
+                        {
+                            tracing::debug!("client responses channel closed");
+                        }
+                        return;
+                    };
+                    let op = update::start_op(key, update, op_manager.ring.max_hops_to_live);
+                    if let Err(err) = update::request_update(&op_manager, op, Some(client_id)).await
+                    {
+                        tracing::error!("{}", err);
+                    }
                 }
                 ContractRequest::Get {
                     key,
@@ -430,7 +589,49 @@ async fn process_open_request(request: OpenRequest<'static>, op_manager: Arc<OpM
                     tracing::error!("Op not supported");
                 }
             },
-            ClientRequest::DelegateOp(_op) => todo!("FIXME: delegate op"),
+            ClientRequest::DelegateOp(req) => {
+                tracing::debug!(
+                    this_peer = %op_manager.ring.peer_key,
+                    "Received delegate operation from user event",
+                );
+                let response = match op_manager
+                    .notify_contract_handler(ContractHandlerEvent::DelegateRequest {
+                        req,
+                        attested_contract,
+                    })
+                    .await
+                {
+                    Ok(ContractHandlerEvent::DelegateResponse {
+                        response: Ok(response),
+                    }) => Ok(response),
+                    Ok(ContractHandlerEvent::DelegateResponse { response: Err(err) })
+                        if err.is_request() =>
+                    {
+                        Err(ErrorKind::RequestError(err.unwrap_request()).into())
+                    }
+                    Ok(ContractHandlerEvent::DelegateResponse { response: Err(err) }) => {
+                        tracing::error!("{err}");
+                        Err(ErrorKind::Unhandled {
+                            cause: format!("{err}").into(),
+                        }
+                        .into())
+                    }
+                    Ok(_) => Err(ErrorKind::Unhandled {
+                        cause: "unexpected contract handler response".into(),
+                    }
+                    .into()),
+                    Err(err) => {
+                        tracing::error!("{err}");
+                        Err(ErrorKind::Unhandled {
+                            cause: format!("{err}").into(),
+                        }
+                        .into())
+                    }
+                };
+                if cli_response_sender.send((client_id, response)).is_err() {
+                    tracing::debug!("client responses channel closed");
+                }
+            }
             ClientRequest::Disconnect { .. } => unreachable!(),
             _ => {
                 tracing::error!("Op not supported");
@@ -577,6 +778,9 @@ async fn process_message<CB>(
     let cli_req = client_id.zip(client_req_handler_callback);
 
     let tx = Some(*msg.id());
+    if let Some(capture) = &op_manager.capture {
+        capture.inbound(&msg);
+    }
     event_listener
         .register_events(NetEventLog::from_inbound_msg(&msg, &op_manager))
         .await;
@@ -647,6 +851,22 @@ async fn process_message<CB>(
                 )
                 .await;
             }
+            NetMessage::Update(op) => {
+                // log_handling_msg!("update", op.id(), op_manager);
+                let op_result =
+                    handle_op_request::<update::UpdateOp, _>(&op_manager, &mut conn_manager, op)
+                        .await;
+                handle_op_not_available!(op_result);
+                break report_result(
+                    tx,
+                    op_result,
+                    &op_manager,
+                    executor_callback,
+                    cli_req,
+                    &mut *event_listener,
+                )
+                .await;
+            }
             NetMessage::Unsubscribed { key, .. } => {
                 subscribe(op_manager, key.clone(), None).await;
                 break;
@@ -741,15 +961,33 @@ where
                     gateway, backoff, ..
                 } = *op;
                 if let Some(gateway) = gateway {
-                    tracing::warn!("Retry connecting to gateway {}", gateway.peer);
-                    connect::join_ring_request(
-                        backoff,
-                        this_peer,
-                        &gateway,
-                        op_manager,
-                        conn_manager,
-                    )
-                    .await?;
+                    if gateways.iter().any(|gw| gw.peer == gateway.peer) {
+                        tracing::warn!("Retry connecting to gateway {}", gateway.peer);
+                        connect::join_ring_request(
+                            backoff,
+                            this_peer,
+                            &gateway,
+                            op_manager,
+                            conn_manager,
+                        )
+                        .await?;
+                    } else if let Some(gateway) =
+                        gateways.iter().shuffle().find(|p| p.peer != this_peer)
+                    {
+                        // a peer known from a previous run may not be around anymore
+                        tracing::warn!(
+                            "Failed connecting to known peer, falling back to gateway {}",
+                            gateway.peer
+                        );
+                        connect::join_ring_request(
+                            None,
+                            this_peer,
+                            gateway,
+                            op_manager,
+                            conn_manager,
+                        )
+                        .await?;
+                    }
                 }
             }
             Ok(Some(OpEnum::Connect(_))) => {
@@ -800,7 +1038,6 @@ impl PeerId {
         PeerId::from(Keypair::generate_ed25519().public())
     }
 
-    #[cfg(test)]
     pub fn to_bytes(self) -> Vec<u8> {
         self.0.to_bytes()
     }
@@ -865,3 +1102,450 @@ mod serialization {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use freenet_stdlib::{
+        client_api::{DelegateRequest, HostResponse},
+        prelude::*,
+    };
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::{
+        client_events::HostResult,
+        contract::{self, executor_channel, NetworkEventListenerHalve},
+        node::network_bridge::event_loop_notification_channel,
+        operations::{get::GetOp, put::PutOp},
+        tracing::TestEventListener,
+        wasm_runtime::tests::get_test_module,
+    };
+
+    #[derive(Serialize, Deserialize)]
+    enum InboundAppMessage {
+        GetContract(ContractInstanceId),
+        PutContract {
+            contract: ContractContainer,
+            state: Vec<u8>,
+        },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum OutboundAppMessage {
+        ContractState(Option<Vec<u8>>),
+        ContractPut(Result<(), String>),
+    }
+
+    /// A node running the WASM runtime, where the test plays the role of the network by
+    /// completing the operations started by the executor.
+    struct TestNode {
+        op_manager: Arc<OpManager>,
+        executor_listener: ExecutorToEventLoopChannel<NetworkEventListenerHalve>,
+        client_responses: ClientResponsesReceiver,
+        cli_response_sender: ClientResponsesSender,
+        _data_dir: tempfile::TempDir,
+    }
+
+    impl TestNode {
+        async fn start() -> Result<Self, anyhow::Error> {
+            let data_dir = tempfile::tempdir()?;
+            let (mut notifications, notification_tx) = event_loop_notification_channel();
+            let (ops_ch_channel, ch_channel, _) = contract::contract_handler_channel();
+            let op_manager = Arc::new(OpManager::new(
+                notification_tx,
+                ops_ch_channel,
+                &NodeConfig::new(),
+                TestEventListener::new().await,
+            )?);
+            // the operations need a peer to be routed to
+            op_manager
+                .ring
+                .add_connection(Location::random(), PeerId::random());
+            GlobalExecutor::spawn(async move { while notifications.recv().await.is_some() {} });
+            let (executor_listener, executor_sender) = executor_channel(op_manager.clone());
+            let contract_handler =
+                NetworkContractHandler::build_test(ch_channel, executor_sender, data_dir.path())
+                    .await
+                    .map_err(|err| anyhow::anyhow!(err))?;
+            GlobalExecutor::spawn(contract::contract_handling(contract_handler));
+            let (client_responses, cli_response_sender) = contract::client_responses_channel();
+            Ok(Self {
+                op_manager,
+                executor_listener,
+                client_responses,
+                cli_response_sender,
+                _data_dir: data_dir,
+            })
+        }
+
+        async fn send(&self, request: ClientRequest<'static>) {
+            process_open_request(
+                OpenRequest::new(ClientId::FIRST, Box::new(request)),
+                self.op_manager.clone(),
+                self.cli_response_sender.clone(),
+            )
+            .await;
+        }
+
+        async fn response(&mut self) -> HostResult {
+            let (_, response) = self.client_responses.recv().await.unwrap();
+            response
+        }
+
+        /// Waits for the executor to start a network operation, failing if it is blocked.
+        async fn started_operation(&mut self) -> Result<Transaction, anyhow::Error> {
+            tokio::time::timeout(
+                Duration::from_secs(10),
+                self.executor_listener.transaction_from_executor(),
+            )
+            .await?
+            .map_err(|err| anyhow::anyhow!(err))
+        }
+
+        async fn complete(&self, op: OpEnum) {
+            self.executor_listener.callback().response(op).await;
+        }
+
+        async fn register_delegate(&mut self) -> Result<DelegateKey, anyhow::Error> {
+            let bytes =
+                get_test_module("test_delegate_2").map_err(|err| anyhow::anyhow!("{err}"))?;
+            let delegate = DelegateContainer::Wasm(DelegateWasmAPIVersion::V1(Delegate::from((
+                &bytes.into(),
+                &vec![].into(),
+            ))));
+            let key = delegate.key().clone();
+            self.send(ClientRequest::DelegateOp(
+                DelegateRequest::RegisterDelegate {
+                    delegate,
+                    cipher: DelegateRequest::DEFAULT_CIPHER,
+                    nonce: DelegateRequest::DEFAULT_NONCE,
+                },
+            ))
+            .await;
+            assert!(matches!(
+                self.response().await,
+                Ok(HostResponse::DelegateResponse { .. })
+            ));
+            Ok(key)
+        }
+    }
+
+    fn app_message(key: &DelegateKey, msg: InboundAppMessage) -> ClientRequest<'static> {
+        let payload = bincode::serialize(&msg).unwrap();
+        ClientRequest::DelegateOp(DelegateRequest::ApplicationMessages {
+            key: key.clone(),
+            params: Parameters::from(vec![]),
+            inbound: vec![InboundDelegateMsg::ApplicationMessage(
+                ApplicationMessage::new(ContractInstanceId::new([2; 32]), payload),
+            )],
+        })
+    }
+
+    fn app_response(response: HostResult) -> OutboundAppMessage {
+        match response {
+            Ok(HostResponse::DelegateResponse { values, .. }) => match values.as_slice() {
+                [OutboundDelegateMsg::ApplicationMessage(msg)] => {
+                    bincode::deserialize(&msg.payload).unwrap()
+                }
+                other => panic!("unexpected delegate response: {other:?}"),
+            },
+            other => panic!("unexpected response: {other:?}"),
+        }
+    }
+
+    /// Client delegate requests are executed by the node's contract handler, and the contracts
+    /// the delegate reads and writes are fetched from and put in the network.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn delegate_requests_through_network() -> Result<(), anyhow::Error> {
+        let mut node = TestNode::start().await?;
+        let key = node.register_delegate().await?;
+
+        let contract_id = ContractInstanceId::new([1; 32]);
+        node.send(app_message(
+            &key,
+            InboundAppMessage::GetContract(contract_id),
+        ))
+        .await;
+        let tx = node.started_operation().await?;
+        let state = WrappedState::new(vec![1, 2, 3]);
+        node.complete(OpEnum::Get(GetOp::finished(tx, contract_id.into(), state)))
+            .await;
+        assert_eq!(
+            app_response(node.response().await),
+            OutboundAppMessage::ContractState(Some(vec![1, 2, 3]))
+        );
+
+        let contract = ContractContainer::Wasm(ContractWasmAPIVersion::V1(WrappedContract::new(
+            Arc::new(ContractCode::from(vec![1])),
+            Parameters::from(vec![]),
+        )));
+        let contract_key = contract.key();
+        node.send(app_message(
+            &key,
+            InboundAppMessage::PutContract {
+                contract,
+                state: vec![4, 5, 6],
+            },
+        ))
+        .await;
+        let tx = node.started_operation().await?;
+        node.complete(OpEnum::Put(PutOp::finished(tx, contract_key)))
+            .await;
+        assert_eq!(
+            app_response(node.response().await),
+            OutboundAppMessage::ContractPut(Ok(()))
+        );
+
+        node.send(ClientRequest::DelegateOp(
+            DelegateRequest::UnregisterDelegate(key),
+        ))
+        .await;
+        assert!(matches!(node.response().await, Ok(HostResponse::Ok)));
+        Ok(())
+    }
+
+    /// A delegate waiting on a network get doesn't hold up the contract handler, and resumes
+    /// where it left off once the result arrives.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn delegate_suspends_on_network_get() -> Result<(), anyhow::Error> {
+        let mut node = TestNode::start().await?;
+        let key = node.register_delegate().await?;
+
+        let first = ContractInstanceId::new([1; 32]);
+        node.send(app_message(&key, InboundAppMessage::GetContract(first)))
+            .await;
+        let first_tx = node.started_operation().await?;
+
+        // while the first execution is suspended the next request is served
+        let second = ContractInstanceId::new([3; 32]);
+        node.send(app_message(&key, InboundAppMessage::GetContract(second)))
+            .await;
+        let second_tx = node.started_operation().await?;
+        assert_ne!(first_tx, second_tx);
+
+        // and results are handed to the delegate waiting on them, in any order
+        let state = WrappedState::new(vec![3]);
+        node.complete(OpEnum::Get(GetOp::finished(
+            second_tx,
+            second.into(),
+            state,
+        )))
+        .await;
+        assert_eq!(
+            app_response(node.response().await),
+            OutboundAppMessage::ContractState(Some(vec![3]))
+        );
+        let state = WrappedState::new(vec![1]);
+        node.complete(OpEnum::Get(GetOp::finished(first_tx, first.into(), state)))
+            .await;
+        assert_eq!(
+            app_response(node.response().await),
+            OutboundAppMessage::ContractState(Some(vec![1]))
+        );
+        Ok(())
+    }
+
+    /// Keeps the connect requests of a node instead of sending them anywhere.
+    #[derive(Default)]
+    struct JoinRequests(parking_lot::Mutex<Vec<(PeerId, Transaction)>>);
+
+    impl JoinRequests {
+        fn sent(&self) -> Vec<(PeerId, Transaction)> {
+            self.0.lock().clone()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl NetworkBridge for JoinRequests {
+        async fn add_connection(&mut self, _peer: PeerId) -> network_bridge::ConnResult<()> {
+            Ok(())
+        }
+
+        async fn drop_connection(&mut self, _peer: &PeerId) -> network_bridge::ConnResult<()> {
+            Ok(())
+        }
+
+        async fn send(&self, target: &PeerId, msg: NetMessage) -> network_bridge::ConnResult<()> {
+            self.0.lock().push((*target, *msg.id()));
+            Ok(())
+        }
+    }
+
+    /// Starts the ring and operations of a node, as the node does when built.
+    async fn start_ring(config: &NodeConfig) -> Result<Arc<OpManager>, anyhow::Error> {
+        let (mut notifications, notification_tx) = event_loop_notification_channel();
+        let (ops_ch_channel, _, _) = contract::contract_handler_channel();
+        let op_manager = Arc::new(OpManager::new(
+            notification_tx,
+            ops_ch_channel,
+            config,
+            TestEventListener::new().await,
+        )?);
+        GlobalExecutor::spawn(async move { while notifications.recv().await.is_some() {} });
+        Ok(op_manager)
+    }
+
+    /// A node which was connected to `known` in a previous run, with its own data directory.
+    async fn restarted_node(
+        data_dir: &std::path::Path,
+        known: PeerId,
+    ) -> Result<(NodeConfig, Arc<OpManager>), anyhow::Error> {
+        let mut config = NodeConfig::new();
+        config.with_data_dir(data_dir).default_peer_book();
+        assert_eq!(config.peer_book, Some(data_dir.join("_PEER_BOOK")));
+
+        let previous_run = start_ring(&config).await?;
+        previous_run.ring.update_location(Some(Location::new(0.4)));
+        previous_run
+            .ring
+            .peer_book
+            .record_addresses(known, vec!["/ip4/127.0.0.1/tcp/5000".parse()?]);
+        previous_run.ring.add_connection(Location::new(0.5), known);
+        previous_run.ring.peer_book.persist()?;
+        drop(previous_run);
+
+        let op_manager = start_ring(&config).await?;
+        assert_eq!(
+            op_manager.ring.own_location().location,
+            Some(Location::new(0.4))
+        );
+        Ok((config, op_manager))
+    }
+
+    fn gateway() -> PeerKeyLocation {
+        PeerKeyLocation {
+            peer: PeerId::random(),
+            location: Some(Location::new(0.9)),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rejoins_through_known_peers_after_restart() -> Result<(), anyhow::Error> {
+        let data_dir = tempfile::tempdir()?;
+        let known = PeerId::random();
+        let (config, op_manager) = restarted_node(data_dir.path(), known).await?;
+
+        let mut bridge = JoinRequests::default();
+        connect::initial_join_procedure(&op_manager, &mut bridge, config.peer_id, &[gateway()])
+            .await?;
+        let targets: Vec<_> = bridge.sent().into_iter().map(|(peer, _)| peer).collect();
+        assert_eq!(targets, vec![known]);
+
+        // a node with a data dir of its own doesn't know about any of those peers
+        let other_dir = tempfile::tempdir()?;
+        let mut config = NodeConfig::new();
+        config.with_data_dir(other_dir.path()).default_peer_book();
+        assert!(start_ring(&config)
+            .await?
+            .ring
+            .peer_book
+            .known_peers()
+            .is_empty());
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn falls_back_to_gateways() -> Result<(), anyhow::Error> {
+        let data_dir = tempfile::tempdir()?;
+        let known = PeerId::random();
+        let (config, op_manager) = restarted_node(data_dir.path(), known).await?;
+        let gateways = [gateway()];
+
+        let mut bridge = JoinRequests::default();
+        connect::initial_join_procedure(&op_manager, &mut bridge, config.peer_id, &gateways)
+            .await?;
+        let [(target, tx)] = bridge.sent()[..] else {
+            panic!("expected a single join request");
+        };
+        assert_eq!(target, known);
+
+        // the known peer is gone, the gateways are tried next
+        handle_aborted_op(tx, config.peer_id, &op_manager, &mut bridge, &gateways).await?;
+        let sent = bridge.sent();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[1].0, gateways[0].peer);
+
+        // and without any known peers they are tried right away
+        let fresh_dir = tempfile::tempdir()?;
+        let mut config = NodeConfig::new();
+        config.with_data_dir(fresh_dir.path()).default_peer_book();
+        let op_manager = start_ring(&config).await?;
+        let mut bridge = JoinRequests::default();
+        connect::initial_join_procedure(&op_manager, &mut bridge, config.peer_id, &gateways)
+            .await?;
+        let targets: Vec<_> = bridge.sent().into_iter().map(|(peer, _)| peer).collect();
+        assert_eq!(targets, vec![gateways[0].peer]);
+        Ok(())
+    }
+
+    /// Contract code run on behalf of a peer is accounted to it, so once the node is over its
+    /// limits that peer is the one dropped and no new peers are accepted.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn drops_peers_over_execution_limits() -> Result<(), anyhow::Error> {
+        let data_dir = tempfile::tempdir()?;
+        let mut config = NodeConfig::new();
+        config.min_number_of_connections(0).max_execution_fuel(1.0);
+        let (mut notifications, notification_tx) = event_loop_notification_channel();
+        let (ops_ch_channel, ch_channel, _) = contract::contract_handler_channel();
+        let op_manager = Arc::new(OpManager::new(
+            notification_tx,
+            ops_ch_channel,
+            &config,
+            TestEventListener::new().await,
+        )?);
+        let (_executor_listener, executor_sender) = executor_channel(op_manager.clone());
+        let contract_handler =
+            NetworkContractHandler::build_test(ch_channel, executor_sender, data_dir.path())
+                .await
+                .map_err(|err| anyhow::anyhow!(err))?;
+        GlobalExecutor::spawn(contract::contract_handling(contract_handler));
+
+        op_manager.ring.update_location(Some(Location::new(0.5)));
+        let (requester, idle) = (PeerId::random(), PeerId::random());
+        op_manager
+            .ring
+            .add_connection(Location::new(0.1), requester);
+        op_manager.ring.add_connection(Location::new(0.9), idle);
+
+        let code = get_test_module("test_contract_1").map_err(|err| anyhow::anyhow!("{err}"))?;
+        let contract = ContractContainer::Wasm(ContractWasmAPIVersion::V1(WrappedContract::new(
+            Arc::new(ContractCode::from(code)),
+            Parameters::from(vec![]),
+        )));
+        let response = op_manager
+            .notify_contract_handler_for(
+                &requester,
+                ContractHandlerEvent::PutQuery {
+                    key: contract.key(),
+                    state: WrappedState::new(vec![1, 2, 3, 4]),
+                    related_contracts: RelatedContracts::default(),
+                    contract: Some(contract),
+                },
+            )
+            .await
+            .map_err(|err| anyhow::anyhow!("{err}"))?;
+        assert!(matches!(
+            response,
+            ContractHandlerEvent::PutResponse { new_value: Ok(_) }
+        ));
+
+        // the connection maintenance of the ring drops it once it is not a new peer anymore
+        let dropped = tokio::time::timeout(Duration::from_secs(60), async {
+            loop {
+                match notifications.recv().await {
+                    Some(Either::Right(NodeEvent::DropConnection(peer))) => break Some(peer),
+                    Some(_) => {}
+                    None => break None,
+                }
+            }
+        })
+        .await?;
+        assert_eq!(dropped, Some(requester));
+        assert!(!op_manager
+            .ring
+            .should_accept(Location::new(0.3), &PeerId::random()));
+        Ok(())
+    }
+}
@@ -37,6 +37,8 @@ pub(crate) enum ConnectionError {
     LocationUnknown,
     #[error("unable to send message")]
     SendNotCompleted,
+    #[error("peer not connected to the network")]
+    UnknownPeer,
     #[error("error while de/serializing message")]
     #[serde(skip)]
     Serialization(#[from] Option<Box<bincode::ErrorKind>>),
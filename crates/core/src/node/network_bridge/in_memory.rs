@@ -1,31 +1,42 @@
 //! A in-memory connection manager and transport implementation. Used for testing purposes.
+//!
+//! All the peers of a simulation share a [`SimulatedWire`] which decides the fate of every
+//! message sent (latency, loss, partitions...) from a seed, so the same seed and the same
+//! sequence of messages sent always result in the same deliveries.
+//!
+//! Deliveries are scheduled on the tokio clock, so when it is paused (e.g. `start_paused`
+//! tests) time only advances once every peer is idle and whole simulations are reproducible.
+//! Messages due at the same time are delivered in an order which doesn't depend on how
+//! the peers sending them were scheduled: by origin and then in the order sent by it.
 use std::{
-    collections::HashMap,
-    io::Cursor,
-    sync::Arc,
-    time::{Duration, Instant},
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 
-use crossbeam::channel::{self, Receiver, Sender};
 use futures::{future::BoxFuture, FutureExt};
-use once_cell::sync::OnceCell;
-use rand::{prelude::StdRng, seq::SliceRandom, Rng, SeedableRng};
-use tokio::sync::Mutex;
+use rand::{prelude::StdRng, Rng, SeedableRng};
+use tokio::{sync::Notify, task::AbortHandle, time::Instant};
 
 use super::{ConnectionError, NetworkBridge, PeerId};
 use crate::{
     config::GlobalExecutor,
-    message::NetMessage,
-    node::{testing_impl::NetworkBridgeExt, NetEventRegister, OpManager},
+    message::{NetMessage, Transaction},
+    node::{
+        testing_impl::{Fault, NetworkBridgeExt, NodeLabel},
+        NetEventRegister, OpManager,
+    },
+    topology::meter::ResourceType,
     tracing::NetEventLog,
 };
 
 #[derive(Clone)]
 pub(in crate::node) struct MemoryConnManager {
-    transport: InMemoryTransport,
+    peer: PeerId,
+    wire: SimulatedWire,
     log_register: Arc<dyn NetEventRegister>,
     op_manager: Arc<OpManager>,
-    msg_queue: Arc<Mutex<Vec<NetMessage>>>,
 }
 
 impl MemoryConnManager {
@@ -33,30 +44,13 @@ impl MemoryConnManager {
         peer: PeerId,
         log_register: impl NetEventRegister,
         op_manager: Arc<OpManager>,
-        add_noise: bool,
+        wire: SimulatedWire,
     ) -> Self {
-        let transport = InMemoryTransport::new(peer, add_noise);
-        let msg_queue = Arc::new(Mutex::new(Vec::new()));
-
-        let msg_queue_cp = msg_queue.clone();
-        let transport_cp = transport.clone();
-        GlobalExecutor::spawn(async move {
-            // evaluate the messages as they arrive
-            loop {
-                let Some(msg) = transport_cp.msg_stack_queue.lock().await.pop() else {
-                    continue;
-                };
-                let msg_data: NetMessage =
-                    bincode::deserialize_from(Cursor::new(msg.data)).unwrap();
-                msg_queue_cp.lock().await.push(msg_data);
-            }
-        });
-
         Self {
-            transport,
+            peer,
+            wire,
             log_register: Arc::new(log_register),
             op_manager,
-            msg_queue,
         }
     }
 }
@@ -68,8 +62,17 @@ impl NetworkBridge for MemoryConnManager {
             .register_events(NetEventLog::from_outbound_msg(&msg, &self.op_manager.ring))
             .await;
         self.op_manager.sending_transaction(target, &msg);
-        let msg = bincode::serialize(&msg)?;
-        self.transport.send(*target, msg);
+        if let Some(capture) = &self.op_manager.capture {
+            capture.outbound(target, &msg);
+        }
+        let data = bincode::serialize(&msg)?;
+        self.op_manager.ring.record_traffic(
+            target,
+            Some(&msg),
+            ResourceType::OutboundBandwidthBytes,
+            data.len(),
+        );
+        self.wire.send(self.peer, *target, data);
         Ok(())
     }
 
@@ -86,113 +89,557 @@ impl NetworkBridgeExt for MemoryConnManager {
     fn recv(&mut self) -> BoxFuture<'_, Result<NetMessage, ConnectionError>> {
         async {
             loop {
-                let mut queue = self.msg_queue.lock().await;
-                let Some(msg) = queue.pop() else {
-                    std::mem::drop(queue);
-                    tokio::time::sleep(Duration::from_millis(10)).await;
-                    continue;
-                };
-                return Ok(msg);
+                let msg = self.wire.recv(&self.peer).await?;
+                let decoded: Result<NetMessage, _> = bincode::deserialize(&msg.data)
+                    .map_err(|err| ConnectionError::Serialization(Some(err)));
+                self.op_manager.ring.record_traffic(
+                    &msg.origin,
+                    decoded.as_ref().ok(),
+                    ResourceType::InboundBandwidthBytes,
+                    msg.data.len(),
+                );
+                if let Ok(decoded) = &decoded {
+                    if !self.op_manager.receiving_message(&msg.origin, decoded) {
+                        continue;
+                    }
+                }
+                break decoded;
             }
         }
         .boxed()
     }
 }
 
-#[derive(Clone, Debug)]
+/// Conditions of the simulated links between peers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LinkConditions {
+    /// Minimum and maximum latency of a message, picked uniformly for each message.
+    pub latency: (Duration, Duration),
+    /// Bytes per second the link can carry, unlimited if not set.
+    pub bandwidth: Option<u64>,
+    /// Probability of a message being lost, between 0 and 1.
+    pub loss: f64,
+}
+
+impl LinkConditions {
+    /// Messages arriving delayed and out of order.
+    pub fn noisy() -> Self {
+        Self {
+            latency: (Duration::from_millis(1), Duration::from_millis(50)),
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for LinkConditions {
+    fn default() -> Self {
+        Self {
+            latency: (Duration::ZERO, Duration::ZERO),
+            bandwidth: None,
+            loss: 0.0,
+        }
+    }
+}
+
+#[derive(Debug)]
 struct MessageOnTransit {
     origin: PeerId,
-    target: PeerId,
+    /// label of the origin, which unlike its id is the same between runs
+    origin_label: NodeLabel,
+    deliver_at: Instant,
+    /// position of the message among the ones sent through the same link
+    seq: u64,
     data: Vec<u8>,
 }
 
-static NETWORK_WIRES: OnceCell<(Sender<MessageOnTransit>, Receiver<MessageOnTransit>)> =
-    OnceCell::new();
-
-#[derive(Clone, Debug)]
-struct InMemoryTransport {
-    interface_peer: PeerId,
-    /// received messages per each peer awaiting processing
-    msg_stack_queue: Arc<Mutex<Vec<MessageOnTransit>>>,
-    /// all messages 'traversing' the network at a given time
-    network: Sender<MessageOnTransit>,
-}
-
-impl InMemoryTransport {
-    fn new(interface_peer: PeerId, add_noise: bool) -> Self {
-        let msg_stack_queue = Arc::new(Mutex::new(Vec::new()));
-        let (network_tx, network_rx) = NETWORK_WIRES.get_or_init(crossbeam::channel::unbounded);
-
-        // store messages incoming from the network in the msg stack
-        let msg_stack_queue_cp = msg_stack_queue.clone();
-        let network_tx_cp = network_tx.clone();
-        GlobalExecutor::spawn(async move {
-            const MAX_DELAYED_MSG: usize = 10;
-            let mut rng = StdRng::from_entropy();
-            // delayed messages per target
-            let mut delayed: HashMap<_, Vec<_>> = HashMap::with_capacity(MAX_DELAYED_MSG);
-            let last_drain = Instant::now();
+impl PartialEq for MessageOnTransit {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for MessageOnTransit {}
+
+impl PartialOrd for MessageOnTransit {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for MessageOnTransit {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.deliver_at, &self.origin_label, self.seq).cmp(&(
+            other.deliver_at,
+            &other.origin_label,
+            other.seq,
+        ))
+    }
+}
+
+#[derive(Default)]
+struct Inbox {
+    queue: BinaryHeap<Reverse<MessageOnTransit>>,
+    notify: Arc<Notify>,
+}
+
+struct WireState {
+    seed: u64,
+    conditions: LinkConditions,
+    /// conditions overriding the default ones for the link between two peers
+    links: HashMap<(PeerId, PeerId), LinkConditions>,
+    /// group of each peer while the network is partitioned
+    partition: HashMap<PeerId, usize>,
+    crashed: HashSet<PeerId>,
+    labels: HashMap<PeerId, NodeLabel>,
+    tasks: HashMap<PeerId, AbortHandle>,
+    /// tasks flooding peers, stopped when the network is healed
+    floods: Vec<AbortHandle>,
+    sent: HashMap<(PeerId, PeerId), u64>,
+    busy_until: HashMap<(PeerId, PeerId), Instant>,
+    inboxes: HashMap<PeerId, Inbox>,
+}
+
+impl WireState {
+    fn link_conditions(&self, a: PeerId, b: PeerId) -> LinkConditions {
+        self.links
+            .get(&(a.min(b), a.max(b)))
+            .copied()
+            .unwrap_or(self.conditions)
+    }
+
+    fn reachable(&self, origin: &PeerId, target: &PeerId) -> bool {
+        const REST: usize = usize::MAX;
+        if self.crashed.contains(origin) || self.crashed.contains(target) {
+            return false;
+        }
+        if self.partition.is_empty() {
+            return true;
+        }
+        self.partition.get(origin).unwrap_or(&REST) == self.partition.get(target).unwrap_or(&REST)
+    }
+
+    /// A source of randomness for a message only depending on the seed and which message it is.
+    fn message_rng(&self, origin: &PeerId, target: &PeerId, seq: u64) -> StdRng {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&self.seed.to_le_bytes());
+        for peer in [origin, target] {
+            // peer ids change between runs but labels don't
+            match self.labels.get(peer) {
+                Some(label) => hasher.update(label.as_bytes()),
+                None => hasher.update(&peer.to_bytes()),
+            };
+        }
+        hasher.update(&seq.to_le_bytes());
+        StdRng::from_seed(*hasher.finalize().as_bytes())
+    }
+}
+
+/// The network shared by all the peers of a simulation, where faults can be injected.
+#[derive(Clone)]
+pub(crate) struct SimulatedWire(Arc<Mutex<WireState>>);
+
+impl SimulatedWire {
+    pub fn new(seed: u64) -> Self {
+        Self(Arc::new(Mutex::new(WireState {
+            seed,
+            conditions: LinkConditions::default(),
+            links: HashMap::new(),
+            partition: HashMap::new(),
+            crashed: HashSet::new(),
+            labels: HashMap::new(),
+            tasks: HashMap::new(),
+            floods: Vec::new(),
+            sent: HashMap::new(),
+            busy_until: HashMap::new(),
+            inboxes: HashMap::new(),
+        })))
+    }
+
+    pub fn set_seed(&self, seed: u64) {
+        self.0.lock().unwrap().seed = seed;
+    }
+
+    /// Connects a peer to the network.
+    pub fn register(&self, peer: PeerId, label: NodeLabel) {
+        let mut state = self.0.lock().unwrap();
+        state.labels.insert(peer, label);
+        state.inboxes.entry(peer).or_default();
+    }
+
+    /// Sets the task running the peer, which is stopped if the peer crashes.
+    pub fn register_task(&self, peer: PeerId, task: AbortHandle) {
+        self.0.lock().unwrap().tasks.insert(peer, task);
+    }
+
+    fn peer_of(&self, label: &NodeLabel) -> Option<PeerId> {
+        let state = self.0.lock().unwrap();
+        let peer = state
+            .labels
+            .iter()
+            .find_map(|(peer, other)| (other == label).then_some(*peer));
+        if peer.is_none() {
+            tracing::warn!(%label, "Fault references an unknown peer");
+        }
+        peer
+    }
+
+    pub fn inject(&self, fault: Fault) {
+        match fault {
+            Fault::Partition(groups) => {
+                let groups = groups
+                    .iter()
+                    .map(|group| {
+                        group
+                            .iter()
+                            .filter_map(|label| self.peer_of(label))
+                            .collect()
+                    })
+                    .collect();
+                self.partition(groups);
+            }
+            Fault::Heal => self.heal(),
+            Fault::Crash(label) => {
+                if let Some(peer) = self.peer_of(&label) {
+                    self.crash(peer);
+                }
+            }
+            Fault::Conditions(conditions) => self.set_conditions(conditions),
+            Fault::Link(a, b, conditions) => {
+                if let (Some(a), Some(b)) = (self.peer_of(&a), self.peer_of(&b)) {
+                    self.set_link_conditions(a, b, conditions);
+                }
+            }
+            Fault::Flood(origin, target, rate) => {
+                if let (Some(origin), Some(target)) = (self.peer_of(&origin), self.peer_of(&target))
+                {
+                    self.flood(origin, target, rate);
+                }
+            }
+            Fault::Malformed(origin, target, count) => {
+                if let (Some(origin), Some(target)) = (self.peer_of(&origin), self.peer_of(&target))
+                {
+                    self.send_malformed(origin, target, count);
+                }
+            }
+        }
+    }
+
+    /// Makes `origin` send `count` messages to `target` which no honest peer would send.
+    pub fn send_malformed(&self, origin: PeerId, target: PeerId, count: usize) {
+        let msg = bincode::serialize(&NetMessage::Aborted(Transaction::malformed()))
+            .expect("message should be serializable");
+        for _ in 0..count {
+            self.send(origin, target, msg.clone());
+        }
+    }
+
+    /// Makes `origin` send junk to `target` at `rate` bytes per second, until the network is
+    /// healed, `origin` crashes or the wire is dropped.
+    pub fn flood(&self, origin: PeerId, target: PeerId, rate: u64) {
+        const EVERY: Duration = Duration::from_millis(100);
+        let wire = Arc::downgrade(&self.0);
+        let chunk = (rate as f64 * EVERY.as_secs_f64()) as usize;
+        let flood = GlobalExecutor::spawn(async move {
+            let mut interval = tokio::time::interval(EVERY);
             loop {
-                match network_rx.try_recv() {
-                    Ok(msg) if msg.target == interface_peer => {
+                interval.tick().await;
+                let Some(wire) = wire.upgrade().map(SimulatedWire) else {
+                    break;
+                };
+                if wire.0.lock().unwrap().crashed.contains(&origin) {
+                    break;
+                }
+                // not a valid message, so it won't be mistaken for one on arrival
+                wire.send(origin, target, vec![u8::MAX; chunk]);
+            }
+        });
+        self.0.lock().unwrap().floods.push(flood.abort_handle());
+    }
+
+    /// Sets the conditions of all the links without specific conditions.
+    pub fn set_conditions(&self, conditions: LinkConditions) {
+        self.0.lock().unwrap().conditions = conditions;
+    }
+
+    /// Sets the conditions of the link between two peers, in both directions.
+    pub fn set_link_conditions(&self, a: PeerId, b: PeerId, conditions: LinkConditions) {
+        self.0
+            .lock()
+            .unwrap()
+            .links
+            .insert((a.min(b), a.max(b)), conditions);
+    }
+
+    /// Splits the network so peers can only reach other peers in the same group,
+    /// peers not in any group can only reach each other.
+    pub fn partition(&self, groups: Vec<Vec<PeerId>>) {
+        let mut state = self.0.lock().unwrap();
+        state.partition = groups
+            .into_iter()
+            .enumerate()
+            .flat_map(|(group, peers)| peers.into_iter().map(move |peer| (peer, group)))
+            .collect();
+    }
+
+    /// Joins all the partitions back and stops any flood.
+    pub fn heal(&self) {
+        let mut state = self.0.lock().unwrap();
+        state.partition.clear();
+        for flood in state.floods.drain(..) {
+            flood.abort();
+        }
+    }
+
+    /// Stops a peer and disconnects it from the network, dropping any message still
+    /// in transit to it.
+    pub fn crash(&self, peer: PeerId) {
+        let mut state = self.0.lock().unwrap();
+        state.crashed.insert(peer);
+        if let Some(task) = state.tasks.remove(&peer) {
+            task.abort();
+        }
+        if let Some(inbox) = state.inboxes.get_mut(&peer) {
+            inbox.queue.clear();
+        }
+    }
+
+    fn send(&self, origin: PeerId, target: PeerId, data: Vec<u8>) {
+        let mut state = self.0.lock().unwrap();
+        let link = (origin, target);
+        let seq = {
+            let sent = state.sent.entry(link).or_default();
+            *sent += 1;
+            *sent
+        };
+        if !state.reachable(&origin, &target) {
+            tracing::trace!(%origin, %target, "Message dropped, peers are unreachable");
+            return;
+        }
+        let conditions = state.link_conditions(origin, target);
+        let mut rng = state.message_rng(&origin, &target, seq);
+        if conditions.loss > 0.0 && rng.gen_bool(conditions.loss.min(1.0)) {
+            tracing::trace!(%origin, %target, "Message lost");
+            return;
+        }
+        let (min_latency, max_latency) = conditions.latency;
+        let latency = if max_latency > min_latency {
+            rng.gen_range(min_latency..=max_latency)
+        } else {
+            min_latency
+        };
+        let now = Instant::now();
+        let sent_at = match conditions.bandwidth {
+            Some(bandwidth) if bandwidth > 0 => {
+                let transmission = Duration::from_secs_f64(data.len() as f64 / bandwidth as f64);
+                let busy_until = state.busy_until.entry(link).or_insert(now);
+                *busy_until = (*busy_until).max(now) + transmission;
+                *busy_until
+            }
+            _ => now,
+        };
+        let origin_label = state
+            .labels
+            .get(&origin)
+            .cloned()
+            .unwrap_or_else(|| NodeLabel::from(origin.to_string().as_str()));
+        let Some(inbox) = state.inboxes.get_mut(&target) else {
+            tracing::error!(%target, "Message sent to an unknown peer");
+            return;
+        };
+        inbox.queue.push(Reverse(MessageOnTransit {
+            origin,
+            origin_label,
+            deliver_at: sent_at + latency,
+            seq,
+            data,
+        }));
+        inbox.notify.notify_one();
+    }
+
+    async fn recv(&self, peer: &PeerId) -> Result<MessageOnTransit, ConnectionError> {
+        loop {
+            let (notify, next_delivery) = {
+                let mut state = self.0.lock().unwrap();
+                let Some(inbox) = state.inboxes.get_mut(peer) else {
+                    tracing::error!(%peer, "Peer not registered in the network");
+                    return Err(ConnectionError::UnknownPeer);
+                };
+                match inbox.queue.peek() {
+                    Some(Reverse(msg)) if msg.deliver_at <= Instant::now() => {
+                        let Reverse(msg) = inbox.queue.pop().expect("not empty");
                         tracing::trace!(
                             "Inbound message received for peer {} from {}",
-                            interface_peer,
+                            peer,
                             msg.origin
                         );
-                        if rng.gen_bool(0.5) && delayed.len() < MAX_DELAYED_MSG && add_noise {
-                            delayed.entry(msg.target).or_default().push(msg);
-                            tokio::time::sleep(Duration::from_millis(10)).await;
-                        } else {
-                            let mut queue = msg_stack_queue_cp.lock().await;
-                            queue.push(msg);
-                            if add_noise && rng.gen_bool(0.2) {
-                                queue.shuffle(&mut rng);
-                            }
-                        }
-                    }
-                    Ok(msg) => {
-                        // send back to the network since this msg belongs to other peer
-                        network_tx_cp
-                            .send(msg)
-                            .expect("failed to send msg back to network");
-                        tokio::time::sleep(Duration::from_nanos(1_000)).await
-                    }
-                    Err(channel::TryRecvError::Disconnected) => break,
-                    Err(channel::TryRecvError::Empty) => {
-                        tokio::time::sleep(Duration::from_millis(10)).await
+                        return Ok(msg);
                     }
+                    Some(Reverse(msg)) => (inbox.notify.clone(), Some(msg.deliver_at)),
+                    None => (inbox.notify.clone(), None),
                 }
-                if (last_drain.elapsed() > Duration::from_millis(rng.gen_range(1_000..5_000))
-                    && !delayed.is_empty())
-                    || delayed.len() == MAX_DELAYED_MSG
-                {
-                    let mut queue = msg_stack_queue_cp.lock().await;
-                    for (_, msgs) in delayed.drain() {
-                        queue.extend(msgs);
+            };
+            match next_delivery {
+                Some(deliver_at) => {
+                    tokio::select! {
+                        _ = notify.notified() => {}
+                        _ = tokio::time::sleep_until(deliver_at) => {}
                     }
-                    let queue = &mut queue;
-                    queue.shuffle(&mut rng);
                 }
+                None => notify.notified().await,
             }
-            tracing::error!("Stopped receiving messages in {}", interface_peer);
-        });
+        }
+    }
+}
 
-        Self {
-            interface_peer,
-            msg_stack_queue,
-            network: network_tx.clone(),
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn deliveries(seed: u64, conditions: LinkConditions) -> Vec<(u64, Vec<u8>)> {
+        let wire = SimulatedWire::new(seed);
+        wire.set_conditions(conditions);
+        let (a, b) = (PeerId::random(), PeerId::random());
+        wire.register(a, NodeLabel::from("node-0"));
+        wire.register(b, NodeLabel::from("node-1"));
+        for i in 0..100u8 {
+            wire.send(a, b, vec![i]);
         }
+        let mut received = vec![];
+        while let Ok(msg) = tokio::time::timeout(Duration::from_millis(100), wire.recv(&b)).await {
+            let msg = msg.unwrap();
+            received.push((msg.seq, msg.data));
+        }
+        received
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn same_seed_same_deliveries() {
+        let conditions = LinkConditions {
+            loss: 0.3,
+            ..LinkConditions::noisy()
+        };
+        let first = deliveries(42, conditions).await;
+        assert!(!first.is_empty() && first.len() < 100);
+        assert_eq!(first, deliveries(42, conditions).await);
+        assert_ne!(first, deliveries(7, conditions).await);
     }
 
-    fn send(&self, peer: PeerId, message: Vec<u8>) {
-        let send_res = self.network.send(MessageOnTransit {
-            origin: self.interface_peer,
-            target: peer,
-            data: message,
+    /// Peers running concurrently keep passing messages around; returns the order in which
+    /// they were delivered.
+    async fn relayed_deliveries(seed: u64) -> Vec<(NodeLabel, NodeLabel, u64)> {
+        const PEERS: usize = 4;
+        const HOPS: u8 = 20;
+        let label = |i: usize| NodeLabel::from(format!("node-{i}").as_str());
+        let wire = SimulatedWire::new(seed);
+        wire.set_conditions(LinkConditions {
+            loss: 0.1,
+            bandwidth: Some(10_000),
+            ..LinkConditions::noisy()
         });
-        if let Err(channel::SendError(_)) = send_res {
-            tracing::error!("Network shutdown")
+        let peers: Vec<_> = (0..PEERS)
+            .map(|i| {
+                let peer = PeerId::random();
+                wire.register(peer, label(i));
+                peer
+            })
+            .collect();
+        let delivered = Arc::new(Mutex::new(vec![]));
+        let tasks: Vec<_> = peers
+            .iter()
+            .enumerate()
+            .map(|(i, &peer)| {
+                let (wire, peers, delivered) = (wire.clone(), peers.clone(), delivered.clone());
+                tokio::spawn(async move {
+                    loop {
+                        let msg = wire.recv(&peer).await.unwrap();
+                        delivered.lock().unwrap().push((
+                            label(i),
+                            msg.origin_label.clone(),
+                            msg.seq,
+                        ));
+                        let hops = msg.data[0];
+                        if hops < HOPS {
+                            // pass it on, growing so later hops are slowed down by bandwidth
+                            let target = peers[(i + 1 + hops as usize % 2) % PEERS];
+                            wire.send(peer, target, vec![hops + 1; (hops as usize + 1) * 50]);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for _ in 0..5 {
+            for (i, &peer) in peers.iter().enumerate() {
+                wire.send(peer, peers[(i + 1) % PEERS], vec![0]);
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        for task in tasks {
+            task.abort();
+        }
+        let delivered = delivered.lock().unwrap().clone();
+        delivered
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn same_seed_same_event_order() {
+        let first = relayed_deliveries(42).await;
+        assert!(first.len() > 50);
+        assert_eq!(first, relayed_deliveries(42).await);
+        assert_ne!(first, relayed_deliveries(7).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn partitions_and_crashes() {
+        let wire = SimulatedWire::new(0);
+        let (a, b, c) = (PeerId::random(), PeerId::random(), PeerId::random());
+        for (peer, label) in [(a, "node-0"), (b, "node-1"), (c, "node-2")] {
+            wire.register(peer, NodeLabel::from(label));
         }
+        wire.partition(vec![vec![a, b]]);
+        wire.send(a, c, vec![0]);
+        wire.send(a, b, vec![1]);
+        assert_eq!(wire.recv(&b).await.unwrap().data, vec![1]);
+        wire.heal();
+        wire.send(a, c, vec![2]);
+        assert_eq!(wire.recv(&c).await.unwrap().data, vec![2]);
+
+        wire.crash(b);
+        wire.send(a, b, vec![3]);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), wire.recv(&b))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn floods_stop_on_heal_and_drop() {
+        let wire = SimulatedWire::new(0);
+        let (a, b) = (PeerId::random(), PeerId::random());
+        wire.register(a, NodeLabel::from("node-0"));
+        wire.register(b, NodeLabel::from("node-1"));
+        wire.flood(a, b, 1_000);
+        assert_eq!(wire.recv(&b).await.unwrap().data.len(), 100);
+        wire.heal();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        while let Ok(Ok(_)) = tokio::time::timeout(Duration::ZERO, wire.recv(&b)).await {}
+        assert!(tokio::time::timeout(Duration::from_secs(1), wire.recv(&b))
+            .await
+            .is_err());
+
+        wire.flood(a, b, 1_000);
+        let flood = wire.0.lock().unwrap().floods[0].clone();
+        drop(wire);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(flood.is_finished());
+    }
+
+    #[tokio::test]
+    async fn unknown_peers_cant_receive() {
+        let wire = SimulatedWire::new(0);
+        assert!(matches!(
+            wire.recv(&PeerId::random()).await,
+            Err(ConnectionError::UnknownPeer)
+        ));
     }
 }
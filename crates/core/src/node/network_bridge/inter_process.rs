@@ -1,8 +1,10 @@
 use std::sync::{Arc, OnceLock};
 
+use either::Either;
 use futures::{future::BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt, BufWriter, Stdout},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter},
     sync::{
         watch::{Receiver, Sender},
         Mutex,
@@ -10,29 +12,58 @@ use tokio::{
 };
 
 use crate::{
-    message::NetMessage,
+    message::{NetMessage, Transaction},
     node::{testing_impl::NetworkBridgeExt, OpManager, PeerId},
+    router::RouteEvent,
     tracing::{NetEventLog, NetEventRegister},
+    DynError,
 };
 
 use super::{ConnectionError, NetworkBridge};
 
 type Data = Vec<u8>;
 
+/// Where the messages sent to other peers are written to.
+pub type PeerOutput = Box<dyn AsyncWrite + Send + Unpin>;
+
+pub(crate) type SharedOutput = Arc<Mutex<BufWriter<PeerOutput>>>;
+
 static INCOMING_DATA: OnceLock<Sender<Data>> = OnceLock::new();
 
+/// Data written by a peer to its output.
+#[derive(Serialize, Deserialize)]
+pub enum PeerOutputMsg {
+    /// A network message for the target peer.
+    Message { target: PeerId, data: Data },
+    /// Serialized events recorded by the peer, to be registered by the supervisor.
+    Events(Data),
+}
+
+impl PeerOutputMsg {
+    async fn write(&self, output: &SharedOutput) -> std::io::Result<usize> {
+        let data = bincode::serialize(self)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?;
+        let output = &mut *output.lock().await;
+        output.write_all(&(data.len() as u32).to_le_bytes()).await?;
+        output.write_all(&data).await?;
+        output.flush().await?;
+        Ok(data.len())
+    }
+}
+
 #[derive(Clone)]
 pub struct InterProcessConnManager {
     recv: Receiver<Data>,
     log_register: Arc<dyn NetEventRegister>,
     op_manager: Arc<OpManager>,
-    output: Arc<Mutex<BufWriter<Stdout>>>,
+    output: SharedOutput,
 }
 
 impl InterProcessConnManager {
     pub(in crate::node) fn new(
         log_register: impl NetEventRegister,
         op_manager: Arc<OpManager>,
+        output: SharedOutput,
     ) -> Self {
         let (sender, recv) = tokio::sync::watch::channel(vec![]);
         INCOMING_DATA.set(sender).expect("shouldn't be set");
@@ -40,17 +71,21 @@ impl InterProcessConnManager {
             recv,
             log_register: Arc::new(log_register),
             op_manager,
-            output: Arc::new(Mutex::new(BufWriter::new(tokio::io::stdout()))),
+            output,
         }
     }
 
+    pub(in crate::node) fn shared_output(output: PeerOutput) -> SharedOutput {
+        Arc::new(Mutex::new(BufWriter::new(output)))
+    }
+
     pub fn push_msg(data: Vec<u8>) {
         let _ = INCOMING_DATA.get().expect("should be set").send(data);
     }
 
     pub async fn pull_msg(
-        stdout: &mut tokio::process::ChildStdout,
-    ) -> std::io::Result<Option<(PeerId, Data)>> {
+        stdout: &mut (impl AsyncRead + Unpin),
+    ) -> std::io::Result<Option<PeerOutputMsg>> {
         let mut msg_len = [0u8; 4];
         let Ok(read_res) = tokio::time::timeout(
             std::time::Duration::from_millis(100),
@@ -64,9 +99,57 @@ impl InterProcessConnManager {
         let msg_len = u32::from_le_bytes(msg_len) as usize;
         let buf = &mut vec![0u8; msg_len];
         stdout.read_exact(buf).await?;
-        let (target, data) = bincode::deserialize(buf)
+        let msg = bincode::deserialize(buf)
             .map_err(|_| std::io::Error::from(std::io::ErrorKind::Other))?;
-        Ok(Some((target, data)))
+        Ok(Some(msg))
+    }
+}
+
+/// Registers the events of a peer and also writes them to its output, so the supervisor
+/// checking the simulation sees the events of every peer.
+#[derive(Clone)]
+pub(crate) struct EventForwarder<ER> {
+    inner: ER,
+    output: SharedOutput,
+}
+
+impl<ER> EventForwarder<ER> {
+    pub fn new(inner: ER, output: SharedOutput) -> Self {
+        Self { inner, output }
+    }
+}
+
+impl<ER: NetEventRegister + Clone> NetEventRegister for EventForwarder<ER> {
+    fn register_events<'a>(
+        &'a self,
+        events: Either<NetEventLog<'a>, Vec<NetEventLog<'a>>>,
+    ) -> BoxFuture<'a, ()> {
+        async move {
+            self.inner.register_events(events.clone()).await;
+            let forwarded = match crate::tracing::serialize_events(events) {
+                Ok(data) => PeerOutputMsg::Events(data),
+                Err(err) => {
+                    tracing::error!("Failed serializing events: {err}");
+                    return;
+                }
+            };
+            if let Err(err) = forwarded.write(&self.output).await {
+                tracing::error!("Failed forwarding events: {err}");
+            }
+        }
+        .boxed()
+    }
+
+    fn notify_of_time_out(&mut self, tx: Transaction) -> BoxFuture<()> {
+        self.inner.notify_of_time_out(tx)
+    }
+
+    fn trait_clone(&self) -> Box<dyn NetEventRegister> {
+        Box::new(self.clone())
+    }
+
+    fn get_router_events(&self, number: usize) -> BoxFuture<Result<Vec<RouteEvent>, DynError>> {
+        self.inner.get_router_events(number)
     }
 }
 
@@ -92,12 +175,17 @@ impl NetworkBridge for InterProcessConnManager {
         self.log_register
             .register_events(NetEventLog::from_outbound_msg(&msg, &self.op_manager.ring))
             .await;
-        let data = bincode::serialize(&(*target, msg))?;
-        let output = &mut *self.output.lock().await;
-        output.write_all(&(data.len() as u32).to_le_bytes()).await?;
-        output.write_all(&data).await?;
-        output.flush().await?;
-        tracing::debug!(%target, bytes = data.len(), "sent network message out");
+        if let Some(capture) = &self.op_manager.capture {
+            capture.outbound(target, &msg);
+        }
+        let data = bincode::serialize(&msg)?;
+        let bytes = PeerOutputMsg::Message {
+            target: *target,
+            data,
+        }
+        .write(&self.output)
+        .await?;
+        tracing::debug!(%target, bytes, "sent network message out");
         Ok(())
     }
 
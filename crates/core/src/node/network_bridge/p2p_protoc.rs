@@ -4,7 +4,8 @@ use std::{
     net::IpAddr,
     pin::Pin,
     sync::Arc,
-    task::Poll,
+    task::{Poll, Waker},
+    time::Duration,
 };
 
 use asynchronous_codec::{BytesMut, Framed};
@@ -16,18 +17,19 @@ use futures::{
 };
 use libp2p::{
     autonat,
-    core::{muxing, transport, UpgradeInfo},
-    identify,
+    core::{muxing, transport, transport::ListenerId, UpgradeInfo},
+    dcutr, identify,
     identity::Keypair,
     multiaddr::Protocol,
-    ping,
+    ping, relay,
     swarm::{
         self,
+        behaviour::toggle::Toggle,
         dial_opts::DialOpts,
         handler::{DialUpgradeError, FullyNegotiatedInbound, FullyNegotiatedOutbound},
         Config as SwarmConfig, ConnectionHandler, ConnectionHandlerEvent, ConnectionId, FromSwarm,
         KeepAlive, NetworkBehaviour, NotifyHandler, Stream as NegotiatedSubstream,
-        SubstreamProtocol, SwarmEvent, ToSwarm,
+        SubstreamProtocol, SwarmEvent, THandlerErr, ToSwarm,
     },
     InboundUpgrade, Multiaddr, OutboundUpgrade, PeerId as Libp2pPeerId, Swarm,
 };
@@ -49,6 +51,7 @@ use crate::{
         PeerId as FreenetPeerId,
     },
     ring::PeerKeyLocation,
+    topology::meter::ResourceType,
     tracing::NetEventLog,
 };
 
@@ -60,10 +63,18 @@ const CURRENT_PROTOC_VER: &str = "/freenet/0.1.0";
 const CURRENT_PROTOC_VER_STR: &str = "/freenet/0.1.0";
 const CURRENT_IDENTIFY_PROTOC_VER: &str = "/id/1.0.0";
 
+/// Time to wait before listening again through a relay after it failed.
+#[cfg(not(test))]
+const RELAY_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+#[cfg(test)]
+const RELAY_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
 fn config_behaviour(
     private_key: &Keypair,
     gateways: &[InitPeerNode],
     _private_addr: &Option<Multiaddr>,
+    is_gateway: bool,
+    relay_client: relay::client::Behaviour,
     op_manager: Arc<OpManager>,
 ) -> NetBehaviour {
     let routing_table: HashMap<_, _> = gateways
@@ -96,13 +107,23 @@ fn config_behaviour(
         behaviour
     };
 
+    // gateways act as circuit relays for the peers which can't be reached directly
+    let relay = is_gateway.then(|| relay::Behaviour::new(peer_id, relay::Config::default()));
+
     NetBehaviour {
         ping,
         identify: identify::Behaviour::new(ident_config),
         auto_nat,
+        relay: relay.into(),
+        relay_client,
+        dcutr: dcutr::Behaviour::new(peer_id),
         freenet: FreenetBehaviour {
             outbound: VecDeque::new(),
             routing_table,
+            relays: relay_addresses(gateways),
+            private_peers: HashSet::new(),
+            redials: VecDeque::new(),
+            waker: None,
             connected: HashMap::new(),
             openning_connection: HashSet::new(),
             inbound: VecDeque::new(),
@@ -111,6 +132,26 @@ fn config_behaviour(
     }
 }
 
+/// Addresses of the gateways, through which peers behind NAT can be reached.
+fn relay_addresses(gateways: &[InitPeerNode]) -> Vec<Multiaddr> {
+    gateways
+        .iter()
+        .filter_map(|p| {
+            p.addr
+                .as_ref()
+                .map(|addr| addr.clone().with(Protocol::P2p(p.identifier.0)))
+        })
+        .collect()
+}
+
+/// The address of a peer when relayed through the given relay address.
+fn relayed_address(relay: &Multiaddr, peer: Libp2pPeerId) -> Multiaddr {
+    relay
+        .clone()
+        .with(Protocol::P2pCircuit)
+        .with(Protocol::P2p(peer))
+}
+
 /// Small helper function to convert a tuple composed of an IP address and a port
 /// to a libp2p Multiaddr type.
 fn multiaddr_from_connection(conn: (IpAddr, u16)) -> Multiaddr {
@@ -182,6 +223,9 @@ impl NetworkBridge for P2pBridge {
         self.log_register
             .register_events(NetEventLog::from_outbound_msg(&msg, &self.op_manager.ring));
         self.op_manager.sending_transaction(target, &msg);
+        if let Some(capture) = &self.op_manager.capture {
+            capture.outbound(target, &msg);
+        }
         self.ev_listener_tx
             .send(Left((*target, Box::new(msg))))
             .await
@@ -198,12 +242,19 @@ pub(in crate::node) struct P2pConnManager {
     /// last valid observed public address
     public_addr: Option<Multiaddr>,
     listening_addr: Option<Multiaddr>,
+    /// whether this peer is already listening for connections relayed through all the gateways
+    listening_through_relays: bool,
+    /// listeners for the connections relayed through each of the gateways
+    relay_listeners: HashMap<Libp2pPeerId, ListenerId>,
+    /// when to listen again through the relays which failed, if any did
+    relays_retry: Option<tokio::time::Instant>,
     event_listener: Box<dyn NetEventRegister>,
 }
 
 impl P2pConnManager {
     pub fn build(
         transport: transport::Boxed<(Libp2pPeerId, muxing::StreamMuxerBox)>,
+        relay_client: relay::client::Behaviour,
         config: &NodeConfig,
         op_manager: Arc<OpManager>,
         event_listener: impl NetEventRegister + Clone,
@@ -231,6 +282,8 @@ impl P2pConnManager {
             &private_key,
             &config.remote_nodes,
             &private_addr,
+            config.is_gateway(),
+            relay_client,
             op_manager.clone(),
         );
         let mut swarm = Swarm::new(
@@ -245,6 +298,17 @@ impl P2pConnManager {
             swarm.add_external_address(remote_addr);
         }
 
+        // peers known from previous runs can be dialed on the addresses they announced
+        for (known, addrs) in op_manager.ring.peer_book.known_peers() {
+            swarm
+                .behaviour_mut()
+                .freenet
+                .routing_table
+                .entry(known.peer.0)
+                .or_default()
+                .extend(addrs);
+        }
+
         let (tx_bridge_cmd, rx_bridge_cmd) = mpsc::channel(100);
         let bridge = P2pBridge::new(tx_bridge_cmd, op_manager, event_listener.clone());
 
@@ -256,6 +320,9 @@ impl P2pConnManager {
             conn_bridge_rx: rx_bridge_cmd,
             public_addr,
             listening_addr: private_addr,
+            listening_through_relays: false,
+            relay_listeners: HashMap::new(),
+            relays_retry: None,
             event_listener: Box::new(event_listener),
         })
     }
@@ -267,6 +334,219 @@ impl P2pConnManager {
         Ok(())
     }
 
+    /// Reserves a slot in every gateway relay, so other peers can reach this peer when
+    /// it is behind NAT; direct connections are then established via hole punching.
+    fn listen_through_relays(&mut self) {
+        if self.listening_through_relays {
+            return;
+        }
+        let relays = self.swarm.behaviour().freenet.relays.clone();
+        let mut failed = false;
+        for relay in relays {
+            let Some(Protocol::P2p(relay_id)) = relay.iter().last() else {
+                continue;
+            };
+            if self.relay_listeners.contains_key(&relay_id) {
+                continue;
+            }
+            let circuit_addr = relay.with(Protocol::P2pCircuit);
+            match self.swarm.listen_on(circuit_addr.clone()) {
+                Ok(listener) => {
+                    tracing::debug!("Listening through relay {circuit_addr}");
+                    self.relay_listeners.insert(relay_id, listener);
+                }
+                Err(err) => {
+                    tracing::warn!("Failed listening through relay {circuit_addr}: {err}");
+                    failed = true;
+                }
+            }
+        }
+        if failed {
+            self.retry_relays();
+        } else {
+            self.listening_through_relays = true;
+        }
+    }
+
+    /// Stops listening through a relay which refused or lost the reservation of this peer,
+    /// it will be listened through again later on.
+    fn relay_failed(&mut self, relay: Libp2pPeerId) {
+        if let Some(listener) = self.relay_listeners.remove(&relay) {
+            tracing::debug!("Stopped listening through relay {relay}");
+            self.swarm.remove_listener(listener);
+            self.listening_through_relays = false;
+            self.retry_relays();
+        }
+    }
+
+    fn retry_relays(&mut self) {
+        if self.relays_retry.is_none() {
+            self.relays_retry = Some(tokio::time::Instant::now() + RELAY_RETRY_INTERVAL);
+        }
+    }
+
+    /// Handles the actions about how this peer and the other peers are reached.
+    pub(in crate::node) fn handle_network_action(&mut self, action: ConnMngrActions) {
+        match action {
+            ConnMngrActions::UpdatePublicAddr(address) => {
+                self.public_addr = Some(address);
+            }
+            ConnMngrActions::IsPrivatePeer(peer) => {
+                // the peer can only be reached through a relay, the relayed connection
+                // is upgraded to a direct one by hole punching (dcutr)
+                tracing::debug!("Peer {peer} is private, dialing it through relays");
+                self.swarm
+                    .behaviour_mut()
+                    .freenet
+                    .private_peers
+                    .insert(peer);
+            }
+            ConnMngrActions::ListenThroughRelays => {
+                self.relays_retry = None;
+                self.listen_through_relays();
+            }
+            ConnMngrActions::RelayFailed(relay) => self.relay_failed(relay),
+            ConnMngrActions::ListenerClosed(listener) => {
+                let relay = self
+                    .relay_listeners
+                    .iter()
+                    .find_map(|(relay, id)| (*id == listener).then_some(*relay));
+                if let Some(relay) = relay {
+                    self.relay_failed(relay);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Turns an event of the swarm into either a message for this peer or an action to take.
+    pub(in crate::node) fn swarm_action(
+        op_manager: &OpManager,
+        event: SwarmEvent<NetEvent, THandlerErr<NetBehaviour>>,
+    ) -> Result<Either<NetMessage, ConnMngrActions>, ConnectionError> {
+        match event {
+            SwarmEvent::Behaviour(NetEvent::Freenet(msg)) => {
+                tracing::debug!("Message inbound: {:?}", msg);
+                Ok(Left(*msg))
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established: 0,
+                ..
+            } => Ok(Right(ConnMngrActions::ConnectionClosed {
+                peer: FreenetPeerId::from(peer_id),
+            })),
+            SwarmEvent::Dialing { peer_id, .. } => {
+                if let Some(peer_id) = peer_id {
+                    tracing::debug!("Attempting connection to {}", peer_id);
+                }
+                Ok(Right(ConnMngrActions::NoAction))
+            }
+            SwarmEvent::Behaviour(NetEvent::Identify(id)) => {
+                if let identify::Event::Received { peer_id, info } = *id {
+                    if Self::is_compatible_peer(&info) {
+                        op_manager
+                            .ring
+                            .peer_book
+                            .record_addresses(FreenetPeerId::from(peer_id), info.listen_addrs);
+                        Ok(Right(ConnMngrActions::ConnectionEstablished {
+                            peer: FreenetPeerId::from(peer_id),
+                            address: info.observed_addr,
+                        }))
+                    } else {
+                        tracing::warn!("Incompatible peer: {}, disconnecting", peer_id);
+                        Ok(Right(ConnMngrActions::ConnectionClosed {
+                            peer: FreenetPeerId::from(peer_id),
+                        }))
+                    }
+                } else {
+                    Ok(Right(ConnMngrActions::NoAction))
+                }
+            }
+            SwarmEvent::Behaviour(NetEvent::Autonat(event)) => match event {
+                autonat::Event::InboundProbe(autonat::InboundProbeEvent::Response {
+                    address,
+                    peer,
+                    ..
+                }) => {
+                    tracing::debug!(
+                        "Successful autonat probe, established conn with {peer} @ {address}"
+                    );
+                    Ok(Right(ConnMngrActions::ConnectionEstablished {
+                        peer: FreenetPeerId::from(peer),
+                        address,
+                    }))
+                }
+                autonat::Event::InboundProbe(autonat::InboundProbeEvent::Error {
+                    peer,
+                    error: autonat::InboundProbeError::Response(err),
+                    ..
+                }) => match err {
+                    autonat::ResponseError::DialError | autonat::ResponseError::DialRefused => {
+                        Ok(Right(ConnMngrActions::IsPrivatePeer(peer)))
+                    }
+                    _ => Ok(Right(ConnMngrActions::NoAction)),
+                },
+                autonat::Event::StatusChanged {
+                    new: autonat::NatStatus::Public(address),
+                    ..
+                } => {
+                    tracing::debug!("NAT status: public @ {address}");
+                    Ok(Right(ConnMngrActions::UpdatePublicAddr(address)))
+                }
+                autonat::Event::StatusChanged {
+                    new: autonat::NatStatus::Private,
+                    ..
+                } => {
+                    tracing::debug!("NAT status: private");
+                    Ok(Right(ConnMngrActions::ListenThroughRelays))
+                }
+                _ => Ok(Right(ConnMngrActions::NoAction)),
+            },
+            SwarmEvent::Behaviour(NetEvent::RelayClient(event)) => match *event {
+                relay::client::Event::ReservationReqAccepted { relay_peer_id, .. } => {
+                    tracing::debug!("Reserved a circuit slot at relay {relay_peer_id}");
+                    Ok(Right(ConnMngrActions::NoAction))
+                }
+                relay::client::Event::ReservationReqFailed {
+                    relay_peer_id,
+                    error,
+                    ..
+                } => {
+                    tracing::warn!(
+                        "Failed reserving a circuit slot at relay {relay_peer_id}: {error}"
+                    );
+                    Ok(Right(ConnMngrActions::RelayFailed(relay_peer_id)))
+                }
+                _ => Ok(Right(ConnMngrActions::NoAction)),
+            },
+            SwarmEvent::ListenerClosed { listener_id, .. } => {
+                Ok(Right(ConnMngrActions::ListenerClosed(listener_id)))
+            }
+            SwarmEvent::Behaviour(NetEvent::Dcutr(event)) => {
+                match *event {
+                    dcutr::Event::DirectConnectionUpgradeSucceeded { remote_peer_id } => {
+                        tracing::debug!("Hole punched a direct connection to {remote_peer_id}");
+                    }
+                    dcutr::Event::DirectConnectionUpgradeFailed {
+                        remote_peer_id,
+                        error,
+                    } => {
+                        tracing::debug!(
+                            "Failed hole punching to {remote_peer_id}, staying relayed: {error}"
+                        );
+                    }
+                    _ => {}
+                }
+                Ok(Right(ConnMngrActions::NoAction))
+            }
+            other_event => {
+                tracing::debug!("Received other swarm event: {:?}", other_event);
+                Ok(Right(ConnMngrActions::NoAction))
+            }
+        }
+    }
+
     #[tracing::instrument(name = "network_event_listener", skip_all)]
     pub async fn run_event_listener(
         mut self,
@@ -291,77 +571,10 @@ impl P2pConnManager {
         );
 
         loop {
-            let network_msg = self.swarm.select_next_some().map(|event| match event {
-                SwarmEvent::Behaviour(NetEvent::Freenet(msg)) => {
-                    tracing::debug!("Message inbound: {:?}", msg);
-                    Ok(Left(*msg))
-                }
-                SwarmEvent::ConnectionClosed { peer_id, .. } => {
-                    Ok(Right(ConnMngrActions::ConnectionClosed {
-                        peer: FreenetPeerId::from(peer_id),
-                    }))
-                }
-                SwarmEvent::Dialing { peer_id, .. } => {
-                    if let Some(peer_id) = peer_id {
-                        tracing::debug!("Attempting connection to {}", peer_id);
-                    }
-                    Ok(Right(ConnMngrActions::NoAction))
-                }
-                SwarmEvent::Behaviour(NetEvent::Identify(id)) => {
-                    if let identify::Event::Received { peer_id, info } = *id {
-                        if Self::is_compatible_peer(&info) {
-                            Ok(Right(ConnMngrActions::ConnectionEstablished {
-                                peer: FreenetPeerId::from(peer_id),
-                                address: info.observed_addr,
-                            }))
-                        } else {
-                            tracing::warn!("Incompatible peer: {}, disconnecting", peer_id);
-                            Ok(Right(ConnMngrActions::ConnectionClosed {
-                                peer: FreenetPeerId::from(peer_id),
-                            }))
-                        }
-                    } else {
-                        Ok(Right(ConnMngrActions::NoAction))
-                    }
-                }
-                SwarmEvent::Behaviour(NetEvent::Autonat(event)) => match event {
-                    autonat::Event::InboundProbe(autonat::InboundProbeEvent::Response {
-                        address,
-                        peer,
-                        ..
-                    }) => {
-                        tracing::debug!(
-                            "Successful autonat probe, established conn with {peer} @ {address}"
-                        );
-                        Ok(Right(ConnMngrActions::ConnectionEstablished {
-                            peer: FreenetPeerId::from(peer),
-                            address,
-                        }))
-                    }
-                    autonat::Event::InboundProbe(autonat::InboundProbeEvent::Error {
-                        peer,
-                        error: autonat::InboundProbeError::Response(err),
-                        ..
-                    }) => match err {
-                        autonat::ResponseError::DialError | autonat::ResponseError::DialRefused => {
-                            Ok(Right(ConnMngrActions::IsPrivatePeer(peer)))
-                        }
-                        _ => Ok(Right(ConnMngrActions::NoAction)),
-                    },
-                    autonat::Event::StatusChanged {
-                        new: autonat::NatStatus::Public(address),
-                        ..
-                    } => {
-                        tracing::debug!("NAT status: public @ {address}");
-                        Ok(Right(ConnMngrActions::UpdatePublicAddr(address)))
-                    }
-                    _ => Ok(Right(ConnMngrActions::NoAction)),
-                },
-                other_event => {
-                    tracing::debug!("Received other swarm event: {:?}", other_event);
-                    Ok(Right(ConnMngrActions::NoAction))
-                }
-            });
+            let network_msg = self
+                .swarm
+                .select_next_some()
+                .map(|event| Self::swarm_action(&op_manager, event));
 
             let notification_msg = notification_channel.0.recv().map(|m| match m {
                 None => Ok(Right(ClosedChannel)),
@@ -378,8 +591,13 @@ impl P2pConnManager {
                 None => Ok(Right(ClosedChannel)),
             });
 
+            let relays_retry = self.relays_retry;
+            let retry_relays =
+                tokio::time::sleep_until(relays_retry.unwrap_or_else(tokio::time::Instant::now));
+
             let msg: Result<_, ConnectionError> = tokio::select! {
                 msg = network_msg => { msg }
+                _ = retry_relays, if relays_retry.is_some() => { Ok(Right(ListenThroughRelays)) }
                 msg = notification_msg => { msg }
                 msg = bridge_msg => { msg }
                 msg = node_controller.recv() => {
@@ -493,11 +711,11 @@ impl P2pConnManager {
                     let _ = self.swarm.disconnect_peer_id(peer_id.0);
                     tracing::info!("Dropped connection with peer {}", peer_id);
                 }
-                Ok(Right(UpdatePublicAddr(address))) => {
-                    self.public_addr = Some(address);
-                }
-                Ok(Right(IsPrivatePeer(_peer))) => {
-                    todo!("this peer is private, attempt hole punching")
+                Ok(Right(
+                    action @ (UpdatePublicAddr(_) | IsPrivatePeer(_) | ListenThroughRelays
+                    | RelayFailed(_) | ListenerClosed(_)),
+                )) => {
+                    self.handle_network_action(action);
                 }
                 Ok(Right(ClosedChannel)) => {
                     tracing::info!("Notification channel closed");
@@ -530,7 +748,7 @@ impl P2pConnManager {
     }
 }
 
-enum ConnMngrActions {
+pub(in crate::node) enum ConnMngrActions {
     /// Received a new connection
     ConnectionEstablished {
         peer: FreenetPeerId,
@@ -549,6 +767,12 @@ enum ConnMngrActions {
     UpdatePublicAddr(Multiaddr),
     /// This is private, so when establishing connections hole-punching should be performed
     IsPrivatePeer(Libp2pPeerId),
+    /// This peer is private, so it must be reachable through the relays
+    ListenThroughRelays,
+    /// The relay refused or lost the reservation of this peer
+    RelayFailed(Libp2pPeerId),
+    /// Stopped listening for connections, e.g. relayed through a gateway
+    ListenerClosed(ListenerId),
     NodeAction(NodeEvent),
     ClosedChannel,
     NoAction,
@@ -561,11 +785,51 @@ pub(in crate::node) struct FreenetBehaviour {
     // FIFO queue for inbound messages
     inbound: VecDeque<Either<NetMessage, NodeEvent>>,
     routing_table: HashMap<Libp2pPeerId, HashSet<Multiaddr>>,
+    /// Addresses of the relays through which private peers are dialed.
+    relays: Vec<Multiaddr>,
+    /// Peers which can't be dialed directly.
+    private_peers: HashSet<Libp2pPeerId>,
+    /// Peers to dial through the relays right away, after failing to dial them directly.
+    redials: VecDeque<Libp2pPeerId>,
+    /// Wakes the swarm up to poll the behaviour once there are peers to redial.
+    waker: Option<Waker>,
     connected: HashMap<Libp2pPeerId, ConnectionId>,
     openning_connection: HashSet<Libp2pPeerId>,
     op_manager: Arc<OpManager>,
 }
 
+impl FreenetBehaviour {
+    /// Accounts the bytes of a message exchanged with a peer as bandwidth usage.
+    fn record_traffic(&self, peer: Libp2pPeerId, msg: &NetMessage, resource: ResourceType) {
+        let bytes = bincode::serialized_size(msg).unwrap_or_default() as usize;
+        self.op_manager
+            .ring
+            .record_traffic(&FreenetPeerId::from(peer), Some(msg), resource, bytes);
+    }
+
+    /// Addresses to dial a peer at; private peers are dialed through the relays first.
+    fn dial_addresses(&self, peer: Libp2pPeerId) -> Vec<Multiaddr> {
+        let direct = self.routing_table.get(&peer).into_iter().flatten().cloned();
+        let relayed = self.relays.iter().map(|relay| relayed_address(relay, peer));
+        if self.private_peers.contains(&peer) {
+            relayed.chain(direct).collect()
+        } else {
+            direct.collect()
+        }
+    }
+
+    /// Starts opening a connection with the peer, at the addresses it is reachable at.
+    fn dial(&mut self, peer_id: Libp2pPeerId) -> ToSwarm<NetMessage, HandlerEvent> {
+        let peer_opts = DialOpts::peer_id(peer_id)
+            .addresses(self.dial_addresses(peer_id))
+            .extend_addresses_through_behaviour();
+        self.openning_connection.insert(peer_id);
+        ToSwarm::Dial {
+            opts: peer_opts.build(),
+        }
+    }
+}
+
 impl NetworkBehaviour for FreenetBehaviour {
     type ConnectionHandler = Handler;
 
@@ -616,24 +880,86 @@ impl NetworkBehaviour for FreenetBehaviour {
                 self.outbound.push_front((peer_id, msg));
             }
             HandlerEvent::Inbound(msg) => {
+                if let Left(msg) = &msg {
+                    self.record_traffic(peer_id, msg, ResourceType::InboundBandwidthBytes);
+                    if !self
+                        .op_manager
+                        .receiving_message(&FreenetPeerId::from(peer_id), msg)
+                    {
+                        return;
+                    }
+                }
                 self.inbound.push_front(msg);
             }
         }
     }
 
     fn on_swarm_event(&mut self, event: libp2p::swarm::FromSwarm<Self::ConnectionHandler>) {
-        if let FromSwarm::ConnectionClosed(swarm::ConnectionClosed { peer_id, .. }) = event {
-            self.connected.remove(&peer_id);
+        match event {
+            FromSwarm::ConnectionEstablished(swarm::ConnectionEstablished {
+                peer_id,
+                endpoint,
+                ..
+            }) => {
+                if !endpoint.is_relayed() && self.private_peers.remove(&peer_id) {
+                    // reached directly (e.g. hole punched), the relays are not needed anymore
+                    tracing::debug!("Direct connection established with private peer {peer_id}");
+                }
+            }
+            FromSwarm::ConnectionClosed(swarm::ConnectionClosed {
+                peer_id,
+                connection_id,
+                remaining_established,
+                ..
+            }) => {
+                // a relayed connection may be closed once upgraded to a direct one
+                if self.connected.get(&peer_id) == Some(&connection_id) {
+                    self.connected.remove(&peer_id);
+                }
+                if remaining_established == 0 {
+                    self.private_peers.remove(&peer_id);
+                }
+            }
+            FromSwarm::DialFailure(swarm::DialFailure {
+                peer_id: Some(peer_id),
+                ..
+            }) => {
+                self.openning_connection.remove(&peer_id);
+                if self.relays.is_empty() || !self.private_peers.insert(peer_id) {
+                    // already tried through the relays, give up on the pending messages
+                    tracing::debug!("Failed dialing {peer_id}, dropping pending messages");
+                    self.outbound.retain(|(peer, _)| *peer != peer_id);
+                } else {
+                    tracing::debug!("Failed dialing {peer_id} directly, dialing through relays");
+                    self.redials.push_back(peer_id);
+                    if let Some(waker) = self.waker.take() {
+                        waker.wake();
+                    }
+                }
+            }
+            _ => {}
         }
     }
 
     fn poll(
         &mut self,
-        _: &mut std::task::Context<'_>,
+        cx: &mut std::task::Context<'_>,
         _: &mut impl libp2p::swarm::PollParameters,
     ) -> std::task::Poll<
         ToSwarm<Self::ToSwarm, <Self::ConnectionHandler as ConnectionHandler>::FromBehaviour>,
     > {
+        self.waker = Some(cx.waker().clone());
+        while let Some(peer_id) = self.redials.pop_front() {
+            // the messages may have been sent meanwhile through an other connection
+            let pending = self.outbound.iter().any(|(peer, _)| *peer == peer_id);
+            if pending
+                && !self.connected.contains_key(&peer_id)
+                && !self.openning_connection.contains(&peer_id)
+            {
+                return Poll::Ready(self.dial(peer_id));
+            }
+        }
+
         if let Some(Left(msg)) = self.inbound.pop_back() {
             let send_to_ev_listener = ToSwarm::GenerateEvent(msg);
             return Poll::Ready(send_to_ev_listener);
@@ -646,6 +972,9 @@ impl NetworkBehaviour for FreenetBehaviour {
             }
 
             if let Some(id) = self.connected.get(&peer_id) {
+                if let Left(msg) = &msg {
+                    self.record_traffic(peer_id, msg, ResourceType::OutboundBandwidthBytes);
+                }
                 let send_to_handler = ToSwarm::NotifyHandler {
                     peer_id,
                     handler: NotifyHandler::One(*id),
@@ -656,19 +985,15 @@ impl NetworkBehaviour for FreenetBehaviour {
                 // waiting to have an open connection
                 self.outbound.push_front((peer_id, msg));
                 Poll::Pending
-            } else if let Some(conn) = self.routing_table.get(&peer_id) {
-                // initiate a connection if one does not exist
-                // FIXME: we dial as listener to perform NAT hole-punching though the `override_role` method,
-                //        if this is required because the other peer
-                let peer_opts = DialOpts::peer_id(peer_id)
-                    .addresses(conn.iter().cloned().collect())
-                    .extend_addresses_through_behaviour();
-                let initiate_conn = ToSwarm::Dial {
-                    opts: peer_opts.build(),
-                };
+            } else if self.routing_table.contains_key(&peer_id) || !self.relays.is_empty() {
+                // initiate a connection if one does not exist; peers without a known address
+                // are dialed through the relays, relayed connections are upgraded to direct ones
+                // by the dcutr behaviour, which dials as listener to perform NAT hole-punching
+                if !self.routing_table.contains_key(&peer_id) {
+                    self.private_peers.insert(peer_id);
+                }
                 self.outbound.push_front((peer_id, msg));
-                self.openning_connection.insert(peer_id);
-                Poll::Ready(initiate_conn)
+                Poll::Ready(self.dial(peer_id))
             } else {
                 Poll::Pending
             }
@@ -1186,6 +1511,10 @@ fn decode_msg(buf: BytesMut) -> Result<NetMessage, ConnectionError> {
 /// - [Ping](https://docs.rs/libp2p/latest/libp2p/ping/index.html) `/ipfs/ping/1.0.0` protocol.
 /// - Freenet ring protocol, which handles the messages.
 /// - [AutoNAT](https://github.com/libp2p/specs/tree/master/autonat) libp2p protocol.
+/// - [Circuit Relay v2](https://github.com/libp2p/specs/blob/master/relay/circuit-v2.md) libp2p
+///   protocol, gateways act as relays.
+/// - [DCUtR](https://github.com/libp2p/specs/blob/master/relay/DCUtR.md) libp2p protocol, to
+///   upgrade relayed connections to direct ones.
 #[derive(libp2p::swarm::NetworkBehaviour)]
 #[behaviour(event_process = false)]
 #[behaviour(to_swarm = "NetEvent")]
@@ -1194,6 +1523,9 @@ pub(in crate::node) struct NetBehaviour {
     ping: ping::Behaviour,
     freenet: FreenetBehaviour,
     auto_nat: autonat::Behaviour,
+    relay: Toggle<relay::Behaviour>,
+    relay_client: relay::client::Behaviour,
+    dcutr: dcutr::Behaviour,
 }
 
 #[derive(Debug)]
@@ -1202,6 +1534,27 @@ pub(in crate::node) enum NetEvent {
     Identify(Box<identify::Event>),
     Ping(ping::Event),
     Autonat(autonat::Event),
+    Relay(Box<relay::Event>),
+    RelayClient(Box<relay::client::Event>),
+    Dcutr(Box<dcutr::Event>),
+}
+
+impl From<relay::Event> for NetEvent {
+    fn from(event: relay::Event) -> NetEvent {
+        Self::Relay(Box::new(event))
+    }
+}
+
+impl From<relay::client::Event> for NetEvent {
+    fn from(event: relay::client::Event) -> NetEvent {
+        Self::RelayClient(Box::new(event))
+    }
+}
+
+impl From<dcutr::Event> for NetEvent {
+    fn from(event: dcutr::Event) -> NetEvent {
+        Self::Dcutr(Box::new(event))
+    }
 }
 
 impl From<autonat::Event> for NetEvent {
@@ -1227,3 +1580,226 @@ impl From<NetMessage> for NetEvent {
         Self::Freenet(Box::new(event))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use tokio::sync::watch::channel;
+
+    use super::*;
+    use crate::{
+        client_events::test::MemoryEventsGen,
+        contract::MemoryContractHandler,
+        node::{p2p_impl::NodeP2P, testing_impl::get_free_port},
+        operations::connect::ConnectMsg,
+        ring::Location,
+        tracing::TestEventListener,
+    };
+
+    async fn build_peer(
+        key: Keypair,
+        config: NodeConfig,
+        name: &str,
+    ) -> Result<NodeP2P, anyhow::Error> {
+        let peer_id = FreenetPeerId::from(Libp2pPeerId::from(key.public()));
+        let (_, receiver) = channel((0, peer_id));
+        let user_events = MemoryEventsGen::new(receiver, peer_id);
+        NodeP2P::build::<MemoryContractHandler, 1, _>(
+            config,
+            key,
+            [Box::new(user_events)],
+            TestEventListener::new().await,
+            name.into(),
+        )
+        .await
+    }
+
+    fn free_port() -> Result<u16, anyhow::Error> {
+        get_free_port().map_err(|_| anyhow::anyhow!("no free port"))
+    }
+
+    /// Listens on a loopback address, announced as external so it is used for hole punching.
+    fn listen_on_loopback(peer: &mut NodeP2P) -> Result<(), anyhow::Error> {
+        let addr = multiaddr_from_connection((Ipv4Addr::LOCALHOST.into(), free_port()?));
+        peer.conn_manager.swarm.listen_on(addr.clone())?;
+        peer.conn_manager.swarm.add_external_address(addr);
+        Ok(())
+    }
+
+    /// Drives the swarm of the peer, handling the actions about reaching peers as the event
+    /// loop does, until an event matching the predicate is emitted.
+    async fn wait_for_event(
+        peer: &mut NodeP2P,
+        mut predicate: impl FnMut(&SwarmEvent<NetEvent, THandlerErr<NetBehaviour>>) -> bool,
+    ) -> Result<(), anyhow::Error> {
+        loop {
+            let relays_retry = peer.conn_manager.relays_retry;
+            let retry_relays =
+                tokio::time::sleep_until(relays_retry.unwrap_or_else(tokio::time::Instant::now));
+            let event = tokio::select! {
+                event = peer.conn_manager.swarm.select_next_some() => event,
+                _ = retry_relays, if relays_retry.is_some() => {
+                    peer.conn_manager
+                        .handle_network_action(ConnMngrActions::ListenThroughRelays);
+                    continue;
+                }
+                _ = tokio::time::sleep(Duration::from_secs(30)) => {
+                    anyhow::bail!("timed out waiting for the event")
+                }
+            };
+            if predicate(&event) {
+                return Ok(());
+            }
+            if let Ok(Right(action)) = P2pConnManager::swarm_action(&peer.op_manager, event) {
+                peer.conn_manager.handle_network_action(action);
+            }
+        }
+    }
+
+    fn reservation_accepted(event: &SwarmEvent<NetEvent, THandlerErr<NetBehaviour>>) -> bool {
+        matches!(
+            event,
+            SwarmEvent::Behaviour(NetEvent::RelayClient(event))
+                if matches!(**event, relay::client::Event::ReservationReqAccepted { .. })
+        )
+    }
+
+    /// A peer behind NAT listens through the relay of the gateway, the peer dialing it goes
+    /// through the relay too and the relayed connection is upgraded to a direct one by
+    /// hole punching. Peers only listen on loopback, the private peer address is only
+    /// learnt by the dialer while hole punching. Peers failing to dial it directly fall back
+    /// to the relay.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn relayed_connection() -> Result<(), anyhow::Error> {
+        let gw_port = free_port()?;
+        let gw_key = Keypair::generate_ed25519();
+        let gw_id: Libp2pPeerId = gw_key.public().into();
+        let gw_config = InitPeerNode::new(gw_id, Location::random())
+            .listening_ip(Ipv4Addr::LOCALHOST)
+            .listening_port(gw_port);
+
+        // the gateway acts as relay
+        let mut config = NodeConfig::new();
+        config
+            .with_ip(Ipv4Addr::LOCALHOST)
+            .with_port(gw_port)
+            .with_location(Location::random())
+            .with_key(gw_key.public().into());
+        let mut gateway = build_peer(gw_key, config, "relay-gateway").await?;
+        gateway.conn_manager.listen_on()?;
+        GlobalExecutor::spawn(async move { wait_for_event(&mut gateway, |_| false).await });
+
+        // the private peer learns it is behind NAT and listens through the relay
+        let private_key = Keypair::generate_ed25519();
+        let private_id: Libp2pPeerId = private_key.public().into();
+        let mut config = NodeConfig::new();
+        config
+            .add_gateway(gw_config.clone())
+            .with_key(private_key.public().into());
+        let mut private_peer = build_peer(private_key, config, "relay-private").await?;
+        listen_on_loopback(&mut private_peer)?;
+        private_peer
+            .conn_manager
+            .handle_network_action(ConnMngrActions::ListenThroughRelays);
+        assert!(private_peer.conn_manager.listening_through_relays);
+        wait_for_event(&mut private_peer, reservation_accepted).await?;
+
+        // once the reservation fails, the relay is listened through again
+        private_peer
+            .conn_manager
+            .handle_network_action(ConnMngrActions::RelayFailed(gw_id));
+        assert!(!private_peer.conn_manager.listening_through_relays);
+        assert!(private_peer.conn_manager.relays_retry.is_some());
+        wait_for_event(&mut private_peer, reservation_accepted).await?;
+        assert!(private_peer.conn_manager.listening_through_relays);
+        assert!(private_peer.conn_manager.relays_retry.is_none());
+        GlobalExecutor::spawn(async move { wait_for_event(&mut private_peer, |_| false).await });
+
+        // the dialer learns the peer is private, so it dials it through the relay first
+        let dialer_key = Keypair::generate_ed25519();
+        let mut config = NodeConfig::new();
+        config
+            .add_gateway(gw_config.clone())
+            .with_key(dialer_key.public().into());
+        let mut dialer = build_peer(dialer_key, config, "relay-dialer").await?;
+        listen_on_loopback(&mut dialer)?;
+        dialer
+            .conn_manager
+            .handle_network_action(ConnMngrActions::IsPrivatePeer(private_id));
+        let relay = gw_config
+            .addr
+            .clone()
+            .expect("gateway address")
+            .with(Protocol::P2p(gw_id));
+        let freenet = &mut dialer.conn_manager.swarm.behaviour_mut().freenet;
+        assert_eq!(
+            freenet.dial_addresses(private_id).first(),
+            Some(&relayed_address(&relay, private_id))
+        );
+        freenet.outbound.push_front((
+            private_id,
+            Left(NetMessage::Aborted(Transaction::new::<ConnectMsg>())),
+        ));
+        wait_for_event(&mut dialer, |event| {
+            matches!(
+                event,
+                SwarmEvent::Behaviour(NetEvent::Dcutr(event))
+                    if matches!(
+                        **event,
+                        dcutr::Event::DirectConnectionUpgradeSucceeded { remote_peer_id }
+                            if remote_peer_id == private_id
+                    )
+            )
+        })
+        .await?;
+        let freenet = &dialer.conn_manager.swarm.behaviour().freenet;
+        assert!(!freenet.private_peers.contains(&private_id));
+
+        // peers are not considered private anymore once disconnected
+        dialer
+            .conn_manager
+            .handle_network_action(ConnMngrActions::IsPrivatePeer(private_id));
+        let _ = dialer.conn_manager.swarm.disconnect_peer_id(private_id);
+        wait_for_event(&mut dialer, |event| {
+            matches!(
+                event,
+                SwarmEvent::ConnectionClosed { peer_id, num_established: 0, .. }
+                    if *peer_id == private_id
+            )
+        })
+        .await?;
+        let freenet = &dialer.conn_manager.swarm.behaviour().freenet;
+        assert!(!freenet.private_peers.contains(&private_id));
+
+        // a peer which can't be dialed directly is redialed through the relay right away
+        let redialer_key = Keypair::generate_ed25519();
+        let mut config = NodeConfig::new();
+        config
+            .add_gateway(gw_config)
+            .with_key(redialer_key.public().into());
+        let mut redialer = build_peer(redialer_key, config, "relay-redialer").await?;
+        listen_on_loopback(&mut redialer)?;
+        let unreachable = multiaddr_from_connection((Ipv4Addr::LOCALHOST.into(), free_port()?));
+        let freenet = &mut redialer.conn_manager.swarm.behaviour_mut().freenet;
+        freenet
+            .routing_table
+            .insert(private_id, [unreachable].into());
+        freenet.outbound.push_front((
+            private_id,
+            Left(NetMessage::Aborted(Transaction::new::<ConnectMsg>())),
+        ));
+        wait_for_event(&mut redialer, |event| {
+            matches!(
+                event,
+                SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. }
+                    if *peer_id == private_id && endpoint.is_relayed()
+            )
+        })
+        .await?;
+        let freenet = &redialer.conn_manager.swarm.behaviour().freenet;
+        assert!(freenet.private_peers.contains(&private_id));
+        assert!(freenet.redials.is_empty());
+        Ok(())
+    }
+}
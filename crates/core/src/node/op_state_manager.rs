@@ -2,6 +2,7 @@ use std::{cmp::Reverse, collections::BTreeSet, sync::Arc, time::Duration};
 
 use dashmap::{DashMap, DashSet};
 use either::Either;
+use freenet_stdlib::{client_api::DelegateRequest, prelude::DelegateKey};
 use tracing::Instrument;
 
 use crate::{
@@ -12,7 +13,8 @@ use crate::{
         connect::ConnectOp, get::GetOp, put::PutOp, subscribe::SubscribeOp, update::UpdateOp,
         OpEnum, OpError,
     },
-    ring::{LiveTransactionTracker, Ring},
+    ring::{Misbehaviour, Ring},
+    tracing::MessageCapture,
 };
 
 use super::{network_bridge::EventLoopNotificationsSender, NetEventRegister, NodeConfig, PeerId};
@@ -53,6 +55,8 @@ pub(crate) struct OpManager {
     to_event_listener: EventLoopNotificationsSender,
     pub ch_outbound: ContractHandlerChannel<SenderHalve>,
     new_transactions: tokio::sync::mpsc::Sender<Transaction>,
+    /// Set when the traffic of the node is being captured.
+    pub capture: Option<MessageCapture>,
 }
 
 impl OpManager {
@@ -62,10 +66,16 @@ impl OpManager {
         config: &NodeConfig,
         event_register: ER,
     ) -> Result<Self, anyhow::Error> {
+        let capture = config
+            .capture_file
+            .as_deref()
+            .map(MessageCapture::new)
+            .transpose()?;
         let ring = Ring::new(
             config,
             notification_channel.clone(),
             event_register.clone(),
+            capture.clone(),
             config.is_gateway(),
         )?;
         let ops = Arc::new(Ops::default());
@@ -78,13 +88,8 @@ impl OpManager {
             tracing::info_span!(parent: current_span, "garbage_cleanup_task")
         };
         GlobalExecutor::spawn(
-            garbage_cleanup_task(
-                rx,
-                ops.clone(),
-                ring.live_tx_tracker.clone(),
-                event_register,
-            )
-            .instrument(garbage_span),
+            garbage_cleanup_task(rx, ops.clone(), ring.clone(), event_register)
+                .instrument(garbage_span),
         );
 
         Ok(Self {
@@ -93,6 +98,7 @@ impl OpManager {
             to_event_listener: notification_channel,
             ch_outbound,
             new_transactions,
+            capture,
         })
     }
 
@@ -104,6 +110,9 @@ impl OpManager {
     pub async fn notify_op_change(&self, msg: NetMessage, op: OpEnum) -> Result<(), OpError> {
         // push back the state to the stack
         self.push(*msg.id(), op).await?;
+        if let Some(capture) = &self.capture {
+            capture.notified(*msg.id());
+        }
         self.to_event_listener
             .send(Either::Left(msg))
             .await
@@ -111,11 +120,33 @@ impl OpManager {
     }
 
     /// Send an event to the contract handler and await a response event from it if successful.
+    ///
+    /// The resources spent running a delegate are accounted to it.
     pub async fn notify_contract_handler(
         &self,
         msg: ContractHandlerEvent,
     ) -> Result<ContractHandlerEvent, ContractError> {
-        self.ch_outbound.send_to_handler(msg).await
+        let delegate = match &msg {
+            ContractHandlerEvent::DelegateRequest { req, .. } => requested_delegate(req),
+            _ => None,
+        };
+        let (response, usage) = self.ch_outbound.send_to_handler(msg).await?;
+        if let Some(delegate) = delegate {
+            self.ring.record_delegate_execution(delegate, usage);
+        }
+        Ok(response)
+    }
+
+    /// Like [`Self::notify_contract_handler`], but the resources spent handling the event are
+    /// accounted to the peer that requested it.
+    pub async fn notify_contract_handler_for(
+        &self,
+        requester: &PeerId,
+        msg: ContractHandlerEvent,
+    ) -> Result<ContractHandlerEvent, ContractError> {
+        let (response, usage) = self.ch_outbound.send_to_handler(msg).await?;
+        self.ring.record_execution(requester, usage);
+        Ok(response)
     }
 
     pub async fn push(&self, id: Transaction, op: OpEnum) -> Result<(), OpError> {
@@ -198,6 +229,25 @@ impl OpManager {
         self.ops.completed.insert(id);
     }
 
+    /// Notify the operation manager that a message was received from `origin` over the network.
+    ///
+    /// Returns false if the message is malformed, in which case it must be dropped; `origin`
+    /// is then reported for a protocol violation since no honest peer would send it.
+    pub fn receiving_message(&self, origin: &PeerId, msg: &NetMessage) -> bool {
+        if !msg.is_well_formed() {
+            tracing::warn!(%origin, "Dropping malformed message");
+            let ring = self.ring.clone();
+            let origin = *origin;
+            GlobalExecutor::spawn(async move {
+                ring.report_misbehaviour(&origin, None, Misbehaviour::ProtocolViolation)
+                    .await
+            });
+            return false;
+        }
+        self.ring.live_tx_tracker.replied(origin, msg.id());
+        true
+    }
+
     /// Notify the operation manager that a transaction is being transacted over the network.
     pub fn sending_transaction(&self, peer: &PeerId, msg: &NetMessage) {
         let transaction = msg.id();
@@ -211,10 +261,21 @@ impl OpManager {
     }
 }
 
+/// The delegate a request runs, if any.
+fn requested_delegate(req: &DelegateRequest<'_>) -> Option<DelegateKey> {
+    match req {
+        DelegateRequest::ApplicationMessages { key, .. }
+        | DelegateRequest::GetSecretRequest { key, .. }
+        | DelegateRequest::UnregisterDelegate(key) => Some(key.clone()),
+        DelegateRequest::RegisterDelegate { delegate, .. } => Some(delegate.key().clone()),
+        _ => None,
+    }
+}
+
 async fn garbage_cleanup_task<ER: NetEventRegister>(
     mut new_transactions: tokio::sync::mpsc::Receiver<Transaction>,
     ops: Arc<Ops>,
-    live_tx_tracker: LiveTransactionTracker,
+    ring: Arc<Ring>,
     mut event_register: ER,
 ) {
     const CLEANUP_INTERVAL: Duration = Duration::from_secs(5);
@@ -223,8 +284,10 @@ async fn garbage_cleanup_task<ER: NetEventRegister>(
 
     let mut ttl_set = BTreeSet::new();
 
+    let live_tx_tracker = ring.live_tx_tracker.clone();
     let mut remove_old = move |ttl_set: &mut BTreeSet<Reverse<Transaction>>,
-                               delayed: &mut Vec<Transaction>| {
+                               delayed: &mut Vec<Transaction>,
+                               timed_out_peers: &mut Vec<PeerId>| {
         let mut old_missing = std::mem::replace(delayed, Vec::with_capacity(200));
         for tx in old_missing.drain(..) {
             if let Some(tx) = ops.completed.remove(&tx) {
@@ -246,11 +309,12 @@ async fn garbage_cleanup_task<ER: NetEventRegister>(
             if still_waiting && !timed_out {
                 delayed.push(tx);
             } else {
+                let awaited = live_tx_tracker.remove_finished_transaction(tx);
                 if still_waiting && timed_out {
                     ops.under_progress.remove(&tx);
                     ops.completed.remove(&tx);
+                    timed_out_peers.extend(awaited);
                 }
-                live_tx_tracker.remove_finished_transaction(tx);
             }
         }
 
@@ -283,6 +347,7 @@ async fn garbage_cleanup_task<ER: NetEventRegister>(
     };
 
     let mut delayed = vec![];
+    let mut timed_out_peers = vec![];
     loop {
         tokio::select! {
             tx = new_transactions.recv() => {
@@ -291,7 +356,10 @@ async fn garbage_cleanup_task<ER: NetEventRegister>(
                 }
             }
             _ = tick.tick() => {
-                remove_old(&mut ttl_set, &mut delayed);
+                remove_old(&mut ttl_set, &mut delayed, &mut timed_out_peers);
+                for peer in timed_out_peers.drain(..) {
+                    ring.report_misbehaviour(&peer, None, Misbehaviour::Timeout).await;
+                }
             }
         }
     }
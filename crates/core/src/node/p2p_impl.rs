@@ -7,7 +7,7 @@ use libp2p::{
     },
     dns,
     identity::Keypair,
-    noise, tcp, yamux, PeerId as Libp2pPeerId, Transport,
+    noise, relay, tcp, yamux, PeerId as Libp2pPeerId, Transport,
 };
 use tracing::Instrument;
 
@@ -101,9 +101,10 @@ impl NodeP2P {
             .map_err(|e| anyhow::anyhow!(e))?;
 
         let conn_manager = {
-            let transport = Self::config_transport(&private_key)?;
+            let (transport, relay_client) = Self::config_transport(&private_key)?;
             P2pConnManager::build(
                 transport,
+                relay_client,
                 &config,
                 op_manager.clone(),
                 event_register,
@@ -123,6 +124,7 @@ impl NodeP2P {
                 op_manager.clone(),
                 clients,
                 client_responses,
+                cli_response_sender.clone(),
                 node_controller_tx,
             )
             .instrument(tracing::info_span!(parent: parent_span, "client_event_handling")),
@@ -144,22 +146,31 @@ impl NodeP2P {
     /// Capabilities built into the transport by default:
     ///
     /// - TCP/IP handling over Tokio streams.
+    /// - Connections relayed through other peers (circuit relay v2), for peers behind NAT.
     /// - DNS when dialing peers.
     /// - Authentication and encryption via [Noise](https://github.com/libp2p/specs/tree/master/noise) protocol.
     /// - Compression using Deflate.
     /// - Multiplexing using [Yamux](https://github.com/hashicorp/yamux/blob/master/spec.md).
+    ///
+    /// Returns the relay client behaviour paired with the relayed transport too.
     fn config_transport(
         local_key: &Keypair,
-    ) -> std::io::Result<transport::Boxed<(Libp2pPeerId, muxing::StreamMuxerBox)>> {
+    ) -> std::io::Result<(
+        transport::Boxed<(Libp2pPeerId, muxing::StreamMuxerBox)>,
+        relay::client::Behaviour,
+    )> {
+        let (relay_transport, relay_client) = relay::client::new(local_key.public().to_peer_id());
         let tcp = tcp::tokio::Transport::new(tcp::Config::new().nodelay(true).port_reuse(true));
         let with_dns = dns::tokio::Transport::system(tcp)?;
-        Ok(with_dns
+        let transport = relay_transport
+            .or_transport(with_dns)
             .upgrade(upgrade::Version::V1)
             .authenticate(noise::Config::new(local_key).unwrap())
             .multiplex(yamux::Config::default())
             .timeout(config::PEER_TIMEOUT)
             .map(|(peer, muxer), _| (peer, muxing::StreamMuxerBox::new(muxer)))
-            .boxed())
+            .boxed();
+        Ok((transport, relay_client))
     }
 }
 
@@ -2,11 +2,13 @@ use std::{
     collections::{HashMap, HashSet},
     fmt::Write,
     net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener},
+    path::Path,
     pin::Pin,
     sync::Arc,
     time::{Duration, Instant},
 };
 
+use chrono::{DateTime, Utc};
 use either::Either;
 use freenet_stdlib::prelude::*;
 use futures::{future::BoxFuture, Future};
@@ -29,18 +31,23 @@ use crate::{
     node::{InitPeerNode, NetEventRegister, NodeConfig},
     operations::connect,
     ring::{Distance, Location, PeerKeyLocation},
-    tracing::TestEventListener,
+    tracing::{Property, TestEventListener},
 };
 
 mod in_memory;
 mod inter_process;
+mod replay;
 
 pub use self::inter_process::SimPeer;
+pub use self::replay::{replay, Divergence, ReplayReport};
 
 use super::{
-    network_bridge::EventLoopNotificationsReceiver, ConnectionError, NetworkBridge, PeerId,
+    network_bridge::{in_memory::SimulatedWire, EventLoopNotificationsReceiver},
+    ConnectionError, NetworkBridge, PeerId,
 };
 
+pub use super::network_bridge::in_memory::LinkConditions;
+
 pub fn get_free_port() -> Result<u16, ()> {
     let mut port;
     for _ in 0..100 {
@@ -281,7 +288,7 @@ pub(super) struct Builder<ER> {
     pub(super) peer_key: PeerId,
     config: NodeConfig,
     contract_handler_name: String,
-    add_noise: bool,
+    wire: SimulatedWire,
     event_register: ER,
     contracts: Vec<(ContractContainer, WrappedState, bool)>,
     contract_subscribers: HashMap<ContractKey, Vec<PeerKeyLocation>>,
@@ -293,14 +300,14 @@ impl<ER: NetEventRegister> Builder<ER> {
         builder: NodeConfig,
         event_register: ER,
         contract_handler_name: String,
-        add_noise: bool,
+        wire: SimulatedWire,
     ) -> Builder<ER> {
         let peer_key = builder.peer_id;
         Builder {
             peer_key,
             config: builder,
             contract_handler_name,
-            add_noise,
+            wire,
             event_register,
             contracts: Vec::new(),
             contract_subscribers: HashMap::new(),
@@ -308,6 +315,28 @@ impl<ER: NetEventRegister> Builder<ER> {
     }
 }
 
+/// A fault injected in a simulated network.
+#[derive(Clone, Debug)]
+pub enum Fault {
+    /// Splits the network so peers can only reach the ones in the same group,
+    /// the peers not listed in any group form one more group.
+    Partition(Vec<Vec<NodeLabel>>),
+    /// Joins all the partitions back and stops any flood.
+    Heal,
+    /// Stops a peer, the messages in transit to it are lost.
+    Crash(NodeLabel),
+    /// Changes the conditions of all the links without specific conditions.
+    Conditions(LinkConditions),
+    /// Changes the conditions of the link between two peers.
+    Link(NodeLabel, NodeLabel, LinkConditions),
+    /// Makes the first peer send junk to the second one at the given bytes per second,
+    /// like a misbehaving peer hogging its bandwidth.
+    Flood(NodeLabel, NodeLabel, u64),
+    /// Makes the first peer send the given number of malformed messages to the second one,
+    /// like a faulty or malicious peer.
+    Malformed(NodeLabel, NodeLabel, usize),
+}
+
 /// A simulated in-memory network topology.
 pub struct SimNetwork {
     name: String,
@@ -325,10 +354,14 @@ pub struct SimNetwork {
     max_connections: usize,
     min_connections: usize,
     start_backoff: Duration,
-    add_noise: bool,
+    wire: SimulatedWire,
+    fault_schedule: Vec<(Duration, Fault)>,
+    delayed_starts: HashMap<NodeLabel, Duration>,
 }
 
 impl SimNetwork {
+    const DEFAULT_SEED: u64 = 0xdeadbeef;
+
     pub async fn new(
         name: &str,
         gateways: usize,
@@ -357,7 +390,9 @@ impl SimNetwork {
             max_connections,
             min_connections,
             start_backoff: Duration::from_millis(1),
-            add_noise: false,
+            wire: SimulatedWire::new(Self::DEFAULT_SEED),
+            fault_schedule: Vec::new(),
+            delayed_starts: HashMap::new(),
         };
         net.config_gateways(gateways).await;
         net.config_nodes(nodes).await;
@@ -373,7 +408,66 @@ impl SimNetwork {
     /// Simulates network random behaviour, like messages arriving delayed or out of order, throttling etc.
     #[allow(unused)]
     pub fn with_noise(&mut self) {
-        self.add_noise = true;
+        self.wire.set_conditions(LinkConditions::noisy());
+    }
+
+    /// Sets the seed deciding the fate of the messages sent through the network.
+    pub fn with_seed(&mut self, seed: u64) {
+        self.wire.set_seed(seed);
+    }
+
+    /// Sets the conditions of all the links in the network.
+    pub fn with_link_conditions(&mut self, conditions: LinkConditions) {
+        self.wire.set_conditions(conditions);
+    }
+
+    /// Captures all the traffic of every peer into `dir`, one `<label>.capture` file per peer,
+    /// so it can be replayed later on with [`replay`].
+    pub fn capture_messages(&mut self, dir: &Path) {
+        for (builder, config) in &mut self.gateways {
+            builder
+                .config
+                .capture_messages(dir.join(format!("{}.capture", config.label)));
+        }
+        for (builder, label) in &mut self.nodes {
+            builder
+                .config
+                .capture_messages(dir.join(format!("{label}.capture")));
+        }
+    }
+
+    /// Injects `fault` once `after` has elapsed since the network started.
+    pub fn schedule_fault(&mut self, after: Duration, fault: Fault) {
+        self.fault_schedule.push((after, fault));
+    }
+
+    /// Holds the peer back until `after` has elapsed since the network started, so it joins
+    /// an already running network.
+    pub fn delay_start(&mut self, label: NodeLabel, after: Duration) {
+        self.delayed_starts.insert(label, after);
+    }
+
+    /// Injects `fault` right away.
+    pub fn inject_fault(&self, fault: Fault) {
+        tracing::info!(?fault, "Injecting fault");
+        self.wire.inject(fault);
+    }
+
+    fn start_fault_schedule(&mut self) {
+        let mut schedule = std::mem::take(&mut self.fault_schedule);
+        if schedule.is_empty() {
+            return;
+        }
+        schedule.sort_by_key(|(after, _)| *after);
+        let wire = self.wire.clone();
+        let started = tokio::time::Instant::now();
+        GlobalExecutor::spawn(async move {
+            for (after, fault) in schedule {
+                tokio::time::sleep_until(started + after).await;
+                tracing::info!(?fault, "Injecting fault");
+                wire.inject(fault);
+            }
+        });
     }
 
     #[allow(unused)]
@@ -404,6 +498,7 @@ impl SimNetwork {
 
             self.event_listener
                 .add_node(label.clone(), PeerId::from(id));
+            self.wire.register(PeerId::from(id), label.clone());
             configs.push((
                 config,
                 GatewayConfig {
@@ -447,7 +542,7 @@ impl SimNetwork {
                 this_node,
                 event_listener,
                 format!("{}-{label}", self.name, label = this_config.label),
-                self.add_noise,
+                self.wire.clone(),
             );
             self.gateways.push((gateway, this_config));
         }
@@ -486,6 +581,7 @@ impl SimNetwork {
 
             let peer = PeerId::from(id);
             self.event_listener.add_node(label.clone(), peer);
+            self.wire.register(peer, label.clone());
 
             let event_listener = {
                 #[cfg(feature = "trace-ot")]
@@ -505,12 +601,26 @@ impl SimNetwork {
                 config,
                 event_listener,
                 format!("{}-{label}", self.name),
-                self.add_noise,
+                self.wire.clone(),
             );
             self.nodes.push((node, label));
         }
     }
 
+    /// Configuration of every peer not started yet.
+    #[cfg(test)]
+    pub(crate) fn node_configs(&self) -> impl Iterator<Item = (NodeLabel, NodeConfig)> + '_ {
+        let gateways = self
+            .gateways
+            .iter()
+            .map(|(builder, config)| (config.label.clone(), builder.config.clone()));
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|(builder, label)| (label.clone(), builder.config.clone()));
+        gateways.chain(nodes)
+    }
+
     #[cfg(test)]
     pub(crate) async fn start(&mut self) {
         self.start_with_spec(HashMap::new()).await
@@ -521,6 +631,8 @@ impl SimNetwork {
         &mut self,
         mut specs: HashMap<NodeLabel, NodeSpecification>,
     ) {
+        let started = tokio::time::Instant::now();
+        self.start_fault_schedule();
         let gw = self.gateways.drain(..).map(|(n, c)| (n, c.label));
         for (mut node, label) in gw.chain(self.nodes.drain(..)).collect::<Vec<_>>() {
             tracing::debug!(peer = %label, "initializing");
@@ -537,12 +649,22 @@ impl SimNetwork {
             if let Some(specs) = node_spec {
                 node.append_contracts(specs.owned_contracts, specs.contract_subscribers);
             }
-            self.labels.push((label, node.peer_key));
+            let peer = node.peer_key;
+            let delay = self.delayed_starts.remove(&label);
+            self.labels.push((label, peer));
 
-            let node_task = async move { node.run_node(user_events, span).await };
-            GlobalExecutor::spawn(node_task);
+            let node_task = async move {
+                if let Some(delay) = delay {
+                    tokio::time::sleep_until(started + delay).await;
+                }
+                node.run_node(user_events, span).await
+            };
+            let handle = GlobalExecutor::spawn(node_task);
+            self.wire.register_task(peer, handle.abort_handle());
 
-            tokio::time::sleep(self.start_backoff).await;
+            if delay.is_none() {
+                tokio::time::sleep(self.start_backoff).await;
+            }
         }
         self.labels.sort_by(|(a, _), (b, _)| a.cmp(b));
     }
@@ -556,6 +678,9 @@ impl SimNetwork {
     where
         R: RandomEventGenerator + Send + 'static,
     {
+        self.wire.set_seed(seed);
+        let started = tokio::time::Instant::now();
+        self.start_fault_schedule();
         let total_peer_num = self.gateways.len() + self.nodes.len();
         let gw = self.gateways.drain(..).map(|(n, c)| (n, c.label));
         let mut peers = vec![];
@@ -569,13 +694,23 @@ impl SimNetwork {
             } else {
                 tracing::info_span!("in_mem_node", %node.peer_key)
             };
-            self.labels.push((label, node.peer_key));
+            let peer = node.peer_key;
+            let delay = self.delayed_starts.remove(&label);
+            self.labels.push((label, peer));
 
-            let node_task = async move { node.run_node(user_events, span).await };
+            let node_task = async move {
+                if let Some(delay) = delay {
+                    tokio::time::sleep_until(started + delay).await;
+                }
+                node.run_node(user_events, span).await
+            };
             let handle = GlobalExecutor::spawn(node_task);
+            self.wire.register_task(peer, handle.abort_handle());
             peers.push(handle);
 
-            tokio::time::sleep(self.start_backoff).await;
+            if delay.is_none() {
+                tokio::time::sleep(self.start_backoff).await;
+            }
         }
         self.labels.sort_by(|(a, _), (b, _)| a.cmp(b));
         peers
@@ -627,6 +762,32 @@ impl SimNetwork {
         self.event_listener.is_connected(&self.labels[pos].1)
     }
 
+    /// Whether `peer` dropped its connection to `from` at some point.
+    pub fn disconnected(&self, peer: &NodeLabel, from: &NodeLabel) -> bool {
+        let peer_id = |label: &NodeLabel| {
+            let pos = self
+                .labels
+                .binary_search_by(|(other, _)| other.cmp(label))
+                .expect("peer not found");
+            self.labels[pos].1
+        };
+        self.event_listener
+            .has_disconnected(&peer_id(peer), &peer_id(from))
+    }
+
+    /// Whether `peer` blacklisted `offender` at some point because of its misbehaviour.
+    pub fn blacklisted(&self, peer: &NodeLabel, offender: &NodeLabel) -> bool {
+        let peer_id = |label: &NodeLabel| {
+            let pos = self
+                .labels
+                .binary_search_by(|(other, _)| other.cmp(label))
+                .expect("peer not found");
+            self.labels[pos].1
+        };
+        self.event_listener
+            .has_blacklisted(&peer_id(peer), &peer_id(offender))
+    }
+
     pub fn has_put_contract(&self, peer: impl Into<NodeLabel>, key: &ContractKey) -> bool {
         let peer = peer.into();
         let pos = self
@@ -637,6 +798,16 @@ impl SimNetwork {
             .has_put_contract(&self.labels[pos].1, key)
     }
 
+    pub fn has_updated_contract(&self, peer: impl Into<NodeLabel>, key: &ContractKey) -> bool {
+        let peer = peer.into();
+        let pos = self
+            .labels
+            .binary_search_by(|(label, _)| label.cmp(&peer))
+            .expect("peer not found");
+        self.event_listener
+            .has_updated_contract(&self.labels[pos].1, key)
+    }
+
     pub fn has_got_contract(&self, peer: impl Into<NodeLabel>, key: &ContractKey) -> bool {
         let peer = peer.into();
         let pos = self
@@ -738,6 +909,16 @@ impl SimNetwork {
         EventChain::new(labels, user_ev_controller, total_events, debug_val)
     }
 
+    /// Starts an event chain for this simulation while keeping the network around, so it can
+    /// still be inspected while the events are generated.
+    pub fn event_chain_by_ref(&mut self, total_events: u32) -> EventChain {
+        let user_ev_controller = self
+            .user_ev_controller
+            .take()
+            .expect("controller should be set");
+        EventChain::new(self.labels.clone(), user_ev_controller, total_events, false)
+    }
+
     /// Checks that all peers in the network have acquired at least one connection to any
     /// other peers.
     pub fn check_connectivity(&self, time_out: Duration) -> Result<(), anyhow::Error> {
@@ -857,6 +1038,115 @@ impl SimNetwork {
         }
         Ok(())
     }
+
+    /// Checks that every contract put in the network before `since` is retrieved by some
+    /// peer after it.
+    pub async fn check_contract_availability(
+        &self,
+        since: DateTime<Utc>,
+        time_out: Duration,
+    ) -> Result<(), anyhow::Error> {
+        let elapsed = Instant::now();
+        loop {
+            let put = self.event_listener.contracts_put(..since).await;
+            if put.is_empty() {
+                anyhow::bail!("no contracts were put before {since}, nothing to check");
+            }
+            let got = self.event_listener.contracts_got(since..).await;
+            let missing: Vec<_> = put.difference(&got).collect();
+            if missing.is_empty() {
+                tracing::info!("All {} contracts put are available", put.len());
+                return Ok(());
+            }
+            if elapsed.elapsed() >= time_out {
+                tracing::error!("Contracts not retrieved: {missing:?}");
+                anyhow::bail!("{} contracts are not available", missing.len());
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Checks that every contract with subscribers before `since` gets subscribed to again
+    /// after it.
+    pub async fn check_subscriptions(
+        &self,
+        since: DateTime<Utc>,
+        time_out: Duration,
+    ) -> Result<(), anyhow::Error> {
+        let elapsed = Instant::now();
+        loop {
+            let before = self.event_listener.contracts_subscribed(..since).await;
+            if before.is_empty() {
+                anyhow::bail!("no contracts were subscribed to before {since}, nothing to check");
+            }
+            let after = self.event_listener.contracts_subscribed(since..).await;
+            let missing: Vec<_> = before.difference(&after).collect();
+            if missing.is_empty() {
+                tracing::info!("All {} subscriptions re-established", before.len());
+                return Ok(());
+            }
+            if elapsed.elapsed() >= time_out {
+                tracing::error!("Contracts without subscriptions: {missing:?}");
+                anyhow::bail!("{} subscriptions were not re-established", missing.len());
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Checks that the peers are spread over the ring, so no gap between the locations of two
+    /// consecutive peers is larger than `max_gap`. Peers in `excluded` are not taken into account.
+    pub async fn check_ring_distribution(
+        &self,
+        max_gap: f64,
+        excluded: &HashSet<NodeLabel>,
+    ) -> Result<(), anyhow::Error> {
+        let mut locations = Vec::with_capacity(self.labels.len());
+        for (label, peer) in &self.labels {
+            if excluded.contains(label) {
+                continue;
+            }
+            if let Some(location) = self.event_listener.location(*peer).await {
+                locations.push(location.as_f64());
+            }
+        }
+        if locations.len() < 2 {
+            anyhow::bail!("not enough peers with a location in the ring");
+        }
+        locations.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let wrap_around = 1.0 - locations[locations.len() - 1] + locations[0];
+        let widest = locations
+            .windows(2)
+            .map(|pair| pair[1] - pair[0])
+            .fold(wrap_around, f64::max);
+        tracing::info!("Widest gap between peers in the ring: {widest:.3}");
+        if widest > max_gap {
+            anyhow::bail!("gap of {widest:.3} between peers in the ring (> {max_gap})");
+        }
+        Ok(())
+    }
+
+    /// Registers the events recorded by a peer running in another process, serialized as
+    /// [`PeerOutputMsg::Events`](crate::node::PeerOutputMsg::Events).
+    pub async fn register_forwarded_events(&self, events: &[u8]) -> Result<(), anyhow::Error> {
+        self.event_listener.register_serialized(events).await?;
+        Ok(())
+    }
+
+    /// Checks the properties over all the events recorded in the network, logging a trace
+    /// of the events leading to each violation found.
+    pub async fn check_properties(&self, properties: &[Property]) -> Result<(), anyhow::Error> {
+        let mut violations = 0;
+        for property in properties {
+            for violation in self.event_listener.check_property(property).await {
+                tracing::error!("{violation}");
+                violations += 1;
+            }
+        }
+        if violations > 0 {
+            anyhow::bail!("{violations} property violations found");
+        }
+        Ok(())
+    }
 }
 
 #[cfg(any(debug_assertions, test))]
@@ -872,7 +1162,7 @@ impl std::fmt::Debug for SimNetwork {
             .field("max_connections", &self.max_connections)
             .field("min_connections", &self.min_connections)
             .field("init_backoff", &self.start_backoff)
-            .field("add_noise", &self.add_noise)
+            .field("fault_schedule", &self.fault_schedule)
             .finish()
     }
 }
@@ -1016,6 +1306,7 @@ where
             config.op_manager.clone(),
             config.user_events.take().expect("should be set"),
             client_responses,
+            cli_response_sender.clone(),
             node_controller_tx,
         )
         .instrument(span),
@@ -50,7 +50,7 @@ impl<ER> Builder<ER> {
             self.peer_key,
             self.event_register.clone(),
             op_manager.clone(),
-            self.add_noise,
+            self.wire,
         );
 
         GlobalExecutor::spawn(
@@ -8,7 +8,10 @@ use crate::{
     contract::{self, ContractHandler, MemoryContractHandler},
     dev_tool::{ClientEventsProxy, NodeConfig},
     node::{
-        network_bridge::{event_loop_notification_channel, inter_process::InterProcessConnManager},
+        network_bridge::{
+            event_loop_notification_channel,
+            inter_process::{EventForwarder, InterProcessConnManager, PeerOutput, SharedOutput},
+        },
         OpManager,
     },
     tracing::{EventRegister, NetEventRegister},
@@ -21,33 +24,50 @@ pub struct SimPeer {
 }
 
 impl SimPeer {
+    /// Runs the peer sending the messages for other peers through stdout.
     pub async fn start_child<UsrEv>(self, event_generator: UsrEv) -> Result<(), anyhow::Error>
     where
         UsrEv: ClientEventsProxy + Send + 'static,
     {
+        self.start_with_output(event_generator, Box::new(tokio::io::stdout()))
+            .await
+    }
+
+    /// Runs the peer sending the messages for other peers, and the events it records, through
+    /// `output`.
+    pub async fn start_with_output<UsrEv>(
+        self,
+        event_generator: UsrEv,
+        output: PeerOutput,
+    ) -> Result<(), anyhow::Error>
+    where
+        UsrEv: ClientEventsProxy + Send + 'static,
+    {
+        let event_log = self.config.event_log()?;
         let event_register = {
             #[cfg(feature = "trace-ot")]
             {
                 use crate::tracing::{CombinedRegister, OTEventRegister};
                 CombinedRegister::new([
-                    Box::new(EventRegister::new(
-                        crate::config::Config::conf().event_log(),
-                    )),
+                    Box::new(EventRegister::new(event_log)),
                     Box::new(OTEventRegister::new()),
                 ])
             }
             #[cfg(not(feature = "trace-ot"))]
             {
-                EventRegister::new(crate::config::Config::conf().event_log())
+                EventRegister::new(event_log)
             }
         };
-        self.run_node(event_generator, event_register).await
+        let output = InterProcessConnManager::shared_output(output);
+        let event_register = EventForwarder::new(event_register, output.clone());
+        self.run_node(event_generator, event_register, output).await
     }
 
     async fn run_node<UsrEv, ER>(
         self,
         event_generator: UsrEv,
         event_register: ER,
+        output: SharedOutput,
     ) -> Result<(), anyhow::Error>
     where
         UsrEv: ClientEventsProxy + Send + 'static,
@@ -73,7 +93,8 @@ impl SimPeer {
         .await
         .map_err(|e| anyhow::anyhow!(e))?;
 
-        let conn_manager = InterProcessConnManager::new(event_register.clone(), op_manager.clone());
+        let conn_manager =
+            InterProcessConnManager::new(event_register.clone(), op_manager.clone(), output);
 
         GlobalExecutor::spawn(
             contract::contract_handling(contract_handler)
@@ -0,0 +1,386 @@
+//! Replays the traffic captured from a node (see [`NodeConfig::capture_messages`]) against a
+//! fresh node, feeding it the same messages and client requests one at a time in the order they
+//! were originally handled, and compares the messages it sends with the captured ones.
+//!
+//! The node goes through the same ring changes (location, connections...) and joins the ring
+//! through the same peers as when captured, at the same point of the session. Replay is driven
+//! by the order of the captured events alone: whenever the node started a transaction while
+//! handling a client request, the replay waits for the node to start it again before going on.
+//!
+//! Captured transactions are replayed as new ones, so old captures don't time out. Transactions
+//! started by the node itself are matched, in the order they start, with the captured ones of
+//! the same type.
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use either::Either;
+use tracing::Instrument;
+
+use crate::{
+    client_events::OpenRequest,
+    config::GlobalExecutor,
+    contract::{self, executor_channel, ContractHandler, MemoryContractHandler},
+    message::{NetMessage, Transaction},
+    node::{
+        network_bridge::{
+            event_loop_notification_channel, ConnResult, EventLoopNotificationsReceiver,
+        },
+        op_state_manager::OpManager,
+        process_message, process_open_request, NetEventRegister, NetworkBridge, NodeConfig, PeerId,
+    },
+    operations::connect,
+    tracing::{load_capture, Captured, CapturedEvent, TestEventListener},
+};
+
+/// Max time waiting for the node to start a transaction it started when captured, before
+/// considering it won't.
+const NOTIFICATION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Outcome of replaying a captured session.
+#[derive(Debug)]
+pub struct ReplayReport {
+    /// Number of captured messages and client requests fed to the node.
+    pub replayed: usize,
+    /// Transactions for which the node did not send the same messages as when captured.
+    pub divergences: Vec<Divergence>,
+}
+
+#[derive(Debug)]
+pub struct Divergence {
+    /// The captured transaction, or the replayed one if it doesn't match any captured.
+    pub transaction: String,
+    pub expected: Vec<String>,
+    pub found: Vec<String>,
+}
+
+/// Replays the session captured at `capture` in a node configured with `config`.
+pub async fn replay(mut config: NodeConfig, capture: &Path) -> Result<ReplayReport, anyhow::Error> {
+    let events = load_capture(capture)
+        .await
+        .map_err(|err| anyhow::anyhow!(err))?;
+    config.capture_file = None;
+    config.peer_book = None;
+
+    let event_register = TestEventListener::new().await;
+    let (mut notification_channel, notification_tx) = event_loop_notification_channel();
+    let (ops_ch_channel, ch_channel, mut wait_for_event) = contract::contract_handler_channel();
+    let op_manager = Arc::new(OpManager::new(
+        notification_tx,
+        ops_ch_channel,
+        &config,
+        event_register.clone(),
+    )?);
+    let (_executor_listener, executor_sender) = executor_channel(op_manager.clone());
+    let contract_handler = MemoryContractHandler::build(
+        ch_channel,
+        executor_sender,
+        format!("replay-{}", config.peer_id),
+    )
+    .await
+    .map_err(|err| anyhow::anyhow!(err))?;
+    GlobalExecutor::spawn(
+        contract::contract_handling(contract_handler)
+            .instrument(tracing::info_span!("contract_handling")),
+    );
+    GlobalExecutor::spawn(async move {
+        while wait_for_event
+            .relay_transaction_result_to_client()
+            .await
+            .is_ok()
+        {}
+    });
+    let (_client_responses, cli_response_sender) = contract::client_responses_channel();
+
+    let bridge = ReplayBridge {
+        op_manager: op_manager.clone(),
+        sent: Default::default(),
+    };
+    let mut transactions = Transactions::default();
+    let mut expected: HashMap<Transaction, Vec<(PeerId, NetMessage)>> = HashMap::new();
+    let mut replayed = 0;
+    for CapturedEvent { event, .. } in events {
+        match event {
+            Captured::Inbound(msg) => {
+                let tx = transactions.replayed_as(msg.id());
+                process_message(
+                    retag(msg, tx),
+                    op_manager.clone(),
+                    bridge.clone(),
+                    event_register.trait_clone(),
+                    None,
+                    None,
+                    None,
+                )
+                .await;
+                replayed += 1;
+            }
+            Captured::Outbound { target, msg } => {
+                let tx = transactions.replayed_as(msg.id());
+                expected
+                    .entry(tx)
+                    .or_default()
+                    .push((target, retag(msg, tx)));
+            }
+            Captured::ClientRequest { client_id, request } => {
+                process_open_request(
+                    OpenRequest::new(client_id, Box::new(request)),
+                    op_manager.clone(),
+                    cli_response_sender.clone(),
+                )
+                .await;
+                replayed += 1;
+            }
+            Captured::Located(location) => op_manager.ring.update_location(Some(location)),
+            Captured::Connected { peer, location } => {
+                // connections are usually added again while replaying the connect messages
+                if !op_manager.ring.is_connected(&peer) {
+                    op_manager.ring.add_connection(location, peer);
+                }
+            }
+            Captured::Disconnected(peer) => {
+                if op_manager.ring.is_connected(&peer) {
+                    op_manager.ring.prune_connection(peer);
+                }
+            }
+            Captured::Notified(captured) => {
+                transactions
+                    .notified(&captured, &mut notification_channel)
+                    .await;
+            }
+            Captured::Joining { tx, gateway } => {
+                let tx = transactions.replayed_as(&tx);
+                connect::join_ring_request_as(
+                    tx,
+                    op_manager.ring.peer_key,
+                    &gateway,
+                    &op_manager,
+                    &mut bridge.clone(),
+                )
+                .await
+                .map_err(|err| anyhow::anyhow!("failed joining the ring: {err}"))?;
+            }
+        }
+    }
+
+    let mut found: HashMap<Transaction, Vec<(PeerId, NetMessage)>> = HashMap::new();
+    for (target, msg) in bridge.sent.lock().expect("not poisoned").drain(..) {
+        found.entry(*msg.id()).or_default().push((target, msg));
+    }
+    let mut divergences = vec![];
+    let mut replayed_txs: Vec<_> = transactions.replayed.into_iter().collect();
+    replayed_txs.sort();
+    for (captured, tx) in replayed_txs {
+        let expected = expected.remove(&tx).unwrap_or_default();
+        let found = found.remove(&tx).unwrap_or_default();
+        if !same_messages(&expected, &found)? {
+            divergences.push(Divergence {
+                transaction: captured.to_string(),
+                expected: describe(&expected),
+                found: describe(&found),
+            });
+        }
+    }
+    let mut unmatched: Vec<_> = found.into_iter().collect();
+    unmatched.sort_by_key(|(tx, _)| *tx);
+    for (tx, found) in unmatched {
+        divergences.push(Divergence {
+            transaction: tx.to_string(),
+            expected: vec![],
+            found: describe(&found),
+        });
+    }
+    Ok(ReplayReport {
+        replayed,
+        divergences,
+    })
+}
+
+/// Stands in for the network, keeping the messages sent by the node.
+#[derive(Clone)]
+struct ReplayBridge {
+    op_manager: Arc<OpManager>,
+    sent: Arc<Mutex<Vec<(PeerId, NetMessage)>>>,
+}
+
+#[async_trait::async_trait]
+impl NetworkBridge for ReplayBridge {
+    async fn send(&self, target: &PeerId, msg: NetMessage) -> ConnResult<()> {
+        self.op_manager.sending_transaction(target, &msg);
+        self.sent.lock().expect("not poisoned").push((*target, msg));
+        Ok(())
+    }
+
+    async fn add_connection(&mut self, _peer: PeerId) -> ConnResult<()> {
+        Ok(())
+    }
+
+    async fn drop_connection(&mut self, _peer: &PeerId) -> ConnResult<()> {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Transactions {
+    /// Captured transactions and the ones they are replayed as.
+    replayed: HashMap<Transaction, Transaction>,
+    /// Transactions of the messages the node sent to its event loop, not matched with a captured
+    /// notification yet.
+    notified: Vec<Transaction>,
+}
+
+impl Transactions {
+    /// Waits for the node to send to its event loop a message of the transaction the captured
+    /// one is replayed as, or of a new transaction of the same type if it is not replayed yet.
+    /// Those messages are not handled, since the captured ones are replayed instead.
+    async fn notified(
+        &mut self,
+        captured: &Transaction,
+        notifications: &mut EventLoopNotificationsReceiver,
+    ) {
+        let replayed_as = self.replayed.get(captured).copied();
+        loop {
+            let found = self.notified.iter().position(|tx| match replayed_as {
+                Some(replayed_as) => tx == &replayed_as,
+                None => {
+                    tx.transaction_type() == captured.transaction_type()
+                        && !self.replayed.values().any(|other| other == tx)
+                }
+            });
+            if let Some(pos) = found {
+                let tx = self.notified.remove(pos);
+                self.replayed.insert(*captured, tx);
+                return;
+            }
+            match tokio::time::timeout(NOTIFICATION_TIMEOUT, notifications.recv()).await {
+                Ok(Some(Either::Left(msg))) => self.notified.push(*msg.id()),
+                Ok(Some(Either::Right(_))) => {}
+                Ok(None) | Err(_) => {
+                    tracing::warn!(%captured, "Transaction not started again while replaying");
+                    self.replayed_as(captured);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn replayed_as(&mut self, captured: &Transaction) -> Transaction {
+        *self
+            .replayed
+            .entry(*captured)
+            .or_insert_with(|| captured.renewed())
+    }
+}
+
+/// Swaps the transaction of a captured message for the one it is replayed as.
+fn retag(mut msg: NetMessage, tx: Transaction) -> NetMessage {
+    *msg.id_mut() = tx;
+    msg
+}
+
+fn same_messages(
+    expected: &[(PeerId, NetMessage)],
+    found: &[(PeerId, NetMessage)],
+) -> Result<bool, bincode::Error> {
+    if expected.len() != found.len() {
+        return Ok(false);
+    }
+    for ((expected_target, expected), (found_target, found)) in expected.iter().zip(found) {
+        if expected_target != found_target
+            || bincode::serialize(expected)? != bincode::serialize(found)?
+        {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn describe(msgs: &[(PeerId, NetMessage)]) -> Vec<String> {
+    msgs.iter()
+        .map(|(target, msg)| format!("to {target}: {msg:?}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        node::testing_impl::SimNetwork,
+        operations::{get::GetMsg, put::PutMsg},
+    };
+
+    #[tokio::test]
+    async fn replayed_transactions() {
+        let captured = Transaction::new::<PutMsg>();
+        let started = Transaction::new::<PutMsg>();
+        let (mut notifications, sender) = event_loop_notification_channel();
+        for tx in [Transaction::new::<GetMsg>(), started] {
+            sender
+                .send(Either::Left(NetMessage::Aborted(tx)))
+                .await
+                .unwrap();
+        }
+        let mut transactions = Transactions::default();
+        transactions.notified(&captured, &mut notifications).await;
+        assert_eq!(transactions.replayed_as(&captured), started);
+        assert_eq!(transactions.notified.len(), 1);
+
+        let remote = Transaction::new::<PutMsg>();
+        let renewed = transactions.replayed_as(&remote);
+        assert_ne!(renewed, remote);
+        assert_eq!(renewed.transaction_type(), remote.transaction_type());
+        assert_eq!(transactions.replayed_as(&remote), renewed);
+
+        let msg = retag(NetMessage::Aborted(captured), renewed);
+        assert_eq!(msg.id(), &renewed);
+    }
+
+    /// The sessions captured from the nodes of a simulated network are replayed by fresh nodes
+    /// without any difference in the messages they send.
+    ///
+    /// Gateways are left out, since they pick at random the location of the peers joining
+    /// through them. Forwarding is never randomized and every connection is accepted, so the
+    /// nodes make the same choices when replayed.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn replay_simulated_session() -> Result<(), anyhow::Error> {
+        const NUM_NODES: usize = 3;
+        const NUM_GW: usize = 1;
+        const MAX_HTL: usize = 3;
+
+        let capture_dir = tempfile::tempdir()?;
+        let mut sim_nw = SimNetwork::new(
+            "replay_simulated_session",
+            NUM_GW,
+            NUM_NODES,
+            MAX_HTL,
+            MAX_HTL,
+            NUM_NODES + NUM_GW,
+            NUM_NODES + NUM_GW,
+        )
+        .await;
+        sim_nw.with_seed(1);
+        sim_nw.capture_messages(capture_dir.path());
+        let configs: Vec<_> = sim_nw
+            .node_configs()
+            .filter(|(label, _)| !label.is_gateway())
+            .collect();
+        sim_nw.start().await;
+        sim_nw.check_connectivity(Duration::from_secs(3))?;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        for (label, config) in configs {
+            let capture = capture_dir.path().join(format!("{label}.capture"));
+            let report = replay(config, &capture).await?;
+            assert!(report.replayed > 0, "nothing captured for {label}");
+            assert!(
+                report.divergences.is_empty(),
+                "{label} diverged: {:#?}",
+                report.divergences
+            );
+        }
+        Ok(())
+    }
+}
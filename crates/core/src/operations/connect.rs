@@ -810,6 +810,9 @@ impl ConnectState {
 /// (to gateways or regular peers) will be treated as regular connections.
 ///
 /// - is_gateway: Whether this peer is a gateway or not.
+///
+/// Peers known from previous runs are tried first, the gateways are only used if there are none;
+/// if joining through a known peer fails it falls back to the gateways.
 pub(crate) async fn initial_join_procedure<CM>(
     op_manager: &OpManager,
     conn_manager: &mut CM,
@@ -830,6 +833,25 @@ where
             / max_potential_conns_per_gw;
         needed_to_cover_max.max(1)
     };
+    let known_peers: Vec<_> = op_manager
+        .ring
+        .peer_book
+        .known_peers()
+        .into_iter()
+        .map(|(peer, _)| peer)
+        .filter(|peer| peer.peer != this_peer && gateways.iter().all(|gw| gw.peer != peer.peer))
+        .take(number_of_parallel_connections)
+        .collect();
+    if !known_peers.is_empty() {
+        tracing::info!(
+            "Attempting to connect to {} previously known peers in parallel",
+            known_peers.len()
+        );
+        for peer in &known_peers {
+            join_ring_request(None, this_peer, peer, op_manager, conn_manager).await?;
+        }
+        return Ok(());
+    }
     tracing::info!(
         "Attempting to connect to {} gateways in parallel",
         number_of_parallel_connections
@@ -879,6 +901,21 @@ where
     Ok(())
 }
 
+/// Joins the ring through `gateway` as the given transaction, used to replay a captured join.
+pub(crate) async fn join_ring_request_as<CM>(
+    tx: Transaction,
+    peer_key: PeerId,
+    gateway: &PeerKeyLocation,
+    op_manager: &OpManager,
+    conn_manager: &mut CM,
+) -> Result<(), OpError>
+where
+    CM: NetworkBridge + Send,
+{
+    let op = initial_request(peer_key, *gateway, op_manager.ring.max_hops_to_live, tx);
+    connect_request(tx, op_manager, conn_manager, op).await
+}
+
 fn initial_request(
     this_peer: PeerId,
     gateway: PeerKeyLocation,
@@ -933,6 +970,9 @@ where
         gateway = %gateway,
         "Connecting to gateway",
     );
+    if let Some(capture) = &op_manager.capture {
+        capture.joining(tx, gateway);
+    }
 
     conn_bridge.add_connection(gateway.peer).await?;
     let assigned_location = op_manager.ring.own_location().location;
@@ -1090,6 +1130,14 @@ mod messages {
             }
         }
 
+        fn id_mut(&mut self) -> &mut Transaction {
+            match self {
+                Self::Request { id, .. } => id,
+                Self::Response { id, .. } => id,
+                Self::Connected { id, .. } => id,
+            }
+        }
+
         fn target(&self) -> Option<&PeerKeyLocation> {
             use ConnectMsg::*;
             match self {
@@ -1117,6 +1165,10 @@ mod messages {
         fn requested_location(&self) -> Option<Location> {
             self.target().and_then(|pkloc| pkloc.location)
         }
+
+        fn requested_contract(&self) -> Option<freenet_stdlib::prelude::ContractInstanceId> {
+            None
+        }
     }
 
     impl ConnectMsg {
@@ -177,6 +177,21 @@ pub(crate) struct GetOp {
 }
 
 impl GetOp {
+    /// An operation which already got the state of the contract.
+    #[cfg(test)]
+    pub(crate) fn finished(id: Transaction, key: ContractKey, state: WrappedState) -> Self {
+        GetOp {
+            id,
+            state: None,
+            result: Some(GetResult {
+                key,
+                state,
+                contract: None,
+            }),
+            stats: None,
+        }
+    }
+
     pub(super) fn outcome(&self) -> OpOutcome {
         if let Some((
             GetResult {
@@ -370,10 +385,13 @@ impl Operation for GetOp {
                     }
 
                     let get_result = op_manager
-                        .notify_contract_handler(ContractHandlerEvent::GetQuery {
-                            key: key.clone(),
-                            fetch_contract,
-                        })
+                        .notify_contract_handler_for(
+                            &sender.peer,
+                            ContractHandlerEvent::GetQuery {
+                                key: key.clone(),
+                                fetch_contract,
+                            },
+                        )
                         .await;
 
                     let (returned_key, contract, state) = match get_result {
@@ -875,6 +893,14 @@ mod messages {
             }
         }
 
+        fn id_mut(&mut self) -> &mut Transaction {
+            match self {
+                Self::RequestGet { id, .. } => id,
+                Self::SeekNode { id, .. } => id,
+                Self::ReturnGet { id, .. } => id,
+            }
+        }
+
         fn target(&self) -> Option<&PeerKeyLocation> {
             match self {
                 Self::SeekNode { target, .. } => Some(target),
@@ -895,6 +921,14 @@ mod messages {
                 GetMsg::ReturnGet { key, .. } => Some(Location::from(key.id())),
             }
         }
+
+        fn requested_contract(&self) -> Option<ContractInstanceId> {
+            match self {
+                GetMsg::RequestGet { key, .. } => Some(*key.id()),
+                GetMsg::SeekNode { key, .. } => Some(*key.id()),
+                GetMsg::ReturnGet { key, .. } => Some(*key.id()),
+            }
+        }
     }
 
     impl GetMsg {
@@ -924,7 +958,10 @@ mod test {
     use std::{collections::HashMap, time::Duration};
 
     use super::*;
-    use crate::node::testing_impl::{NodeSpecification, SimNetwork};
+    use crate::{
+        node::testing_impl::{NodeSpecification, SimNetwork},
+        tracing::Property,
+    };
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
     async fn successful_get_op_between_nodes() -> Result<(), anyhow::Error> {
@@ -978,6 +1015,7 @@ mod test {
             .trigger_event("node-1", 1, Some(Duration::from_secs(1)))
             .await?;
         assert!(sim_nw.has_got_contract("node-1", &key));
+        sim_nw.check_properties(&[Property::HopsWithin(3)]).await?;
         Ok(())
     }
 
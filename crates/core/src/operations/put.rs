@@ -20,7 +20,7 @@ use crate::{
     contract::ContractHandlerEvent,
     message::{InnerMessage, NetMessage, Transaction},
     node::{NetworkBridge, OpManager, PeerId},
-    ring::{Location, PeerKeyLocation, RingError},
+    ring::{Location, Misbehaviour, PeerKeyLocation, RingError},
 };
 
 pub(crate) struct PutOp {
@@ -30,6 +30,20 @@ pub(crate) struct PutOp {
 }
 
 impl PutOp {
+    /// An operation which already put the contract.
+    #[cfg(test)]
+    pub(crate) fn finished(id: Transaction, key: ContractKey) -> Self {
+        PutOp {
+            id,
+            state: Some(PutState::Finished { key }),
+            stats: Some(PutStats {
+                transfer_time: None,
+                target: None,
+                step: RecordingStats::Completed,
+            }),
+        }
+    }
+
     pub(super) fn outcome(&self) -> OpOutcome {
         // todo: track in the future
         // match &self.stats {
@@ -248,6 +262,7 @@ impl Operation for PutOp {
                         tracing::debug!(tx = %id, "Attempting contract value update");
                         put_contract(
                             op_manager,
+                            &sender.peer,
                             key.clone(),
                             value.clone(),
                             related_contracts.clone(),
@@ -278,6 +293,7 @@ impl Operation for PutOp {
                             // if already subscribed the value was already put and merging succeeded
                             put_contract(
                                 op_manager,
+                                &sender.peer,
                                 key.clone(),
                                 value.clone(),
                                 RelatedContracts::default(),
@@ -290,6 +306,7 @@ impl Operation for PutOp {
                         // should put in this location, no hops left
                         put_contract(
                             op_manager,
+                            &sender.peer,
                             key.clone(),
                             value.clone(),
                             RelatedContracts::default(),
@@ -330,6 +347,7 @@ impl Operation for PutOp {
                     tracing::debug!("Attempting contract value update");
                     let new_value = put_contract(
                         op_manager,
+                        &sender.peer,
                         key.clone(),
                         new_value.clone(),
                         RelatedContracts::default(),
@@ -475,6 +493,7 @@ impl Operation for PutOp {
                         // after the contract has been cached, push the update query
                         put_contract(
                             op_manager,
+                            &sender.peer,
                             key.clone(),
                             new_value.clone(),
                             RelatedContracts::default(),
@@ -503,6 +522,7 @@ impl Operation for PutOp {
                             // if already subscribed the value was already put and merging succeeded
                             put_contract(
                                 op_manager,
+                                &sender.peer,
                                 key.clone(),
                                 new_value.clone(),
                                 RelatedContracts::default(),
@@ -531,6 +551,7 @@ impl Operation for PutOp {
                         // should put in this location, no hops left
                         put_contract(
                             op_manager,
+                            &sender.peer,
                             key.clone(),
                             new_value.clone(),
                             RelatedContracts::default(),
@@ -568,15 +589,17 @@ impl Operation for PutOp {
 }
 
 impl OpManager {
-    fn get_broadcast_targets(&self, key: &ContractKey, sender: &PeerId) -> Vec<PeerKeyLocation> {
+    pub(super) fn get_broadcast_targets(
+        &self,
+        key: &ContractKey,
+        sender: &PeerId,
+    ) -> Vec<PeerKeyLocation> {
         let subscribers = self
             .ring
             .subscribers_of(key)
             .map(|subs| {
-                subs.value()
-                    .iter()
+                subs.into_iter()
                     .filter(|pk| &pk.peer != sender)
-                    .copied()
                     .collect::<Vec<_>>()
             })
             .unwrap_or_default();
@@ -774,6 +797,7 @@ pub(crate) async fn request_put(op_manager: &OpManager, mut put_op: PutOp) -> Re
 
 async fn put_contract(
     op_manager: &OpManager,
+    requester: &PeerId,
     key: ContractKey,
     state: WrappedState,
     related_contracts: RelatedContracts<'static>,
@@ -781,22 +805,30 @@ async fn put_contract(
 ) -> Result<WrappedState, OpError> {
     // after the contract has been cached, push the update query
     match op_manager
-        .notify_contract_handler(ContractHandlerEvent::PutQuery {
-            key,
-            state,
-            related_contracts,
-            contract: Some(contract.clone()),
-        })
+        .notify_contract_handler_for(
+            requester,
+            ContractHandlerEvent::PutQuery {
+                key: key.clone(),
+                state,
+                related_contracts,
+                contract: Some(contract.clone()),
+            },
+        )
         .await
     {
         Ok(ContractHandlerEvent::PutResponse {
             new_value: Ok(new_val),
         }) => Ok(new_val),
         Ok(ContractHandlerEvent::PutResponse {
-            new_value: Err(_err),
+            new_value: Err(err),
         }) => {
-            // return Err(OpError::from(ContractError::StorageError(err)));
-            todo!("not a valid value update, notify back to requester")
+            if err.is_invalid() && requester != &op_manager.ring.peer_key {
+                op_manager
+                    .ring
+                    .report_misbehaviour(requester, Some(&key), Misbehaviour::InvalidState)
+                    .await;
+            }
+            Err(err.into())
         }
         Err(err) => Err(err.into()),
         Ok(_) => Err(OpError::UnexpectedOpState),
@@ -937,6 +969,18 @@ mod messages {
             }
         }
 
+        fn id_mut(&mut self) -> &mut Transaction {
+            match self {
+                Self::SeekNode { id, .. } => id,
+                Self::RequestPut { id, .. } => id,
+                Self::Broadcasting { id, .. } => id,
+                Self::SuccessfulPut { id, .. } => id,
+                Self::PutForward { id, .. } => id,
+                Self::AwaitPut { id } => id,
+                Self::BroadcastTo { id, .. } => id,
+            }
+        }
+
         fn target(&self) -> Option<&PeerKeyLocation> {
             match self {
                 Self::SeekNode { target, .. } => Some(target),
@@ -964,6 +1008,17 @@ mod messages {
                 _ => None,
             }
         }
+
+        fn requested_contract(&self) -> Option<ContractInstanceId> {
+            match self {
+                Self::SeekNode { contract, .. } => Some(*contract.key().id()),
+                Self::RequestPut { contract, .. } => Some(*contract.key().id()),
+                Self::Broadcasting { key, .. } => Some(*key.id()),
+                Self::PutForward { contract, .. } => Some(*contract.key().id()),
+                Self::BroadcastTo { key, .. } => Some(*key.id()),
+                _ => None,
+            }
+        }
     }
 
     impl PutMsg {
@@ -999,7 +1054,10 @@ mod test {
     use freenet_stdlib::client_api::ContractRequest;
     use freenet_stdlib::prelude::*;
 
-    use crate::node::testing_impl::{NodeSpecification, SimNetwork};
+    use crate::{
+        node::testing_impl::{NodeSpecification, SimNetwork},
+        tracing::Property,
+    };
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
     async fn successful_put_op_between_nodes() -> Result<(), anyhow::Error> {
@@ -1081,6 +1139,13 @@ mod test {
             .await?;
         assert!(sim_nw.has_put_contract("gateway-0", &key));
         assert!(sim_nw.event_listener.contract_broadcasted(&key));
+        sim_nw
+            .check_properties(&[
+                Property::PutReachesCachingPeer,
+                Property::HopsWithin(2),
+                Property::SubscribersConverge,
+            ])
+            .await?;
         Ok(())
     }
 }
@@ -468,6 +468,15 @@ mod messages {
             }
         }
 
+        fn id_mut(&mut self) -> &mut Transaction {
+            match self {
+                Self::SeekNode { id, .. } => id,
+                Self::FetchRouting { id, .. } => id,
+                Self::RequestSub { id, .. } => id,
+                Self::ReturnSub { id, .. } => id,
+            }
+        }
+
         fn target(&self) -> Option<&PeerKeyLocation> {
             match self {
                 Self::SeekNode { target, .. } => Some(target),
@@ -489,6 +498,15 @@ mod messages {
                 _ => None,
             }
         }
+
+        fn requested_contract(&self) -> Option<ContractInstanceId> {
+            match self {
+                Self::SeekNode { key, .. } => Some(*key.id()),
+                Self::RequestSub { key, .. } => Some(*key.id()),
+                Self::ReturnSub { key, .. } => Some(*key.id()),
+                _ => None,
+            }
+        }
     }
 
     impl SubscribeMsg {
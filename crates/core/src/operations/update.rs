@@ -1,17 +1,32 @@
-// TODO: complete update logic in the network
+//! An UPDATE is routed towards the peers seeding a contract, which merge the update into their
+//! local copy of the contract state and then propagate the same update (preferably as a delta)
+//! down the tree of subscribers to the contract.
 
-use freenet_stdlib::prelude::*;
+use std::future::Future;
+use std::pin::Pin;
+
+pub(crate) use self::messages::{UpdateMsg, UpdateValue};
+use either::Either;
+use freenet_stdlib::{
+    client_api::{ContractResponse, ErrorKind, HostResponse},
+    prelude::*,
+};
 use futures::future::BoxFuture;
+use futures::FutureExt;
 
-use super::{OpError, OpOutcome, Operation};
+use super::{OpEnum, OpError, OpInitialization, OpOutcome, Operation, OperationResult};
 use crate::{
     client_events::{ClientId, HostResult},
-    node::{NetworkBridge, OpManager},
+    contract::ContractHandlerEvent,
+    message::{InnerMessage, NetMessage, Transaction},
+    node::{NetworkBridge, OpManager, PeerId},
+    ring::{Location, Misbehaviour, PeerKeyLocation, RingError},
 };
 
-pub(crate) use self::messages::UpdateMsg;
-
-pub(crate) struct UpdateOp {}
+pub(crate) struct UpdateOp {
+    pub id: Transaction,
+    state: Option<UpdateState>,
+}
 
 impl UpdateOp {
     pub fn outcome(&self) -> OpOutcome {
@@ -19,13 +34,25 @@ impl UpdateOp {
     }
 
     pub fn finalized(&self) -> bool {
-        todo!()
+        matches!(self.state, Some(UpdateState::Finished { .. }))
     }
 
     pub fn record_transfer(&mut self) {}
 
     pub(super) fn to_host_result(&self) -> HostResult {
-        todo!()
+        if let Some(UpdateState::Finished { key, summary }) = &self.state {
+            Ok(HostResponse::ContractResponse(
+                ContractResponse::UpdateResponse {
+                    key: key.clone(),
+                    summary: summary.clone(),
+                },
+            ))
+        } else {
+            Err(ErrorKind::OperationError {
+                cause: "update didn't finish successfully".into(),
+            }
+            .into())
+        }
     }
 }
 
@@ -34,8 +61,12 @@ pub(crate) struct UpdateResult {}
 impl TryFrom<UpdateOp> for UpdateResult {
     type Error = OpError;
 
-    fn try_from(_value: UpdateOp) -> Result<Self, Self::Error> {
-        todo!()
+    fn try_from(op: UpdateOp) -> Result<Self, Self::Error> {
+        if op.finalized() {
+            Ok(UpdateResult {})
+        } else {
+            Err(OpError::UnexpectedOpState)
+        }
     }
 }
 
@@ -44,76 +75,800 @@ impl Operation for UpdateOp {
     type Result = UpdateResult;
 
     fn load_or_init<'a>(
-        _op_manager: &'a crate::node::OpManager,
-        _msg: &'a Self::Message,
-    ) -> BoxFuture<'a, Result<super::OpInitialization<Self>, OpError>> {
-        todo!()
+        op_manager: &'a OpManager,
+        msg: &'a Self::Message,
+    ) -> BoxFuture<'a, Result<OpInitialization<Self>, OpError>> {
+        async move {
+            let sender = msg.sender().map(|peer_key_loc| peer_key_loc.peer);
+            let tx = *msg.id();
+            match op_manager.pop(msg.id()) {
+                Ok(Some(OpEnum::Update(update_op))) => {
+                    // was an existing operation, the other peer messaged back
+                    Ok(OpInitialization {
+                        op: update_op,
+                        sender,
+                    })
+                }
+                Ok(Some(op)) => {
+                    let _ = op_manager.push(tx, op).await;
+                    Err(OpError::OpNotPresent(tx))
+                }
+                Ok(None) => {
+                    // new request to update a contract, initialize the machine
+                    Ok(OpInitialization {
+                        op: Self {
+                            state: Some(UpdateState::ReceivedRequest),
+                            id: tx,
+                        },
+                        sender,
+                    })
+                }
+                Err(err) => Err(err.into()),
+            }
+        }
+        .boxed()
     }
 
-    fn id(&self) -> &crate::message::Transaction {
-        todo!()
+    fn id(&self) -> &Transaction {
+        &self.id
     }
 
     fn process_message<'a, NB: NetworkBridge>(
         self,
-        _conn_manager: &'a mut NB,
-        _op_manager: &'a crate::node::OpManager,
-        _input: &Self::Message,
-        // _client_id: Option<ClientId>,
-    ) -> std::pin::Pin<
-        Box<dyn futures::Future<Output = Result<super::OperationResult, OpError>> + Send + 'a>,
-    > {
-        todo!()
+        conn_manager: &'a mut NB,
+        op_manager: &'a OpManager,
+        input: &'a Self::Message,
+    ) -> Pin<Box<dyn Future<Output = Result<OperationResult, OpError>> + Send + 'a>> {
+        Box::pin(async move {
+            let return_msg;
+            let new_state;
+
+            match input {
+                UpdateMsg::RequestUpdate {
+                    id,
+                    key,
+                    value,
+                    htl,
+                } => {
+                    let sender = op_manager.ring.own_location();
+
+                    if op_manager.ring.is_seeding_contract(key) {
+                        // this peer is already seeding the contract, no need to route the request
+                        tracing::debug!(tx = %id, %key, "Updating contract seeded at the requester");
+                        let (_, summary) =
+                            update_contract(op_manager, &sender.peer, key.clone(), value.clone())
+                                .await?;
+                        let broadcast_to = op_manager.get_broadcast_targets(key, &sender.peer);
+                        match try_to_broadcast(
+                            *id,
+                            op_manager,
+                            (broadcast_to, sender),
+                            key.clone(),
+                            value.clone(),
+                            summary,
+                        )
+                        .await
+                        {
+                            Ok((state, msg)) => {
+                                new_state = state;
+                                return_msg = msg;
+                            }
+                            Err(err) => return Err(err),
+                        }
+                    } else {
+                        let target = op_manager
+                            .ring
+                            .closest_potentially_caching(key, [&sender.peer].as_slice())
+                            .ok_or(RingError::EmptyRing)?;
+                        tracing::debug!(
+                            "Requesting update for contract {} from {} to {}",
+                            key,
+                            sender.peer,
+                            target.peer
+                        );
+                        return_msg = Some(UpdateMsg::SeekNode {
+                            id: *id,
+                            sender,
+                            target,
+                            key: key.clone(),
+                            value: value.clone(),
+                            htl: *htl,
+                            skip_list: vec![sender.peer],
+                        });
+                        new_state = Some(UpdateState::AwaitingResponse {
+                            key: key.clone(),
+                            upstream: None,
+                        });
+                    }
+                }
+                UpdateMsg::SeekNode {
+                    id,
+                    sender,
+                    target,
+                    key,
+                    value,
+                    htl,
+                    skip_list,
+                } => {
+                    if op_manager.ring.is_seeding_contract(key) {
+                        tracing::debug!(
+                            tx = %id,
+                            %key,
+                            target = %target.peer,
+                            "Updating contract at seeding peer",
+                        );
+                        let (_, summary) =
+                            update_contract(op_manager, &sender.peer, key.clone(), value.clone())
+                                .await?;
+                        let broadcast_to = op_manager.get_broadcast_targets(key, &sender.peer);
+                        match try_to_broadcast(
+                            *id,
+                            op_manager,
+                            (broadcast_to, *sender),
+                            key.clone(),
+                            value.clone(),
+                            summary,
+                        )
+                        .await
+                        {
+                            Ok((state, msg)) => {
+                                new_state = state;
+                                return_msg = msg;
+                            }
+                            Err(err) => return Err(err),
+                        }
+                    } else {
+                        // not seeding the contract, forward the request closer to the contract location
+                        let Some(new_htl) = htl.checked_sub(1) else {
+                            tracing::warn!(tx = %id, %key, "Reached max hops without finding a seeding peer");
+                            return Err(RingError::NoCachingPeers(key.clone()).into());
+                        };
+                        let this_peer = op_manager.ring.own_location();
+                        let mut new_skip_list = skip_list.clone();
+                        new_skip_list.push(this_peer.peer);
+                        let Some(forward_to) = op_manager
+                            .ring
+                            .closest_potentially_caching(key, &*new_skip_list)
+                        else {
+                            tracing::warn!(tx = %id, %key, "No peer to forward the update to");
+                            return Err(RingError::NoCachingPeers(key.clone()).into());
+                        };
+                        tracing::debug!(
+                            tx = %id,
+                            %key,
+                            this_peer = %this_peer.peer,
+                            forward_to = %forward_to.peer,
+                            "Forwarding update request",
+                        );
+                        return_msg = Some(UpdateMsg::SeekNode {
+                            id: *id,
+                            sender: this_peer,
+                            target: forward_to,
+                            key: key.clone(),
+                            value: value.clone(),
+                            htl: new_htl,
+                            skip_list: new_skip_list,
+                        });
+                        new_state = Some(UpdateState::AwaitingResponse {
+                            key: key.clone(),
+                            upstream: Some(*sender),
+                        });
+                    }
+                }
+                UpdateMsg::Broadcasting {
+                    id,
+                    broadcasted_to,
+                    broadcast_to,
+                    key,
+                    new_value,
+                    upstream,
+                } => {
+                    let Some(UpdateState::BroadcastOngoing { summary }) = self.state else {
+                        return Err(OpError::invalid_transition(self.id));
+                    };
+                    let broadcasted_to = *broadcasted_to
+                        + broadcast_update(
+                            conn_manager,
+                            op_manager,
+                            *id,
+                            key,
+                            new_value,
+                            broadcast_to,
+                        )
+                        .await?;
+                    tracing::debug!(
+                        "Successfully broadcasted update into contract {key} to {broadcasted_to} peers"
+                    );
+
+                    // subscriber nodes have been notified of the change, inform back the requester
+                    match reply_upstream(*id, op_manager, *upstream, key.clone(), summary).await {
+                        Ok((state, msg)) => {
+                            new_state = state;
+                            return_msg = msg;
+                        }
+                        Err(err) => return Err(err),
+                    }
+                }
+                UpdateMsg::BroadcastTo {
+                    id,
+                    sender,
+                    key,
+                    new_value,
+                } => {
+                    match update_contract(op_manager, &sender.peer, key.clone(), new_value.clone())
+                        .await
+                    {
+                        Ok(_) => {
+                            tracing::debug!(tx = %id, %key, "Applied broadcasted contract update");
+                            // relay the update down the subscriber tree
+                            let broadcast_to = op_manager.get_broadcast_targets(key, &sender.peer);
+                            broadcast_update(
+                                conn_manager,
+                                op_manager,
+                                *id,
+                                key,
+                                new_value,
+                                &broadcast_to,
+                            )
+                            .await?;
+                        }
+                        Err(err) => {
+                            // don't abort the op, this peer may be the one which requested the update
+                            tracing::warn!(tx = %id, %key, "Failed applying broadcasted update: {err}");
+                        }
+                    }
+                    return_msg = None;
+                    // keep the state around in case this peer is waiting for the update to complete
+                    new_state = match self.state {
+                        Some(UpdateState::ReceivedRequest) | None => None,
+                        other => other,
+                    };
+                }
+                UpdateMsg::SuccessfulUpdate { id, summary, .. } => match self.state {
+                    Some(UpdateState::AwaitingResponse { key, upstream }) => {
+                        tracing::info!(
+                            tx = %id,
+                            %key,
+                            this_peer = %op_manager.ring.peer_key,
+                            "Peer completed contract value update",
+                        );
+                        if let Some(upstream) = upstream {
+                            return_msg = Some(UpdateMsg::SuccessfulUpdate {
+                                id: *id,
+                                target: upstream,
+                                summary: summary.clone(),
+                            });
+                            new_state = None;
+                        } else {
+                            return_msg = None;
+                            new_state = Some(UpdateState::Finished {
+                                key,
+                                summary: StateSummary::from(summary.clone()),
+                            });
+                        }
+                    }
+                    _ => return Err(OpError::invalid_transition(self.id)),
+                },
+            }
+
+            build_op_result(self.id, new_state, return_msg)
+        })
     }
 }
 
-// todo: new_state should be a delta when possible!
-pub(crate) fn start_op(_key: ContractKey, _new_state: WrappedState, _htl: usize) -> UpdateOp {
-    todo!()
+fn build_op_result(
+    id: Transaction,
+    state: Option<UpdateState>,
+    msg: Option<UpdateMsg>,
+) -> Result<OperationResult, OpError> {
+    let output_op = state.map(|state| UpdateOp {
+        id,
+        state: Some(state),
+    });
+    Ok(OperationResult {
+        return_msg: msg.map(NetMessage::from),
+        state: output_op.map(OpEnum::Update),
+    })
+}
+
+/// Starts broadcasting the update to the subscribers of the contract, if there are any,
+/// otherwise replies back to the upstream peer.
+async fn try_to_broadcast(
+    id: Transaction,
+    op_manager: &OpManager,
+    (broadcast_to, upstream): (Vec<PeerKeyLocation>, PeerKeyLocation),
+    key: ContractKey,
+    new_value: UpdateValue,
+    summary: StateSummary<'static>,
+) -> Result<(Option<UpdateState>, Option<UpdateMsg>), OpError> {
+    if broadcast_to.is_empty() {
+        tracing::debug!(
+            "Empty broadcast list while updating value for contract {}",
+            key
+        );
+        return reply_upstream(id, op_manager, upstream, key, summary).await;
+    }
+
+    tracing::debug!("Callback to start broadcasting to other nodes");
+    let msg = UpdateMsg::Broadcasting {
+        id,
+        broadcasted_to: 0,
+        broadcast_to,
+        key,
+        new_value,
+        upstream,
+    };
+    let op = UpdateOp {
+        id,
+        state: Some(UpdateState::BroadcastOngoing { summary }),
+    };
+    op_manager
+        .notify_op_change(NetMessage::from(msg), OpEnum::Update(op))
+        .await?;
+    Err(OpError::StatePushed)
 }
 
+/// Informs the upstream peer that the update has been applied by a seeding peer.
+///
+/// When the upstream peer is this same peer (the requester was seeding the contract itself)
+/// the response is pushed through the local event loop so the operation is completed there.
+async fn reply_upstream(
+    id: Transaction,
+    op_manager: &OpManager,
+    upstream: PeerKeyLocation,
+    key: ContractKey,
+    summary: StateSummary<'static>,
+) -> Result<(Option<UpdateState>, Option<UpdateMsg>), OpError> {
+    let msg = UpdateMsg::SuccessfulUpdate {
+        id,
+        target: upstream,
+        summary: summary.to_vec(),
+    };
+    if upstream.peer == op_manager.ring.peer_key {
+        let op = UpdateOp {
+            id,
+            state: Some(UpdateState::AwaitingResponse {
+                key,
+                upstream: None,
+            }),
+        };
+        op_manager
+            .notify_op_change(NetMessage::from(msg), OpEnum::Update(op))
+            .await?;
+        return Err(OpError::StatePushed);
+    }
+    Ok((None, Some(msg)))
+}
+
+/// Sends the update to all the given peers, dropping the connection to the ones that failed.
+///
+/// Returns the number of peers which successfully received the update.
+async fn broadcast_update<NB: NetworkBridge>(
+    conn_manager: &mut NB,
+    op_manager: &OpManager,
+    id: Transaction,
+    key: &ContractKey,
+    new_value: &UpdateValue,
+    broadcast_to: &[PeerKeyLocation],
+) -> Result<usize, OpError> {
+    let sender = op_manager.ring.own_location();
+    let mut broadcasting = Vec::with_capacity(broadcast_to.len());
+    for peer in broadcast_to.iter() {
+        let msg = UpdateMsg::BroadcastTo {
+            id,
+            key: key.clone(),
+            new_value: new_value.clone(),
+            sender,
+        };
+        let f = conn_manager.send(&peer.peer, msg.into());
+        broadcasting.push(f);
+    }
+    let error_futures = futures::future::join_all(broadcasting)
+        .await
+        .into_iter()
+        .enumerate()
+        .filter_map(|(p, err)| {
+            if let Err(err) = err {
+                Some((p, err))
+            } else {
+                None
+            }
+        });
+
+    let mut incorrect_results = 0;
+    for (peer_num, err) in error_futures {
+        let peer = broadcast_to.get(peer_num).unwrap();
+        tracing::warn!(
+            "failed broadcasting update change to {} with error {}; dropping connection",
+            peer.peer,
+            err
+        );
+        conn_manager.drop_connection(&peer.peer).await?;
+        incorrect_results += 1;
+    }
+    Ok(broadcast_to.len() - incorrect_results)
+}
+
+/// Converts the update data sent by a client into the value which is propagated through the network.
+///
+/// Updates to related contracts are not supported yet.
+pub(crate) fn client_update_value(
+    data: UpdateData<'_>,
+) -> Option<Either<WrappedState, StateDelta<'static>>> {
+    match data {
+        UpdateData::State(state) => Some(Either::Left(WrappedState::new(state.to_vec()))),
+        UpdateData::Delta(delta) => Some(Either::Right(delta.into_owned())),
+        // prefer propagating the delta, subscribers hold the previous state already
+        UpdateData::StateAndDelta { delta, .. } => Some(Either::Right(delta.into_owned())),
+        _ => None,
+    }
+}
+
+pub(crate) fn start_op(
+    key: ContractKey,
+    update: Either<WrappedState, StateDelta<'static>>,
+    htl: usize,
+) -> UpdateOp {
+    let contract_location = Location::from(&key);
+    tracing::debug!(%contract_location, %key, "Requesting update");
+
+    let id = Transaction::new::<UpdateMsg>();
+    let state = Some(UpdateState::PrepareRequest {
+        key,
+        value: UpdateValue::from(update),
+        htl,
+    });
+    UpdateOp { id, state }
+}
+
+/// Request to update the value of a contract.
 pub(crate) async fn request_update(
-    _op_manager: &OpManager,
-    _update_op: UpdateOp,
-    _client_id: Option<ClientId>,
+    op_manager: &OpManager,
+    update_op: UpdateOp,
+    client_id: Option<ClientId>,
 ) -> Result<(), OpError> {
-    todo!()
+    let id = update_op.id;
+    match update_op.state {
+        Some(UpdateState::PrepareRequest { key, value, htl }) => {
+            if let Some(client_id) = client_id {
+                let _ = op_manager
+                    .ch_outbound
+                    .waiting_for_transaction_result(id, client_id)
+                    .await;
+            }
+            let msg = UpdateMsg::RequestUpdate {
+                id,
+                key,
+                value,
+                htl,
+            };
+            // the state is re-initialized by the RequestUpdate message itself
+            let op = UpdateOp {
+                id,
+                state: Some(UpdateState::ReceivedRequest),
+            };
+            op_manager
+                .notify_op_change(NetMessage::from(msg), OpEnum::Update(op))
+                .await?;
+        }
+        _ => return Err(OpError::invalid_transition(id)),
+    };
+
+    Ok(())
+}
+
+async fn update_contract(
+    op_manager: &OpManager,
+    requester: &PeerId,
+    key: ContractKey,
+    value: UpdateValue,
+) -> Result<(WrappedState, StateSummary<'static>), OpError> {
+    let misbehaviour = match &value {
+        UpdateValue::State(_) => Misbehaviour::InvalidState,
+        UpdateValue::Delta(_) => Misbehaviour::InvalidDelta,
+    };
+    match op_manager
+        .notify_contract_handler_for(
+            requester,
+            ContractHandlerEvent::UpdateQuery {
+                key,
+                data: value.into_either(),
+                related_contracts: RelatedContracts::default(),
+            },
+        )
+        .await
+    {
+        Ok(ContractHandlerEvent::UpdateResponse {
+            new_value: Ok(new_val),
+            ..
+        }) => Ok(new_val),
+        Ok(ContractHandlerEvent::UpdateResponse {
+            key,
+            new_value: Err(err),
+        }) => {
+            if err.is_invalid() && requester != &op_manager.ring.peer_key {
+                op_manager
+                    .ring
+                    .report_misbehaviour(requester, Some(&key), misbehaviour)
+                    .await;
+            }
+            Err(err.into())
+        }
+        Err(err) => Err(err.into()),
+        Ok(_) => Err(OpError::UnexpectedOpState),
+    }
+}
+
+enum UpdateState {
+    ReceivedRequest,
+    PrepareRequest {
+        key: ContractKey,
+        value: UpdateValue,
+        htl: usize,
+    },
+    AwaitingResponse {
+        key: ContractKey,
+        upstream: Option<PeerKeyLocation>,
+    },
+    BroadcastOngoing {
+        summary: StateSummary<'static>,
+    },
+    Finished {
+        key: ContractKey,
+        summary: StateSummary<'static>,
+    },
 }
 
 mod messages {
     use std::fmt::Display;
 
+    use super::*;
+
     use serde::{Deserialize, Serialize};
 
-    use crate::{
-        message::{InnerMessage, Transaction},
-        ring::PeerKeyLocation,
-    };
+    /// The value of an update as sent over the network.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub(crate) enum UpdateValue {
+        State(WrappedState),
+        Delta(Vec<u8>),
+    }
+
+    impl UpdateValue {
+        pub fn into_either(self) -> Either<WrappedState, StateDelta<'static>> {
+            match self {
+                Self::State(state) => Either::Left(state),
+                Self::Delta(delta) => Either::Right(StateDelta::from(delta)),
+            }
+        }
+    }
+
+    impl From<Either<WrappedState, StateDelta<'_>>> for UpdateValue {
+        fn from(update: Either<WrappedState, StateDelta<'_>>) -> Self {
+            match update {
+                Either::Left(state) => Self::State(state),
+                Either::Right(delta) => Self::Delta(delta.to_vec()),
+            }
+        }
+    }
 
     #[derive(Debug, Serialize, Deserialize)]
-    pub(crate) enum UpdateMsg {}
+    pub(crate) enum UpdateMsg {
+        /// Internal node instruction to find a route to a peer seeding the contract.
+        RequestUpdate {
+            id: Transaction,
+            key: ContractKey,
+            value: UpdateValue,
+            /// max hops to live
+            htl: usize,
+        },
+        /// Route the update towards a peer seeding the contract.
+        SeekNode {
+            id: Transaction,
+            sender: PeerKeyLocation,
+            target: PeerKeyLocation,
+            key: ContractKey,
+            value: UpdateValue,
+            /// current htl, reduced by one at each hop
+            htl: usize,
+            skip_list: Vec<PeerId>,
+        },
+        /// Internal node instruction to broadcast an update to the subscribers of a contract.
+        Broadcasting {
+            id: Transaction,
+            broadcasted_to: usize,
+            broadcast_to: Vec<PeerKeyLocation>,
+            key: ContractKey,
+            new_value: UpdateValue,
+            upstream: PeerKeyLocation,
+        },
+        /// Broadcasting an update to a subscriber, which then will relay it to its own subscribers.
+        BroadcastTo {
+            id: Transaction,
+            sender: PeerKeyLocation,
+            key: ContractKey,
+            new_value: UpdateValue,
+        },
+        /// Update successfully applied by a seeding peer.
+        SuccessfulUpdate {
+            id: Transaction,
+            target: PeerKeyLocation,
+            /// summary of the state after merging the update
+            summary: Vec<u8>,
+        },
+    }
 
     impl InnerMessage for UpdateMsg {
         fn id(&self) -> &Transaction {
-            todo!()
+            match self {
+                Self::RequestUpdate { id, .. } => id,
+                Self::SeekNode { id, .. } => id,
+                Self::Broadcasting { id, .. } => id,
+                Self::BroadcastTo { id, .. } => id,
+                Self::SuccessfulUpdate { id, .. } => id,
+            }
+        }
+
+        fn id_mut(&mut self) -> &mut Transaction {
+            match self {
+                Self::RequestUpdate { id, .. } => id,
+                Self::SeekNode { id, .. } => id,
+                Self::Broadcasting { id, .. } => id,
+                Self::BroadcastTo { id, .. } => id,
+                Self::SuccessfulUpdate { id, .. } => id,
+            }
         }
 
         fn target(&self) -> Option<&PeerKeyLocation> {
-            todo!()
+            match self {
+                Self::SeekNode { target, .. } => Some(target),
+                Self::SuccessfulUpdate { target, .. } => Some(target),
+                _ => None,
+            }
         }
 
         fn terminal(&self) -> bool {
-            todo!()
+            use UpdateMsg::*;
+            matches!(self, SuccessfulUpdate { .. } | SeekNode { .. })
+        }
+
+        fn requested_location(&self) -> Option<Location> {
+            match self {
+                Self::RequestUpdate { key, .. } => Some(Location::from(key.id())),
+                Self::SeekNode { key, .. } => Some(Location::from(key.id())),
+                Self::Broadcasting { key, .. } => Some(Location::from(key.id())),
+                Self::BroadcastTo { key, .. } => Some(Location::from(key.id())),
+                _ => None,
+            }
         }
 
-        fn requested_location(&self) -> Option<crate::ring::Location> {
-            todo!()
+        fn requested_contract(&self) -> Option<ContractInstanceId> {
+            match self {
+                Self::RequestUpdate { key, .. } => Some(*key.id()),
+                Self::SeekNode { key, .. } => Some(*key.id()),
+                Self::Broadcasting { key, .. } => Some(*key.id()),
+                Self::BroadcastTo { key, .. } => Some(*key.id()),
+                _ => None,
+            }
+        }
+    }
+
+    impl UpdateMsg {
+        pub fn sender(&self) -> Option<&PeerKeyLocation> {
+            match self {
+                Self::SeekNode { sender, .. } => Some(sender),
+                Self::BroadcastTo { sender, .. } => Some(sender),
+                _ => None,
+            }
         }
     }
 
     impl Display for UpdateMsg {
-        fn fmt(&self, _: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            todo!()
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let id = self.id();
+            match self {
+                Self::RequestUpdate { .. } => write!(f, "RequestUpdate(id: {id})"),
+                Self::SeekNode { .. } => write!(f, "SeekNode(id: {id})"),
+                Self::Broadcasting { .. } => write!(f, "Broadcasting(id: {id})"),
+                Self::BroadcastTo { .. } => write!(f, "BroadcastTo(id: {id})"),
+                Self::SuccessfulUpdate { .. } => write!(f, "SuccessfulUpdate(id: {id})"),
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, time::Duration};
+
+    use freenet_stdlib::client_api::ContractRequest;
+    use freenet_stdlib::prelude::*;
+
+    use crate::{
+        node::testing_impl::{NodeSpecification, SimNetwork},
+        tracing::Property,
+    };
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn successful_update_op_between_nodes() -> Result<(), anyhow::Error> {
+        const NUM_NODES: usize = 2usize;
+        const NUM_GW: usize = 1usize;
+
+        let bytes = crate::util::test::random_bytes_1kb();
+        let mut gen = arbitrary::Unstructured::new(&bytes);
+        let contract: WrappedContract = gen.arbitrary()?;
+        let key = contract.key().clone();
+        let contract_val: WrappedState = gen.arbitrary()?;
+        let delta = StateDelta::from(Vec::from_iter(gen.arbitrary::<[u8; 20]>().unwrap()));
+
+        let mut sim_nw = SimNetwork::new(
+            "successful_update_op_between_nodes",
+            NUM_GW,
+            NUM_NODES,
+            3,
+            2,
+            3,
+            2,
+        )
+        .await;
+        let mut locations = sim_nw.get_locations_by_node();
+        let gw_loc = locations.remove(&"gateway-0".into()).unwrap();
+        let node2_loc = locations.remove(&"node-2".into()).unwrap();
+
+        // node-1 seeds the contract and the other peers are subscribed to it
+        let node_1 = NodeSpecification {
+            owned_contracts: vec![(
+                ContractContainer::Wasm(ContractWasmAPIVersion::V1(contract.clone())),
+                contract_val.clone(),
+                true,
+            )],
+            events_to_generate: HashMap::new(),
+            contract_subscribers: HashMap::from_iter([(key.clone(), vec![gw_loc, node2_loc])]),
+        };
+
+        let node_2 = NodeSpecification {
+            owned_contracts: vec![(
+                ContractContainer::Wasm(ContractWasmAPIVersion::V1(contract.clone())),
+                contract_val.clone(),
+                false,
+            )],
+            events_to_generate: HashMap::new(),
+            contract_subscribers: HashMap::new(),
+        };
+
+        let update_event = ContractRequest::Update {
+            key: key.clone(),
+            data: UpdateData::Delta(delta),
+        }
+        .into();
+
+        let gw_0 = NodeSpecification {
+            owned_contracts: vec![(
+                ContractContainer::Wasm(ContractWasmAPIVersion::V1(contract.clone())),
+                contract_val,
+                false,
+            )],
+            events_to_generate: HashMap::from_iter([(1, update_event)]),
+            contract_subscribers: HashMap::new(),
+        };
+
+        let update_specs = HashMap::from_iter([
+            ("node-1".into(), node_1),
+            ("node-2".into(), node_2),
+            ("gateway-0".into(), gw_0),
+        ]);
+
+        sim_nw.start_with_spec(update_specs).await;
+        sim_nw.check_connectivity(Duration::from_secs(3))?;
+
+        // trigger the update op @ gw-0
+        sim_nw
+            .trigger_event("gateway-0", 1, Some(Duration::from_secs(1)))
+            .await?;
+        assert!(sim_nw.has_updated_contract("gateway-0", &key));
+        assert!(sim_nw.event_listener.update_broadcasted(&key));
+        sim_nw
+            .check_properties(&[Property::HopsWithin(3), Property::SubscribersConverge])
+            .await?;
+        Ok(())
+    }
+}
@@ -7,7 +7,7 @@ use std::collections::VecDeque;
 use std::hash::Hash;
 use std::{
     cmp::Reverse,
-    collections::BTreeMap,
+    collections::{BTreeMap, HashSet},
     convert::TryFrom,
     fmt::Display,
     hash::Hasher,
@@ -20,9 +20,9 @@ use std::{
 };
 
 use anyhow::bail;
-use dashmap::{mapref::one::Ref as DmRef, DashMap};
+use dashmap::DashMap;
 use either::Either;
-use freenet_stdlib::prelude::{ContractInstanceId, ContractKey};
+use freenet_stdlib::prelude::{ContractInstanceId, ContractKey, DelegateKey};
 use parking_lot::RwLock;
 use rand::seq::SliceRandom;
 use rand::Rng;
@@ -30,19 +30,28 @@ use serde::{Deserialize, Serialize};
 use tokio::sync;
 use tracing::Instrument;
 
-use crate::message::TransactionType;
+mod peer_book;
+mod reputation;
+
+use crate::message::{NetMessage, TransactionType};
+use crate::topology::meter::{AttributionSource, ResourceType};
 use crate::topology::rate::Rate;
+use crate::topology::traffic::TrafficMeter;
 use crate::topology::{Limits, TopologyAdjustment, TopologyManager};
-use crate::tracing::{NetEventLog, NetEventRegister};
+use crate::tracing::{MessageCapture, NetEventLog, NetEventRegister};
 use crate::util::Contains;
 use crate::{
     config::GlobalExecutor,
+    contract::ExecutionUsage,
     message::Transaction,
     node::{self, EventLoopNotificationsSender, NodeConfig, PeerId},
     operations::connect,
     router::Router,
     DynError,
 };
+use peer_book::PeerBook;
+pub(crate) use reputation::Misbehaviour;
+use reputation::Reputation;
 
 #[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[cfg_attr(test, derive(arbitrary::Arbitrary))]
@@ -96,6 +105,8 @@ pub(crate) struct Connection {
 #[derive(Clone)]
 pub(crate) struct LiveTransactionTracker {
     tx_per_peer: Arc<DashMap<PeerId, Vec<Transaction>>>,
+    /// Peers a message was sent to for each transaction, and which did not answer since.
+    awaiting_reply: Arc<DashMap<Transaction, HashSet<PeerId>>>,
     missing_candidate_sender: sync::mpsc::Sender<PeerId>,
 }
 
@@ -114,9 +125,20 @@ impl LiveTransactionTracker {
 
     pub fn add_transaction(&self, peer: PeerId, tx: Transaction) {
         self.tx_per_peer.entry(peer).or_default().push(tx);
+        self.awaiting_reply.entry(tx).or_default().insert(peer);
+    }
+
+    /// A message for the transaction was received from the peer, so it is not awaited anymore.
+    pub fn replied(&self, peer: &PeerId, tx: &Transaction) {
+        self.awaiting_reply.remove_if_mut(tx, |_, awaited| {
+            awaited.remove(peer);
+            awaited.is_empty()
+        });
     }
 
-    pub fn remove_finished_transaction(&self, tx: Transaction) {
+    /// Returns the peers the transaction was still awaiting on: those a message was sent to
+    /// and which did not send anything back for the transaction since.
+    pub fn remove_finished_transaction(&self, tx: Transaction) -> Vec<PeerId> {
         let keys_to_remove: Vec<PeerId> = self
             .tx_per_peer
             .iter()
@@ -124,12 +146,16 @@ impl LiveTransactionTracker {
             .map(|entry| *entry.key())
             .collect();
 
-        for k in keys_to_remove {
-            self.tx_per_peer.remove_if_mut(&k, |_, v| {
+        for k in &keys_to_remove {
+            self.tx_per_peer.remove_if_mut(k, |_, v| {
                 v.retain(|otx| otx != &tx);
                 v.is_empty()
             });
         }
+        self.awaiting_reply
+            .remove(&tx)
+            .map(|(_, awaited)| awaited.into_iter().collect())
+            .unwrap_or_default()
     }
 
     fn new() -> (Self, sync::mpsc::Receiver<PeerId>) {
@@ -137,6 +163,7 @@ impl LiveTransactionTracker {
         (
             Self {
                 tx_per_peer: Arc::new(DashMap::default()),
+                awaiting_reply: Arc::new(DashMap::default()),
                 missing_candidate_sender: missing_peer,
             },
             rx,
@@ -187,6 +214,12 @@ pub(crate) struct Ring {
     pub min_connections: usize,
     router: Arc<RwLock<Router>>,
     topology_manager: RwLock<TopologyManager>,
+    /// Traffic exchanged with other peers not yet reported to the topology manager.
+    traffic: TrafficMeter,
+    /// Operations started on behalf of a delegate, whose traffic is attributed to it too.
+    delegate_transactions: DashMap<Transaction, DelegateKey>,
+    /// Peers known from this and previous runs, to rejoin the ring through them.
+    pub peer_book: PeerBook,
     connections_by_location: RwLock<BTreeMap<Location, Vec<Connection>>>,
     location_for_peer: RwLock<BTreeMap<PeerId, Location>>,
     own_location: AtomicU64,
@@ -202,24 +235,17 @@ pub(crate) struct Ring {
     /// Is important to keep track of this so no more connections are accepted prematurely.
     open_connections: AtomicUsize,
     pub live_tx_tracker: LiveTransactionTracker,
-    // A peer which has been blacklisted to perform actions regarding a given contract.
-    // todo: add blacklist
-    // contract_blacklist: Arc<DashMap<ContractKey, Vec<Blacklisted>>>,
+    /// Misbehaviour of other peers, and whether they are blacklisted because of it.
+    reputation: Reputation,
     event_register: Box<dyn NetEventRegister>,
+    /// Set when the traffic of the node is being captured.
+    capture: Option<MessageCapture>,
     /// Whether this peer is a gateway or not. This will affect behavior of the node when acquiring
     /// and dropping connections.
     #[allow(unused)]
     is_gateway: bool,
 }
 
-// /// A data type that represents the fact that a peer has been blacklisted
-// /// for some action. Has to be coupled with that action
-// #[derive(Debug)]
-// struct Blacklisted {
-//     since: Instant,
-//     peer: PeerKey,
-// }
-
 impl Ring {
     const DEFAULT_MIN_CONNECTIONS: usize = 5;
 
@@ -229,6 +255,13 @@ impl Ring {
 
     const DEFAULT_MAX_DOWNSTREAM_BANDWIDTH: Rate = Rate::new_per_second(1_000_000.0);
 
+    /// Fuel spent executing contracts per second, about half of a core.
+    const DEFAULT_MAX_EXECUTION_FUEL: Rate = Rate::new_per_second(500_000_000.0);
+
+    const DEFAULT_MAX_STORAGE: Rate = Rate::new_per_second(1_000_000.0);
+
+    const DEFAULT_MAX_WASM_MEMORY: Rate = Rate::new_per_second(100_000_000.0);
+
     /// Max number of subscribers for a contract.
     const MAX_SUBSCRIBERS: usize = 10;
 
@@ -247,10 +280,14 @@ impl Ring {
     /// Min number of seeding contracts.
     const MIN_SEEDING_CONTRACTS: usize = Self::MAX_SEEDING_CONTRACTS / 4;
 
+    /// Max distance to a contract at which a peer seeds it while it has room for more contracts.
+    pub(crate) const CACHING_DISTANCE: f64 = 0.05;
+
     pub fn new<ER: NetEventRegister + Clone>(
         config: &NodeConfig,
         event_loop_notifier: EventLoopNotificationsSender,
         event_register: ER,
+        capture: Option<MessageCapture>,
         is_gateway: bool,
     ) -> Result<Arc<Self>, anyhow::Error> {
         let (live_tx_tracker, missing_candidate_rx) = LiveTransactionTracker::new();
@@ -296,13 +333,39 @@ impl Ring {
             Self::DEFAULT_MAX_DOWNSTREAM_BANDWIDTH
         };
 
+        let max_execution_fuel = if let Some(v) = config.max_execution_fuel {
+            v
+        } else {
+            Self::DEFAULT_MAX_EXECUTION_FUEL
+        };
+
+        let max_storage = if let Some(v) = config.max_storage {
+            v
+        } else {
+            Self::DEFAULT_MAX_STORAGE
+        };
+
+        let max_wasm_memory = if let Some(v) = config.max_wasm_memory {
+            v
+        } else {
+            Self::DEFAULT_MAX_WASM_MEMORY
+        };
+
         let topology_manager = RwLock::new(TopologyManager::new(Limits {
             max_upstream_bandwidth,
             max_downstream_bandwidth,
+            max_execution_fuel,
+            max_storage,
+            max_wasm_memory,
             min_connections,
             max_connections,
         }));
 
+        let peer_book = PeerBook::load(config.peer_book.clone()).unwrap_or_else(|error| {
+            tracing::warn!(%error, "Failed loading the peer book, starting with an empty one");
+            PeerBook::new(config.peer_book.clone())
+        });
+
         let router = Arc::new(RwLock::new(Router::new(&[])));
         GlobalExecutor::spawn(Self::refresh_router(router.clone(), event_register.clone()));
 
@@ -314,6 +377,9 @@ impl Ring {
             min_connections,
             router,
             topology_manager,
+            traffic: TrafficMeter::default(),
+            delegate_transactions: DashMap::new(),
+            peer_book,
             connections_by_location: RwLock::new(BTreeMap::new()),
             location_for_peer: RwLock::new(BTreeMap::new()),
             own_location,
@@ -322,7 +388,9 @@ impl Ring {
             seeding_contract: DashMap::new(),
             open_connections: AtomicUsize::new(0),
             live_tx_tracker: live_tx_tracker.clone(),
+            reputation: Reputation::default(),
             event_register: Box::new(event_register),
+            capture,
             is_gateway,
         };
 
@@ -331,6 +399,9 @@ impl Ring {
                 return Err(anyhow::anyhow!("IP and port are required for gateways"));
             }
             ring.update_location(Some(loc));
+        } else if let Some(loc) = ring.peer_book.own_location() {
+            // rejoin the ring at the same location as in the previous run
+            ring.update_location(Some(loc));
         }
 
         let ring = Arc::new(ring);
@@ -377,8 +448,7 @@ impl Ring {
 
     /// Return if a contract is within appropiate seeding distance.
     pub fn should_seed(&self, key: &ContractKey) -> bool {
-        const CACHING_DISTANCE: f64 = 0.05;
-        let caching_distance = Distance::new(CACHING_DISTANCE);
+        let caching_distance = Distance::new(Self::CACHING_DISTANCE);
         if self.seeding_contract.len() < Self::MIN_SEEDING_CONTRACTS {
             return true;
         }
@@ -439,7 +509,11 @@ impl Ring {
 
     /// Update this node location.
     pub fn update_location(&self, loc: Option<Location>) {
+        self.peer_book.set_own_location(loc);
         if let Some(loc) = loc {
+            if let Some(capture) = &self.capture {
+                capture.located(loc);
+            }
             self.own_location.store(
                 u64::from_le_bytes(loc.0.to_le_bytes()),
                 std::sync::atomic::Ordering::Release,
@@ -476,6 +550,10 @@ impl Ring {
     /// # Panic
     /// Will panic if the node checking for this condition has no location assigned.
     pub fn should_accept(&self, location: Location, peer: &PeerId) -> bool {
+        if self.reputation.is_blacklisted(peer, None, Instant::now()) {
+            tracing::debug!(%peer, "Rejecting connection from blacklisted peer");
+            return false;
+        }
         let open_conn = self
             .open_connections
             .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
@@ -495,7 +573,9 @@ impl Ring {
             false
         } else if open_conn < self.min_connections {
             true
-        } else if open_conn >= self.max_connections {
+        } else if open_conn >= self.max_connections
+            || self.topology_manager.write().over_capacity(Instant::now())
+        {
             false
         } else {
             self.topology_manager
@@ -521,6 +601,93 @@ impl Ring {
             .record_request(recipient, target, request_type);
     }
 
+    /// Accounts the bytes of a message exchanged with a connected peer as bandwidth usage,
+    /// attributed too to the contract the message was about and to the delegate its operation
+    /// was started for, if any.
+    pub fn record_traffic(
+        &self,
+        peer: &PeerId,
+        msg: Option<&NetMessage>,
+        resource: ResourceType,
+        bytes: usize,
+    ) {
+        let Some(location) = self.location_for_peer.read().get(peer).copied() else {
+            return;
+        };
+        let peer = PeerKeyLocation {
+            peer: *peer,
+            location: Some(location),
+        };
+        let contract = msg.and_then(NetMessage::requested_contract);
+        let delegate = msg.and_then(|msg| {
+            self.delegate_transactions
+                .get(msg.id())
+                .map(|entry| entry.value().clone())
+        });
+        let attributions = [
+            Some(AttributionSource::Peer(peer)),
+            contract.map(AttributionSource::Contract),
+            delegate.map(AttributionSource::Delegate),
+        ];
+        self.traffic
+            .record(attributions.into_iter().flatten(), resource, bytes);
+    }
+
+    /// Attributes the traffic of an operation to the delegate it was started for, until
+    /// [`Self::delegate_transaction_done`] is called.
+    pub fn delegate_transaction(&self, transaction: Transaction, delegate: DelegateKey) {
+        self.delegate_transactions.insert(transaction, delegate);
+    }
+
+    pub fn delegate_transaction_done(&self, transaction: &Transaction) {
+        self.delegate_transactions.remove(transaction);
+    }
+
+    /// Accounts the resources spent executing contract code on behalf of a connected peer.
+    pub fn record_execution(&self, peer: &PeerId, usage: ExecutionUsage) {
+        let Some(location) = self.location_for_peer.read().get(peer).copied() else {
+            return;
+        };
+        let peer = PeerKeyLocation {
+            peer: *peer,
+            location: Some(location),
+        };
+        self.record_usage(AttributionSource::Peer(peer), usage);
+    }
+
+    /// Accounts the resources spent executing a delegate.
+    pub fn record_delegate_execution(&self, delegate: DelegateKey, usage: ExecutionUsage) {
+        self.record_usage(AttributionSource::Delegate(delegate), usage);
+    }
+
+    fn record_usage(&self, source: AttributionSource, usage: ExecutionUsage) {
+        let usage = [
+            (ResourceType::ExecutionFuel, usage.fuel as usize),
+            (ResourceType::StorageBytes, usage.storage),
+            (ResourceType::WasmMemoryBytes, usage.wasm_memory),
+        ];
+        for (resource, amount) in usage {
+            self.traffic.record([source.clone()], resource, amount);
+        }
+    }
+
+    /// Reports the usage recorded since the last report to the topology manager.
+    fn report_traffic(&self) {
+        let traffic = self.traffic.take();
+        let now = Instant::now();
+        let location_for_peer = self.location_for_peer.read();
+        let topology_manager = &mut *self.topology_manager.write();
+        for ((source, resource), bytes) in traffic {
+            if let AttributionSource::Peer(peer) = &source {
+                // the peer may have been disconnected since the traffic was recorded
+                if !location_for_peer.contains_key(&peer.peer) {
+                    continue;
+                }
+            }
+            topology_manager.report_resource_usage(&source, resource, bytes, now);
+        }
+    }
+
     pub fn add_connection(&self, loc: Location, peer: PeerId) {
         let mut cbl = self.connections_by_location.write();
         self.event_register
@@ -534,6 +701,10 @@ impl Ring {
         });
         self.location_for_peer.write().insert(peer, loc);
         std::mem::drop(cbl);
+        if let Some(capture) = &self.capture {
+            capture.connected(peer, loc);
+        }
+        self.peer_book.record_connected(peer, loc);
         self.refresh_density_request_cache()
     }
 
@@ -550,7 +721,12 @@ impl Ring {
         contract_key: &ContractKey,
         skip_list: impl Contains<PeerId>,
     ) -> Option<PeerKeyLocation> {
-        self.routing(Location::from(contract_key), None, skip_list)
+        self.route(
+            Location::from(contract_key),
+            None,
+            Some(contract_key),
+            skip_list,
+        )
     }
 
     /// Route an op to the most optimal target.
@@ -560,6 +736,18 @@ impl Ring {
         requesting: Option<&PeerId>,
         skip_list: impl Contains<PeerId>,
     ) -> Option<PeerKeyLocation> {
+        self.route(target, requesting, None, skip_list)
+    }
+
+    /// Route an op to the most optimal target, skipping peers blacklisted for the contract.
+    fn route(
+        &self,
+        target: Location,
+        requesting: Option<&PeerId>,
+        contract: Option<&ContractKey>,
+        skip_list: impl Contains<PeerId>,
+    ) -> Option<PeerKeyLocation> {
+        let now = Instant::now();
         let connections = self.connections_by_location.read();
         let peers = connections.values().filter_map(|conns| {
             let conn = conns.choose(&mut rand::thread_rng()).unwrap();
@@ -568,6 +756,12 @@ impl Ring {
                     return None;
                 }
             }
+            if self
+                .reputation
+                .is_blacklisted(&conn.location.peer, contract, now)
+            {
+                return None;
+            }
             (!skip_list.has_element(&conn.location.peer)).then_some(&conn.location)
         });
         let router = &*self.router.read();
@@ -643,17 +837,51 @@ impl Ring {
         Ok(())
     }
 
-    pub fn subscribers_of(
+    /// Subscribers of the contract, excluding those blacklisted for it.
+    pub fn subscribers_of(&self, contract: &ContractKey) -> Option<Vec<PeerKeyLocation>> {
+        let now = Instant::now();
+        self.subscribers.get(contract).map(|subs| {
+            subs.iter()
+                .filter(|sub| {
+                    !self
+                        .reputation
+                        .is_blacklisted(&sub.peer, Some(contract), now)
+                })
+                .copied()
+                .collect()
+        })
+    }
+
+    /// Records a misbehaviour of another peer, blacklisting it if it has misbehaved too often.
+    pub async fn report_misbehaviour(
         &self,
-        contract: &ContractKey,
-    ) -> Option<DmRef<ContractKey, Vec<PeerKeyLocation>>> {
-        self.subscribers.get(contract)
+        peer: &PeerId,
+        contract: Option<&ContractKey>,
+        misbehaviour: Misbehaviour,
+    ) {
+        tracing::debug!(%peer, ?contract, ?misbehaviour, "Peer misbehaved");
+        let blacklisted = self
+            .reputation
+            .report(*peer, contract, misbehaviour, Instant::now());
+        let mut events = vec![NetEventLog::misbehaved(self, peer, contract, misbehaviour)];
+        for scope in blacklisted {
+            tracing::warn!(%peer, contract = ?scope, "Blacklisting misbehaving peer");
+            events.push(NetEventLog::blacklisted(self, peer, scope));
+        }
+        self.event_register
+            .register_events(Either::Right(events))
+            .await;
     }
 
     pub fn num_connections(&self) -> usize {
         self.connections_by_location.read().len()
     }
 
+    /// Whether there is an open connection to the peer.
+    pub fn is_connected(&self, peer: &PeerId) -> bool {
+        self.location_for_peer.read().contains_key(peer)
+    }
+
     pub fn prune_connection(&self, peer: PeerId) {
         #[cfg(debug_assertions)]
         {
@@ -661,6 +889,12 @@ impl Ring {
         }
         self.live_tx_tracker.prune_transactions_from_peer(&peer);
         let loc = self.location_for_peer.write().remove(&peer).unwrap();
+        self.topology_manager
+            .write()
+            .remove_source(&AttributionSource::Peer(PeerKeyLocation {
+                peer,
+                location: Some(loc),
+            }));
         {
             let conns = &mut *self.connections_by_location.write();
             conns.remove(&loc);
@@ -673,6 +907,10 @@ impl Ring {
                 subs
             });
         }
+        self.peer_book.record_disconnected(&peer);
+        if let Some(capture) = &self.capture {
+            capture.disconnected(peer);
+        }
         self.event_register
             .register_events(Either::Left(NetEventLog::disconnected(self, &peer)));
         self.open_connections
@@ -787,6 +1025,11 @@ impl Ring {
                     .collect()
             };
 
+            if let Err(error) = self.peer_book.persist() {
+                tracing::warn!(%error, "Failed persisting the peer book");
+            }
+            self.report_traffic();
+            self.reputation.prune(Instant::now());
             let adjustment = self.topology_manager.write().adjust_topology(
                 &neighbor_locations,
                 &self.own_location().location,
@@ -1074,4 +1317,101 @@ mod test {
         let l1 = Location(0.50);
         assert!(l0.distance(l1) == Distance(0.25));
     }
+
+    #[test]
+    fn only_unanswered_peers_are_awaited() {
+        let (tracker, _rx) = LiveTransactionTracker::new();
+        let tx = Transaction::new::<crate::operations::get::GetMsg>();
+        let (answered, silent) = (PeerId::random(), PeerId::random());
+        tracker.add_transaction(answered, tx);
+        tracker.add_transaction(silent, tx);
+        tracker.replied(&answered, &tx);
+        assert_eq!(tracker.remove_finished_transaction(tx), vec![silent]);
+        assert!(tracker.remove_finished_transaction(tx).is_empty());
+    }
+
+    /// A peer using more bandwidth than this peer can afford ends up being disconnected.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn prunes_bandwidth_hogs() -> Result<(), anyhow::Error> {
+        use crate::node::testing_impl::{Fault, NodeLabel, SimNetwork};
+
+        const NUM_NODES: usize = 3usize;
+        const NUM_GW: usize = 1usize;
+        const MAX_HTL: usize = 3usize;
+        const RAND_IF_HTL_ABOVE: usize = 2usize;
+        const MAX_CONNS: usize = 4usize;
+        const MIN_CONNS: usize = 1usize;
+        let mut sim_nw = SimNetwork::new(
+            "prunes_bandwidth_hogs",
+            NUM_GW,
+            NUM_NODES,
+            MAX_HTL,
+            RAND_IF_HTL_ABOVE,
+            MAX_CONNS,
+            MIN_CONNS,
+        )
+        .await;
+        let gateway = NodeLabel::from("gateway-0");
+        let hog = NodeLabel::from("node-1");
+        // twice the default downstream bandwidth limit of the gateway
+        let rate = 2 * Ring::DEFAULT_MAX_DOWNSTREAM_BANDWIDTH.per_second() as u64;
+        sim_nw.schedule_fault(
+            Duration::from_secs(3),
+            Fault::Flood(hog.clone(), gateway.clone(), rate),
+        );
+        sim_nw.start().await;
+        sim_nw.check_connectivity(Duration::from_secs(3))?;
+
+        // usage is only acted upon once the hog is done ramping up
+        for _ in 0..60 {
+            if sim_nw.disconnected(&gateway, &hog) {
+                return Ok(());
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        anyhow::bail!("the gateway didn't disconnect from {hog}")
+    }
+
+    /// A peer sending malformed messages ends up blacklisted, while honest ones don't.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn blacklists_misbehaving_peers() -> Result<(), anyhow::Error> {
+        use crate::node::testing_impl::{Fault, NodeLabel, SimNetwork};
+
+        const NUM_NODES: usize = 3usize;
+        const NUM_GW: usize = 1usize;
+        const MAX_HTL: usize = 3usize;
+        const RAND_IF_HTL_ABOVE: usize = 2usize;
+        const MAX_CONNS: usize = 4usize;
+        const MIN_CONNS: usize = 1usize;
+        let mut sim_nw = SimNetwork::new(
+            "blacklists_misbehaving_peers",
+            NUM_GW,
+            NUM_NODES,
+            MAX_HTL,
+            RAND_IF_HTL_ABOVE,
+            MAX_CONNS,
+            MIN_CONNS,
+        )
+        .await;
+        let gateway = NodeLabel::from("gateway-0");
+        let offender = NodeLabel::from("node-1");
+        // each one is a protocol violation, together well over the blacklisting threshold
+        sim_nw.schedule_fault(
+            Duration::from_secs(3),
+            Fault::Malformed(offender.clone(), gateway.clone(), 20),
+        );
+        sim_nw.start().await;
+        sim_nw.check_connectivity(Duration::from_secs(3))?;
+
+        for _ in 0..30 {
+            if sim_nw.blacklisted(&gateway, &offender) {
+                for honest in ["node-0", "node-2"] {
+                    assert!(!sim_nw.blacklisted(&gateway, &NodeLabel::from(honest)));
+                }
+                return Ok(());
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        anyhow::bail!("the gateway didn't blacklist {offender}")
+    }
 }
@@ -0,0 +1,286 @@
+//! Peers known from previous runs of the node, persisted so the node can rejoin the ring
+//! through them even when the gateways are unreachable.
+
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use libp2p::Multiaddr;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::node::PeerId;
+
+use super::{Location, PeerKeyLocation};
+
+/// Max number of peers kept in the book, the least recently seen are forgotten first.
+const MAX_KNOWN_PEERS: usize = 128;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KnownPeer {
+    location: Option<Location>,
+    #[serde(with = "multiaddrs")]
+    addrs: Vec<Multiaddr>,
+    last_seen: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Book {
+    own_location: Option<Location>,
+    peers: HashMap<PeerId, KnownPeer>,
+    /// Whether there are changes not persisted yet.
+    #[serde(skip)]
+    changed: bool,
+}
+
+/// Known peers with their last location and the addresses they listen on.
+///
+/// Changes are only kept in memory until [`PeerBook::persist`] is called, and never written
+/// anywhere if the book has no backing file.
+pub(crate) struct PeerBook {
+    path: Option<PathBuf>,
+    book: Mutex<Book>,
+}
+
+impl PeerBook {
+    /// An empty book, which will be persisted to the given file.
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self {
+            path,
+            book: Mutex::new(Book::default()),
+        }
+    }
+
+    /// Loads the book from the given file, starts with an empty book if it does not exist yet.
+    pub fn load(path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let book = match &path {
+            Some(path) if path.exists() => bincode::deserialize(&std::fs::read(path)?)?,
+            _ => Book::default(),
+        };
+        Ok(Self {
+            path,
+            book: Mutex::new(book),
+        })
+    }
+
+    /// Location of this peer the last time it was in the ring.
+    pub fn own_location(&self) -> Option<Location> {
+        self.book.lock().own_location
+    }
+
+    pub fn set_own_location(&self, location: Option<Location>) {
+        self.update(|book| book.own_location = location);
+    }
+
+    pub fn record_connected(&self, peer: PeerId, location: Location) {
+        self.update(|book| {
+            let known = book.peers.entry(peer).or_insert_with(|| KnownPeer {
+                location: None,
+                addrs: vec![],
+                last_seen: Utc::now(),
+            });
+            known.location = Some(location);
+            known.last_seen = Utc::now();
+        });
+    }
+
+    pub fn record_disconnected(&self, peer: &PeerId) {
+        self.update(|book| {
+            if let Some(known) = book.peers.get_mut(peer) {
+                known.last_seen = Utc::now();
+            }
+        });
+    }
+
+    /// Records the addresses a peer is listening on, as announced by the peer itself.
+    pub fn record_addresses(&self, peer: PeerId, addrs: Vec<Multiaddr>) {
+        if addrs.is_empty() {
+            return;
+        }
+        self.update(|book| {
+            let known = book.peers.entry(peer).or_insert_with(|| KnownPeer {
+                location: None,
+                addrs: vec![],
+                last_seen: Utc::now(),
+            });
+            known.addrs = addrs;
+        });
+    }
+
+    /// Peers with a known location and addresses, the most recently seen first.
+    pub fn known_peers(&self) -> Vec<(PeerKeyLocation, Vec<Multiaddr>)> {
+        let book = self.book.lock();
+        let mut peers: Vec<_> = book
+            .peers
+            .iter()
+            .filter(|(_, known)| known.location.is_some() && !known.addrs.is_empty())
+            .collect();
+        peers.sort_by_key(|(_, known)| std::cmp::Reverse(known.last_seen));
+        peers
+            .into_iter()
+            .map(|(peer, known)| {
+                let peer = PeerKeyLocation {
+                    peer: *peer,
+                    location: known.location,
+                };
+                (peer, known.addrs.clone())
+            })
+            .collect()
+    }
+
+    /// Writes the book to its file, if there were any changes since the last time.
+    pub fn persist(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let serialized = {
+            let mut book = self.book.lock();
+            if !book.changed {
+                return Ok(());
+            }
+            book.changed = false;
+            bincode::serialize(&*book)?
+        };
+        write_atomically(path, &serialized)?;
+        Ok(())
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Book)) {
+        let book = &mut *self.book.lock();
+        f(book);
+        while book.peers.len() > MAX_KNOWN_PEERS {
+            let Some(oldest) = book
+                .peers
+                .iter()
+                .min_by_key(|(_, known)| known.last_seen)
+                .map(|(peer, _)| *peer)
+            else {
+                break;
+            };
+            book.peers.remove(&oldest);
+        }
+        book.changed = true;
+    }
+}
+
+/// Writes to a temporary file first so a crash never leaves a truncated book behind, syncing
+/// both the file and the rename to disk so it survives a power loss too.
+fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    {
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+    }
+    std::fs::rename(&tmp_path, path)?;
+    // directories can't be opened for syncing on windows
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        std::fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+mod multiaddrs {
+    use libp2p::Multiaddr;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S>(addrs: &[Multiaddr], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let addrs: Vec<_> = addrs.iter().map(|addr| addr.to_vec()).collect();
+        addrs.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<Multiaddr>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let addrs: Vec<Vec<u8>> = Deserialize::deserialize(deserializer)?;
+        addrs
+            .into_iter()
+            .map(|addr| Multiaddr::try_from(addr).map_err(serde::de::Error::custom))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use libp2p::multiaddr::Protocol;
+
+    use super::*;
+
+    fn addr(port: u16) -> Multiaddr {
+        Multiaddr::from(Ipv4Addr::LOCALHOST).with(Protocol::Tcp(port))
+    }
+
+    #[test]
+    fn persists_known_peers() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("peer_book");
+        let (first, second, unaddressed) = (PeerId::random(), PeerId::random(), PeerId::random());
+
+        let book = PeerBook::load(Some(path.clone()))?;
+        book.set_own_location(Some(Location::new(0.5)));
+        book.record_addresses(first, vec![addr(1000)]);
+        book.record_connected(first, Location::new(0.1));
+        book.record_addresses(second, vec![addr(2000)]);
+        book.record_connected(second, Location::new(0.2));
+        book.record_connected(unaddressed, Location::new(0.3));
+        book.record_disconnected(&first);
+        book.persist()?;
+
+        let book = PeerBook::load(Some(path))?;
+        assert_eq!(book.own_location(), Some(Location::new(0.5)));
+        let known = book.known_peers();
+        assert_eq!(
+            known,
+            vec![
+                (
+                    PeerKeyLocation {
+                        peer: first,
+                        location: Some(Location::new(0.1)),
+                    },
+                    vec![addr(1000)]
+                ),
+                (
+                    PeerKeyLocation {
+                        peer: second,
+                        location: Some(Location::new(0.2)),
+                    },
+                    vec![addr(2000)]
+                ),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn forgets_least_recently_seen() -> anyhow::Result<()> {
+        let book = PeerBook::new(None);
+        let oldest = PeerId::random();
+        book.record_addresses(oldest, vec![addr(1000)]);
+        book.record_connected(oldest, Location::new(0.1));
+        for _ in 0..MAX_KNOWN_PEERS {
+            let peer = PeerId::random();
+            book.record_addresses(peer, vec![addr(2000)]);
+            book.record_connected(peer, Location::new(0.2));
+        }
+        let known = book.known_peers();
+        assert_eq!(known.len(), MAX_KNOWN_PEERS);
+        assert!(known.iter().all(|(peer, _)| peer.peer != oldest));
+        // nothing is written without a backing file
+        book.persist()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,198 @@
+//! Reputation of other peers, based on how they misbehaved in the past.
+//!
+//! Each misbehaviour adds a penalty to the score of the offending peer, both for the contract
+//! involved (if any) and for the peer as a whole. Scores decay over time, so only peers which keep
+//! misbehaving end up over the threshold and get blacklisted for a while.
+
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use freenet_stdlib::prelude::ContractKey;
+use serde::{Deserialize, Serialize};
+
+use crate::node::PeerId;
+
+/// Time it takes for a score to drop to half of its value.
+const HALF_LIFE: Duration = Duration::from_secs(10 * 60);
+
+/// Score over which a peer is blacklisted for a given contract.
+const CONTRACT_THRESHOLD: f64 = 20.0;
+
+/// Score over which a peer is blacklisted for everything.
+const GLOBAL_THRESHOLD: f64 = 50.0;
+
+#[cfg(not(test))]
+const BLACKLIST_DURATION: Duration = Duration::from_secs(30 * 60);
+#[cfg(test)]
+const BLACKLIST_DURATION: Duration = Duration::from_secs(5);
+
+/// Scores which decayed below this value are forgotten.
+const FORGET_BELOW: f64 = 0.1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(test, derive(arbitrary::Arbitrary))]
+pub(crate) enum Misbehaviour {
+    /// Sent a contract state which did not pass the contract validation.
+    InvalidState,
+    /// Sent a delta which did not pass the contract validation.
+    InvalidDelta,
+    /// Did not answer to a request before the transaction timed out.
+    Timeout,
+    /// Sent a message which was not expected in the current state of the operation.
+    ProtocolViolation,
+}
+
+impl Misbehaviour {
+    fn penalty(self) -> f64 {
+        match self {
+            Misbehaviour::InvalidState => 10.0,
+            Misbehaviour::InvalidDelta => 5.0,
+            Misbehaviour::Timeout => 1.0,
+            Misbehaviour::ProtocolViolation => 5.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Standing {
+    score: f64,
+    updated: Instant,
+    blacklisted_until: Option<Instant>,
+}
+
+impl Standing {
+    fn decay(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated);
+        self.score *= 0.5f64.powf(elapsed.as_secs_f64() / HALF_LIFE.as_secs_f64());
+        self.updated = now;
+    }
+
+    fn is_blacklisted(&self, now: Instant) -> bool {
+        self.blacklisted_until.is_some_and(|until| until > now)
+    }
+}
+
+/// Misbehaviour scores of other peers, keyed by peer and optionally by contract; the entry
+/// without contract being the global score of the peer.
+#[derive(Default)]
+pub(crate) struct Reputation {
+    standings: DashMap<(PeerId, Option<ContractKey>), Standing>,
+}
+
+impl Reputation {
+    /// Records a misbehaviour of the peer, returns for which scopes (a contract, or globally if
+    /// `None`) the peer has just been blacklisted.
+    pub fn report(
+        &self,
+        peer: PeerId,
+        contract: Option<&ContractKey>,
+        misbehaviour: Misbehaviour,
+        now: Instant,
+    ) -> Vec<Option<ContractKey>> {
+        let mut scopes = vec![(None, GLOBAL_THRESHOLD)];
+        if let Some(contract) = contract {
+            scopes.push((Some(contract.clone()), CONTRACT_THRESHOLD));
+        }
+        scopes
+            .into_iter()
+            .filter_map(|(scope, threshold)| {
+                let mut standing =
+                    self.standings
+                        .entry((peer, scope.clone()))
+                        .or_insert_with(|| Standing {
+                            score: 0.0,
+                            updated: now,
+                            blacklisted_until: None,
+                        });
+                if standing.is_blacklisted(now) {
+                    return None;
+                }
+                standing.decay(now);
+                standing.score += misbehaviour.penalty();
+                if standing.score < threshold {
+                    return None;
+                }
+                standing.score = 0.0;
+                standing.blacklisted_until = Some(now + BLACKLIST_DURATION);
+                Some(scope)
+            })
+            .collect()
+    }
+
+    /// Whether the peer is blacklisted, either globally or for the given contract.
+    pub fn is_blacklisted(
+        &self,
+        peer: &PeerId,
+        contract: Option<&ContractKey>,
+        now: Instant,
+    ) -> bool {
+        let blacklisted = |scope: Option<ContractKey>| {
+            self.standings
+                .get(&(*peer, scope))
+                .is_some_and(|standing| standing.is_blacklisted(now))
+        };
+        blacklisted(None) || contract.is_some_and(|contract| blacklisted(Some(contract.clone())))
+    }
+
+    /// Forgets peers which are no longer blacklisted and whose score decayed away.
+    pub fn prune(&self, now: Instant) {
+        self.standings.retain(|_, standing| {
+            standing.decay(now);
+            standing.is_blacklisted(now) || standing.score >= FORGET_BELOW
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use freenet_stdlib::prelude::ContractInstanceId;
+
+    use super::*;
+
+    fn contract_key() -> ContractKey {
+        ContractKey::from(ContractInstanceId::new([1; 32]))
+    }
+
+    #[test]
+    fn blacklists_per_contract_then_globally() {
+        let reputation = Reputation::default();
+        let (peer, key, now) = (PeerId::random(), contract_key(), Instant::now());
+
+        assert!(reputation
+            .report(peer, Some(&key), Misbehaviour::InvalidState, now)
+            .is_empty());
+        assert_eq!(
+            reputation.report(peer, Some(&key), Misbehaviour::InvalidState, now),
+            vec![Some(key.clone())]
+        );
+        assert!(reputation.is_blacklisted(&peer, Some(&key), now));
+        assert!(!reputation.is_blacklisted(&peer, None, now));
+        assert!(!reputation.is_blacklisted(&PeerId::random(), Some(&key), now));
+
+        for _ in 0..2 {
+            reputation.report(peer, None, Misbehaviour::InvalidState, now);
+        }
+        assert_eq!(
+            reputation.report(peer, None, Misbehaviour::InvalidState, now),
+            vec![None]
+        );
+        assert!(reputation.is_blacklisted(&peer, None, now));
+
+        let later = now + BLACKLIST_DURATION;
+        assert!(!reputation.is_blacklisted(&peer, Some(&key), later));
+    }
+
+    #[test]
+    fn scores_decay_over_time() {
+        let reputation = Reputation::default();
+        let (peer, now) = (PeerId::random(), Instant::now());
+        for hour in 0..100 {
+            let at = now + Duration::from_secs(hour * 60 * 60);
+            assert!(reputation
+                .report(peer, None, Misbehaviour::ProtocolViolation, at)
+                .is_empty());
+        }
+        reputation.prune(now + Duration::from_secs(101 * 60 * 60));
+        assert!(reputation.standings.is_empty());
+    }
+}
@@ -122,7 +122,7 @@ pub mod local_node {
                 ClientRequest::DelegateOp(op) => {
                     let attested_contract =
                         token.and_then(|token| gw.attested_contracts.get(&token).map(|(t, _)| t));
-                    executor.delegate_request(op, attested_contract)
+                    executor.delegate_request(op, attested_contract).await
                 }
                 ClientRequest::Disconnect { cause } => {
                     if let Some(cause) = cause {
@@ -150,7 +150,16 @@ impl ClientEventsProxy for HttpGateway {
                         client_id,
                         req,
                         auth_token,
-                    } => return Ok(OpenRequest::new(client_id, req).with_token(auth_token)),
+                    } => {
+                        let attested_contract = auth_token.as_ref().and_then(|token| {
+                            self.attested_contracts
+                                .get(token)
+                                .map(|(contract, _)| *contract)
+                        });
+                        return Ok(OpenRequest::new(client_id, req)
+                            .with_token(auth_token)
+                            .with_attested_contract(attested_contract));
+                    }
                 }
             }
             tracing::warn!("Shutting down http gateway receiver");
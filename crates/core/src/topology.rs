@@ -19,6 +19,7 @@ pub(crate) mod rate;
 pub mod request_density_tracker;
 pub(crate) mod running_average;
 mod small_world_rand;
+pub(crate) mod traffic;
 
 use crate::ring::{Connection, PeerKeyLocation};
 use crate::topology::meter::{AttributionSource, ResourceType};
@@ -189,7 +190,6 @@ impl TopologyManager {
         }
     }
 
-    #[allow(dead_code)] // fixme: use this
     pub(crate) fn report_resource_usage(
         &mut self,
         attribution: &AttributionSource,
@@ -210,6 +210,12 @@ impl TopologyManager {
         self.meter.report(attribution, resource, amount, at_time);
     }
 
+    /// Forget the usage attributed to a source which is gone, like a disconnected peer.
+    pub(crate) fn remove_source(&mut self, attribution: &AttributionSource) {
+        self.source_creation_times.remove(attribution);
+        self.meter.remove(attribution);
+    }
+
     /// Record an outbound request to a peer, along with the target Location of that request
     pub(crate) fn report_outbound_request(&mut self, peer: PeerKeyLocation, target: Location) {
         self.request_density_tracker.sample(target);
@@ -276,6 +282,22 @@ impl TopologyManager {
         self.meter.attributed_usage_rate(source, resource_type, now)
     }
 
+    /// Whether the usage of any resource is above the maximum desired proportion of its limit,
+    /// in which case no new connections should be accepted.
+    pub(crate) fn over_capacity(&mut self, at_time: Instant) -> bool {
+        let (resource_type, usage_proportion) =
+            self.calculate_usage_proportion(at_time).max_usage_rate;
+        let over_capacity =
+            usage_proportion > RateProportion::new(MAXIMUM_DESIRED_RESOURCE_USAGE_PROPORTION);
+        if over_capacity {
+            debug!(
+                "{:?} resource usage ({:?}) is above threshold",
+                resource_type, usage_proportion
+            );
+        }
+        over_capacity
+    }
+
     // A function that will determine if any peers should be added or removed based on
     // the current resource usage, and either add or remove them
     pub(crate) fn adjust_topology(
@@ -446,7 +468,7 @@ impl TopologyManager {
         let function_span = span!(Level::INFO, "remove_connections");
         let _enter = function_span.enter();
 
-        let mut worst: Option<(PeerKeyLocation, f64)> = None;
+        let mut worst: Option<(PeerKeyLocation, f64, f64)> = None;
 
         for (source, source_usage) in self
             .meter
@@ -484,13 +506,17 @@ impl TopologyManager {
                         value_per_usage = value_per_usage
                     );
 
-                    if let Some((_, worst_value_per_usage)) = worst {
-                        if value_per_usage < worst_value_per_usage {
-                            worst = Some((peer, value_per_usage));
+                    if let Some((_, worst_value_per_usage, worst_usage)) = worst {
+                        // on a tie, the peer using more of the resource is worse
+                        if value_per_usage < worst_value_per_usage
+                            || (value_per_usage == worst_value_per_usage
+                                && source_usage.per_second() > worst_usage)
+                        {
+                            worst = Some((peer, value_per_usage, source_usage.per_second()));
                             event!(Level::DEBUG, "Found a worse peer");
                         }
                     } else {
-                        worst = Some((peer, value_per_usage));
+                        worst = Some((peer, value_per_usage, source_usage.per_second()));
                         event!(Level::DEBUG, "Setting initial worst peer");
                     }
                 }
@@ -500,7 +526,7 @@ impl TopologyManager {
             }
         }
 
-        if let Some((peer, _)) = worst {
+        if let Some((peer, _, _)) = worst {
             event!(Level::INFO, action = "Recommend peer for removal", peer = ?peer);
             TopologyAdjustment::RemoveConnections(vec![peer])
         } else {
@@ -531,6 +557,9 @@ mod tests {
         let mut topology_manager = TopologyManager::new(Limits {
             max_upstream_bandwidth: Rate::new_per_second(1000.0),
             max_downstream_bandwidth: Rate::new_per_second(1000.0),
+            max_execution_fuel: Rate::new_per_second(1000.0),
+            max_storage: Rate::new_per_second(1000.0),
+            max_wasm_memory: Rate::new_per_second(1000.0),
             min_connections: 5,
             max_connections: 200,
         });
@@ -617,6 +646,9 @@ mod tests {
             let limits = Limits {
                 max_upstream_bandwidth: Rate::new_per_second(1000.0),
                 max_downstream_bandwidth: Rate::new_per_second(1000.0),
+                max_execution_fuel: Rate::new_per_second(1000.0),
+                max_storage: Rate::new_per_second(1000.0),
+                max_wasm_memory: Rate::new_per_second(1000.0),
                 max_connections: 200,
                 min_connections: 5,
             };
@@ -679,6 +711,42 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_remove_connections_over_execution_fuel() {
+        with_tracing(|| {
+            let mut resource_manager = setup_topology_manager(1000.0);
+            let peers = generate_random_peers(5);
+            // No bandwidth is used but total fuel is above the limit of 100000
+            let fuel_by_peer = [20_000.0, 90_000.0, 30_000.0, 20_000.0, 10_000.0];
+            let report_time = Instant::now() - SOURCE_RAMP_UP_DURATION - Duration::from_secs(30);
+            for (peer, fuel) in peers.iter().zip(fuel_by_peer) {
+                for seconds in 1..600 {
+                    resource_manager.report_resource_usage(
+                        &AttributionSource::Peer(*peer),
+                        ResourceType::ExecutionFuel,
+                        fuel,
+                        report_time - Duration::from_secs(600 - seconds),
+                    );
+                }
+            }
+            report_outbound_requests(&mut resource_manager, &peers, &[10; 5]);
+            assert!(resource_manager.over_capacity(Instant::now()));
+
+            let mut neighbor_locations = BTreeMap::new();
+            for peer in &peers {
+                neighbor_locations.insert(peer.location.unwrap(), vec![]);
+            }
+            let adjustment =
+                resource_manager.adjust_topology(&neighbor_locations, &None, Instant::now());
+            match adjustment {
+                TopologyAdjustment::RemoveConnections(removed) => {
+                    assert_eq!(removed, vec![peers[1]]);
+                }
+                _ => panic!("Expected to remove a peer, adjustment was {:?}", adjustment),
+            }
+        });
+    }
+
     #[test]
     fn test_add_connections() {
         with_tracing(|| {
@@ -816,6 +884,9 @@ mod tests {
             // This won't be used
             max_upstream_bandwidth: Rate::new_per_second(100000.0),
             max_downstream_bandwidth: Rate::new_per_second(max_downstream_rate),
+            max_execution_fuel: Rate::new_per_second(100000.0),
+            max_storage: Rate::new_per_second(100000.0),
+            max_wasm_memory: Rate::new_per_second(100000.0),
             max_connections: 200,
             min_connections: 5,
         };
@@ -895,6 +966,9 @@ mod tests {
         let limits = Limits {
             max_upstream_bandwidth: Rate::new_per_second(1000.0),
             max_downstream_bandwidth: Rate::new_per_second(1000.0),
+            max_execution_fuel: Rate::new_per_second(1000.0),
+            max_storage: Rate::new_per_second(1000.0),
+            max_wasm_memory: Rate::new_per_second(1000.0),
             max_connections: 200,
             min_connections: 5,
         };
@@ -903,6 +977,9 @@ mod tests {
         let new_limits = Limits {
             max_upstream_bandwidth: Rate::new_per_second(2000.0),
             max_downstream_bandwidth: Rate::new_per_second(2000.0),
+            max_execution_fuel: Rate::new_per_second(2000.0),
+            max_storage: Rate::new_per_second(2000.0),
+            max_wasm_memory: Rate::new_per_second(2000.0),
             max_connections: 200,
             min_connections: 5,
         };
@@ -916,6 +993,10 @@ mod tests {
             topology_manager.limits.max_downstream_bandwidth,
             Rate::new_per_second(2000.0)
         );
+        assert_eq!(
+            topology_manager.limits.max_execution_fuel,
+            Rate::new_per_second(2000.0)
+        );
     }
 }
 
@@ -935,6 +1016,12 @@ struct Usage {
 pub(crate) struct Limits {
     pub max_upstream_bandwidth: Rate,
     pub max_downstream_bandwidth: Rate,
+    /// Fuel spent executing contract code per second.
+    pub max_execution_fuel: Rate,
+    /// Bytes of contract state stored per second.
+    pub max_storage: Rate,
+    /// Bytes of WASM instance memory used per second.
+    pub max_wasm_memory: Rate,
     pub min_connections: usize,
     pub max_connections: usize,
 }
@@ -944,6 +1031,9 @@ impl Limits {
         match resource_type {
             ResourceType::OutboundBandwidthBytes => self.max_upstream_bandwidth,
             ResourceType::InboundBandwidthBytes => self.max_downstream_bandwidth,
+            ResourceType::ExecutionFuel => self.max_execution_fuel,
+            ResourceType::StorageBytes => self.max_storage,
+            ResourceType::WasmMemoryBytes => self.max_wasm_memory,
         }
     }
 }
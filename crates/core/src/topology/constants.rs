@@ -3,7 +3,10 @@ use std::time::Duration;
 pub(super) const SLOW_CONNECTION_EVALUATOR_WINDOW_DURATION: Duration = Duration::from_secs(5 * 60);
 pub(super) const FAST_CONNECTION_EVALUATOR_WINDOW_DURATION: Duration = Duration::from_secs(60);
 pub(super) const REQUEST_DENSITY_TRACKER_WINDOW_SIZE: usize = 10_000;
+#[cfg(not(test))]
 pub(super) const SOURCE_RAMP_UP_DURATION: Duration = Duration::from_secs(5 * 60);
+#[cfg(test)]
+pub(super) const SOURCE_RAMP_UP_DURATION: Duration = Duration::from_secs(10);
 pub(super) const OUTBOUND_REQUEST_COUNTER_WINDOW_SIZE: usize = 10000;
 pub(super) const MINIMUM_DESIRED_RESOURCE_USAGE_PROPORTION: f64 = 0.5;
 pub(super) const MAXIMUM_DESIRED_RESOURCE_USAGE_PROPORTION: f64 = 0.9;
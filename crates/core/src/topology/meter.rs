@@ -167,7 +167,6 @@ impl Meter {
     /// Report the use of a resource. This should be done in the lowest-level
     /// functions that consume the resource, taking an AttributionMeter
     /// as a parameter.
-    #[allow(dead_code)] // fixme: use this
     pub(crate) fn report(
         &mut self,
         attribution: &AttributionSource,
@@ -186,6 +185,11 @@ impl Meter {
             .or_insert_with(|| RunningAverage::new(self.running_average_window_size));
         resource_value.insert_with_time(at_time, value);
     }
+
+    /// Forget all the usage attributed to a source, e.g. once a peer is disconnected.
+    pub(crate) fn remove(&self, attribution: &AttributionSource) {
+        self.attribution_meters.remove(attribution);
+    }
 }
 
 #[allow(dead_code)] // todo use this
@@ -193,19 +197,30 @@ impl Meter {
 pub(crate) enum AttributionSource {
     Peer(PeerKeyLocation),
     Delegate(DelegateKey),
+    Contract(ContractInstanceId),
 }
 
 #[derive(Eq, Hash, PartialEq, Clone, Copy, Debug)]
 pub(crate) enum ResourceType {
     InboundBandwidthBytes,
     OutboundBandwidthBytes,
+    /// Fuel spent executing contract and delegate code, roughly the number of WASM
+    /// instructions.
+    ExecutionFuel,
+    /// Bytes of contract state written to the state store.
+    StorageBytes,
+    /// Bytes of WASM instance memory used while executing contract and delegate code.
+    WasmMemoryBytes,
 }
 
 impl ResourceType {
-    pub(crate) fn all() -> [ResourceType; 2] {
+    pub(crate) fn all() -> [ResourceType; 5] {
         [
             ResourceType::InboundBandwidthBytes,
             ResourceType::OutboundBandwidthBytes,
+            ResourceType::ExecutionFuel,
+            ResourceType::StorageBytes,
+            ResourceType::WasmMemoryBytes,
         ]
     }
 }
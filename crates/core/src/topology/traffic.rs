@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+
+use parking_lot::Mutex;
+
+use super::meter::{AttributionSource, ResourceType};
+
+/// Accumulates the resources used on behalf of other peers, contracts and delegates, like the
+/// bytes exchanged by the network bridges or the fuel spent executing code, until they are
+/// taken to be reported to the topology manager.
+///
+/// Usage attributed to several sources at once, like the traffic with a peer about a contract,
+/// is split evenly between them, so the total usage is not counted twice.
+#[derive(Default)]
+pub(crate) struct TrafficMeter {
+    pending: Mutex<HashMap<(AttributionSource, ResourceType), f64>>,
+}
+
+impl TrafficMeter {
+    pub fn record(
+        &self,
+        attributions: impl IntoIterator<Item = AttributionSource>,
+        resource: ResourceType,
+        amount: usize,
+    ) {
+        let attributions = attributions.into_iter().collect::<Vec<_>>();
+        if attributions.is_empty() {
+            return;
+        }
+        let mut pending = self.pending.lock();
+        let split_amount = amount as f64 / attributions.len() as f64;
+        for attribution in attributions {
+            *pending.entry((attribution, resource)).or_default() += split_amount;
+        }
+    }
+
+    /// Takes all the usage recorded since the last time it was taken.
+    pub fn take(&self) -> HashMap<(AttributionSource, ResourceType), f64> {
+        std::mem::take(&mut *self.pending.lock())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use freenet_stdlib::prelude::{CodeHash, ContractInstanceId, DelegateKey};
+
+    use super::*;
+    use crate::ring::PeerKeyLocation;
+
+    #[test]
+    fn splits_contract_traffic() {
+        let meter = TrafficMeter::default();
+        let peer = AttributionSource::Peer(PeerKeyLocation::random());
+        let contract = AttributionSource::Contract(ContractInstanceId::new([1; 32]));
+        meter.record([peer.clone()], ResourceType::InboundBandwidthBytes, 100);
+        meter.record(
+            [peer.clone(), contract.clone()],
+            ResourceType::InboundBandwidthBytes,
+            100,
+        );
+        meter.record([peer.clone()], ResourceType::OutboundBandwidthBytes, 10);
+
+        let traffic = meter.take();
+        assert_eq!(
+            traffic[&(peer.clone(), ResourceType::InboundBandwidthBytes)],
+            150.0
+        );
+        assert_eq!(
+            traffic[&(contract, ResourceType::InboundBandwidthBytes)],
+            50.0
+        );
+        assert_eq!(traffic[&(peer, ResourceType::OutboundBandwidthBytes)], 10.0);
+        assert!(meter.take().is_empty());
+    }
+
+    #[test]
+    fn attributes_delegate_usage() {
+        let meter = TrafficMeter::default();
+        let peer = AttributionSource::Peer(PeerKeyLocation::random());
+        let contract = AttributionSource::Contract(ContractInstanceId::new([1; 32]));
+        let delegate =
+            AttributionSource::Delegate(DelegateKey::new([2; 32], CodeHash::new([3; 32])));
+        meter.record(
+            [peer.clone(), contract.clone(), delegate.clone()],
+            ResourceType::OutboundBandwidthBytes,
+            300,
+        );
+        meter.record([delegate.clone()], ResourceType::ExecutionFuel, 1000);
+        meter.record([], ResourceType::ExecutionFuel, 1000);
+
+        let traffic = meter.take();
+        for source in [peer, contract, delegate.clone()] {
+            assert_eq!(
+                traffic[&(source, ResourceType::OutboundBandwidthBytes)],
+                100.0
+            );
+        }
+        assert_eq!(traffic[&(delegate, ResourceType::ExecutionFuel)], 1000.0);
+        assert_eq!(traffic.len(), 4);
+    }
+}
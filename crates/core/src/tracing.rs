@@ -25,14 +25,19 @@ use crate::{
     contract::StoreResponse,
     message::{NetMessage, Transaction},
     node::PeerId,
-    operations::{connect, get::GetMsg, put::PutMsg, subscribe::SubscribeMsg},
-    ring::{Location, PeerKeyLocation, Ring},
+    operations::{connect, get::GetMsg, put::PutMsg, subscribe::SubscribeMsg, update::UpdateMsg},
+    ring::{Location, Misbehaviour, PeerKeyLocation, Ring},
     router::RouteEvent,
     DynError,
 };
 
+mod capture;
+mod properties;
+
+pub(crate) use capture::{load_capture, Captured, CapturedEvent, MessageCapture};
 #[cfg(feature = "trace-ot")]
 pub(crate) use opentelemetry_tracer::OTEventRegister;
+pub use properties::{Property, Violation};
 pub(crate) use test::TestEventListener;
 
 use crate::node::OpManager;
@@ -153,6 +158,34 @@ impl<'a> NetEventLog<'a> {
         }
     }
 
+    pub fn misbehaved(
+        ring: &'a Ring,
+        peer: &PeerId,
+        contract: Option<&ContractKey>,
+        misbehaviour: Misbehaviour,
+    ) -> Self {
+        NetEventLog {
+            tx: Transaction::NULL,
+            peer_id: &ring.peer_key,
+            kind: EventKind::Misbehaved {
+                peer: *peer,
+                contract: contract.cloned(),
+                misbehaviour,
+            },
+        }
+    }
+
+    pub fn blacklisted(ring: &'a Ring, peer: &PeerId, contract: Option<ContractKey>) -> Self {
+        NetEventLog {
+            tx: Transaction::NULL,
+            peer_id: &ring.peer_key,
+            kind: EventKind::Blacklisted {
+                peer: *peer,
+                contract,
+            },
+        }
+    }
+
     pub fn from_outbound_msg(msg: &'a NetMessage, ring: &'a Ring) -> Either<Self, Vec<Self>> {
         let kind = match msg {
             NetMessage::Connect(connect::ConnectMsg::Response {
@@ -283,6 +316,40 @@ impl<'a> NetEventLog<'a> {
                 key: key.clone(),
                 value: new_value.clone(),
             }),
+            NetMessage::Update(UpdateMsg::RequestUpdate { key, .. }) => {
+                EventKind::Update(UpdateEvent::Request {
+                    requester: op_manager.ring.peer_key,
+                    key: key.clone(),
+                })
+            }
+            NetMessage::Update(UpdateMsg::SuccessfulUpdate { .. }) => {
+                EventKind::Update(UpdateEvent::UpdateSuccess {
+                    requester: op_manager.ring.peer_key,
+                })
+            }
+            NetMessage::Update(UpdateMsg::Broadcasting {
+                broadcast_to, key, ..
+            }) => EventKind::Update(UpdateEvent::BroadcastEmitted {
+                broadcast_to: broadcast_to.clone(),
+                key: key.clone(),
+            }),
+            NetMessage::Update(UpdateMsg::BroadcastTo {
+                sender,
+                key,
+                new_value,
+                ..
+            }) => EventKind::Update(UpdateEvent::BroadcastReceived {
+                requester: sender.peer,
+                key: key.clone(),
+                value: new_value.clone(),
+            }),
+            NetMessage::Put(PutMsg::SeekNode { contract, .. }) => EventKind::Forwarded {
+                key: contract.key(),
+            },
+            NetMessage::Get(GetMsg::SeekNode { key, .. })
+            | NetMessage::Subscribe(SubscribeMsg::SeekNode { key, .. }) => {
+                EventKind::Forwarded { key: key.clone() }
+            }
             NetMessage::Get(GetMsg::ReturnGet {
                 key,
                 value: StoreResponse { state: Some(_), .. },
@@ -339,11 +406,21 @@ impl NetLogMessage {
             EventKind::Connect(_) => false,
             EventKind::Put(PutEvent::PutSuccess { .. }) => true,
             EventKind::Put(_) => false,
+            EventKind::Update(UpdateEvent::UpdateSuccess { .. }) => true,
+            EventKind::Update(_) => false,
             _ => false,
         }
     }
 }
 
+/// Serializes the events so they can be registered by a listener running in another process,
+/// see [`TestEventListener::register_serialized`].
+pub(crate) fn serialize_events(
+    events: Either<NetEventLog<'_>, Vec<NetEventLog<'_>>>,
+) -> bincode::Result<Vec<u8>> {
+    bincode::serialize(&NetLogMessage::to_log_message(events).collect::<Vec<_>>())
+}
+
 impl<'a> From<NetEventLog<'a>> for NetLogMessage {
     fn from(log: NetEventLog<'a>) -> NetLogMessage {
         NetLogMessage {
@@ -398,6 +475,19 @@ static FILE_LOCK: Mutex<()> = Mutex::const_new(());
 
 const EVENT_REGISTER_BATCH_SIZE: usize = 100;
 
+/// Version of the format of the records persisted in the event log. Bump it whenever the
+/// serialized form of [`NetLogMessage`] changes, so logs written by older versions are
+/// discarded instead of failing to deserialize.
+const EVENT_LOG_VERSION: u32 = 1;
+const EVENT_LOG_HEADER_LEN: u64 = 8;
+
+/// Header at the start of the event log, identifying the format of its records.
+fn event_log_header() -> [u8; EVENT_LOG_HEADER_LEN as usize] {
+    let mut header = *b"FNEL\0\0\0\0";
+    header[4..].copy_from_slice(&EVENT_LOG_VERSION.to_be_bytes());
+    header
+}
+
 const DEFAULT_METRICS_SERVER_PORT: u16 = 55010;
 
 impl EventRegister {
@@ -438,6 +528,10 @@ impl EventRegister {
                 panic!("Failed openning log file"); // fixme: propagate this to the main event loop
             }
         };
+        if let Err(err) = Self::check_log_version(&mut event_log).await {
+            tracing::error!("Failed checking the version of log file {event_log_path:?}: {err}");
+            panic!("Failed checking the version of log file");
+        }
         let mut num_written = 0;
         let mut log_batch = Vec::with_capacity(Self::BATCH_SIZE);
 
@@ -568,11 +662,37 @@ impl EventRegister {
         }
     }
 
+    /// Makes sure the event log starts with the header of the current format, discarding the
+    /// records of a log written in any other format, and leaves it ready for appending records.
+    async fn check_log_version(event_log: &mut tokio::fs::File) -> io::Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+        let _guard = FILE_LOCK.lock().await;
+        event_log.rewind().await?;
+        let mut header = [0u8; EVENT_LOG_HEADER_LEN as usize];
+        let is_current = match event_log.read_exact(&mut header).await {
+            Ok(_) => header == event_log_header(),
+            Err(err) if matches!(err.kind(), io::ErrorKind::UnexpectedEof) => false,
+            Err(err) => return Err(err),
+        };
+        if !is_current {
+            if event_log.metadata().await?.len() > 0 {
+                tracing::warn!("Discarding event log written in another format");
+            }
+            event_log.set_len(0).await?;
+            event_log.rewind().await?;
+            event_log.write_all(&event_log_header()).await?;
+        }
+        event_log.seek(io::SeekFrom::End(0)).await?;
+        Ok(())
+    }
+
     async fn num_lines(path: &Path) -> io::Result<usize> {
         use tokio::fs::File;
         use tokio::io::{AsyncReadExt, AsyncSeekExt};
 
         let mut file = tokio::io::BufReader::new(File::open(path).await?);
+        file.seek(io::SeekFrom::Start(EVENT_LOG_HEADER_LEN)).await?;
         let mut num_records = 0;
         let mut buf = [0; 4]; // Read the u32 length prefix
 
@@ -600,7 +720,7 @@ impl EventRegister {
         use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 
         let _guard = FILE_LOCK.lock().await;
-        file.rewind().await?;
+        file.seek(io::SeekFrom::Start(EVENT_LOG_HEADER_LEN)).await?;
         // tracing::debug!(position = file.stream_position().await.unwrap());
         let mut records_count = 0;
         while records_count < remove_records {
@@ -635,12 +755,13 @@ impl EventRegister {
             }
         }
 
-        // Seek back to the beginning and write the remaining content
-        file.rewind().await?;
+        // Seek back to the first record and write the remaining content
+        file.seek(io::SeekFrom::Start(EVENT_LOG_HEADER_LEN)).await?;
         file.write_all(&buffer).await?;
 
         // Truncate the file to the new size
-        file.set_len(buffer.len() as u64).await?;
+        file.set_len(EVENT_LOG_HEADER_LEN + buffer.len() as u64)
+            .await?;
         file.seek(io::SeekFrom::End(0)).await?;
         Ok(())
     }
@@ -657,6 +778,14 @@ impl EventRegister {
         let _guard: tokio::sync::MutexGuard<'_, ()> = FILE_LOCK.lock().await;
         let mut file =
             tokio::io::BufReader::new(OpenOptions::new().read(true).open(event_log_path).await?);
+        let mut header = [0u8; EVENT_LOG_HEADER_LEN as usize];
+        match file.read_exact(&mut header).await {
+            Ok(_) if header == event_log_header() => {}
+            // not written yet, or in another format
+            Ok(_) => return Ok(vec![]),
+            Err(err) if matches!(err.kind(), io::ErrorKind::UnexpectedEof) => return Ok(vec![]),
+            Err(err) => return Err(err.into()),
+        }
 
         let new_records_ts = NEW_RECORDS_TS
             .get()
@@ -1091,7 +1220,7 @@ enum EventKind {
         key: ContractKey,
     },
     Route(RouteEvent),
-    // todo: add update sequences too
+    Update(UpdateEvent),
     Subscribed {
         key: ContractKey,
         at: PeerKeyLocation,
@@ -1100,6 +1229,20 @@ enum EventKind {
     Disconnected {
         from: PeerId,
     },
+    /// A request for the contract was routed one more hop through the network.
+    Forwarded {
+        key: ContractKey,
+    },
+    Misbehaved {
+        peer: PeerId,
+        contract: Option<ContractKey>,
+        misbehaviour: Misbehaviour,
+    },
+    /// The peer was blacklisted for the contract, or for everything if there is no contract.
+    Blacklisted {
+        peer: PeerId,
+        contract: Option<ContractKey>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -1146,6 +1289,32 @@ enum PutEvent {
     },
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[cfg_attr(test, derive(arbitrary::Arbitrary))]
+enum UpdateEvent {
+    Request {
+        requester: PeerId,
+        key: ContractKey,
+    },
+    UpdateSuccess {
+        requester: PeerId,
+    },
+    BroadcastEmitted {
+        /// subscribed peers
+        broadcast_to: Vec<PeerKeyLocation>,
+        /// key of the contract which value was being updated
+        key: ContractKey,
+    },
+    BroadcastReceived {
+        /// peer who started the broadcast op
+        requester: PeerId,
+        /// key of the contract which value was being updated
+        key: ContractKey,
+        /// value after applying the update
+        value: WrappedState,
+    },
+}
+
 #[cfg(feature = "trace")]
 pub(crate) mod tracer {
     use tracing_subscriber::{Layer, Registry};
@@ -1227,7 +1396,8 @@ pub(crate) mod tracer {
 
 pub(super) mod test {
     use std::{
-        collections::HashMap,
+        collections::{HashMap, HashSet},
+        ops::RangeBounds,
         sync::{
             atomic::{AtomicUsize, Ordering::SeqCst},
             Arc,
@@ -1286,6 +1456,29 @@ pub(super) mod test {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn event_log_of_another_version_is_discarded() -> Result<(), DynError> {
+        use std::time::Duration;
+        let temp_dir = tempfile::tempdir()?;
+        let log_path = temp_dir.path().join("event_log");
+        // a record without any header, as written by previous versions
+        let record = b"not a record of the current format";
+        let mut old_log = (record.len() as u32).to_be_bytes().to_vec();
+        old_log.extend(record);
+        std::fs::write(&log_path, &old_log)?;
+
+        let _register = EventRegister::new(log_path.clone());
+        assert!(EventRegister::get_router_events(10, &log_path)
+            .await?
+            .is_empty());
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(std::fs::read(&log_path)?, event_log_header());
+        assert!(EventRegister::get_router_events(10, &log_path)
+            .await?
+            .is_empty());
+        Ok(())
+    }
+
     #[derive(Clone)]
     pub(crate) struct TestEventListener {
         node_labels: Arc<DashMap<NodeLabel, PeerId>>,
@@ -1321,6 +1514,26 @@ pub(super) mod test {
             })
         }
 
+        pub fn has_disconnected(&self, peer: &PeerId, from: &PeerId) -> bool {
+            let Ok(logs) = self.logs.try_lock() else {
+                return false;
+            };
+            logs.iter().any(|log| {
+                &log.peer_id == peer
+                    && matches!(log.kind, EventKind::Disconnected { from: other } if &other == from)
+            })
+        }
+
+        pub fn has_blacklisted(&self, peer: &PeerId, blacklisted: &PeerId) -> bool {
+            let Ok(logs) = self.logs.try_lock() else {
+                return false;
+            };
+            logs.iter().any(|log| {
+                &log.peer_id == peer
+                    && matches!(log.kind, EventKind::Blacklisted { peer: other, .. } if &other == blacklisted)
+            })
+        }
+
         pub fn has_put_contract(&self, peer: &PeerId, for_key: &ContractKey) -> bool {
             let Ok(logs) = self.logs.try_lock() else {
                 return false;
@@ -1393,6 +1606,80 @@ pub(super) mod test {
             false
         }
 
+        pub fn has_updated_contract(&self, peer: &PeerId, for_key: &ContractKey) -> bool {
+            let Ok(logs) = self.logs.try_lock() else {
+                return false;
+            };
+            let update_ops = logs.iter().filter_map(|l| match &l.kind {
+                EventKind::Update(ev) => Some((&l.tx, ev)),
+                _ => None,
+            });
+            let update_ops: HashMap<_, Vec<_>> =
+                update_ops.fold(HashMap::new(), |mut acc, (id, ev)| {
+                    acc.entry(id).or_default().push(ev);
+                    acc
+                });
+
+            for (_tx, events) in update_ops {
+                let mut is_expected_key = false;
+                let mut is_expected_peer = false;
+                for ev in events {
+                    match ev {
+                        UpdateEvent::Request { key, .. } if key == for_key => {
+                            is_expected_key = true;
+                        }
+                        UpdateEvent::UpdateSuccess { requester } if requester == peer => {
+                            is_expected_peer = true;
+                        }
+                        _ => {}
+                    }
+                }
+                if is_expected_peer && is_expected_key {
+                    return true;
+                }
+            }
+            false
+        }
+
+        /// The contract update was broadcasted from one peer to an other successfully.
+        #[cfg(test)]
+        pub fn update_broadcasted(&self, for_key: &ContractKey) -> bool {
+            let Ok(logs) = self.logs.try_lock() else {
+                return false;
+            };
+            let update_broadcast_ops = logs.iter().filter_map(|l| match &l.kind {
+                EventKind::Update(ev @ UpdateEvent::BroadcastEmitted { .. })
+                | EventKind::Update(ev @ UpdateEvent::BroadcastReceived { .. }) => {
+                    Some((&l.tx, ev))
+                }
+                _ => None,
+            });
+            let update_broadcast_by_tx: HashMap<_, Vec<_>> =
+                update_broadcast_ops.fold(HashMap::new(), |mut acc, (id, ev)| {
+                    acc.entry(id).or_default().push(ev);
+                    acc
+                });
+            for (_tx, events) in update_broadcast_by_tx {
+                let mut was_emitted = false;
+                let mut was_received = false;
+                for ev in events {
+                    match ev {
+                        UpdateEvent::BroadcastEmitted { key, .. } if key == for_key => {
+                            was_emitted = true;
+                        }
+                        UpdateEvent::BroadcastReceived { key, .. } if key == for_key => {
+                            was_received = true;
+                        }
+                        _ => {}
+                    }
+                }
+                if was_emitted && was_received {
+                    return true;
+                }
+            }
+            false
+        }
+
         pub fn has_got_contract(&self, peer: &PeerId, expected_key: &ContractKey) -> bool {
             let Ok(logs) = self.logs.try_lock() else {
                 return false;
@@ -1413,6 +1700,78 @@ pub(super) mod test {
             })
         }
 
+        /// Contracts successfully put in the network within the given time range.
+        pub async fn contracts_put(
+            &self,
+            range: impl RangeBounds<DateTime<Utc>>,
+        ) -> HashSet<ContractKey> {
+            let logs = self.logs.lock().await;
+            let mut requested = HashMap::new();
+            let mut succeeded = HashSet::new();
+            for log in logs.iter() {
+                match &log.kind {
+                    EventKind::Put(PutEvent::Request { key, .. }) => {
+                        requested.insert(log.tx, key.clone());
+                    }
+                    EventKind::Put(PutEvent::PutSuccess { .. })
+                        if range.contains(&log.datetime) =>
+                    {
+                        succeeded.insert(log.tx);
+                    }
+                    _ => {}
+                }
+            }
+            succeeded
+                .into_iter()
+                .filter_map(|tx| requested.remove(&tx))
+                .collect()
+        }
+
+        /// Contracts retrieved by any peer within the given time range.
+        pub async fn contracts_got(
+            &self,
+            range: impl RangeBounds<DateTime<Utc>>,
+        ) -> HashSet<ContractKey> {
+            let logs = self.logs.lock().await;
+            logs.iter()
+                .filter_map(|log| match &log.kind {
+                    EventKind::Get { key } if range.contains(&log.datetime) => Some(key.clone()),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        /// Contracts any peer subscribed to within the given time range.
+        pub async fn contracts_subscribed(
+            &self,
+            range: impl RangeBounds<DateTime<Utc>>,
+        ) -> HashSet<ContractKey> {
+            let logs = self.logs.lock().await;
+            logs.iter()
+                .filter_map(|log| match &log.kind {
+                    EventKind::Subscribed { key, .. } if range.contains(&log.datetime) => {
+                        Some(key.clone())
+                    }
+                    _ => None,
+                })
+                .collect()
+        }
+
+        /// Location in the ring acquired by the peer when joining the network.
+        pub async fn location(&self, peer: PeerId) -> Option<Location> {
+            let logs = self.logs.lock().await;
+            logs.iter().find_map(|log| match log.kind {
+                EventKind::Connect(ConnectEvent::Finished {
+                    initiator,
+                    location,
+                }) if initiator == peer => Some(location),
+                EventKind::Connect(ConnectEvent::Connected { this, .. }) if this.peer == peer => {
+                    this.location
+                }
+                _ => None,
+            })
+        }
+
         /// Unique connections for a given peer and their relative distance to other peers.
         pub fn connections(&self, peer: PeerId) -> Box<dyn Iterator<Item = (PeerId, Distance)>> {
             let Ok(logs) = self.logs.try_lock() else {
@@ -1449,6 +1808,34 @@ pub(super) mod test {
             Box::new(iter)
         }
 
+        /// Evaluates the property over all the events recorded so far.
+        pub async fn check_property(&self, property: &Property) -> Vec<Violation> {
+            let labels: HashMap<_, _> = self
+                .node_labels
+                .iter()
+                .map(|entry| (*entry.value(), entry.key().clone()))
+                .collect();
+            let logs = self.logs.lock().await;
+            property.check(&logs, &labels)
+        }
+
+        /// Registers the events recorded by a peer running in another process, as serialized
+        /// by [`super::serialize_events`], keeping the time at which they happened.
+        pub async fn register_serialized(&self, events: &[u8]) -> bincode::Result<()> {
+            let events: Vec<NetLogMessage> = bincode::deserialize(events)?;
+            let logs_list = &mut *self.logs.lock().await;
+            let mut lock = self.network_metrics_server.lock().await;
+            for msg_log in events {
+                let log_id = ListenerLogId(LOG_ID.fetch_add(1, SeqCst));
+                if let Some(conn) = &mut *lock {
+                    send_to_metrics_server(conn, &msg_log).await;
+                }
+                self.tx_log.entry(msg_log.tx).or_default().push(log_id);
+                logs_list.push(msg_log);
+            }
+            Ok(())
+        }
+
         fn create_log(log: NetEventLog) -> (NetLogMessage, ListenerLogId) {
             let log_id = ListenerLogId(LOG_ID.fetch_add(1, SeqCst));
             let NetEventLog { peer_id, kind, .. } = log;
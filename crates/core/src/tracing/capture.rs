@@ -0,0 +1,235 @@
+//! Opt-in capture of all the traffic handled by a node: every network message it receives or
+//! sends and every client request, along with the changes to its place in the ring and the
+//! transactions it starts, so the session can be replayed later on. Unlike the
+//! [`EventRegister`](super::EventRegister), which keeps a summary of what happened, messages
+//! are recorded whole.
+use std::{io, path::Path};
+
+use chrono::{DateTime, Utc};
+use freenet_stdlib::client_api::ClientRequest;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter},
+    sync::mpsc,
+};
+
+use crate::{
+    client_events::ClientId,
+    config::GlobalExecutor,
+    message::{NetMessage, Transaction},
+    node::PeerId,
+    ring::{Location, PeerKeyLocation},
+    DynError,
+};
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CapturedEvent<M = NetMessage, R = ClientRequest<'static>> {
+    pub datetime: DateTime<Utc>,
+    pub event: Captured<M, R>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) enum Captured<M = NetMessage, R = ClientRequest<'static>> {
+    /// A message handled by the node, coming either from the network or from the node itself.
+    Inbound(M),
+    /// A message sent to an other peer.
+    Outbound {
+        target: PeerId,
+        msg: M,
+    },
+    ClientRequest {
+        client_id: ClientId,
+        request: R,
+    },
+    /// The node took this location in the ring.
+    Located(Location),
+    /// A connection to an other peer was added to the ring.
+    Connected {
+        peer: PeerId,
+        location: Location,
+    },
+    /// A connection to an other peer was removed from the ring.
+    Disconnected(PeerId),
+    /// The node sent a message of this transaction to its own event loop, as it does when
+    /// starting a transaction.
+    Notified(Transaction),
+    /// The node started joining the ring through the gateway, as the transaction.
+    Joining {
+        tx: Transaction,
+        gateway: PeerKeyLocation,
+    },
+}
+
+impl CapturedEvent<NetMessage, ClientRequest<'_>> {
+    fn into_owned(self) -> CapturedEvent {
+        let event = match self.event {
+            Captured::Inbound(msg) => Captured::Inbound(msg),
+            Captured::Outbound { target, msg } => Captured::Outbound { target, msg },
+            Captured::ClientRequest { client_id, request } => Captured::ClientRequest {
+                client_id,
+                request: request.into_owned(),
+            },
+            Captured::Located(location) => Captured::Located(location),
+            Captured::Connected { peer, location } => Captured::Connected { peer, location },
+            Captured::Disconnected(peer) => Captured::Disconnected(peer),
+            Captured::Notified(tx) => Captured::Notified(tx),
+            Captured::Joining { tx, gateway } => Captured::Joining { tx, gateway },
+        };
+        CapturedEvent {
+            datetime: self.datetime,
+            event,
+        }
+    }
+}
+
+/// Records the traffic of a node into a capture file.
+#[derive(Clone)]
+pub(crate) struct MessageCapture {
+    sender: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+impl MessageCapture {
+    /// Starts capturing into the file at `path`, replacing any previous capture in it.
+    pub fn new(path: &Path) -> io::Result<Self> {
+        let file = std::fs::File::create(path)?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        GlobalExecutor::spawn(Self::persist(receiver, File::from_std(file)));
+        Ok(Self { sender })
+    }
+
+    pub fn inbound(&self, msg: &NetMessage) {
+        self.record(Captured::Inbound(msg));
+    }
+
+    pub fn outbound(&self, target: &PeerId, msg: &NetMessage) {
+        self.record(Captured::Outbound {
+            target: *target,
+            msg,
+        });
+    }
+
+    pub fn client_request(&self, client_id: ClientId, request: &ClientRequest) {
+        self.record(Captured::ClientRequest { client_id, request });
+    }
+
+    pub fn located(&self, location: Location) {
+        self.record(Captured::Located(location));
+    }
+
+    pub fn connected(&self, peer: PeerId, location: Location) {
+        self.record(Captured::Connected { peer, location });
+    }
+
+    pub fn disconnected(&self, peer: PeerId) {
+        self.record(Captured::Disconnected(peer));
+    }
+
+    pub fn notified(&self, tx: Transaction) {
+        self.record(Captured::Notified(tx));
+    }
+
+    pub fn joining(&self, tx: Transaction, gateway: PeerKeyLocation) {
+        self.record(Captured::Joining { tx, gateway });
+    }
+
+    fn record(&self, event: Captured<&NetMessage, &ClientRequest>) {
+        let event = CapturedEvent {
+            datetime: Utc::now(),
+            event,
+        };
+        match bincode::serialize(&event) {
+            Ok(record) => {
+                let _ = self.sender.send(record);
+            }
+            Err(err) => tracing::error!("Failed serializing captured message: {err}"),
+        }
+    }
+
+    async fn persist(mut receiver: mpsc::UnboundedReceiver<Vec<u8>>, file: File) {
+        let mut file = BufWriter::new(file);
+        while let Some(record) = receiver.recv().await {
+            let mut batch = vec![record];
+            while let Ok(record) = receiver.try_recv() {
+                batch.push(record);
+            }
+            let written = async {
+                for record in batch {
+                    file.write_u32(record.len() as u32).await?;
+                    file.write_all(&record).await?;
+                }
+                file.flush().await
+            };
+            if let Err(err) = written.await {
+                tracing::error!("Failed writing captured messages: {err}");
+                break;
+            }
+        }
+    }
+}
+
+/// Reads all the events of a capture file, in the order they were recorded.
+pub(crate) async fn load_capture(path: &Path) -> Result<Vec<CapturedEvent>, DynError> {
+    let mut file = BufReader::new(File::open(path).await?);
+    let mut events = vec![];
+    loop {
+        let length = match file.read_u32().await {
+            Ok(length) => length,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        };
+        let mut buf = vec![0; length as usize];
+        file.read_exact(&mut buf).await?;
+        let event: CapturedEvent<NetMessage, ClientRequest> = bincode::deserialize(&buf)?;
+        events.push(event.into_owned());
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{message::Transaction, operations::put::PutMsg};
+
+    #[tokio::test]
+    async fn capture_round_trip() -> Result<(), DynError> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("capture");
+        let tx = Transaction::new::<PutMsg>();
+        let target = PeerId::random();
+
+        let capture = MessageCapture::new(&path)?;
+        capture.inbound(&NetMessage::Aborted(tx));
+        capture.outbound(&target, &NetMessage::Aborted(tx));
+        capture.client_request(ClientId::FIRST, &ClientRequest::Disconnect { cause: None });
+        capture.notified(tx);
+        drop(capture);
+
+        let mut events = vec![];
+        for _ in 0..10 {
+            events = load_capture(&path).await?;
+            if events.len() == 4 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(events.len(), 4);
+        assert!(matches!(&events[0].event, Captured::Inbound(msg) if msg.id() == &tx));
+        assert!(matches!(
+            &events[1].event,
+            Captured::Outbound { target: peer, msg } if peer == &target && msg.id() == &tx
+        ));
+        assert!(matches!(
+            &events[2].event,
+            Captured::ClientRequest {
+                request: ClientRequest::Disconnect { .. },
+                ..
+            }
+        ));
+        assert!(matches!(&events[3].event, Captured::Notified(id) if id == &tx));
+        assert!(events[0].datetime <= events[2].datetime);
+        Ok(())
+    }
+}
@@ -0,0 +1,399 @@
+//! Invariants checked over the events recorded while running a simulated network.
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
+
+use freenet_stdlib::prelude::*;
+
+use super::{ConnectEvent, EventKind, NetLogMessage, PutEvent, UpdateEvent};
+use crate::{
+    message::Transaction,
+    node::{testing_impl::NodeLabel, PeerId},
+    ring::{Location, Ring},
+};
+
+/// An invariant that must hold over all the events recorded during a simulation.
+#[derive(Debug, Clone)]
+pub enum Property {
+    /// Every successful put reaches a peer within caching distance of the contract,
+    /// or the peer closest to the contract when there is none that close.
+    PutReachesCachingPeer,
+    /// No transaction is routed through more hops than the given max hops to live.
+    HopsWithin(usize),
+    /// All the subscribers of a contract which received updates for it end up with the same state.
+    SubscribersConverge,
+}
+
+/// A property which did not hold, along with the events showing why.
+#[derive(Debug)]
+pub struct Violation {
+    pub property: Property,
+    pub reason: String,
+    /// The fewest events showing the violation, in the order they happened.
+    pub trace: Vec<String>,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{:?} violated: {}", self.property, self.reason)?;
+        for event in &self.trace {
+            writeln!(f, "  {event}")?;
+        }
+        Ok(())
+    }
+}
+
+type Found<'a> = Vec<(String, Vec<&'a NetLogMessage>)>;
+
+impl Property {
+    pub(super) fn check(
+        &self,
+        logs: &[NetLogMessage],
+        labels: &HashMap<PeerId, NodeLabel>,
+    ) -> Vec<Violation> {
+        let mut found = match self {
+            Property::PutReachesCachingPeer => put_reaches_caching_peer(logs),
+            Property::HopsWithin(max) => hops_within(logs, *max),
+            Property::SubscribersConverge => subscribers_converge(logs),
+        };
+        for (_, events) in &mut found {
+            events.sort_by_key(|log| log.datetime);
+            events.dedup_by(|a, b| std::ptr::eq(*a, *b));
+        }
+        found.sort_by_key(|(_, events)| events.first().map(|log| log.datetime));
+        found
+            .into_iter()
+            .map(|(reason, events)| Violation {
+                property: self.clone(),
+                reason,
+                trace: events
+                    .into_iter()
+                    .map(|log| describe(log, labels))
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+fn describe(log: &NetLogMessage, labels: &HashMap<PeerId, NodeLabel>) -> String {
+    let peer = labels
+        .get(&log.peer_id)
+        .map(|label| label.to_string())
+        .unwrap_or_else(|| log.peer_id.to_string());
+    format!(
+        "{} {} @ {peer}: {:?}",
+        log.datetime.format("%H:%M:%S%.3f"),
+        log.tx,
+        log.kind
+    )
+}
+
+fn by_transaction(logs: &[NetLogMessage]) -> HashMap<Transaction, Vec<&NetLogMessage>> {
+    logs.iter()
+        .filter(|log| &log.tx != Transaction::NULL)
+        .fold(HashMap::new(), |mut acc, log| {
+            acc.entry(log.tx).or_default().push(log);
+            acc
+        })
+}
+
+/// Location of each peer, along with the event where it was first seen.
+fn locations(logs: &[NetLogMessage]) -> HashMap<PeerId, (Location, &NetLogMessage)> {
+    let mut locations = HashMap::new();
+    for log in logs {
+        match &log.kind {
+            EventKind::Connect(ConnectEvent::Finished {
+                initiator,
+                location,
+            }) => {
+                locations.entry(*initiator).or_insert((*location, log));
+            }
+            EventKind::Connect(ConnectEvent::Connected { this, .. }) => {
+                if let Some(location) = this.location {
+                    locations.entry(this.peer).or_insert((location, log));
+                }
+            }
+            _ => {}
+        }
+    }
+    locations
+}
+
+/// The item with the lowest finite distance, if any.
+fn closest<T>(items: impl Iterator<Item = (f64, T)>) -> Option<(f64, T)> {
+    items
+        .filter(|(distance, _)| distance.is_finite())
+        .fold(None, |closest, (distance, item)| match closest {
+            Some((min, _)) if min <= distance => closest,
+            _ => Some((distance, item)),
+        })
+}
+
+fn put_reaches_caching_peer(logs: &[NetLogMessage]) -> Found<'_> {
+    let locations = locations(logs);
+    let mut found = vec![];
+    for (_, events) in by_transaction(logs) {
+        let Some((request, key)) = events.iter().find_map(|log| match &log.kind {
+            EventKind::Put(PutEvent::Request { key, .. }) => Some((*log, key)),
+            _ => None,
+        }) else {
+            continue;
+        };
+        let Some(success) = events
+            .iter()
+            .find(|log| matches!(log.kind, EventKind::Put(PutEvent::PutSuccess { .. })))
+        else {
+            continue;
+        };
+        let key_location = Location::from(key);
+        let distance = |peer: &PeerId| {
+            locations
+                .get(peer)
+                .map(|(location, _)| location.distance(key_location).as_f64())
+                .unwrap_or(f64::INFINITY)
+        };
+        let closest_peer = closest(
+            locations
+                .iter()
+                .map(|(peer, (_, joined))| (distance(peer), *joined)),
+        );
+        let reached = closest(
+            events
+                .iter()
+                .filter(|log| {
+                    matches!(
+                        log.kind,
+                        EventKind::Put(PutEvent::Request { .. })
+                            | EventKind::Put(PutEvent::BroadcastEmitted { .. })
+                            | EventKind::Forwarded { .. }
+                    )
+                })
+                .map(|log| (distance(&log.peer_id), *log)),
+        );
+        let reached_distance = reached.map_or(f64::INFINITY, |(distance, _)| distance);
+        let closest_distance = closest_peer.map_or(f64::INFINITY, |(distance, _)| distance);
+        if reached_distance > Ring::CACHING_DISTANCE && reached_distance > closest_distance {
+            let reason = if reached_distance.is_finite() {
+                format!(
+                    "put of contract {key} only reached peers {reached_distance:.3} away from it"
+                )
+            } else {
+                format!("put of contract {key} did not reach any peer with a known location")
+            };
+            // the put, how close it got and the peer it should have reached
+            let trace = [
+                Some(request),
+                reached.map(|(_, log)| log),
+                Some(*success),
+                closest_peer.map(|(_, joined)| joined),
+            ]
+            .into_iter()
+            .flatten()
+            .collect();
+            found.push((reason, trace));
+        }
+    }
+    found
+}
+
+fn hops_within(logs: &[NetLogMessage], max: usize) -> Found<'_> {
+    by_transaction(logs)
+        .into_iter()
+        .filter_map(|(tx, events)| {
+            let mut hops: Vec<_> = events
+                .into_iter()
+                .filter(|log| matches!(log.kind, EventKind::Forwarded { .. }))
+                .collect();
+            (hops.len() > max).then(|| {
+                let reason = format!(
+                    "transaction {tx} was routed through {} hops (> {max})",
+                    hops.len()
+                );
+                // one hop over the limit is enough to show it
+                hops.truncate(max + 1);
+                (reason, hops)
+            })
+        })
+        .collect()
+}
+
+fn subscribers_converge(logs: &[NetLogMessage]) -> Found<'_> {
+    let mut subscribers: HashMap<&ContractKey, HashMap<PeerId, &NetLogMessage>> = HashMap::new();
+    // last state of each contract received by each peer
+    let mut states: HashMap<(&ContractKey, PeerId), (&NetLogMessage, &WrappedState)> =
+        HashMap::new();
+    for log in logs {
+        match &log.kind {
+            EventKind::Subscribed { key, .. } => {
+                subscribers
+                    .entry(key)
+                    .or_default()
+                    .entry(log.peer_id)
+                    .or_insert(log);
+            }
+            EventKind::Put(PutEvent::BroadcastReceived { key, value, .. })
+            | EventKind::Update(UpdateEvent::BroadcastReceived { key, value, .. }) => {
+                states.insert((key, log.peer_id), (log, value));
+            }
+            _ => {}
+        }
+    }
+    let mut found = vec![];
+    for (key, peers) in subscribers {
+        let mut by_state: HashMap<blake3::Hash, Vec<(&NetLogMessage, &NetLogMessage)>> =
+            HashMap::new();
+        for (peer, subscribed) in peers {
+            if let Some(&(log, state)) = states.get(&(key, peer)) {
+                by_state
+                    .entry(blake3::hash(state.as_ref()))
+                    .or_default()
+                    .push((subscribed, log));
+            }
+        }
+        if by_state.len() > 1 {
+            let reason = format!(
+                "subscribers of contract {key} ended up with {} different states",
+                by_state.len()
+            );
+            // two subscribers which disagree are enough to show it
+            let mut disagreeing: Vec<_> = by_state
+                .into_values()
+                .filter_map(|mut subscribers| {
+                    subscribers.sort_by_key(|(_, received)| received.datetime);
+                    subscribers.into_iter().next()
+                })
+                .collect();
+            disagreeing.sort_by_key(|(_, received)| received.datetime);
+            let events = disagreeing
+                .into_iter()
+                .take(2)
+                .flat_map(|(subscribed, received)| [subscribed, received])
+                .collect();
+            found.push((reason, events));
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{operations::put::PutMsg, ring::PeerKeyLocation};
+
+    fn log(tx: Transaction, peer_id: PeerId, kind: EventKind) -> NetLogMessage {
+        NetLogMessage {
+            tx,
+            datetime: Utc::now(),
+            peer_id,
+            kind,
+        }
+    }
+
+    fn joined(peer: PeerId, location: Location) -> NetLogMessage {
+        log(
+            *Transaction::NULL,
+            peer,
+            EventKind::Connect(ConnectEvent::Finished {
+                initiator: peer,
+                location,
+            }),
+        )
+    }
+
+    #[test]
+    fn check_properties() -> Result<(), anyhow::Error> {
+        let bytes = crate::util::test::random_bytes_1kb();
+        let mut gen = arbitrary::Unstructured::new(&bytes);
+        let key = gen.arbitrary::<WrappedContract>()?.key().clone();
+        let key_location = Location::from(&key);
+        let far_location = Location::new((key_location.as_f64() + 0.5) % 1.0);
+
+        let (requester, far, close) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let labels = HashMap::from([(requester, NodeLabel::from("node-1"))]);
+        let tx = Transaction::new::<PutMsg>();
+        let request = EventKind::Put(PutEvent::Request {
+            requester,
+            key: key.clone(),
+        });
+        let success = EventKind::Put(PutEvent::PutSuccess { requester });
+        let forwarded = EventKind::Forwarded { key: key.clone() };
+        let mut logs = vec![
+            joined(requester, far_location),
+            joined(far, far_location),
+            joined(close, key_location),
+            log(tx, requester, request),
+            log(tx, far, forwarded.clone()),
+            log(tx, requester, success),
+        ];
+
+        let violations = Property::PutReachesCachingPeer.check(&logs, &labels);
+        assert_eq!(violations.len(), 1);
+        // the request, its success and the closer peer; the hop to an equally far peer is
+        // not needed to show the violation
+        let trace = &violations[0].trace;
+        assert_eq!(trace.len(), 3);
+        assert!(trace[0].contains(&close.to_string()));
+        assert!(trace[1].contains("node-1") && trace[1].contains("Request"));
+        assert!(!trace.iter().any(|event| event.contains("Forwarded")));
+        assert_eq!(Property::HopsWithin(1).check(&logs, &labels).len(), 0);
+
+        logs.push(log(tx, close, forwarded));
+        assert!(Property::PutReachesCachingPeer
+            .check(&logs, &labels)
+            .is_empty());
+        let violations = Property::HopsWithin(1).check(&logs, &labels);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].trace.len(), 2);
+        assert_eq!(
+            Property::HopsWithin(0).check(&logs, &labels)[0].trace.len(),
+            1
+        );
+
+        let state = |value: u8| WrappedState::new(vec![value]);
+        let subscribed = |peer| {
+            log(
+                Transaction::new::<PutMsg>(),
+                peer,
+                EventKind::Subscribed {
+                    key: key.clone(),
+                    at: PeerKeyLocation::random(),
+                },
+            )
+        };
+        let received = |peer, value| {
+            log(
+                Transaction::new::<PutMsg>(),
+                peer,
+                EventKind::Update(UpdateEvent::BroadcastReceived {
+                    requester,
+                    key: key.clone(),
+                    value,
+                }),
+            )
+        };
+        logs.extend([
+            subscribed(far),
+            subscribed(close),
+            subscribed(requester),
+            received(far, state(1)),
+            received(close, state(2)),
+            received(requester, state(1)),
+        ]);
+        let violations = Property::SubscribersConverge.check(&logs, &labels);
+        assert_eq!(violations.len(), 1);
+        // a subscriber with each state, along with their subscriptions
+        assert_eq!(violations[0].trace.len(), 4);
+        assert!(!violations[0]
+            .trace
+            .iter()
+            .any(|event| event.contains("node-1")));
+        logs.push(received(close, state(1)));
+        assert!(Property::SubscribersConverge
+            .check(&logs, &labels)
+            .is_empty());
+        Ok(())
+    }
+}
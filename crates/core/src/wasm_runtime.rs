@@ -3,21 +3,24 @@ mod contract_store;
 mod delegate;
 mod delegate_store;
 mod error;
+mod module_cache;
 mod native_api;
 mod runtime;
 mod secrets_store;
 mod state_store;
 mod store;
 #[cfg(test)]
-mod tests;
+pub(crate) mod tests;
+mod version;
 
-pub(crate) use contract::ContractRuntimeInterface;
+pub use contract::ContractRuntimeInterface;
 pub use contract_store::ContractStore;
 pub(crate) use delegate::DelegateRuntimeInterface;
 pub use delegate_store::DelegateStore;
 pub(crate) use error::{ContractError, RuntimeInnerError, RuntimeResult};
-pub use runtime::{ContractExecError, Runtime};
+pub use runtime::{ContractExecError, ExecutionLimits, Runtime};
 pub(crate) use secrets_store::SecretStoreError;
-pub use secrets_store::SecretsStore;
-pub use state_store::StateStore;
+pub use secrets_store::{MasterKey, SecretsStore};
 pub(crate) use state_store::{StateStorage, StateStoreError};
+pub use state_store::{StateStore, StateVersion};
+pub use store::IndexStats;
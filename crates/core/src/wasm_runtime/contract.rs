@@ -1,14 +1,10 @@
+use super::{ContractExecError, RuntimeResult};
 use freenet_stdlib::prelude::{
     ContractInterfaceResult, ContractKey, Parameters, RelatedContracts, StateDelta, StateSummary,
     UpdateData, UpdateModification, ValidateResult, WrappedState,
 };
-use wasmer::TypedFunction;
-
-use super::{ContractExecError, RuntimeResult};
 
-type FfiReturnTy = i64;
-
-pub(crate) trait ContractRuntimeInterface {
+pub trait ContractRuntimeInterface {
     /// Verify that the state is valid, given the parameters. This will be used before a peer
     /// caches a new state.
     fn validate_state(
@@ -95,24 +91,21 @@ impl ContractRuntimeInterface for super::Runtime {
             related_buf.ptr()
         };
 
-        let validate_func: TypedFunction<(i64, i64, i64), FfiReturnTy> =
-            running
-                .instance
-                .exports
-                .get_typed_function(&self.wasm_store, "validate_state")?;
+        let res = self.call_entry_point(
+            &running,
+            "validate_state",
+            &[
+                param_buf_ptr as i64,
+                state_buf_ptr as i64,
+                related_buf_ptr as i64,
+            ],
+        )?;
         let is_valid = unsafe {
-            ContractInterfaceResult::from_raw(
-                validate_func.call(
-                    &mut self.wasm_store,
-                    param_buf_ptr as i64,
-                    state_buf_ptr as i64,
-                    related_buf_ptr as i64,
-                )?,
-                &linear_mem,
-            )
-            .unwrap_validate_state_res(linear_mem)
-            .map_err(Into::<ContractExecError>::into)?
+            ContractInterfaceResult::from_raw(res, &linear_mem)
+                .unwrap_validate_state_res(linear_mem)
+                .map_err(Into::<ContractExecError>::into)?
         };
+        self.release(running);
         Ok(is_valid)
     }
 
@@ -138,22 +131,17 @@ impl ContractRuntimeInterface for super::Runtime {
             delta_buf.ptr()
         };
 
-        let validate_func: TypedFunction<(i64, i64), FfiReturnTy> = running
-            .instance
-            .exports
-            .get_typed_function(&self.wasm_store, "validate_delta")?;
+        let res = self.call_entry_point(
+            &running,
+            "validate_delta",
+            &[param_buf_ptr as i64, delta_buf_ptr as i64],
+        )?;
         let is_valid = unsafe {
-            ContractInterfaceResult::from_raw(
-                validate_func.call(
-                    &mut self.wasm_store,
-                    param_buf_ptr as i64,
-                    delta_buf_ptr as i64,
-                )?,
-                &linear_mem,
-            )
-            .unwrap_validate_delta_res(linear_mem)
-            .map_err(Into::<ContractExecError>::into)?
+            ContractInterfaceResult::from_raw(res, &linear_mem)
+                .unwrap_validate_delta_res(linear_mem)
+                .map_err(Into::<ContractExecError>::into)?
         };
+        self.release(running);
         Ok(is_valid)
     }
 
@@ -189,24 +177,21 @@ impl ContractRuntimeInterface for super::Runtime {
             update_data_buf.ptr()
         };
 
-        let validate_func: TypedFunction<(i64, i64, i64), FfiReturnTy> =
-            running
-                .instance
-                .exports
-                .get_typed_function(&self.wasm_store, "update_state")?;
+        let res = self.call_entry_point(
+            &running,
+            "update_state",
+            &[
+                param_buf_ptr as i64,
+                state_buf_ptr as i64,
+                update_data_buf_ptr as i64,
+            ],
+        )?;
         let update_res = unsafe {
-            ContractInterfaceResult::from_raw(
-                validate_func.call(
-                    &mut self.wasm_store,
-                    param_buf_ptr as i64,
-                    state_buf_ptr as i64,
-                    update_data_buf_ptr as i64,
-                )?,
-                &linear_mem,
-            )
-            .unwrap_update_state(linear_mem)
-            .map_err(Into::<ContractExecError>::into)?
+            ContractInterfaceResult::from_raw(res, &linear_mem)
+                .unwrap_update_state(linear_mem)
+                .map_err(Into::<ContractExecError>::into)?
         };
+        self.release(running);
         Ok(update_res)
     }
 
@@ -231,24 +216,18 @@ impl ContractRuntimeInterface for super::Runtime {
             state_buf.ptr()
         };
 
-        let summary_func: TypedFunction<(i64, i64), FfiReturnTy> = running
-            .instance
-            .exports
-            .get_typed_function(&self.wasm_store, "summarize_state")?;
-
+        let res = self.call_entry_point(
+            &running,
+            "summarize_state",
+            &[param_buf_ptr as i64, state_buf_ptr as i64],
+        )?;
         let result = unsafe {
-            let int_res = ContractInterfaceResult::from_raw(
-                summary_func.call(
-                    &mut self.wasm_store,
-                    param_buf_ptr as i64,
-                    state_buf_ptr as i64,
-                )?,
-                &linear_mem,
-            );
+            let int_res = ContractInterfaceResult::from_raw(res, &linear_mem);
             int_res
                 .unwrap_summarize_state(linear_mem)
                 .map_err(Into::<ContractExecError>::into)?
         };
+        self.release(running);
         Ok(result)
     }
 
@@ -279,27 +258,22 @@ impl ContractRuntimeInterface for super::Runtime {
             summary_buf.ptr()
         };
 
-        let get_state_delta_func: TypedFunction<(i64, i64, i64), FfiReturnTy> = running
-            .instance
-            .exports
-            .get_typed_function(&self.wasm_store, "get_state_delta")?;
-
+        let res = self.call_entry_point(
+            &running,
+            "get_state_delta",
+            &[
+                param_buf_ptr as i64,
+                state_buf_ptr as i64,
+                summary_buf_ptr as i64,
+            ],
+        )?;
         let result = unsafe {
-            let int_res = {
-                ContractInterfaceResult::from_raw(
-                    get_state_delta_func.call(
-                        &mut self.wasm_store,
-                        param_buf_ptr as i64,
-                        state_buf_ptr as i64,
-                        summary_buf_ptr as i64,
-                    )?,
-                    &linear_mem,
-                )
-            };
+            let int_res = { ContractInterfaceResult::from_raw(res, &linear_mem) };
             int_res
                 .unwrap_get_state_delta(linear_mem)
                 .map_err(Into::<ContractExecError>::into)?
         };
+        self.release(running);
         Ok(result)
     }
 }
@@ -6,7 +6,9 @@ use stretto::Cache;
 
 use super::{
     error::RuntimeInnerError,
-    store::{SafeWriter, StoreFsManagement},
+    module_cache::ModuleCache,
+    store::{DiskUsage, IndexLock, IndexStats, SafeWriter, StoreFsManagement, WatchGuard},
+    version::ContractVersion,
     RuntimeResult,
 };
 
@@ -14,12 +16,14 @@ use super::{
 pub struct ContractStore {
     contracts_dir: PathBuf,
     key_file: PathBuf,
-    contract_cache: Cache<CodeHash, Arc<ContractCode<'static>>>,
+    contract_cache: Cache<CodeHash, (Arc<ContractCode<'static>>, ContractVersion)>,
     key_to_code_part: Arc<DashMap<ContractInstanceId, (u64, CodeHash)>>,
     index_file: SafeWriter<Self>,
+    disk_usage: DiskUsage,
+    module_cache: ModuleCache,
+    /// stops the maintenance of the index file once the store is dropped
+    _watch: WatchGuard,
 }
-// TODO: add functionality to delete old contracts which have not been used for a while
-//       to keep the total space used under a configured threshold
 
 impl StoreFsManagement for ContractStore {
     type MemContainer = Arc<DashMap<ContractInstanceId, (u64, CodeHash)>>;
@@ -51,18 +55,53 @@ impl ContractStore {
         } else {
             Self::load_from_file(&key_file, &mut key_to_code_part)?;
         }
-        Self::watch_changes(key_to_code_part.clone(), &key_file)?;
+        let watch = Self::watch_changes(key_to_code_part.clone(), &key_file)?;
+
+        let disk_usage = DiskUsage::default();
+        for entry in key_to_code_part.iter() {
+            let code_hash = entry.value().1;
+            let path = contracts_dir
+                .join(code_hash.encode())
+                .with_extension("wasm");
+            disk_usage.load(code_hash, &path);
+        }
 
         let index_file = SafeWriter::new(&key_file, false)?;
+        let module_cache = ModuleCache::new(&contracts_dir.join("modules"))?;
         Ok(Self {
             contract_cache: Cache::new(100, max_size).expect(ERR),
             contracts_dir,
             key_file,
             key_to_code_part,
             index_file,
+            disk_usage,
+            module_cache,
+            _watch: watch,
         })
     }
 
+    /// Sets the max size in bytes the contracts stored on disk can use,
+    /// see [`Self::evict_least_recently_used`].
+    pub fn with_disk_quota(mut self, quota: u64) -> Self {
+        self.disk_usage = self.disk_usage.with_quota(quota);
+        self
+    }
+
+    /// Disk space in bytes currently used by the stored contracts.
+    pub fn disk_usage(&self) -> u64 {
+        self.disk_usage.used()
+    }
+
+    /// Compiled modules for the stored contracts.
+    pub(super) fn module_cache(&self) -> &ModuleCache {
+        &self.module_cache
+    }
+
+    /// Compiles in the background every contract stored from now on, with the given engine.
+    pub(super) fn precompile_with(&mut self, engine: wasmer::Engine) {
+        self.module_cache.precompile_with(engine);
+    }
+
     /// Returns a copy of the contract bytes if available, none otherwise.
     // todo: instead return Result<Option<_>, _> to handle IO errors upstream
     pub fn fetch_contract(
@@ -74,91 +113,93 @@ impl ContractStore {
             .code_hash()
             .and_then(|code_hash| {
                 self.contract_cache.get(code_hash).map(|data| {
-                    Some(ContractContainer::Wasm(ContractWasmAPIVersion::V1(
-                        WrappedContract::new(data.value().clone(), params.clone().into_owned()),
-                    )))
+                    let (code, version) = data.value();
+                    Some(version.container(code.clone(), params.clone().into_owned()))
                 })
             })
             .flatten();
+        if let Some(code_hash) = key.code_hash() {
+            self.disk_usage.touch(code_hash);
+        }
         if result.is_some() {
             return result;
         }
 
         self.key_to_code_part.get(key.id()).and_then(|key| {
             let code_hash = key.value().1;
+            self.disk_usage.touch(&code_hash);
             let path = code_hash.encode();
             let key_path = self.contracts_dir.join(path).with_extension("wasm");
-            let ContractContainer::Wasm(ContractWasmAPIVersion::V1(WrappedContract {
-                data,
-                params,
-                ..
-            })) = ContractContainer::try_from((&*key_path, params.clone().into_owned()))
+            let contract = ContractContainer::try_from((&*key_path, params.clone().into_owned()))
                 .map_err(|err| {
                     tracing::debug!("contract not found: {err}");
                     err
                 })
-                .ok()?
-            else {
-                unimplemented!()
-            };
+                .ok()?;
+            let (version, _, code) = ContractVersion::unwrap(&contract)
+                .map_err(|err| tracing::warn!("can't load contract `{path}`: {err}"))
+                .ok()?;
             // add back the contract part to the mem store
-            let size = data.data().len() as i64;
-            self.contract_cache.insert(code_hash, data.clone(), size);
-            Some(ContractContainer::Wasm(ContractWasmAPIVersion::V1(
-                WrappedContract::new(data, params),
-            )))
+            let size = code.data().len() as i64;
+            self.contract_cache
+                .insert(code_hash, (code.clone(), version), size);
+            Some(contract)
         })
     }
 
     /// Store a copy of the contract in the local store, in case it hasn't been stored previously.
     pub fn store_contract(&mut self, contract: ContractContainer) -> RuntimeResult<()> {
-        let (key, code) = match contract.clone() {
-            ContractContainer::Wasm(ContractWasmAPIVersion::V1(contract_v1)) => {
-                (contract_v1.key().clone(), contract_v1.code().clone())
-            }
-            _ => unimplemented!(),
-        };
+        let (version, key, code) = ContractVersion::unwrap(&contract)?;
+        let (key, code) = (key.clone(), code.clone());
         let code_hash = key.code_hash().ok_or_else(|| {
             tracing::warn!("trying to store partially unspecified contract `{}`", key);
             RuntimeInnerError::UnwrapContract
         })?;
         if self.contract_cache.get(code_hash).is_some() {
+            self.disk_usage.touch(code_hash);
             return Ok(());
         }
         let key_path = code_hash.encode();
         let key_path = self.contracts_dir.join(key_path).with_extension("wasm");
         if let Ok((code, _ver)) = ContractCode::load_versioned_from_path(&key_path) {
+            self.disk_usage.touch(code_hash);
             let size = code.data().len() as i64;
-            self.contract_cache.insert(*code_hash, Arc::new(code), size);
+            self.contract_cache
+                .insert(*code_hash, (Arc::new(code), version), size);
             return Ok(());
         }
 
         // insert in the memory cache
         let size = code.data().len() as i64;
         let data = code.data().to_vec();
-        self.contract_cache
-            .insert(*code_hash, Arc::new(ContractCode::from(data)), size);
+        self.contract_cache.insert(
+            *code_hash,
+            (Arc::new(ContractCode::from(data)), version),
+            size,
+        );
 
         // save on disc
-        let version = APIVersion::from(contract);
-        let output: Vec<u8> = code.to_bytes_versioned(version)?;
+        let output: Vec<u8> = code.to_bytes_versioned(APIVersion::from(contract))?;
         let mut file = File::create(key_path)?;
         file.write_all(output.as_slice())?;
+        self.disk_usage.record(*code_hash, output.len() as u64);
+        self.module_cache.precompile(code_hash, code.data());
 
-        // Update index
+        // Update index, the offsets can't change while holding the lock
+        let lock = IndexLock::acquire(&self.key_file)?;
         let keys = self.key_to_code_part.entry(*key.id());
         match keys {
             dashmap::mapref::entry::Entry::Occupied(mut v) => {
                 let current_version_offset = v.get().0;
                 let prev_val = &mut v.get_mut().1;
                 // first mark the old entry (if it exists) as removed
-                Self::remove(&self.key_file, current_version_offset)?;
-                let new_offset = Self::insert(&mut self.index_file, *key.id(), code_hash)?;
+                Self::remove(&self.key_file, &lock, current_version_offset)?;
+                let new_offset = Self::insert(&mut self.index_file, &lock, *key.id(), code_hash)?;
                 *prev_val = *code_hash;
                 v.get_mut().0 = new_offset;
             }
             dashmap::mapref::entry::Entry::Vacant(v) => {
-                let offset = Self::insert(&mut self.index_file, *key.id(), code_hash)?;
+                let offset = Self::insert(&mut self.index_file, &lock, *key.id(), code_hash)?;
                 v.insert((offset, *code_hash));
             }
         }
@@ -186,17 +227,80 @@ impl ContractStore {
                 RuntimeInnerError::UnwrapContract
             })?,
         };
+        let lock = IndexLock::acquire(&self.key_file)?;
         if let Some((_, (offset, _))) = self.key_to_code_part.remove(key.id()) {
-            Self::remove(&self.key_file, offset)?;
+            Self::remove(&self.key_file, &lock, offset)?;
         }
+        drop(lock);
         let key_path = self
             .contracts_dir
             .join(contract_hash.encode())
             .with_extension("wasm");
         std::fs::remove_file(key_path)?;
+        self.disk_usage.forget(&contract_hash);
+        self.module_cache.remove(&contract_hash)?;
         Ok(())
     }
 
+    /// Removes the least recently used contracts from disk until the space used is back under the
+    /// configured quota, skipping any contract for which `keep` returns true (e.g. contracts being
+    /// seeded or subscribed to). Returns the code hashes of the evicted contracts.
+    pub fn evict_least_recently_used(
+        &mut self,
+        keep: impl Fn(&ContractKey) -> bool,
+    ) -> RuntimeResult<Vec<CodeHash>> {
+        let Some(mut excess) = self.disk_usage.excess() else {
+            return Ok(vec![]);
+        };
+        let mut evicted = vec![];
+        for (code_hash, size) in self.disk_usage.least_recently_used() {
+            if excess == 0 {
+                break;
+            }
+            // the same code may be shared by multiple contract instances
+            let instances: Vec<ContractInstanceId> = self
+                .key_to_code_part
+                .iter()
+                .filter(|entry| entry.value().1 == code_hash)
+                .map(|entry| *entry.key())
+                .collect();
+            if instances.iter().any(|id| keep(&ContractKey::from(*id))) {
+                continue;
+            }
+            let lock = IndexLock::acquire(&self.key_file)?;
+            for id in instances {
+                if let Some((_, (offset, _))) = self.key_to_code_part.remove(&id) {
+                    Self::remove(&self.key_file, &lock, offset)?;
+                }
+            }
+            drop(lock);
+            self.contract_cache.remove(&code_hash);
+            let key_path = self
+                .contracts_dir
+                .join(code_hash.encode())
+                .with_extension("wasm");
+            match std::fs::remove_file(key_path) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+            self.disk_usage.forget(&code_hash);
+            self.module_cache.remove(&code_hash)?;
+            tracing::debug!("evicted contract code `{}` from disk", code_hash.encode());
+            excess = excess.saturating_sub(size);
+            evicted.push(code_hash);
+        }
+        if !evicted.is_empty() {
+            Self::compact_index(&self.key_file, &mut self.key_to_code_part)?;
+            self.index_file = SafeWriter::new(&self.key_file, false)?;
+        }
+        Ok(evicted)
+    }
+
+    /// Live vs removed records in the key index file.
+    pub fn index_stats(&self) -> RuntimeResult<IndexStats> {
+        Ok(IndexStats::from_file(&self.key_file)?)
+    }
+
     pub fn code_hash_from_key(&self, key: &ContractKey) -> Option<CodeHash> {
         self.key_to_code_part.get(key.id()).map(|r| r.value().1)
     }
@@ -221,4 +325,42 @@ mod test {
         assert!(f.is_some());
         Ok(())
     }
+
+    #[test]
+    fn evict_least_recently_used() -> Result<(), Box<dyn std::error::Error>> {
+        let contract_dir = crate::util::tests::get_temp_dir();
+        let mut store = ContractStore::new(contract_dir.path().into(), 10_000)?;
+        let contracts: Vec<_> = (0..3u8)
+            .map(|i| {
+                WrappedContract::new(
+                    Arc::new(ContractCode::from(vec![i; 100])),
+                    [i].as_ref().into(),
+                )
+            })
+            .collect();
+        for contract in &contracts {
+            store.store_contract(ContractContainer::Wasm(ContractWasmAPIVersion::V1(
+                contract.clone(),
+            )))?;
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let per_contract = store.disk_usage() / 3;
+        let mut store = store.with_disk_quota(per_contract * 2);
+
+        // the least recently used one is being kept, so the next one gets evicted
+        let kept = contracts[0].key().clone();
+        let evicted = store.evict_least_recently_used(|key| key == &kept)?;
+        assert_eq!(evicted, vec![*contracts[1].key().code_hash().unwrap()]);
+        assert!(store.code_hash_from_key(contracts[1].key()).is_none());
+        assert!(store
+            .fetch_contract(contracts[0].key(), &[0].as_ref().into())
+            .is_some());
+        assert_eq!(store.disk_usage(), per_contract * 2);
+
+        // the index is compacted and still usable after the eviction
+        let reloaded = ContractStore::new(contract_dir.path().into(), 10_000)?;
+        assert!(reloaded.code_hash_from_key(contracts[1].key()).is_none());
+        assert!(reloaded.code_hash_from_key(contracts[2].key()).is_some());
+        Ok(())
+    }
 }
@@ -1,15 +1,17 @@
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
 
-use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use chacha20poly1305::{Key, XNonce};
 use freenet_stdlib::prelude::{
     ApplicationMessage, ClientResponse, DelegateContainer, DelegateContext, DelegateError,
-    DelegateInterfaceResult, DelegateKey, GetSecretRequest, GetSecretResponse, InboundDelegateMsg,
-    OutboundDelegateMsg, Parameters, SecretsId, SetSecretRequest,
+    DelegateInterfaceResult, DelegateKey, GetContractRequest, GetSecretRequest, GetSecretResponse,
+    InboundDelegateMsg, OutboundDelegateMsg, Parameters, PutContractRequest, SecretsId,
+    SetSecretRequest,
 };
 use serde::{Deserialize, Serialize};
-use wasmer::{Instance, TypedFunction};
 
 use super::error::RuntimeInnerError;
+use super::runtime::RunningInstance;
 use super::{ContractError, Runtime, RuntimeResult};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -38,6 +40,12 @@ pub enum DelegateExecError {
 
     #[error("Received an unexpected message from the client apps: {0}")]
     UnexpectedMessage(&'static str),
+
+    #[error("delegate execution ran out of fuel (limit: {0})")]
+    OutOfFuel(u64),
+
+    #[error("delegate execution exceeded the time limit ({0:?})")]
+    Timeout(Duration),
 }
 
 pub(crate) trait DelegateRuntimeInterface {
@@ -49,10 +57,21 @@ pub(crate) trait DelegateRuntimeInterface {
         inbound: Vec<InboundDelegateMsg>,
     ) -> RuntimeResult<Vec<OutboundDelegateMsg>>;
 
+    /// Feeds the delegate the response to a contract request it made, then carries on with the
+    /// messages it had queued after the request.
+    fn inbound_contract_response(
+        &mut self,
+        key: &DelegateKey,
+        params: &Parameters,
+        attested: Option<&[u8]>,
+        response: InboundDelegateMsg,
+        pending: Vec<OutboundDelegateMsg>,
+    ) -> RuntimeResult<Vec<OutboundDelegateMsg>>;
+
     fn register_delegate(
         &mut self,
         delegate: DelegateContainer,
-        cipher: XChaCha20Poly1305,
+        cipher_key: Key,
         nonce: XNonce,
     ) -> RuntimeResult<()>;
 
@@ -65,9 +84,9 @@ impl Runtime {
         params: &Parameters<'_>,
         attested: Option<&[u8]>,
         msg: &InboundDelegateMsg,
-        process_func: &TypedFunction<(i64, i64, i64), i64>,
-        instance: &Instance,
+        running: &RunningInstance,
     ) -> RuntimeResult<Vec<OutboundDelegateMsg>> {
+        let instance = &running.instance;
         let param_buf_ptr = {
             let mut param_buf = self.init_buf(instance, params)?;
             param_buf.write(params)?;
@@ -85,11 +104,14 @@ impl Runtime {
             msg_buf.write(msg)?;
             msg_buf.ptr()
         };
-        let res = process_func.call(
-            &mut self.wasm_store,
-            param_buf_ptr as i64,
-            attested_buf_ptr as i64,
-            msg_ptr as i64,
+        let res = self.call_entry_point(
+            running,
+            "process",
+            &[
+                param_buf_ptr as i64,
+                attested_buf_ptr as i64,
+                msg_ptr as i64,
+            ],
         )?;
         let linear_mem = self.linear_mem(instance)?;
         let outbound = unsafe {
@@ -101,12 +123,10 @@ impl Runtime {
     }
 
     // FIXME: modify the context atomically from the delegates, requires some changes to handle function calls with envs
-    #[allow(clippy::too_many_arguments)]
     fn get_outbound(
         &mut self,
         delegate_key: &DelegateKey,
-        instance: &Instance,
-        process_func: &TypedFunction<(i64, i64, i64), i64>,
+        running: &RunningInstance,
         params: &Parameters<'_>,
         attested: Option<&[u8]>,
         outbound_msgs: &mut VecDeque<OutboundDelegateMsg>,
@@ -132,8 +152,7 @@ impl Runtime {
                     if recurssion >= MAX_ITERATIONS {
                         return Err(ContractError::from(RuntimeInnerError::DelegateExecError(DelegateError::Other("The maximum number of attempts to get the secret has been exceeded".to_string()).into())));
                     }
-                    let new_msgs =
-                        self.exec_inbound(params, attested, &inbound, process_func, instance)?;
+                    let new_msgs = self.exec_inbound(params, attested, &inbound, running)?;
                     recurssion += 1;
                     let Some(last_msg) = new_msgs.last() else {
                         return Err(ContractError::from(RuntimeInnerError::DelegateExecError(
@@ -189,8 +208,7 @@ impl Runtime {
                                 .processed(msg.processed)
                                 .with_context(last_context.clone()),
                         ),
-                        process_func,
-                        instance,
+                        running,
                     )?;
                     recurssion += 1;
                     for msg in outbound {
@@ -219,6 +237,22 @@ impl Runtime {
                 OutboundDelegateMsg::ContextUpdated(context) => {
                     last_context = context;
                 }
+                // contract requests are served by the executor, the delegate continues once
+                // it gets the response, so hand back everything still queued
+                OutboundDelegateMsg::GetContractRequest(req) if !req.processed => {
+                    results.push(OutboundDelegateMsg::GetContractRequest(req));
+                    results.extend(outbound_msgs.drain(..));
+                    break;
+                }
+                OutboundDelegateMsg::PutContractRequest(req) if !req.processed => {
+                    results.push(OutboundDelegateMsg::PutContractRequest(req));
+                    results.extend(outbound_msgs.drain(..));
+                    break;
+                }
+                OutboundDelegateMsg::GetContractRequest(GetContractRequest { context, .. })
+                | OutboundDelegateMsg::PutContractRequest(PutContractRequest { context, .. }) => {
+                    last_context = context;
+                }
             }
         }
         Ok(last_context)
@@ -238,10 +272,6 @@ impl DelegateRuntimeInterface for Runtime {
             return Ok(results);
         }
         let running = self.prepare_delegate_call(params, delegate_key, 4096)?;
-        let process_func: TypedFunction<(i64, i64, i64), i64> = running
-            .instance
-            .exports
-            .get_typed_function(&self.wasm_store, "process")?;
 
         // Initialize the shared context with the first message context
         let mut last_context = match inbound.first() {
@@ -273,8 +303,7 @@ impl DelegateRuntimeInterface for Runtime {
                                     .processed(processed)
                                     .with_context(last_context.clone()),
                             ),
-                            &process_func,
-                            &running.instance,
+                            &running,
                         )?,
                     );
 
@@ -292,8 +321,7 @@ impl DelegateRuntimeInterface for Runtime {
                     // Update the shared context for next messages
                     last_context = self.get_outbound(
                         delegate_key,
-                        &running.instance,
-                        &process_func,
+                        &running,
                         params,
                         attested,
                         &mut real_outbound,
@@ -305,8 +333,7 @@ impl DelegateRuntimeInterface for Runtime {
                         params,
                         attested,
                         &InboundDelegateMsg::UserResponse(response),
-                        &process_func,
-                        &running.instance,
+                        &running,
                     )?;
 
                     let mut real_outbound = VecDeque::new();
@@ -319,8 +346,7 @@ impl DelegateRuntimeInterface for Runtime {
 
                     self.get_outbound(
                         delegate_key,
-                        &running.instance,
-                        &process_func,
+                        &running,
                         params,
                         attested,
                         &mut real_outbound,
@@ -363,6 +389,40 @@ impl DelegateRuntimeInterface for Runtime {
                 _ => unreachable!(),
             }
         }
+        self.release(running);
+        Ok(results)
+    }
+
+    fn inbound_contract_response(
+        &mut self,
+        delegate_key: &DelegateKey,
+        params: &Parameters,
+        attested: Option<&[u8]>,
+        response: InboundDelegateMsg,
+        pending: Vec<OutboundDelegateMsg>,
+    ) -> RuntimeResult<Vec<OutboundDelegateMsg>> {
+        let running = self.prepare_delegate_call(params, delegate_key, 4096)?;
+        let mut outbound =
+            VecDeque::from(self.exec_inbound(params, attested, &response, &running)?);
+        // the queued messages were produced before the response, continue them with the
+        // context the delegate has now
+        let context = outbound.back().and_then(|m| m.get_context().cloned());
+        for mut msg in pending {
+            if let (Some(context), Some(ctx)) = (&context, msg.get_mut_context()) {
+                *ctx = context.clone();
+            }
+            outbound.push_back(msg);
+        }
+        let mut results = vec![];
+        self.get_outbound(
+            delegate_key,
+            &running,
+            params,
+            attested,
+            &mut outbound,
+            &mut results,
+        )?;
+        self.release(running);
         Ok(results)
     }
 
@@ -370,30 +430,44 @@ impl DelegateRuntimeInterface for Runtime {
     fn register_delegate(
         &mut self,
         delegate: DelegateContainer,
-        cipher: XChaCha20Poly1305,
+        cipher_key: Key,
         nonce: XNonce,
     ) -> RuntimeResult<()> {
         self.secret_store
-            .register_delegate(delegate.key().clone(), cipher, nonce)?;
-        self.delegate_store.store_delegate(delegate)
+            .register_delegate(delegate.key().clone(), cipher_key, nonce)?;
+        self.delegate_store.store_delegate(delegate)?;
+        // delegates holding secrets are still in use by some application
+        let secret_store = &self.secret_store;
+        let evicted = self
+            .delegate_store
+            .evict_least_recently_used(|key| !secret_store.list_secrets(key).is_empty())?;
+        for key in evicted {
+            self.instance_pool.remove(key.code_hash());
+            self.delegate_modules.remove(&key);
+        }
+        Ok(())
     }
 
     #[inline]
     fn unregister_delegate(&mut self, key: &DelegateKey) -> RuntimeResult<()> {
+        self.secret_store.remove_all_for_delegate(key)?;
         self.delegate_store.remove_delegate(key)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use chacha20poly1305::aead::{AeadCore, KeyInit, OsRng};
+    use chacha20poly1305::{
+        aead::{AeadCore, KeyInit, OsRng},
+        XChaCha20Poly1305,
+    };
     use freenet_stdlib::prelude::*;
     use serde::{Deserialize, Serialize};
     use std::sync::Arc;
 
     use crate::util::tests::get_temp_dir;
 
-    use super::super::{delegate_store::DelegateStore, ContractStore, SecretsStore};
+    use super::super::{delegate_store::DelegateStore, ContractStore, MasterKey, SecretsStore};
     use super::*;
 
     const TEST_DELEGATE_1: &str = "test_delegate_1";
@@ -426,7 +500,7 @@ mod test {
 
         let contract_store = ContractStore::new(contracts_dir, 10_000)?;
         let delegate_store = DelegateStore::new(delegates_dir, 10_000)?;
-        let secret_store = SecretsStore::new(secrets_dir)?;
+        let secret_store = SecretsStore::new(secrets_dir, MasterKey::from_passphrase(b"test"))?;
 
         let mut runtime =
             Runtime::build(contract_store, delegate_store, secret_store, false).unwrap();
@@ -441,11 +515,10 @@ mod test {
         let _ = runtime.delegate_store.store_delegate(delegate.clone());
 
         let key = XChaCha20Poly1305::generate_key(&mut OsRng);
-        let cipher = XChaCha20Poly1305::new(&key);
         let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
         let _ = runtime
             .secret_store
-            .register_delegate(delegate.key().clone(), cipher, nonce);
+            .register_delegate(delegate.key().clone(), key, nonce);
 
         Ok((delegate, runtime, temp_dir))
     }
@@ -1,14 +1,15 @@
 use dashmap::DashMap;
 use freenet_stdlib::prelude::{
-    APIVersion, CodeHash, Delegate, DelegateCode, DelegateContainer, DelegateKey,
-    DelegateWasmAPIVersion, Parameters,
+    APIVersion, CodeHash, Delegate, DelegateCode, DelegateContainer, DelegateKey, Parameters,
 };
 use std::{fs::File, io::Write, path::PathBuf, sync::Arc};
 use stretto::Cache;
 
 use crate::wasm_runtime::store::SafeWriter;
 
-use super::store::StoreFsManagement;
+use super::module_cache::ModuleCache;
+use super::store::{DiskUsage, IndexLock, IndexStats, StoreFsManagement, WatchGuard};
+use super::version::DelegateVersion;
 use super::RuntimeResult;
 
 pub struct DelegateStore {
@@ -17,6 +18,10 @@ pub struct DelegateStore {
     key_to_code_part: Arc<DashMap<DelegateKey, (u64, CodeHash)>>,
     index_file: SafeWriter<Self>,
     key_file: PathBuf,
+    disk_usage: DiskUsage,
+    module_cache: ModuleCache,
+    /// stops the maintenance of the index file once the store is dropped
+    _watch: WatchGuard,
 }
 
 impl StoreFsManagement for DelegateStore {
@@ -49,24 +54,60 @@ impl DelegateStore {
         } else {
             Self::load_from_file(&key_file, &mut key_to_code_part)?;
         }
-        Self::watch_changes(key_to_code_part.clone(), &key_file)?;
+        let watch = Self::watch_changes(key_to_code_part.clone(), &key_file)?;
+
+        let disk_usage = DiskUsage::default();
+        for entry in key_to_code_part.iter() {
+            let code_hash = entry.value().1;
+            let path = delegates_dir
+                .join(code_hash.encode())
+                .with_extension("wasm");
+            disk_usage.load(code_hash, &path);
+        }
 
         let index_file = SafeWriter::new(&key_file, false)?;
+        let module_cache = ModuleCache::new(&delegates_dir.join("modules"))?;
         Ok(Self {
             delegate_cache: Cache::new(100, max_size).expect(ERR),
             delegates_dir,
             key_to_code_part,
             index_file,
             key_file,
+            disk_usage,
+            module_cache,
+            _watch: watch,
         })
     }
 
+    /// Sets the max size in bytes the delegates stored on disk can use,
+    /// see [`Self::evict_least_recently_used`].
+    pub fn with_disk_quota(mut self, quota: u64) -> Self {
+        self.disk_usage = self.disk_usage.with_quota(quota);
+        self
+    }
+
+    /// Disk space in bytes currently used by the stored delegates.
+    pub fn disk_usage(&self) -> u64 {
+        self.disk_usage.used()
+    }
+
+    /// Compiled modules for the stored delegates.
+    pub(super) fn module_cache(&self) -> &ModuleCache {
+        &self.module_cache
+    }
+
+    /// Compiles in the background every delegate stored from now on, with the given engine.
+    pub(super) fn precompile_with(&mut self, engine: wasmer::Engine) {
+        self.module_cache.precompile_with(engine);
+    }
+
     // Returns a copy of the delegate bytes if available, none otherwise.
     pub fn fetch_delegate(
         &self,
         key: &DelegateKey,
         params: &Parameters<'_>,
     ) -> Option<Delegate<'static>> {
+        self.disk_usage.touch(key.code_hash());
         if let Some(delegate_code) = self.delegate_cache.get(key.code_hash()) {
             return Some(Delegate::from((delegate_code.value(), params)).into_owned());
         }
@@ -76,29 +117,30 @@ impl DelegateStore {
                 .join(code_part.value().1.encode())
                 .with_extension("wasm");
             tracing::debug!("loading delegate `{key}` from {delegate_code_path:?}");
-            let DelegateContainer::Wasm(DelegateWasmAPIVersion::V1(Delegate {
-                data: delegate_code,
-                ..
-            })) = DelegateContainer::try_from((
+            let delegate = DelegateContainer::try_from((
                 delegate_code_path.as_path(),
                 params.clone().into_owned(),
             ))
-            .ok()?
-            else {
-                unimplemented!()
-            };
+            .ok()?;
+            let delegate = DelegateVersion::unwrap(delegate)
+                .map_err(|err| tracing::warn!("can't load delegate `{key}`: {err}"))
+                .ok()?;
             tracing::debug!("loaded `{key}` from path");
-            let size = delegate_code.as_ref().len() as i64;
-            let delegate = Delegate::from((&delegate_code, &params.clone().into_owned()));
-            self.delegate_cache
-                .insert(*key.code_hash(), delegate_code, size);
+            let size = delegate.code().as_ref().len() as i64;
+            self.delegate_cache.insert(
+                *key.code_hash(),
+                delegate.code().clone().into_owned(),
+                size,
+            );
             Some(delegate)
         })
     }
 
     pub fn store_delegate(&mut self, delegate: DelegateContainer) -> RuntimeResult<()> {
+        DelegateVersion::of(&delegate)?;
         let code_hash = delegate.code_hash();
         if self.delegate_cache.get(code_hash).is_some() {
+            self.disk_usage.touch(code_hash);
             return Ok(());
         }
 
@@ -107,6 +149,7 @@ impl DelegateStore {
         let key_path = code_hash.encode();
         let delegate_path = self.delegates_dir.join(key_path).with_extension("wasm");
         if let Ok((code, _ver)) = DelegateCode::load_versioned_from_path(delegate_path.as_path()) {
+            self.disk_usage.touch(code_hash);
             let size = delegate.code().size() as i64;
             self.delegate_cache.insert(*code_hash, code, size);
             return Ok(());
@@ -123,21 +166,24 @@ impl DelegateStore {
         let output: Vec<u8> = delegate.code().to_bytes_versioned(version)?;
         let mut file = File::create(delegate_path)?;
         file.write_all(output.as_slice())?;
+        self.disk_usage.record(*code_hash, output.len() as u64);
+        self.module_cache.precompile(code_hash, data);
 
-        // Update index
+        // Update index, the offsets can't change while holding the lock
+        let lock = IndexLock::acquire(&self.key_file)?;
         let keys = self.key_to_code_part.entry(key.clone());
         match keys {
             dashmap::mapref::entry::Entry::Occupied(mut v) => {
                 let current_version_offset = v.get().0;
                 let prev_val = &mut v.get_mut().1;
                 // first mark the old entry (if it exists) as removed
-                Self::remove(&self.key_file, current_version_offset)?;
-                let new_offset = Self::insert(&mut self.index_file, key.clone(), code_hash)?;
+                Self::remove(&self.key_file, &lock, current_version_offset)?;
+                let new_offset = Self::insert(&mut self.index_file, &lock, key.clone(), code_hash)?;
                 *prev_val = *code_hash;
                 v.get_mut().0 = new_offset;
             }
             dashmap::mapref::entry::Entry::Vacant(v) => {
-                let offset = Self::insert(&mut self.index_file, key.clone(), code_hash)?;
+                let offset = Self::insert(&mut self.index_file, &lock, key.clone(), code_hash)?;
                 v.insert((offset, *code_hash));
             }
         }
@@ -147,10 +193,13 @@ impl DelegateStore {
 
     pub fn remove_delegate(&mut self, key: &DelegateKey) -> RuntimeResult<()> {
         self.delegate_cache.remove(key.code_hash());
+        self.module_cache.remove(key.code_hash())?;
         let cmp_path: PathBuf = self.delegates_dir.join(key.encode()).with_extension("wasm");
+        let lock = IndexLock::acquire(&self.key_file)?;
         if let Some((_, (offset, _))) = self.key_to_code_part.remove(key) {
-            Self::remove(&self.key_file, offset)?;
+            Self::remove(&self.key_file, &lock, offset)?;
         }
+        drop(lock);
         match std::fs::remove_file(cmp_path) {
             Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
             Err(err) => Err(err.into()),
@@ -158,11 +207,69 @@ impl DelegateStore {
         }
     }
 
+    /// Removes the least recently used delegates from disk until the space used is back under the
+    /// configured quota, skipping any delegate for which `keep` returns true. Returns the keys of
+    /// the evicted delegates.
+    pub fn evict_least_recently_used(
+        &mut self,
+        keep: impl Fn(&DelegateKey) -> bool,
+    ) -> RuntimeResult<Vec<DelegateKey>> {
+        let Some(mut excess) = self.disk_usage.excess() else {
+            return Ok(vec![]);
+        };
+        let mut evicted = vec![];
+        for (code_hash, size) in self.disk_usage.least_recently_used() {
+            if excess == 0 {
+                break;
+            }
+            let delegates: Vec<DelegateKey> = self
+                .key_to_code_part
+                .iter()
+                .filter(|entry| entry.value().1 == code_hash)
+                .map(|entry| entry.key().clone())
+                .collect();
+            if delegates.iter().any(&keep) {
+                continue;
+            }
+            let lock = IndexLock::acquire(&self.key_file)?;
+            for key in &delegates {
+                if let Some((_, (offset, _))) = self.key_to_code_part.remove(key) {
+                    Self::remove(&self.key_file, &lock, offset)?;
+                }
+            }
+            drop(lock);
+            self.delegate_cache.remove(&code_hash);
+            let delegate_path = self
+                .delegates_dir
+                .join(code_hash.encode())
+                .with_extension("wasm");
+            match std::fs::remove_file(delegate_path) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+            self.disk_usage.forget(&code_hash);
+            self.module_cache.remove(&code_hash)?;
+            tracing::debug!("evicted delegate code `{}` from disk", code_hash.encode());
+            excess = excess.saturating_sub(size);
+            evicted.extend(delegates);
+        }
+        if !evicted.is_empty() {
+            Self::compact_index(&self.key_file, &mut self.key_to_code_part)?;
+            self.index_file = SafeWriter::new(&self.key_file, false)?;
+        }
+        Ok(evicted)
+    }
+
     pub fn get_delegate_path(&mut self, key: &DelegateKey) -> RuntimeResult<PathBuf> {
         let key_path = key.encode().to_lowercase();
         Ok(self.delegates_dir.join(key_path).with_extension("wasm"))
     }
 
+    /// Live vs removed records in the key index file.
+    pub fn index_stats(&self) -> RuntimeResult<IndexStats> {
+        Ok(IndexStats::from_file(&self.key_file)?)
+    }
+
     pub fn code_hash_from_key(&self, key: &DelegateKey) -> Option<CodeHash> {
         self.key_to_code_part.get(key).map(|r| r.value().1)
     }
@@ -170,6 +277,8 @@ impl DelegateStore {
 
 #[cfg(test)]
 mod test {
+    use freenet_stdlib::prelude::DelegateWasmAPIVersion;
+
     use super::*;
 
     #[test]
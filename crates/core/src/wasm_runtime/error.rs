@@ -86,6 +86,9 @@ pub(crate) enum RuntimeInnerError {
     #[error("failed while unwrapping contract to raw bytes")]
     UnwrapContract,
 
+    #[error("unsupported {0} container version")]
+    UnsupportedVersion(&'static str),
+
     // wasm runtime errors
     #[error(transparent)]
     WasmCompileError(#[from] wasmer::CompileError),
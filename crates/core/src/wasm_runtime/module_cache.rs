@@ -0,0 +1,218 @@
+//! On-disk cache of compiled contract and delegate modules, so code doesn't need to be
+//! recompiled every time the node restarts.
+//!
+//! Artifacts are kept under a directory per engine version, since they can only be loaded by
+//! the same engine (and compiler configuration) which produced them. Each artifact is prefixed
+//! by a checksum which is validated before loading it; invalid artifacts are discarded and the
+//! code is compiled again.
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+};
+
+use freenet_stdlib::prelude::CodeHash;
+use wasmer::{Engine, Module};
+
+use super::RuntimeResult;
+
+/// Bump whenever the compiler configuration changes (e.g. the metering middleware),
+/// invalidating all the previously compiled artifacts.
+const COMPILER_REVISION: u32 = 1;
+
+const CHECKSUM_LEN: usize = blake3::OUT_LEN;
+
+fn engine_version() -> String {
+    format!(
+        "wasmer-{}-r{COMPILER_REVISION}-{}-{}",
+        wasmer::VERSION,
+        std::env::consts::ARCH,
+        std::env::consts::OS
+    )
+}
+
+pub(super) struct ModuleCache {
+    dir: PathBuf,
+    /// Code queued for compilation in the background.
+    precompile: Option<mpsc::Sender<(CodeHash, Vec<u8>)>>,
+}
+
+impl ModuleCache {
+    /// Opens the cache under `base_dir`, removing any artifacts compiled by other engine versions.
+    pub fn new(base_dir: &Path) -> io::Result<Self> {
+        let version = engine_version();
+        if base_dir.exists() {
+            for entry in std::fs::read_dir(base_dir)? {
+                let entry = entry?;
+                if entry.file_name() != version.as_str() && entry.file_type()?.is_dir() {
+                    tracing::debug!("removing stale compiled modules at {:?}", entry.path());
+                    std::fs::remove_dir_all(entry.path())?;
+                }
+            }
+        }
+        let dir = base_dir.join(version);
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            precompile: None,
+        })
+    }
+
+    /// Compiles in the background, with the given engine, the code queued with [`Self::precompile`].
+    pub fn precompile_with(&mut self, engine: Engine) {
+        let (sender, receiver) = mpsc::channel::<(CodeHash, Vec<u8>)>();
+        let dir = self.dir.clone();
+        std::thread::spawn(move || {
+            while let Ok((code_hash, code)) = receiver.recv() {
+                let path = Self::artifact_path(&dir, &code_hash);
+                if path.exists() {
+                    continue;
+                }
+                match Module::new(&engine, code) {
+                    Ok(module) => Self::save(&path, &module),
+                    Err(err) => {
+                        tracing::debug!("failed precompiling `{}`: {err}", code_hash.encode());
+                    }
+                }
+            }
+        });
+        self.precompile = Some(sender);
+    }
+
+    /// Queues the code for compilation if a background compiler is set up.
+    pub fn precompile(&self, code_hash: &CodeHash, code: &[u8]) {
+        if let Some(sender) = &self.precompile {
+            let _ = sender.send((*code_hash, code.to_vec()));
+        }
+    }
+
+    /// Returns the module for the given code, loading it from the cache if it was previously
+    /// compiled, or compiling and caching it otherwise.
+    pub fn compile(
+        &self,
+        engine: &Engine,
+        code_hash: &CodeHash,
+        code: &[u8],
+    ) -> RuntimeResult<Module> {
+        let path = Self::artifact_path(&self.dir, code_hash);
+        if let Some(module) = Self::load(engine, &path) {
+            return Ok(module);
+        }
+        let module = Module::new(engine, code)?;
+        Self::save(&path, &module);
+        Ok(module)
+    }
+
+    pub fn remove(&self, code_hash: &CodeHash) -> io::Result<()> {
+        match std::fs::remove_file(Self::artifact_path(&self.dir, code_hash)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    fn artifact_path(dir: &Path, code_hash: &CodeHash) -> PathBuf {
+        dir.join(code_hash.encode()).with_extension("module")
+    }
+
+    fn load(engine: &Engine, path: &Path) -> Option<Module> {
+        let bytes = std::fs::read(path).ok()?;
+        let valid = bytes.len() > CHECKSUM_LEN
+            && blake3::hash(&bytes[CHECKSUM_LEN..]).as_bytes() == &bytes[..CHECKSUM_LEN];
+        let module = if valid {
+            // Safety: the artifact was produced by this same engine version and has not been
+            // modified since, as checked by the checksum
+            unsafe { Module::deserialize(engine, &bytes[CHECKSUM_LEN..]) }
+                .map_err(|err| tracing::warn!("failed loading compiled module {path:?}: {err}"))
+                .ok()
+        } else {
+            tracing::warn!("invalid compiled module at {path:?}");
+            None
+        };
+        if module.is_none() {
+            let _ = std::fs::remove_file(path);
+        }
+        module
+    }
+
+    /// Persists the compiled module, failing to do so only means it will be compiled again.
+    fn save(path: &Path, module: &Module) {
+        let write = || -> Result<(), Box<dyn std::error::Error>> {
+            let artifact = module.serialize()?;
+            // write to a temporary file first so partially written artifacts are never loaded
+            let tmp_path = path.with_extension("tmp");
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(blake3::hash(&artifact).as_bytes())?;
+            file.write_all(&artifact)?;
+            file.sync_all()?;
+            std::fs::rename(tmp_path, path)?;
+            Ok(())
+        };
+        if let Err(err) = write() {
+            tracing::warn!("failed caching compiled module at {path:?}: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    const MODULE: &str = r#"
+        (module
+            (func (export "run")))
+    "#;
+
+    #[test]
+    fn reuses_compiled_modules() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = crate::util::tests::get_temp_dir();
+        let engine = Engine::default();
+        let code_hash = CodeHash::from_code(MODULE.as_bytes());
+
+        std::fs::create_dir_all(temp_dir.path().join("wasmer-0.0.0"))?;
+        let cache = ModuleCache::new(temp_dir.path())?;
+        assert!(!temp_dir.path().join("wasmer-0.0.0").exists());
+        cache.compile(&engine, &code_hash, MODULE.as_bytes())?;
+        let path = ModuleCache::artifact_path(&cache.dir, &code_hash);
+        assert!(path.exists());
+
+        // compiled artifacts survive restarts
+        let cache = ModuleCache::new(temp_dir.path())?;
+        assert!(ModuleCache::load(&engine, &path).is_some());
+
+        // corrupted artifacts are discarded and the code recompiled
+        let mut bytes = std::fs::read(&path)?;
+        *bytes.last_mut().unwrap() ^= 0xff;
+        std::fs::write(&path, bytes)?;
+        assert!(ModuleCache::load(&engine, &path).is_none());
+        assert!(!path.exists());
+        cache.compile(&engine, &code_hash, MODULE.as_bytes())?;
+        assert!(ModuleCache::load(&engine, &path).is_some());
+
+        cache.remove(&code_hash)?;
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn precompiles_in_background() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = crate::util::tests::get_temp_dir();
+        let engine = Engine::default();
+        let code_hash = CodeHash::from_code(MODULE.as_bytes());
+        let mut cache = ModuleCache::new(temp_dir.path())?;
+        cache.precompile_with(engine.clone());
+        cache.precompile(&code_hash, MODULE.as_bytes());
+
+        let path = ModuleCache::artifact_path(&cache.dir, &code_hash);
+        for _ in 0..100 {
+            if path.exists() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        assert!(ModuleCache::load(&engine, &path).is_some());
+        Ok(())
+    }
+}
@@ -1,49 +1,359 @@
 //! Implementation of native API's exported and available in the WASM modules.
+//!
+//! Host functions receive pointers into the linear memory of the calling instance, which are
+//! untrusted: every access goes through a bounds checked [`MemoryView`] of that memory, and
+//! calls with out of bounds arguments trap instead of touching any other memory.
 
-use dashmap::DashMap;
-use once_cell::sync::Lazy;
-use wasmer::{Function, Imports};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use wasmer::{Function, FunctionEnv, FunctionEnvMut, Imports, Memory, MemoryView, RuntimeError};
 
 use super::runtime::InstanceInfo;
 
-/// This is a map of starting addresses of the instance memory space.
+/// Environment of the host functions imported by a single instance, holding the memory of that
+/// instance while it runs.
 ///
-/// A hackish way of having the information necessary to compute the address
-/// at which bytes must be written when calling host functions from the WASM modules.
-pub(super) static MEM_ADDR: Lazy<DashMap<InstanceId, InstanceInfo>> = Lazy::new(DashMap::default);
+/// Every instance gets its own environment, so host functions never look up instances by the id
+/// passed by the guest, which is still part of the imported signatures but ignored.
+#[derive(Clone, Default)]
+pub(super) struct HostEnv {
+    instance: Arc<Mutex<Option<InstanceInfo>>>,
+}
+
+impl HostEnv {
+    pub fn register(&self, info: InstanceInfo) {
+        *self.instance.lock() = Some(info);
+    }
+
+    pub fn unregister(&self) {
+        self.instance.lock().take();
+    }
+
+    fn with_instance<R>(&self, f: impl FnOnce(&mut InstanceInfo) -> R) -> Result<R, RuntimeError> {
+        let mut info = self.instance.lock();
+        let info = info
+            .as_mut()
+            .ok_or_else(|| RuntimeError::new("instance is not running"))?;
+        Ok(f(info))
+    }
 
-type InstanceId = i64;
+    fn memory(&self) -> Result<Memory, RuntimeError> {
+        self.with_instance(|info| info.memory.clone())
+    }
+}
 
-#[inline(always)]
-fn compute_ptr<T>(ptr: i64, start_ptr: i64) -> *mut T {
-    (start_ptr + ptr) as _
+/// Copies `len` bytes starting at `ptr` out of the memory of the instance.
+fn read_bytes(env: &FunctionEnvMut<HostEnv>, ptr: i64, len: i32) -> Result<Vec<u8>, RuntimeError> {
+    let memory = env.data().memory()?;
+    let view = memory.view(env);
+    let len = u64::try_from(len).map_err(|_| RuntimeError::new("negative length"))?;
+    let offset = checked_offset(&view, ptr, len)?;
+    let mut bytes = vec![0; len as usize];
+    view.read(offset, &mut bytes)
+        .map_err(|err| RuntimeError::new(err.to_string()))?;
+    Ok(bytes)
 }
 
+/// Returns the offset in the memory at which `len` bytes starting at `ptr` can be accessed.
+fn checked_offset(view: &MemoryView, ptr: i64, len: u64) -> Result<u64, RuntimeError> {
+    u64::try_from(ptr)
+        .ok()
+        .filter(|offset| {
+            offset
+                .checked_add(len)
+                .is_some_and(|end| end <= view.data_size())
+        })
+        .ok_or_else(|| {
+            RuntimeError::new(format!(
+                "out of bounds memory access (ptr: {ptr}, len: {len}, memory size: {})",
+                view.data_size()
+            ))
+        })
+}
+
+/// Structured logging for contracts and delegates, bridged into the node `tracing` subscriber.
+///
+/// Log events and spans are passed as a bincode encoded [`LogRecord`](log::LogRecord) along
+/// with their level (0: trace, 1: debug, 2: info, 3: warn, 4: error). Events from a call are
+/// nested under a `contract_call` or `delegate_call` span recording the key of the contract or
+/// delegate, and under any span opened by the guest which was not exited yet, so they can be
+/// filtered by key and show up in the exported traces.
 pub(crate) mod log {
-    use wasmer::Function;
+    use std::{collections::HashMap, sync::OnceLock};
+
+    use once_cell::sync::Lazy;
+    use serde::{Deserialize, Serialize};
+    use tracing::{
+        callsite::{Callsite, Identifier},
+        field::{Field, FieldSet, Value, ValueSet},
+        level_filters::LevelFilter,
+        metadata::Kind,
+        subscriber::Interest,
+        Event, Level, Metadata, Span,
+    };
 
     use super::*;
 
-    pub(crate) fn prepare_export(store: &mut wasmer::Store, imports: &mut Imports) {
-        let utc_now = Function::new_typed(store, info);
+    /// Max number of callsites created for guest records. Each distinct set of field keys logged
+    /// at a level takes one, past it the fields of new sets are recorded flattened.
+    const MAX_CALLSITES: usize = 1024;
+
+    /// Max number of fields of a guest record, including its message or name and the flattened
+    /// `fields`; value sets can't be built out of larger arrays.
+    const MAX_FIELDS: usize = 32;
+
+    type CallsiteKey = (Level, bool, Vec<String>);
+
+    static CALLSITES: Lazy<Mutex<HashMap<CallsiteKey, &'static GuestCallsite>>> =
+        Lazy::new(Default::default);
+
+    /// Callsite of the guest events or spans with a given set of field keys. These are only known
+    /// at runtime, so the callsite is created and leaked then, instead of by the `tracing` macros.
+    struct GuestCallsite {
+        metadata: OnceLock<Metadata<'static>>,
+    }
+
+    impl Callsite for GuestCallsite {
+        fn set_interest(&self, _interest: Interest) {}
+
+        fn metadata(&self) -> &Metadata<'_> {
+            self.metadata.get().expect("set on creation")
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub(super) struct LogRecord {
+        /// The message of an event or the name of a span.
+        pub message: String,
+        pub fields: Vec<(String, String)>,
+    }
+
+    impl LogRecord {
+        fn fields(&self) -> String {
+            self.fields
+                .iter()
+                .map(|(key, value)| format!("{key}={value}"))
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
+    }
+
+    pub(crate) fn prepare_export(
+        store: &mut wasmer::Store,
+        env: &FunctionEnv<HostEnv>,
+        imports: &mut Imports,
+    ) {
+        let info = Function::new_typed_with_env(store, env, info);
+        let log = Function::new_typed_with_env(store, env, log);
+        let span_enter = Function::new_typed_with_env(store, env, span_enter);
+        let span_exit = Function::new_typed_with_env(store, env, span_exit);
         imports.register_namespace(
             "freenet_log",
-            [("__frnt__logger__info".to_owned(), utc_now.into())],
+            [
+                ("__frnt__logger__info".to_owned(), info.into()),
+                ("__frnt__logger__log".to_owned(), log.into()),
+                ("__frnt__logger__span_enter".to_owned(), span_enter.into()),
+                ("__frnt__logger__span_exit".to_owned(), span_exit.into()),
+            ],
+        );
+    }
+
+    fn level(level: i32) -> Result<Level, RuntimeError> {
+        match level {
+            0 => Ok(Level::TRACE),
+            1 => Ok(Level::DEBUG),
+            2 => Ok(Level::INFO),
+            3 => Ok(Level::WARN),
+            4 => Ok(Level::ERROR),
+            other => Err(RuntimeError::new(format!("unknown log level {other}"))),
+        }
+    }
+
+    fn record(
+        env: &FunctionEnvMut<HostEnv>,
+        ptr: i64,
+        len: i32,
+    ) -> Result<LogRecord, RuntimeError> {
+        let bytes = read_bytes(env, ptr, len)?;
+        bincode::deserialize(&bytes)
+            .map_err(|err| RuntimeError::new(format!("malformed log record: {err}")))
+    }
+
+    /// Logs a plain message at info level, kept for modules built against the former API.
+    fn info(
+        env: FunctionEnvMut<HostEnv>,
+        _id: i64,
+        ptr: i64,
+        len: i32,
+    ) -> Result<(), RuntimeError> {
+        let msg = read_bytes(&env, ptr, len)?;
+        let record = LogRecord {
+            message: String::from_utf8_lossy(&msg).into_owned(),
+            fields: vec![],
+        };
+        emit(&env, Level::INFO, &record)
+    }
+
+    fn log(
+        env: FunctionEnvMut<HostEnv>,
+        _id: i64,
+        level_num: i32,
+        ptr: i64,
+        len: i32,
+    ) -> Result<(), RuntimeError> {
+        let level = level(level_num)?;
+        let record = record(&env, ptr, len)?;
+        emit(&env, level, &record)
+    }
+
+    /// Returns the metadata of the guest events (or spans) at `level` with the given fields,
+    /// followed by the flattened `fields` if any.
+    fn callsite(
+        level: Level,
+        span: bool,
+        fields: &[(&str, &str)],
+        flattened: bool,
+    ) -> Option<&'static Metadata<'static>> {
+        let names = fields
+            .iter()
+            .map(|(key, _)| *key)
+            .chain(flattened.then_some("fields"))
+            .map(ToOwned::to_owned)
+            .collect::<Vec<_>>();
+        let key = (level, span, names);
+        let mut callsites = CALLSITES.lock();
+        if let Some(&callsite) = callsites.get(&key) {
+            return callsite.metadata.get();
+        }
+        // callsites without guest keys are always available, there is one per level and kind
+        if fields.len() > 1 && callsites.len() >= MAX_CALLSITES {
+            return None;
+        }
+        let names: &'static [&'static str] = Box::leak(
+            key.2
+                .iter()
+                .map(|name| &*Box::leak(name.clone().into_boxed_str()))
+                .collect::<Vec<_>>()
+                .into_boxed_slice(),
         );
+        let callsite: &'static GuestCallsite = Box::leak(Box::new(GuestCallsite {
+            metadata: OnceLock::new(),
+        }));
+        let (name, kind) = if span {
+            ("contract_span", Kind::SPAN)
+        } else {
+            ("contract_event", Kind::EVENT)
+        };
+        let _ = callsite.metadata.set(Metadata::new(
+            name,
+            "contract",
+            level,
+            None,
+            None,
+            None,
+            FieldSet::new(names, Identifier(callsite)),
+            kind,
+        ));
+        tracing::callsite::register(callsite);
+        callsites.insert(key, callsite);
+        callsite.metadata.get()
     }
 
-    // TODO: this API right now is just a patch, ideally we want to impl a tracing subscriber
-    // that can be used in wasm and that under the hood will just pass data to the host via
-    // functions like this in a structured way
-    fn info(id: i64, ptr: i64, len: i32) {
-        if id == -1 {
-            panic!("unset module id");
+    /// Calls `f` with the metadata and values of a guest record: its message (or span name) and
+    /// each of its fields under its own key. Fields with a repeated or reserved key, or past the
+    /// limits, are joined as `key=value` into a single `fields` value.
+    fn with_values<R>(
+        level: Level,
+        span: bool,
+        record: &LogRecord,
+        f: impl FnOnce(&'static Metadata<'static>, &ValueSet<'_>) -> R,
+    ) -> R {
+        let first = if span { "name" } else { "message" };
+        let mut fields = vec![(first, record.message.as_str())];
+        let mut flattened = vec![];
+        for (key, value) in &record.fields {
+            if fields.len() < MAX_FIELDS - 1
+                && key != "fields"
+                && fields.iter().all(|(other, _)| *other != key.as_str())
+            {
+                fields.push((key.as_str(), value.as_str()));
+            } else {
+                flattened.push(format!("{key}={value}"));
+            }
         }
-        let info = MEM_ADDR.get(&id).expect("instance mem space not recorded");
-        let ptr = compute_ptr::<u8>(ptr, info.start_ptr);
-        let msg =
-            unsafe { std::str::from_utf8_unchecked(std::slice::from_raw_parts(ptr, len as _)) };
-        tracing::info!(target: "contract", contract = %info.value().key(), "{msg}");
+        let mut flattened = flattened.join(" ");
+        let metadata = match callsite(level, span, &fields, !flattened.is_empty()) {
+            Some(metadata) => metadata,
+            None => {
+                fields.truncate(1);
+                flattened = record.fields();
+                callsite(level, span, &fields, !flattened.is_empty())
+                    .expect("callsites without guest keys are not limited")
+            }
+        };
+
+        let flattened = flattened.as_str();
+        let values = fields
+            .iter()
+            .map(|(_, value)| value as &dyn Value)
+            .chain((!flattened.is_empty()).then_some(&flattened as &dyn Value));
+        let field_set = metadata.fields();
+        let keys = field_set.iter().collect::<Vec<Field>>();
+        let mut entries = [(&keys[0], None); MAX_FIELDS];
+        for (entry, (key, value)) in entries.iter_mut().zip(keys.iter().zip(values)) {
+            *entry = (key, Some(value));
+        }
+        f(metadata, &field_set.value_set(&entries))
+    }
+
+    fn enabled(metadata: &Metadata<'_>) -> bool {
+        *metadata.level() <= LevelFilter::current()
+            && tracing::dispatcher::get_default(|dispatch| dispatch.enabled(metadata))
+    }
+
+    fn emit(
+        env: &FunctionEnvMut<HostEnv>,
+        level: Level,
+        record: &LogRecord,
+    ) -> Result<(), RuntimeError> {
+        let parent = env.data().with_instance(|info| info.current_span().id())?;
+        with_values(level, false, record, |metadata, values| {
+            if enabled(metadata) {
+                Event::child_of(parent, metadata, values);
+            }
+        });
+        Ok(())
+    }
+
+    fn span_enter(
+        env: FunctionEnvMut<HostEnv>,
+        _id: i64,
+        level_num: i32,
+        ptr: i64,
+        len: i32,
+    ) -> Result<(), RuntimeError> {
+        let level = level(level_num)?;
+        let record = record(&env, ptr, len)?;
+        env.data().with_instance(|info| {
+            let parent = info.current_span().id();
+            let span = with_values(level, true, &record, |metadata, values| {
+                if enabled(metadata) {
+                    Span::child_of(parent, metadata, values)
+                } else {
+                    Span::none()
+                }
+            });
+            info.guest_spans.push(span);
+        })
+    }
+
+    fn span_exit(env: FunctionEnvMut<HostEnv>, _id: i64) -> Result<(), RuntimeError> {
+        env.data().with_instance(|info| {
+            if info.guest_spans.pop().is_none() {
+                tracing::debug!(contract = %info.key(), "exiting span with no span entered");
+            }
+        })
     }
 }
 
@@ -52,23 +362,32 @@ pub(crate) mod rand {
 
     use super::*;
 
-    pub(crate) fn prepare_export(store: &mut wasmer::Store, imports: &mut Imports) {
-        let rand_bytes = Function::new_typed(store, rand_bytes);
+    pub(crate) fn prepare_export(
+        store: &mut wasmer::Store,
+        env: &FunctionEnv<HostEnv>,
+        imports: &mut Imports,
+    ) {
+        let rand_bytes = Function::new_typed_with_env(store, env, rand_bytes);
         imports.register_namespace(
             "freenet_rand",
             [("__frnt__rand__rand_bytes".to_owned(), rand_bytes.into())],
         );
     }
 
-    fn rand_bytes(id: i64, ptr: i64, len: u32) {
-        if id == -1 {
-            panic!("unset module id");
-        }
-        let info = MEM_ADDR.get(&id).expect("instance mem space not recorded");
-        let ptr = compute_ptr::<u8>(ptr, info.start_ptr);
-        let slice = unsafe { &mut *std::ptr::slice_from_raw_parts_mut(ptr, len as usize) };
-        let mut rng = thread_rng();
-        rng.fill_bytes(slice);
+    fn rand_bytes(
+        env: FunctionEnvMut<HostEnv>,
+        _id: i64,
+        ptr: i64,
+        len: u32,
+    ) -> Result<(), RuntimeError> {
+        let memory = env.data().memory()?;
+        let view = memory.view(&env);
+        let offset = checked_offset(&view, ptr, len as u64)?;
+        let mut bytes = vec![0; len as usize];
+        thread_rng().fill_bytes(&mut bytes);
+        view.write(offset, &bytes)
+            .map_err(|err| RuntimeError::new(err.to_string()))?;
+        Ok(())
     }
 }
 
@@ -76,24 +395,274 @@ pub(crate) mod time {
     use super::*;
     use chrono::{DateTime, Utc as UtcOriginal};
 
-    pub(crate) fn prepare_export(store: &mut wasmer::Store, imports: &mut Imports) {
-        let utc_now = Function::new_typed(store, utc_now);
+    pub(crate) fn prepare_export(
+        store: &mut wasmer::Store,
+        env: &FunctionEnv<HostEnv>,
+        imports: &mut Imports,
+    ) {
+        let utc_now = Function::new_typed_with_env(store, env, utc_now);
         imports.register_namespace(
             "freenet_time",
             [("__frnt__time__utc_now".to_owned(), utc_now.into())],
         );
     }
 
-    fn utc_now(id: i64, ptr: i64) {
-        if id == -1 {
-            panic!("unset module id");
-        }
-        let info = MEM_ADDR.get(&id).expect("instance mem space not recorded");
+    fn utc_now(env: FunctionEnvMut<HostEnv>, _id: i64, ptr: i64) -> Result<(), RuntimeError> {
+        const SIZE: usize = std::mem::size_of::<DateTime<UtcOriginal>>();
+        let memory = env.data().memory()?;
+        let view = memory.view(&env);
+        let offset = checked_offset(&view, ptr, SIZE as u64)?;
         let now = UtcOriginal::now();
-        let ptr = compute_ptr::<DateTime<UtcOriginal>>(ptr, info.start_ptr);
-        // eprintln!("{ptr:p} ({}) outside", ptr as i64);
-        unsafe {
-            ptr.write(now);
+        // Safety: the guest reads back the value with the same layout, and reading
+        // the bytes of an initialized value is always valid
+        let bytes = unsafe { std::slice::from_raw_parts(&now as *const _ as *const u8, SIZE) };
+        view.write(offset, bytes)
+            .map_err(|err| RuntimeError::new(err.to_string()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use freenet_stdlib::prelude::ContractInstanceId;
+    use wasmer::{imports, Instance, Module, Store, TypedFunction};
+
+    use super::*;
+    use crate::wasm_runtime::runtime::Key;
+
+    const HOST_CALLS_MODULE: &str = r#"
+        (module
+            (import "freenet_log" "__frnt__logger__info" (func $log (param i64 i64 i32)))
+            (import "freenet_rand" "__frnt__rand__rand_bytes" (func $rand (param i64 i64 i32)))
+            (import "freenet_time" "__frnt__time__utc_now" (func $now (param i64 i64)))
+            (memory (export "memory") 1)
+            (func (export "log") (param i64 i64 i32)
+                (call $log (local.get 0) (local.get 1) (local.get 2)))
+            (func (export "rand") (param i64 i64 i32)
+                (call $rand (local.get 0) (local.get 1) (local.get 2)))
+            (func (export "now") (param i64 i64)
+                (call $now (local.get 0) (local.get 1))))
+    "#;
+
+    /// Instantiates the module with its own host environment, registered as running.
+    fn instantiate(
+        store: &mut Store,
+        module: &Module,
+    ) -> Result<(Instance, HostEnv, Memory), Box<dyn std::error::Error>> {
+        let host_env = HostEnv::default();
+        let env = FunctionEnv::new(store, host_env.clone());
+        let mut imports = imports! {};
+        log::prepare_export(store, &env, &mut imports);
+        rand::prepare_export(store, &env, &mut imports);
+        time::prepare_export(store, &env, &mut imports);
+        let instance = Instance::new(store, module, &imports)?;
+        let memory = instance.exports.get_memory("memory")?.clone();
+        host_env.register(InstanceInfo::new(
+            memory.clone(),
+            Key::Contract(ContractInstanceId::new([0; 32])),
+        ));
+        Ok((instance, host_env, memory))
+    }
+
+    #[test]
+    fn rejects_out_of_bounds_access() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = Store::default();
+        let module = Module::new(&store, HOST_CALLS_MODULE)?;
+        let (instance, host_env, memory) = instantiate(&mut store, &module)?;
+
+        let log: TypedFunction<(i64, i64, i32), ()> =
+            instance.exports.get_typed_function(&store, "log")?;
+        let rand: TypedFunction<(i64, i64, u32), ()> =
+            instance.exports.get_typed_function(&store, "rand")?;
+        let now: TypedFunction<(i64, i64), ()> =
+            instance.exports.get_typed_function(&store, "now")?;
+        let size = memory.view(&store).data_size() as i64;
+
+        rand.call(&mut store, 0, 0, 32)?;
+        now.call(&mut store, 0, 64)?;
+        log.call(&mut store, 0, 0, 32)?;
+        let mut written = [0; 32];
+        memory.view(&store).read(0, &mut written)?;
+        assert_ne!(written, [0; 32]);
+
+        assert!(rand.call(&mut store, 0, size - 16, 32).is_err());
+        assert!(rand.call(&mut store, 0, -1, 1).is_err());
+        assert!(rand.call(&mut store, 0, i64::MAX, 1).is_err());
+        assert!(now.call(&mut store, 0, size - 1).is_err());
+        assert!(log.call(&mut store, 0, 0, i32::MAX).is_err());
+        assert!(log.call(&mut store, 0, 0, -1).is_err());
+        // the tail of the memory was not written by the rejected calls
+        let mut tail = [0; 16];
+        memory.view(&store).read(size as u64 - 16, &mut tail)?;
+        assert_eq!(tail, [0; 16]);
+
+        // calls from instances which are not running are rejected too
+        host_env.unregister();
+        assert!(rand.call(&mut store, 0, 0, 1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn host_calls_only_access_own_memory() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = Store::default();
+        let module = Module::new(&store, HOST_CALLS_MODULE)?;
+        let (_, _, victim_memory) = instantiate(&mut store, &module)?;
+        let (instance, host_env, memory) = instantiate(&mut store, &module)?;
+        let rand: TypedFunction<(i64, i64, u32), ()> =
+            instance.exports.get_typed_function(&store, "rand")?;
+
+        // whatever id the guest claims to have, it only writes into its own memory
+        for id in [-1, 0, 1, i64::MAX] {
+            rand.call(&mut store, id, 0, 32)?;
+        }
+        let mut written = [0; 32];
+        memory.view(&store).read(0, &mut written)?;
+        assert_ne!(written, [0; 32]);
+        victim_memory.view(&store).read(0, &mut written)?;
+        assert_eq!(written, [0; 32]);
+
+        host_env.unregister();
+        assert!(rand.call(&mut store, 0, 0, 32).is_err());
+        Ok(())
+    }
+
+    const LOGGING_MODULE: &str = r#"
+        (module
+            (import "freenet_log" "__frnt__logger__log" (func $log (param i64 i32 i64 i32)))
+            (import "freenet_log" "__frnt__logger__span_enter"
+                (func $span_enter (param i64 i32 i64 i32)))
+            (import "freenet_log" "__frnt__logger__span_exit" (func $span_exit (param i64)))
+            (memory (export "memory") 1)
+            (func (export "log") (param i64 i32 i64 i32)
+                (call $log (local.get 0) (local.get 1) (local.get 2) (local.get 3)))
+            (func (export "span_enter") (param i64 i32 i64 i32)
+                (call $span_enter (local.get 0) (local.get 1) (local.get 2) (local.get 3)))
+            (func (export "span_exit") (param i64)
+                (call $span_exit (local.get 0))))
+    "#;
+
+    #[test]
+    fn logs_structured_records() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = Store::default();
+        let module = Module::new(&store, LOGGING_MODULE)?;
+        let (instance, host_env, memory) = instantiate(&mut store, &module)?;
+
+        let log: TypedFunction<(i64, i32, i64, i32), ()> =
+            instance.exports.get_typed_function(&store, "log")?;
+        let span_enter: TypedFunction<(i64, i32, i64, i32), ()> =
+            instance.exports.get_typed_function(&store, "span_enter")?;
+        let span_exit: TypedFunction<i64, ()> =
+            instance.exports.get_typed_function(&store, "span_exit")?;
+        let record = bincode::serialize(&log::LogRecord {
+            message: "updating state".to_owned(),
+            fields: vec![("size".to_owned(), "42".to_owned())],
+        })?;
+        memory.view(&store).write(0, &record)?;
+        let len = record.len() as i32;
+        let open_spans = || host_env.with_instance(|info| info.guest_spans.len());
+
+        for level in 0..=4 {
+            log.call(&mut store, 0, level, 0, len)?;
+        }
+        span_enter.call(&mut store, 0, 2, 0, len)?;
+        span_enter.call(&mut store, 0, 1, 0, len)?;
+        assert_eq!(open_spans()?, 2);
+        log.call(&mut store, 0, 2, 0, len)?;
+        span_exit.call(&mut store, 0)?;
+        assert_eq!(open_spans()?, 1);
+
+        assert!(log.call(&mut store, 0, 5, 0, len).is_err());
+        assert!(log.call(&mut store, 0, 2, 0, len - 1).is_err());
+        assert!(span_enter.call(&mut store, 0, -1, 0, len).is_err());
+        assert_eq!(open_spans()?, 1);
+        Ok(())
+    }
+
+    /// Subscriber keeping the fields of every event and span recorded under the `contract` target.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<Vec<(String, String)>>>>);
+
+    struct Fields(Vec<(String, String)>);
+
+    impl tracing::field::Visit for Fields {
+        fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+            self.0.push((field.name().to_owned(), value.to_owned()));
+        }
+
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0.push((field.name().to_owned(), format!("{value:?}")));
+        }
+    }
+
+    impl tracing::Subscriber for Recorder {
+        fn enabled(&self, metadata: &tracing::Metadata<'_>) -> bool {
+            metadata.target() == "contract"
+        }
+
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let mut fields = Fields(vec![]);
+            span.record(&mut fields);
+            self.0.lock().push(fields.0);
+            tracing::span::Id::from_u64(1)
+        }
+
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, event: &tracing::Event<'_>) {
+            let mut fields = Fields(vec![]);
+            event.record(&mut fields);
+            self.0.lock().push(fields.0);
+        }
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[test]
+    fn records_guest_fields_individually() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = Store::default();
+        let module = Module::new(&store, LOGGING_MODULE)?;
+        let (instance, _host_env, memory) = instantiate(&mut store, &module)?;
+
+        let log: TypedFunction<(i64, i32, i64, i32), ()> =
+            instance.exports.get_typed_function(&store, "log")?;
+        let span_enter: TypedFunction<(i64, i32, i64, i32), ()> =
+            instance.exports.get_typed_function(&store, "span_enter")?;
+        let record = bincode::serialize(&log::LogRecord {
+            message: "updating state".to_owned(),
+            fields: [
+                ("size", "42"),
+                ("user", "bob"),
+                ("size", "7"),
+                ("fields", "x"),
+            ]
+            .into_iter()
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .collect(),
+        })?;
+        memory.view(&store).write(0, &record)?;
+        let len = record.len() as i32;
+
+        let recorder = Recorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            log.call(&mut store, 0, 2, 0, len)?;
+            span_enter.call(&mut store, 0, 3, 0, len)
+        })?;
+
+        let fields = |first: &str| {
+            [
+                (first, "updating state"),
+                ("size", "42"),
+                ("user", "bob"),
+                ("fields", "size=7 fields=x"),
+            ]
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .to_vec()
         };
+        assert_eq!(*recorder.0.lock(), [fields("message"), fields("name")]);
+        Ok(())
     }
 }
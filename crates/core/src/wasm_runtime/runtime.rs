@@ -1,4 +1,8 @@
-use std::{collections::HashMap, sync::atomic::AtomicI64};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{atomic::AtomicI64, Arc},
+    time::{Duration, Instant},
+};
 
 use freenet_stdlib::{
     memory::{
@@ -7,67 +11,283 @@ use freenet_stdlib::{
     },
     prelude::*,
 };
-use wasmer::{imports, Bytes, Imports, Instance, Memory, MemoryType, Module, Store, TypedFunction};
+use wasmer::{
+    imports, wasmparser::Operator, Bytes, CompilerConfig, Extern, FunctionEnv, Global, Imports,
+    Instance, Memory, MemoryType, Module, Mutability, Store, TypedFunction, Value,
+};
+use wasmer_middlewares::{
+    metering::{get_remaining_points, set_remaining_points, MeteringPoints},
+    Metering,
+};
 
 use super::{
-    contract_store::ContractStore, delegate_store::DelegateStore, error::RuntimeInnerError,
-    native_api, secrets_store::SecretsStore, RuntimeResult,
+    contract_store::ContractStore,
+    delegate::DelegateExecError,
+    delegate_store::DelegateStore,
+    error::RuntimeInnerError,
+    native_api::{self, HostEnv},
+    secrets_store::SecretsStore,
+    version::{AbiVersion, ContractVersion},
+    RuntimeResult,
 };
 
 static INSTANCE_ID: AtomicI64 = AtomicI64::new(0);
 
+/// Default fuel available for each call, roughly the number of wasm instructions executed.
+pub const DEFAULT_MAX_FUEL: u64 = 10_000_000_000;
+/// Default wall-clock time available for each call.
+pub const DEFAULT_MAX_EXECUTION_TIME: Duration = Duration::from_secs(5);
+/// Conservative estimate of the fuel a call burns per second, low enough for slow machines,
+/// used to derive from the time budget of a call the fuel it can spend.
+pub const FUEL_PER_SECOND: u64 = 100_000_000;
+/// Default number of warm instances kept for the code of each contract or delegate.
+pub const DEFAULT_INSTANCE_POOL_SIZE: usize = 4;
+
+/// Limits applied to every call into contract or delegate code.
+#[derive(Clone, Copy, Debug)]
+pub struct ExecutionLimits {
+    /// Fuel available for each call, execution is aborted once it runs out.
+    pub max_fuel: u64,
+    /// Wall-clock time available for each call. Since a running instance can't be preempted,
+    /// the fuel of a call is capped to what it can burn within this time (see [`FUEL_PER_SECOND`])
+    /// so it is stopped while running, and the time is also checked whenever control returns
+    /// to the host.
+    pub max_execution_time: Duration,
+}
+
+impl ExecutionLimits {
+    /// Fuel available for each call, the lowest of the fuel limit and the fuel which can be
+    /// burnt within the time budget.
+    pub fn fuel(&self) -> u64 {
+        let timed_fuel = self.max_execution_time.as_secs_f64() * FUEL_PER_SECOND as f64;
+        // the conversion saturates on overflow
+        self.max_fuel.min(timed_fuel as u64)
+    }
+}
+
+impl Default for ExecutionLimits {
+    fn default() -> Self {
+        Self {
+            max_fuel: DEFAULT_MAX_FUEL,
+            max_execution_time: DEFAULT_MAX_EXECUTION_TIME,
+        }
+    }
+}
+
 pub(super) struct RunningInstance {
     pub id: i64,
     pub instance: Instance,
+    /// version of the interface exported by the code of the instance
+    abi: AbiVersion,
+    key: Key,
+    deadline: Option<Instant>,
+    /// Set if the instance can go back to the pool once the call is done.
+    pooled: Option<(CodeHash, InitialState)>,
+    /// environment of the host functions imported by this instance only
+    host_env: HostEnv,
 }
 
 impl Drop for RunningInstance {
     fn drop(&mut self) {
-        let _ = native_api::MEM_ADDR.remove(&self.id);
+        self.host_env.unregister();
     }
 }
 
+/// What host functions need to know about the instance calling them.
 pub(super) struct InstanceInfo {
-    pub start_ptr: i64,
+    pub memory: Memory,
     key: Key,
+    /// span of the call, parent of all the events and spans logged by the instance
+    pub span: tracing::Span,
+    /// spans entered by the instance and not exited yet
+    pub guest_spans: Vec<tracing::Span>,
 }
 
 impl InstanceInfo {
+    pub fn new(memory: Memory, key: Key) -> Self {
+        let span = match &key {
+            Key::Contract(id) => {
+                tracing::info_span!(target: "contract", "contract_call", contract = %id.encode())
+            }
+            Key::Delegate(key) => {
+                tracing::info_span!(target: "contract", "delegate_call", delegate = %key.encode())
+            }
+        };
+        Self {
+            memory,
+            key,
+            span,
+            guest_spans: vec![],
+        }
+    }
+
     pub fn key(&self) -> String {
-        match &self.key {
-            Key::Contract(k) => k.encode(),
-            Key::Delegate(k) => k.encode(),
+        self.key.encode()
+    }
+
+    /// The innermost span open for the instance.
+    pub fn current_span(&self) -> &tracing::Span {
+        self.guest_spans.last().unwrap_or(&self.span)
+    }
+}
+
+/// State of an instance right after being instantiated, restored before each reuse.
+pub(super) struct InitialState {
+    memory: Vec<u8>,
+    /// mutable globals exported by the instance, with their initial values
+    globals: Vec<(Global, Value)>,
+}
+
+/// An idle instance, along with its state right after being instantiated.
+struct PooledInstance {
+    instance: Instance,
+    host_env: HostEnv,
+    initial_state: InitialState,
+}
+
+/// Warm instances of the contract and delegate code, so hot contracts don't need to be
+/// instantiated on every call.
+///
+/// Reused instances must behave exactly as fresh ones, so only code whose whole mutable state
+/// can be restored is pooled: modules with mutable globals which are not exported always get a
+/// fresh instance, and so do calls which grew the memory of the instance, since it can't shrink
+/// back. Instances are only reused after calls which finished successfully, since a trap may
+/// leave them in an inconsistent state, and are never shared between different contracts or
+/// delegates, even if they have the same code.
+pub(super) struct InstancePool {
+    /// Max number of idle instances kept for the same contract or delegate.
+    max_size: usize,
+    instances: HashMap<(CodeHash, Key), Vec<PooledInstance>>,
+    /// code compiled by the runtime whose instances can be reset between calls
+    resettable: HashSet<CodeHash>,
+}
+
+impl InstancePool {
+    fn new(max_size: usize) -> Self {
+        Self {
+            max_size,
+            instances: HashMap::new(),
+            resettable: HashSet::new(),
+        }
+    }
+
+    /// Records whether instances of the code can be pooled, checked when compiling it.
+    fn compiled(&mut self, code_hash: CodeHash, code: &[u8]) {
+        if resettable(code) {
+            self.resettable.insert(code_hash);
+        } else {
+            self.resettable.remove(&code_hash);
         }
     }
+
+    fn take(&mut self, code_hash: CodeHash, key: &Key) -> Option<PooledInstance> {
+        self.instances.get_mut(&(code_hash, key.clone()))?.pop()
+    }
+
+    fn has_room(&self, code_hash: CodeHash, key: &Key) -> bool {
+        self.instances
+            .get(&(code_hash, key.clone()))
+            .map(|idle| idle.len())
+            .unwrap_or(0)
+            < self.max_size
+    }
+
+    pub fn remove(&mut self, code_hash: &CodeHash) {
+        self.instances.retain(|(hash, _), _| hash != code_hash);
+        self.resettable.remove(code_hash);
+    }
 }
 
-enum Key {
+/// Whether all the mutable state of the instances of a module, besides their memory, is exported
+/// and so can be restored: imported globals and globals only visible to the module can't be.
+fn resettable(code: &[u8]) -> bool {
+    use wasmer::wasmparser::{BinaryReaderError, ExternalKind, Parser, Payload, TypeRef};
+
+    let Ok(code) = wasmer::wat2wasm(code) else {
+        return false;
+    };
+    let check = || -> Result<bool, BinaryReaderError> {
+        let mut globals = 0;
+        let mut mutable = HashSet::new();
+        let mut exported = HashSet::new();
+        for payload in Parser::new(0).parse_all(&code) {
+            match payload? {
+                Payload::ImportSection(imports) => {
+                    for import in imports {
+                        if let TypeRef::Global(ty) = import?.ty {
+                            if ty.mutable {
+                                return Ok(false);
+                            }
+                            globals += 1;
+                        }
+                    }
+                }
+                Payload::GlobalSection(section) => {
+                    for global in section {
+                        if global?.ty.mutable {
+                            mutable.insert(globals);
+                        }
+                        globals += 1;
+                    }
+                }
+                Payload::ExportSection(exports) => {
+                    for export in exports {
+                        let export = export?;
+                        if export.kind == ExternalKind::Global {
+                            exported.insert(export.index);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(mutable.is_subset(&exported))
+    };
+    check().unwrap_or(false)
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub(super) enum Key {
     Contract(ContractInstanceId),
     Delegate(DelegateKey),
 }
 
+impl Key {
+    fn encode(&self) -> String {
+        match self {
+            Key::Contract(k) => k.encode(),
+            Key::Delegate(k) => k.encode(),
+        }
+    }
+}
+
 impl RunningInstance {
-    fn new(rt: &mut Runtime, instance: Instance, key: Key) -> RuntimeResult<Self> {
+    fn new(
+        rt: &mut Runtime,
+        instance: Instance,
+        host_env: HostEnv,
+        key: Key,
+        pooled: Option<(CodeHash, InitialState)>,
+    ) -> RuntimeResult<Self> {
         let memory = rt
             .host_memory
             .as_ref()
             .map(Ok)
             .unwrap_or_else(|| instance.exports.get_memory("memory"))?;
-        let set_id: TypedFunction<i64, ()> = instance
-            .exports
-            .get_typed_function(&rt.wasm_store, "__frnt_set_id")
-            .unwrap();
+        let abi = AbiVersion::of(&mut rt.wasm_store, &instance)?;
         let id = INSTANCE_ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-        set_id.call(&mut rt.wasm_store, id).unwrap();
-        let ptr = memory.view(&rt.wasm_store).data_ptr() as i64;
-        native_api::MEM_ADDR.insert(
+        set_remaining_points(&mut rt.wasm_store, &instance, rt.limits.fuel());
+        abi.abi().init(&mut rt.wasm_store, &instance, id)?;
+        host_env.register(InstanceInfo::new(memory.clone(), key.clone()));
+        Ok(Self {
+            instance,
             id,
-            InstanceInfo {
-                start_ptr: ptr,
-                key,
-            },
-        );
-        Ok(Self { instance, id })
+            abi,
+            key,
+            deadline: Instant::now().checked_add(rt.limits.max_execution_time),
+            pooled,
+            host_env,
+        })
     }
 }
 
@@ -87,12 +307,18 @@ pub enum ContractExecError {
 
     #[error("unexpected result from contract interface")]
     UnexpectedResult,
+
+    #[error("contract execution ran out of fuel (limit: {0})")]
+    OutOfFuel(u64),
+
+    #[error("contract execution exceeded the time limit ({0:?})")]
+    Timeout(Duration),
 }
 
 pub struct Runtime {
     /// Working memory store used by the inner engine
     pub(super) wasm_store: Store,
-    /// includes all the necessary imports to interact with the native runtime environment
+    /// imports shared by all the instances, the native API is added for each instance
     pub(super) top_level_imports: Imports,
     /// assigned growable host memory
     pub(super) host_memory: Option<Memory>,
@@ -104,19 +330,28 @@ pub struct Runtime {
 
     /// Local contract storage.
     pub(crate) contract_store: ContractStore,
-    /// loaded contract modules
+    /// loaded contract modules, also cached on disk by the contract store
     pub(super) contract_modules: HashMap<ContractKey, Module>,
+
+    /// limits applied to each call into the contracts and delegates
+    pub(super) limits: ExecutionLimits,
+    /// idle instances ready to serve new calls
+    pub(super) instance_pool: InstancePool,
+    /// bytes of instance memory used by the calls since it was last taken
+    pub(super) memory_usage: usize,
+    /// fuel spent by the calls since it was last taken
+    pub(super) fuel_usage: u64,
 }
 
 impl Runtime {
     pub fn build(
-        contract_store: ContractStore,
-        delegate_store: DelegateStore,
+        mut contract_store: ContractStore,
+        mut delegate_store: DelegateStore,
         secret_store: SecretsStore,
         host_mem: bool,
     ) -> RuntimeResult<Self> {
         let mut store = Self::instance_store();
-        let (host_memory, mut top_level_imports) = if host_mem {
+        let (host_memory, top_level_imports) = if host_mem {
             let mem = Self::instance_host_mem(&mut store)?;
             let imports = imports! {
                 "env" => {
@@ -127,9 +362,8 @@ impl Runtime {
         } else {
             (None, imports! {})
         };
-        native_api::log::prepare_export(&mut store, &mut top_level_imports);
-        native_api::rand::prepare_export(&mut store, &mut top_level_imports);
-        native_api::time::prepare_export(&mut store, &mut top_level_imports);
+        contract_store.precompile_with(store.engine().clone());
+        delegate_store.precompile_with(store.engine().clone());
 
         Ok(Self {
             wasm_store: store,
@@ -142,9 +376,170 @@ impl Runtime {
 
             contract_store,
             delegate_modules: HashMap::new(),
+
+            limits: ExecutionLimits::default(),
+            instance_pool: InstancePool::new(DEFAULT_INSTANCE_POOL_SIZE),
+            memory_usage: 0,
+            fuel_usage: 0,
         })
     }
 
+    pub fn with_limits(mut self, limits: ExecutionLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets the max number of idle instances kept for the code of each contract or delegate,
+    /// zero disables pooling and every call gets a fresh instance. Pooling is always disabled
+    /// when running with host memory, since it is shared by all the instances.
+    pub fn with_instance_pool(mut self, size: usize) -> Self {
+        self.instance_pool.max_size = size;
+        self.instance_pool.instances.clear();
+        self
+    }
+
+    /// Calls an entry point of a running instance, through the shim for the interface version of
+    /// its code, with the pointers to the buffers holding its arguments. Returns the pointer to
+    /// the result once checked against the execution limits.
+    pub(super) fn call_entry_point(
+        &mut self,
+        running: &RunningInstance,
+        name: &str,
+        args: &[i64],
+    ) -> RuntimeResult<i64> {
+        let func = running.abi.abi().entry_point(&running.instance, name)?;
+        let args = args.iter().copied().map(Value::I64).collect::<Vec<_>>();
+        let res = func
+            .call(&mut self.wasm_store, &args)
+            .and_then(|res| match *res {
+                [Value::I64(ptr)] => Ok(ptr),
+                _ => Err(wasmer::RuntimeError::new(format!(
+                    "unexpected result from `{name}`"
+                ))),
+            });
+        self.check_limits(running, res)
+    }
+
+    /// Checks the outcome of a call into a running instance against the execution limits,
+    /// accounting the fuel it spent and refilling it for the next call.
+    pub(super) fn check_limits<T>(
+        &mut self,
+        running: &RunningInstance,
+        result: Result<T, wasmer::RuntimeError>,
+    ) -> RuntimeResult<T> {
+        let max_fuel = self.limits.fuel();
+        let remaining = get_remaining_points(&mut self.wasm_store, &running.instance);
+        let exhausted = matches!(remaining, MeteringPoints::Exhausted);
+        self.fuel_usage += match remaining {
+            MeteringPoints::Remaining(remaining) => max_fuel.saturating_sub(remaining),
+            MeteringPoints::Exhausted => max_fuel,
+        };
+        if !exhausted {
+            set_remaining_points(&mut self.wasm_store, &running.instance, max_fuel);
+        }
+        // when the fuel was capped by the time budget running out of it means the call took
+        // too long, rather than exceeding the fuel limit
+        let timed_out = (exhausted && max_fuel < self.limits.max_fuel)
+            || running
+                .deadline
+                .is_some_and(|deadline| Instant::now() > deadline);
+        let out_of_fuel = exhausted && !timed_out;
+        if out_of_fuel || timed_out {
+            let max_execution_time = self.limits.max_execution_time;
+            tracing::warn!(
+                key = %running.key.encode(),
+                out_of_fuel,
+                "execution limits exceeded"
+            );
+            return Err(match (&running.key, out_of_fuel) {
+                (Key::Contract(_), true) => ContractExecError::OutOfFuel(max_fuel).into(),
+                (Key::Contract(_), false) => ContractExecError::Timeout(max_execution_time).into(),
+                (Key::Delegate(_), true) => DelegateExecError::OutOfFuel(max_fuel).into(),
+                (Key::Delegate(_), false) => DelegateExecError::Timeout(max_execution_time).into(),
+            });
+        }
+        Ok(result?)
+    }
+
+    /// Evicts the least recently used contracts from the store if over its disk quota,
+    /// dropping any module compiled for them.
+    pub(crate) fn evict_unused_contracts(
+        &mut self,
+        keep: impl Fn(&ContractKey) -> bool,
+    ) -> RuntimeResult<()> {
+        let evicted = self.contract_store.evict_least_recently_used(keep)?;
+        if !evicted.is_empty() {
+            self.contract_modules.retain(|key, _| {
+                key.code_hash()
+                    .map(|code_hash| !evicted.contains(code_hash))
+                    .unwrap_or(true)
+            });
+            for code_hash in &evicted {
+                self.instance_pool.remove(code_hash);
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes the bytes of instance memory used by the calls since the last time it was taken.
+    pub fn take_memory_usage(&mut self) -> usize {
+        std::mem::take(&mut self.memory_usage)
+    }
+
+    /// Takes the fuel spent by the calls since the last time it was taken.
+    pub fn take_fuel_usage(&mut self) -> u64 {
+        std::mem::take(&mut self.fuel_usage)
+    }
+
+    /// Returns the instance used by a successful call to the pool, if there is room for it.
+    pub(super) fn release(&mut self, mut running: RunningInstance) {
+        if let Ok(memory) = running.instance.exports.get_memory("memory") {
+            self.memory_usage += memory.view(&self.wasm_store).data_size() as usize;
+        }
+        let Some((code_hash, initial_state)) = running.pooled.take() else {
+            return;
+        };
+        if !self.instance_pool.has_room(code_hash, &running.key) {
+            return;
+        }
+        let reset = running
+            .instance
+            .exports
+            .get_memory("memory")
+            .map_err(|err| err.to_string())
+            .and_then(|memory| {
+                let view = memory.view(&self.wasm_store);
+                let initial_memory = &initial_state.memory;
+                if view.data_size() as usize != initial_memory.len() {
+                    return Err("memory grew during the call".to_owned());
+                }
+                view.write(0, initial_memory).map_err(|err| err.to_string())
+            })
+            .and_then(|()| {
+                initial_state
+                    .globals
+                    .iter()
+                    .try_for_each(|(global, value)| {
+                        global
+                            .set(&mut self.wasm_store, value.clone())
+                            .map_err(|err| err.to_string())
+                    })
+            });
+        match reset {
+            Ok(()) => self
+                .instance_pool
+                .instances
+                .entry((code_hash, running.key.clone()))
+                .or_default()
+                .push(PooledInstance {
+                    instance: running.instance.clone(),
+                    host_env: running.host_env.clone(),
+                    initial_state,
+                }),
+            Err(err) => tracing::debug!("failed resetting instance state: {err}"),
+        }
+    }
+
     pub(super) fn init_buf<T>(&mut self, instance: &Instance, data: T) -> RuntimeResult<BufferMut>
     where
         T: AsRef<[u8]>,
@@ -179,6 +574,17 @@ impl Runtime {
         parameters: &Parameters,
         req_bytes: usize,
     ) -> RuntimeResult<RunningInstance> {
+        let code_hash = key
+            .code_hash()
+            .copied()
+            .or_else(|| self.contract_store.code_hash_from_key(key));
+        if let Some(code_hash) = code_hash {
+            if let Some(running) =
+                self.take_pooled(code_hash, Key::Contract(*key.id()), req_bytes)?
+            {
+                return Ok(running);
+            }
+        }
         let module = if let Some(module) = self.contract_modules.get(key) {
             module
         } else {
@@ -186,19 +592,21 @@ impl Runtime {
                 .contract_store
                 .fetch_contract(key, parameters)
                 .ok_or_else(|| RuntimeInnerError::ContractNotFound(key.clone()))?;
-            let module = match contract {
-                ContractContainer::Wasm(ContractWasmAPIVersion::V1(contract_v1)) => {
-                    Module::new(&self.wasm_store, contract_v1.code().data())?
-                }
-                _ => unimplemented!(),
-            };
+            let (_, _, code) = ContractVersion::unwrap(&contract)?;
+            let module = self.contract_store.module_cache().compile(
+                self.wasm_store.engine(),
+                code.hash(),
+                code.data(),
+            )?;
+            self.instance_pool.compiled(*code.hash(), code.data());
             self.contract_modules.insert(key.clone(), module);
             self.contract_modules.get(key).unwrap()
         }
         .clone();
-        let instance = self.prepare_instance(&module)?;
+        let (instance, host_env) = self.prepare_instance(&module)?;
+        let pooled = code_hash.and_then(|code_hash| self.snapshot(code_hash, &instance));
         self.set_instance_mem(req_bytes, &instance)?;
-        RunningInstance::new(self, instance, Key::Contract(*key.id()))
+        RunningInstance::new(self, instance, host_env, Key::Contract(*key.id()), pooled)
     }
 
     pub(super) fn prepare_delegate_call(
@@ -207,6 +615,10 @@ impl Runtime {
         key: &DelegateKey,
         req_bytes: usize,
     ) -> RuntimeResult<RunningInstance> {
+        let code_hash = *key.code_hash();
+        if let Some(running) = self.take_pooled(code_hash, Key::Delegate(key.clone()), req_bytes)? {
+            return Ok(running);
+        }
         let module = if let Some(module) = self.delegate_modules.get(key) {
             module
         } else {
@@ -214,14 +626,82 @@ impl Runtime {
                 .delegate_store
                 .fetch_delegate(key, params)
                 .ok_or_else(|| RuntimeInnerError::DelegateNotFound(key.clone()))?;
-            let module = Module::new(&self.wasm_store, delegate.code().as_ref())?;
+            let module = self.delegate_store.module_cache().compile(
+                self.wasm_store.engine(),
+                key.code_hash(),
+                delegate.code().as_ref(),
+            )?;
+            self.instance_pool
+                .compiled(*key.code_hash(), delegate.code().as_ref());
             self.delegate_modules.insert(key.clone(), module);
             self.delegate_modules.get(key).unwrap()
         }
         .clone();
-        let instance = self.prepare_instance(&module)?;
+        let (instance, host_env) = self.prepare_instance(&module)?;
+        let pooled = self.snapshot(code_hash, &instance);
+        self.set_instance_mem(req_bytes, &instance)?;
+        RunningInstance::new(self, instance, host_env, Key::Delegate(key.clone()), pooled)
+    }
+
+    fn take_pooled(
+        &mut self,
+        code_hash: CodeHash,
+        key: Key,
+        req_bytes: usize,
+    ) -> RuntimeResult<Option<RunningInstance>> {
+        let Some(PooledInstance {
+            instance,
+            host_env,
+            initial_state,
+        }) = self.instance_pool.take(code_hash, &key)
+        else {
+            return Ok(None);
+        };
         self.set_instance_mem(req_bytes, &instance)?;
-        RunningInstance::new(self, instance, Key::Delegate(key.clone()))
+        let running = RunningInstance::new(
+            self,
+            instance,
+            host_env,
+            key,
+            Some((code_hash, initial_state)),
+        )?;
+        Ok(Some(running))
+    }
+
+    /// Copies the memory and mutable globals of a fresh instance so they can be restored when
+    /// returned to the pool, if pooling is enabled and the instance can be fully reset.
+    fn snapshot(
+        &mut self,
+        code_hash: CodeHash,
+        instance: &Instance,
+    ) -> Option<(CodeHash, InitialState)> {
+        if self.instance_pool.max_size == 0
+            || self.host_memory.is_some()
+            || !self.instance_pool.resettable.contains(&code_hash)
+        {
+            return None;
+        }
+        let memory = instance.exports.get_memory("memory").ok()?;
+        let memory = memory.view(&self.wasm_store).copy_to_vec().ok()?;
+        let globals = instance
+            .exports
+            .iter()
+            .filter_map(|(_, export)| match export {
+                Extern::Global(global)
+                    if global.ty(&self.wasm_store).mutability == Mutability::Var =>
+                {
+                    Some(global.clone())
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|global| {
+                let value = global.get(&mut self.wasm_store);
+                (global, value)
+            })
+            .collect();
+        Some((code_hash, InitialState { memory, globals }))
     }
 
     fn set_instance_mem(&mut self, req_bytes: usize, instance: &Instance) -> RuntimeResult<()> {
@@ -250,17 +730,26 @@ impl Runtime {
         Ok(Memory::new(store, MemoryType::new(20u32, None, false))?)
     }
 
-    fn prepare_instance(&mut self, module: &Module) -> RuntimeResult<Instance> {
-        Ok(Instance::new(
-            &mut self.wasm_store,
-            module,
-            &self.top_level_imports,
-        )?)
+    /// Instantiates the module, along with the environment of the host functions it imports.
+    fn prepare_instance(&mut self, module: &Module) -> RuntimeResult<(Instance, HostEnv)> {
+        let store = &mut self.wasm_store;
+        let host_env = HostEnv::default();
+        let env = FunctionEnv::new(store, host_env.clone());
+        let mut imports = self.top_level_imports.clone();
+        native_api::log::prepare_export(store, &env, &mut imports);
+        native_api::rand::prepare_export(store, &env, &mut imports);
+        native_api::time::prepare_export(store, &env, &mut imports);
+        let instance = Instance::new(store, module, &imports)?;
+        Ok((instance, host_env))
     }
 
     fn instance_store() -> Store {
         use wasmer::Cranelift;
-        Store::new(Cranelift::new())
+        let mut compiler = Cranelift::new();
+        // the actual limit is set for each instance before running it
+        let metering = Metering::new(DEFAULT_MAX_FUEL, |_: &Operator| -> u64 { 1 });
+        compiler.push_middleware(Arc::new(metering));
+        Store::new(compiler)
     }
 
     // #[cfg(not(test))]
@@ -277,3 +766,263 @@ impl Runtime {
     //     // Store::new(&Dylib::headless().engine())
     // }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{util::tests::get_temp_dir, wasm_runtime::MasterKey};
+
+    const LOOP_MODULE: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "__frnt_set_id") (param i64))
+            (func (export "run") (loop br 0)))
+    "#;
+
+    const COUNTER_MODULE: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "__frnt_set_id") (param i64))
+            (func (export "bump") (result i32)
+                (i32.store (i32.const 0) (i32.add (i32.load (i32.const 0)) (i32.const 1)))
+                (i32.load (i32.const 0))))
+    "#;
+
+    /// Bumps an exported global.
+    const GLOBALS_MODULE: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (global $exported (export "exported") (mut i32) (i32.const 0))
+            (func (export "__frnt_set_id") (param i64))
+            (func (export "bump") (result i32)
+                (global.set $exported (i32.add (global.get $exported) (i32.const 1)))
+                (global.get $exported)))
+    "#;
+
+    /// Bumps a global only visible to the module itself.
+    const PRIVATE_GLOBALS_MODULE: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (global $private (mut i32) (i32.const 0))
+            (func (export "__frnt_set_id") (param i64))
+            (func (export "bump") (result i32)
+                (global.set $private (i32.add (global.get $private) (i32.const 1)))
+                (global.get $private)))
+    "#;
+
+    /// Grows the memory by a page, returning the number of pages it had before.
+    const GROWING_MODULE: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "__frnt_set_id") (param i64))
+            (func (export "bump") (result i32)
+                (memory.grow (i32.const 1))))
+    "#;
+
+    /// Returns the given argument, built against the first version of the interface.
+    const ECHO_V1_MODULE: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (global $id (mut i64) (i64.const -1))
+            (func (export "__frnt_set_id") (param i64)
+                (global.set $id (local.get 0)))
+            (func (export "echo") (param i64) (result i64)
+                (local.get 0)))
+    "#;
+
+    /// Returns the given argument, built against the second version of the interface.
+    const ECHO_V2_MODULE: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (global (export "__frnt_abi_version") i32 (i32.const 2))
+            (func (export "echo") (param i64) (result i64)
+                (local.get 0)))
+    "#;
+
+    fn test_runtime(temp_dir: &tempfile::TempDir) -> Result<Runtime, Box<dyn std::error::Error>> {
+        let contract_store = ContractStore::new(temp_dir.path().join("contracts"), 10_000)?;
+        let delegate_store = DelegateStore::new(temp_dir.path().join("delegates"), 10_000)?;
+        let secret_store = SecretsStore::new(
+            temp_dir.path().join("secrets"),
+            MasterKey::from_passphrase(b"test"),
+        )?;
+        Ok(Runtime::build(
+            contract_store,
+            delegate_store,
+            secret_store,
+            false,
+        )?)
+    }
+
+    #[test]
+    fn stops_on_execution_limits() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = get_temp_dir();
+        let mut runtime = test_runtime(&temp_dir)?.with_limits(ExecutionLimits {
+            max_fuel: 1_000,
+            max_execution_time: Duration::from_secs(60),
+        });
+        let module = Module::new(&runtime.wasm_store, LOOP_MODULE)?;
+
+        let (instance, host_env) = runtime.prepare_instance(&module)?;
+        let key = Key::Contract(ContractInstanceId::new([0; 32]));
+        let running = RunningInstance::new(&mut runtime, instance, host_env, key, None)?;
+        let run: TypedFunction<(), ()> = running
+            .instance
+            .exports
+            .get_typed_function(&runtime.wasm_store, "run")?;
+        let res = run.call(&mut runtime.wasm_store);
+        let err = runtime.check_limits(&running, res).unwrap_err();
+        assert!(matches!(
+            err.deref(),
+            RuntimeInnerError::ContractExecError(ContractExecError::OutOfFuel(1_000))
+        ));
+
+        runtime.limits.max_execution_time = Duration::ZERO;
+        let (instance, host_env) = runtime.prepare_instance(&module)?;
+        let key = Key::Delegate(DelegateKey::new([0; 32], CodeHash::new([0; 32])));
+        let running = RunningInstance::new(&mut runtime, instance, host_env, key, None)?;
+        std::thread::sleep(Duration::from_millis(1));
+        let err = runtime.check_limits(&running, Ok(())).unwrap_err();
+        assert!(matches!(
+            err.deref(),
+            RuntimeInnerError::DelegateExecError(DelegateExecError::Timeout(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn stops_running_calls_on_time() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = get_temp_dir();
+        let max_execution_time = Duration::from_secs(1);
+        let mut runtime = test_runtime(&temp_dir)?.with_limits(ExecutionLimits {
+            max_fuel: u64::MAX,
+            max_execution_time,
+        });
+        let module = Module::new(&runtime.wasm_store, LOOP_MODULE)?;
+
+        let (instance, host_env) = runtime.prepare_instance(&module)?;
+        let key = Key::Contract(ContractInstanceId::new([0; 32]));
+        let running = RunningInstance::new(&mut runtime, instance, host_env, key, None)?;
+        let run: TypedFunction<(), ()> = running
+            .instance
+            .exports
+            .get_typed_function(&runtime.wasm_store, "run")?;
+        let started = Instant::now();
+        let res = run.call(&mut runtime.wasm_store);
+        assert!(started.elapsed() < max_execution_time);
+        let err = runtime.check_limits(&running, res).unwrap_err();
+        assert!(matches!(
+            err.deref(),
+            RuntimeInnerError::ContractExecError(ContractExecError::Timeout(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn runs_interface_versions_side_by_side() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = get_temp_dir();
+        let mut runtime = test_runtime(&temp_dir)?;
+        let v1 = store_contract(&mut runtime, ECHO_V1_MODULE, vec![])?;
+        let v2 = store_contract(&mut runtime, ECHO_V2_MODULE, vec![])?;
+        let unknown = store_contract(
+            &mut runtime,
+            &ECHO_V2_MODULE.replace("(i32.const 2)", "(i32.const 3)"),
+            vec![],
+        )?;
+
+        for (key, version) in [(&v1, AbiVersion::V1), (&v2, AbiVersion::V2)] {
+            for arg in [1, 42] {
+                let running = runtime.prepare_contract_call(key, &Parameters::from(vec![]), 0)?;
+                assert_eq!(running.abi, version);
+                assert_eq!(runtime.call_entry_point(&running, "echo", &[arg])?, arg);
+                runtime.release(running);
+            }
+        }
+        let err = runtime
+            .prepare_contract_call(&unknown, &Parameters::from(vec![]), 0)
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.deref(),
+            RuntimeInnerError::UnsupportedVersion(_)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn reuses_pooled_instances() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = get_temp_dir();
+        let mut runtime = test_runtime(&temp_dir)?.with_instance_pool(1);
+        let key = store_contract(&mut runtime, COUNTER_MODULE, vec![])?;
+
+        let pool_key = (*key.code_hash().unwrap(), Key::Contract(*key.id()));
+        assert_eq!(bump(&mut runtime, &key)?, 1);
+        assert_eq!(runtime.instance_pool.instances[&pool_key].len(), 1);
+        // the pooled instance is reused with its memory reset
+        assert_eq!(bump(&mut runtime, &key)?, 1);
+        assert_eq!(runtime.instance_pool.instances[&pool_key].len(), 1);
+
+        runtime = runtime.with_instance_pool(0);
+        assert_eq!(bump(&mut runtime, &key)?, 1);
+        assert!(runtime.instance_pool.instances.is_empty());
+        Ok(())
+    }
+
+    fn store_contract(
+        runtime: &mut Runtime,
+        module: &str,
+        params: Vec<u8>,
+    ) -> Result<ContractKey, Box<dyn std::error::Error>> {
+        let contract = WrappedContract::new(
+            Arc::new(ContractCode::from(module.as_bytes().to_vec())),
+            Parameters::from(params),
+        );
+        let key = contract.key().clone();
+        runtime
+            .contract_store
+            .store_contract(ContractContainer::Wasm(ContractWasmAPIVersion::V1(
+                contract,
+            )))?;
+        Ok(key)
+    }
+
+    /// Calls `bump` on the contract, returning the instance to the pool afterwards.
+    fn bump(runtime: &mut Runtime, key: &ContractKey) -> Result<i32, Box<dyn std::error::Error>> {
+        let running = runtime.prepare_contract_call(key, &Parameters::from(vec![]), 0)?;
+        let bump: TypedFunction<(), i32> = running
+            .instance
+            .exports
+            .get_typed_function(&runtime.wasm_store, "bump")?;
+        let res = bump.call(&mut runtime.wasm_store);
+        let counter = runtime.check_limits(&running, res)?;
+        runtime.release(running);
+        Ok(counter)
+    }
+
+    #[test]
+    fn pooled_instances_dont_leak_globals() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = get_temp_dir();
+        let mut runtime = test_runtime(&temp_dir)?.with_instance_pool(1);
+        let first = store_contract(&mut runtime, GLOBALS_MODULE, vec![1])?;
+        let second = store_contract(&mut runtime, GLOBALS_MODULE, vec![2])?;
+
+        assert_eq!(bump(&mut runtime, &first)?, 1);
+        // a contract with the same code never gets the instance used by the other one
+        assert_eq!(bump(&mut runtime, &second)?, 1);
+        assert_eq!(runtime.instance_pool.instances.len(), 2);
+        // and the exported global is back to its initial value when the instance is reused
+        assert_eq!(bump(&mut runtime, &first)?, 1);
+        assert_eq!(runtime.instance_pool.instances.len(), 2);
+
+        // globals only visible to the module can't be restored, so every call gets a fresh
+        // instance, and so do the calls after one which grew the memory
+        let private = store_contract(&mut runtime, PRIVATE_GLOBALS_MODULE, vec![])?;
+        let growing = store_contract(&mut runtime, GROWING_MODULE, vec![])?;
+        for _ in 0..2 {
+            assert_eq!(bump(&mut runtime, &private)?, 1);
+            assert_eq!(bump(&mut runtime, &growing)?, 1);
+        }
+        assert_eq!(runtime.instance_pool.instances.len(), 2);
+        Ok(())
+    }
+}
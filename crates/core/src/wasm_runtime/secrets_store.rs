@@ -2,23 +2,34 @@ use std::{
     collections::{HashMap, HashSet},
     fs::{self, File},
     io::Write,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::Arc,
 };
 
 use blake3::traits::digest::generic_array::GenericArray;
-use chacha20poly1305::{aead::Aead, Error as EncryptionError, KeyInit, XChaCha20Poly1305, XNonce};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, OsRng},
+    Error as EncryptionError, Key, KeyInit, XChaCha20Poly1305, XNonce,
+};
 use dashmap::DashMap;
 use freenet_stdlib::{client_api::DelegateRequest, prelude::*};
 use once_cell::sync::Lazy;
 
 use super::{
-    store::{SafeWriter, StoreFsManagement},
+    store::{IndexLock, IndexStats, SafeWriter, StoreFsManagement, WatchGuard},
     RuntimeResult,
 };
 
 type SecretKey = [u8; 32];
 
+/// Extension of the ciphers wrapped with a new master key while a rotation is in progress.
+const ROTATING_EXT: &str = "rotating";
+/// Extension of the secrets encrypted with new delegate material while it is being registered.
+const REENCRYPTED_EXT: &str = "reencrypted";
+/// Marker written once all the rotated ciphers are staged, after which the rotation must be
+/// finished rather than discarded.
+const ROTATION_MARKER: &str = "ROTATING_MASTER_KEY";
+
 #[derive(Debug, thiserror::Error)]
 pub enum SecretStoreError {
     #[error("encryption error: {0}")]
@@ -29,24 +40,178 @@ pub enum SecretStoreError {
     MissingCipher,
     #[error("missing secret: {0}")]
     MissingSecret(SecretsId),
+    #[error("corrupted encryption data for delegate {0}")]
+    CorruptedCipher(DelegateKey),
+}
+
+/// Key used to encrypt the per-delegate encryption material persisted on disk.
+#[derive(Clone)]
+pub struct MasterKey(Key);
+
+impl MasterKey {
+    const CONTEXT: &'static str = "freenet secrets store 2023-11 master key";
+
+    pub fn from_passphrase(passphrase: &[u8]) -> Self {
+        Self(blake3::derive_key(Self::CONTEXT, passphrase).into())
+    }
+
+    pub fn from_keypair(keypair: &libp2p_identity::Keypair) -> Self {
+        let encoded = keypair
+            .to_protobuf_encoding()
+            .expect("keypair should be encodable");
+        Self(blake3::derive_key(Self::CONTEXT, &encoded).into())
+    }
+
+    /// Loads a random master key from the given file, creating it if it does not exist yet.
+    pub fn load_or_create(path: &Path) -> std::io::Result<Self> {
+        match fs::read(path) {
+            Ok(key) if key.len() == 32 => return Ok(Self(*GenericArray::from_slice(&key))),
+            Ok(_) => return Err(std::io::ErrorKind::InvalidData.into()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+        let tmp_path = path.with_extension("tmp");
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&tmp_path)?;
+        file.write_all(&key)?;
+        file.sync_all()?;
+        fs::rename(tmp_path, path)?;
+        if let Some(parent) = path.parent() {
+            sync_dir(parent)?;
+        }
+        Ok(Self(key))
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(&self.0)
+    }
+}
+
+fn write_synced(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+fn discard_staged(staged: Vec<(PathBuf, PathBuf)>) {
+    for (staged_path, _) in staged {
+        let _ = fs::remove_file(staged_path);
+    }
+}
+
+fn has_extension(path: &Path, ext: &str) -> bool {
+    path.extension().map_or(false, |e| e == ext)
 }
 
 #[derive(Clone)]
 struct Encryption {
+    key: Key,
     cipher: XChaCha20Poly1305,
     nonce: XNonce,
 }
 
+impl Encryption {
+    fn new(key: Key, nonce: XNonce) -> Self {
+        Self {
+            cipher: XChaCha20Poly1305::new(&key),
+            key,
+            nonce,
+        }
+    }
+
+    /// Serializes the key and nonce encrypted with the given master key,
+    /// prefixed by the random nonce used for the encryption.
+    fn wrap(&self, master_key: &MasterKey) -> Result<Vec<u8>, SecretStoreError> {
+        let wrap_nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut plaintext = Vec::with_capacity(self.key.len() + self.nonce.len());
+        plaintext.extend_from_slice(&self.key);
+        plaintext.extend_from_slice(&self.nonce);
+        let ciphertext = master_key
+            .cipher()
+            .encrypt(&wrap_nonce, plaintext.as_ref())
+            .map_err(SecretStoreError::Encryption)?;
+        let mut wrapped = wrap_nonce.to_vec();
+        wrapped.extend(ciphertext);
+        Ok(wrapped)
+    }
+
+    fn from_wrapped(
+        delegate: &DelegateKey,
+        wrapped: &[u8],
+        master_key: &MasterKey,
+    ) -> Result<Self, SecretStoreError> {
+        const NONCE_SIZE: usize = 24;
+        const KEY_SIZE: usize = 32;
+        if wrapped.len() <= NONCE_SIZE {
+            return Err(SecretStoreError::CorruptedCipher(delegate.clone()));
+        }
+        let (wrap_nonce, ciphertext) = wrapped.split_at(NONCE_SIZE);
+        let plaintext = master_key
+            .cipher()
+            .decrypt(GenericArray::from_slice(wrap_nonce), ciphertext)
+            .map_err(SecretStoreError::Encryption)?;
+        if plaintext.len() != KEY_SIZE + NONCE_SIZE {
+            return Err(SecretStoreError::CorruptedCipher(delegate.clone()));
+        }
+        let (key, nonce) = plaintext.split_at(KEY_SIZE);
+        Ok(Self::new(
+            *GenericArray::from_slice(key),
+            *GenericArray::from_slice(nonce),
+        ))
+    }
+
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, SecretStoreError> {
+        self.cipher.encrypt(&self.nonce, plaintext).map_err(|err| {
+            if self.nonce == *DEFAULT_NONCE {
+                SecretStoreError::MissingCipher
+            } else {
+                SecretStoreError::Encryption(err)
+            }
+        })
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, SecretStoreError> {
+        self.cipher.decrypt(&self.nonce, ciphertext).map_err(|err| {
+            if self.nonce == *DEFAULT_NONCE {
+                SecretStoreError::MissingCipher
+            } else {
+                SecretStoreError::Encryption(err)
+            }
+        })
+    }
+}
+
 pub struct SecretsStore {
     base_path: PathBuf,
+    ciphers_path: PathBuf,
+    master_key: MasterKey,
     ciphers: HashMap<DelegateKey, Encryption>,
     key_to_secret_part: Arc<DashMap<DelegateKey, (u64, HashSet<SecretKey>)>>,
     index_file: SafeWriter<Self>,
     key_file: PathBuf,
+    /// stops the maintenance of the index file once the store is dropped
+    _watch: WatchGuard,
 }
 
 pub(super) struct ConcatenatedSecretKeys(Vec<u8>);
 
+impl ConcatenatedSecretKeys {
+    fn from_hashes(hashes: &HashSet<SecretKey>) -> Self {
+        Self(hashes.iter().flatten().copied().collect())
+    }
+}
+
 impl AsRef<[u8]> for ConcatenatedSecretKeys {
     fn as_ref(&self) -> &[u8] {
         &self.0
@@ -94,21 +259,18 @@ impl StoreFsManagement for SecretsStore {
     }
 }
 
-static DEFAULT_CIPHER: Lazy<XChaCha20Poly1305> = Lazy::new(|| {
-    let arr = GenericArray::from_slice(&DelegateRequest::DEFAULT_CIPHER);
-    XChaCha20Poly1305::new(arr)
-});
-
 static DEFAULT_NONCE: Lazy<XNonce> =
     Lazy::new(|| GenericArray::from_slice(&DelegateRequest::DEFAULT_NONCE).to_owned());
 
-static DEFAULT_ENCRYPTION: Lazy<Encryption> = Lazy::new(|| Encryption {
-    cipher: (*DEFAULT_CIPHER).clone(),
-    nonce: *DEFAULT_NONCE,
+static DEFAULT_ENCRYPTION: Lazy<Encryption> = Lazy::new(|| {
+    Encryption::new(
+        *GenericArray::from_slice(&DelegateRequest::DEFAULT_CIPHER),
+        *DEFAULT_NONCE,
+    )
 });
 
 impl SecretsStore {
-    pub fn new(secrets_dir: PathBuf) -> RuntimeResult<Self> {
+    pub fn new(secrets_dir: PathBuf, master_key: MasterKey) -> RuntimeResult<Self> {
         let mut key_to_secret_part = Arc::new(DashMap::new());
         let key_file = secrets_dir.join("KEY_DATA");
         if !key_file.exists() {
@@ -120,29 +282,274 @@ impl SecretsStore {
         } else {
             Self::load_from_file(&key_file, &mut key_to_secret_part)?;
         }
-        Self::watch_changes(key_to_secret_part.clone(), &key_file)?;
+        let watch = Self::watch_changes(key_to_secret_part.clone(), &key_file)?;
+
+        let ciphers_path = secrets_dir.join("ciphers");
+        fs::create_dir_all(&ciphers_path)?;
+        Self::recover_rotation(&ciphers_path, &secrets_dir.join(ROTATION_MARKER))?;
+        let ciphers = Self::load_ciphers(&ciphers_path, &master_key)?;
+        Self::recover_reencryption(&secrets_dir, &ciphers)?;
 
         let index_file = SafeWriter::new(&key_file, false)?;
         Ok(Self {
             base_path: secrets_dir,
-            ciphers: HashMap::new(),
+            ciphers_path,
+            master_key,
+            ciphers,
             key_to_secret_part,
             index_file,
             key_file,
+            _watch: watch,
         })
     }
 
+    fn load_ciphers(
+        ciphers_path: &Path,
+        master_key: &MasterKey,
+    ) -> RuntimeResult<HashMap<DelegateKey, Encryption>> {
+        let mut ciphers = HashMap::new();
+        for entry in fs::read_dir(ciphers_path)? {
+            let path = entry?.path();
+            if path.extension().is_some() {
+                continue;
+            }
+            let Ok((delegate, wrapped)) =
+                bincode::deserialize::<(DelegateKey, Vec<u8>)>(&fs::read(&path)?)
+            else {
+                tracing::warn!("unexpected file in ciphers directory: {path:?}");
+                continue;
+            };
+            match Encryption::from_wrapped(&delegate, &wrapped, master_key) {
+                Ok(encryption) => {
+                    ciphers.insert(delegate, encryption);
+                }
+                Err(err) => {
+                    // can't fail the whole store because of a single delegate,
+                    // its secrets will be unavailable until registered again
+                    tracing::error!("failed loading cipher for delegate `{delegate}`: {err}");
+                }
+            }
+        }
+        Ok(ciphers)
+    }
+
+    /// Finishes a master key rotation that was interrupted after being committed,
+    /// or discards the staged ciphers if it was interrupted before.
+    fn recover_rotation(ciphers_path: &Path, marker: &Path) -> std::io::Result<()> {
+        let committed = marker.exists();
+        let mut staged = false;
+        for entry in fs::read_dir(ciphers_path)? {
+            let path = entry?.path();
+            if !has_extension(&path, ROTATING_EXT) {
+                continue;
+            }
+            staged = true;
+            if committed {
+                fs::rename(&path, path.with_extension(""))?;
+            } else {
+                fs::remove_file(&path)?;
+            }
+        }
+        if staged || committed {
+            sync_dir(ciphers_path)?;
+        }
+        if committed {
+            tracing::warn!("finishing interrupted master key rotation");
+            fs::remove_file(marker)?;
+        } else if staged {
+            tracing::warn!("discarding interrupted master key rotation");
+        }
+        Ok(())
+    }
+
+    /// Finishes or discards the re-encryption of secrets interrupted while registering
+    /// new encryption material for a delegate.
+    ///
+    /// The staged secrets are only kept if they can be decrypted with the persisted cipher,
+    /// which means the new material was persisted before the process stopped.
+    fn recover_reencryption(
+        base_path: &Path,
+        ciphers: &HashMap<DelegateKey, Encryption>,
+    ) -> std::io::Result<()> {
+        for (delegate, encryption) in ciphers {
+            let delegate_path = base_path.join(delegate.encode());
+            let Ok(entries) = fs::read_dir(&delegate_path) else {
+                continue;
+            };
+            let mut staged = false;
+            for entry in entries {
+                let path = entry?.path();
+                if !has_extension(&path, REENCRYPTED_EXT) {
+                    continue;
+                }
+                staged = true;
+                let committed = fs::read(&path)
+                    .ok()
+                    .map_or(false, |ciphertext| encryption.decrypt(&ciphertext).is_ok());
+                if committed {
+                    fs::rename(&path, path.with_extension(""))?;
+                } else {
+                    fs::remove_file(&path)?;
+                }
+            }
+            if staged {
+                tracing::warn!("recovered interrupted re-encryption of secrets for `{delegate}`");
+                sync_dir(&delegate_path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn wrap_cipher(
+        delegate: &DelegateKey,
+        encryption: &Encryption,
+        master_key: &MasterKey,
+    ) -> Result<Vec<u8>, SecretStoreError> {
+        bincode::serialize(&(delegate, encryption.wrap(master_key)?))
+            .map_err(|_| SecretStoreError::CorruptedCipher(delegate.clone()))
+    }
+
+    fn persist_cipher(
+        &self,
+        delegate: &DelegateKey,
+        encryption: &Encryption,
+    ) -> Result<(), SecretStoreError> {
+        let wrapped = Self::wrap_cipher(delegate, encryption, &self.master_key)?;
+        let cipher_path = self.ciphers_path.join(delegate.encode());
+        let tmp_path = cipher_path.with_extension("tmp");
+        write_synced(&tmp_path, &wrapped)?;
+        fs::rename(tmp_path, cipher_path)?;
+        sync_dir(&self.ciphers_path)?;
+        Ok(())
+    }
+
     pub fn register_delegate(
         &mut self,
         delegate: DelegateKey,
-        cipher: XChaCha20Poly1305,
+        cipher_key: Key,
         nonce: XNonce,
     ) -> Result<(), SecretStoreError> {
-        // FIXME: store/initialize the cyphers from disc
-        if nonce != *DEFAULT_NONCE {
-            let encryption = Encryption { cipher, nonce };
-            self.ciphers.insert(delegate, encryption);
+        if nonce == *DEFAULT_NONCE {
+            return Ok(());
+        }
+        let encryption = Encryption::new(cipher_key, nonce);
+        let mut staged = vec![];
+        if let Some(current) = self.ciphers.get(&delegate) {
+            if current.key == encryption.key && current.nonce == encryption.nonce {
+                return Ok(());
+            }
+            // the delegate was registered again with new encryption material,
+            // existing secrets must be encrypted with the new one
+            staged = self.stage_reencryption(&delegate, current, &encryption)?;
+        }
+        // persisting the new cipher commits the re-encryption, from there on
+        // the staged secrets are the valid ones even if the process stops
+        if let Err(err) = self.persist_cipher(&delegate, &encryption) {
+            discard_staged(staged);
+            return Err(err);
         }
+        self.ciphers.insert(delegate.clone(), encryption);
+        if !staged.is_empty() {
+            for (staged_path, path) in staged {
+                fs::rename(staged_path, path)?;
+            }
+            sync_dir(&self.base_path.join(delegate.encode()))?;
+        }
+        Ok(())
+    }
+
+    /// Writes every secret of the delegate encrypted with the new material next to the current
+    /// one, returning the staged and final paths. Nothing is staged if any of them fails.
+    fn stage_reencryption(
+        &self,
+        delegate: &DelegateKey,
+        current: &Encryption,
+        new: &Encryption,
+    ) -> Result<Vec<(PathBuf, PathBuf)>, SecretStoreError> {
+        let delegate_path = self.base_path.join(delegate.encode());
+        if !delegate_path.exists() {
+            return Ok(vec![]);
+        }
+        let mut staged = vec![];
+        if let Err(err) = Self::write_reencrypted(&delegate_path, current, new, &mut staged) {
+            discard_staged(staged);
+            return Err(err);
+        }
+        Ok(staged)
+    }
+
+    fn write_reencrypted(
+        delegate_path: &Path,
+        current: &Encryption,
+        new: &Encryption,
+        staged: &mut Vec<(PathBuf, PathBuf)>,
+    ) -> Result<(), SecretStoreError> {
+        for entry in fs::read_dir(delegate_path)? {
+            let path = entry?.path();
+            if path.extension().is_some() {
+                continue;
+            }
+            let plaintext = current.decrypt(&fs::read(&path)?)?;
+            let staged_path = path.with_extension(REENCRYPTED_EXT);
+            staged.push((staged_path.clone(), path));
+            write_synced(&staged_path, &new.encrypt(&plaintext)?)?;
+        }
+        sync_dir(delegate_path)?;
+        Ok(())
+    }
+
+    /// Replaces the master key, re-encrypting all the persisted delegate ciphers with the new key.
+    ///
+    /// All the ciphers are first staged wrapped with the new key; once they are persisted the
+    /// rotation is committed and they replace the current ones. If the process stops halfway,
+    /// reopening the store either finishes or discards the rotation, so the ciphers are never
+    /// left wrapped with different master keys.
+    pub fn rotate_master_key(&mut self, master_key: MasterKey) -> Result<(), SecretStoreError> {
+        let staged = self.stage_rotation(&master_key)?;
+        let marker = self.base_path.join(ROTATION_MARKER);
+        write_synced(&marker, &[])?;
+        sync_dir(&self.base_path)?;
+        self.master_key = master_key;
+        // from here on the rotation is committed, if any rename fails
+        // it will be finished next time the store is opened
+        for (staged_path, path) in staged {
+            fs::rename(staged_path, path)?;
+        }
+        sync_dir(&self.ciphers_path)?;
+        fs::remove_file(marker)?;
+        Ok(())
+    }
+
+    /// Writes all the ciphers wrapped with the given master key next to the current ones,
+    /// returning the staged and final paths. Nothing is staged if any of them fails.
+    fn stage_rotation(
+        &self,
+        master_key: &MasterKey,
+    ) -> Result<Vec<(PathBuf, PathBuf)>, SecretStoreError> {
+        let mut staged = vec![];
+        if let Err(err) = self.write_rotated(master_key, &mut staged) {
+            tracing::error!("failed rotating master key: {err}");
+            discard_staged(staged);
+            return Err(err);
+        }
+        Ok(staged)
+    }
+
+    fn write_rotated(
+        &self,
+        master_key: &MasterKey,
+        staged: &mut Vec<(PathBuf, PathBuf)>,
+    ) -> Result<(), SecretStoreError> {
+        for (delegate, encryption) in &self.ciphers {
+            let cipher_path = self.ciphers_path.join(delegate.encode());
+            let staged_path = cipher_path.with_extension(ROTATING_EXT);
+            staged.push((staged_path.clone(), cipher_path));
+            write_synced(
+                &staged_path,
+                &Self::wrap_cipher(delegate, encryption, master_key)?,
+            )?;
+        }
+        sync_dir(&self.ciphers_path)?;
         Ok(())
     }
 
@@ -157,46 +564,36 @@ impl SecretsStore {
         let secret_key = *key.hash();
         let encryption = self.ciphers.get(delegate).unwrap_or(&*DEFAULT_ENCRYPTION);
 
-        let ciphertext = encryption
-            .cipher
-            .encrypt(&encryption.nonce, plaintext.as_ref())
-            .map_err(|err| {
-                if encryption.nonce == *DEFAULT_NONCE {
-                    SecretStoreError::MissingCipher
-                } else {
-                    SecretStoreError::Encryption(err)
-                }
-            })?;
+        let ciphertext = encryption.encrypt(&plaintext)?;
 
-        // Update index
-        let hashes = self.key_to_secret_part.entry(delegate.clone());
-        match hashes {
+        // Update index, the lock is taken before the entry guard and released after it: a
+        // compaction reloads the container while holding the lock, so it can't run meanwhile
+        let lock = IndexLock::acquire(&self.key_file)?;
+        match self.key_to_secret_part.entry(delegate.clone()) {
             dashmap::mapref::entry::Entry::Occupied(mut v) => {
-                let current_version_offset = v.get().0;
-                let secret_hashes = &mut v.get_mut().1;
-                let mut value = vec![];
-                for hash in &*secret_hashes {
-                    value.extend_from_slice(hash);
+                let (current_version_offset, secret_hashes) = v.get_mut();
+                if secret_hashes.insert(secret_key) {
+                    // first mark the old entry as removed
+                    Self::remove(&self.key_file, &lock, *current_version_offset)?;
+                    *current_version_offset = Self::insert(
+                        &mut self.index_file,
+                        &lock,
+                        delegate.clone(),
+                        &ConcatenatedSecretKeys::from_hashes(secret_hashes),
+                    )?;
                 }
-                // first mark the old entry (if it exists) as removed
-                Self::remove(&self.key_file, current_version_offset)?;
-                let new_offset = Self::insert(
-                    &mut self.index_file,
-                    delegate.clone(),
-                    &ConcatenatedSecretKeys(value),
-                )?;
-                secret_hashes.insert(secret_key);
-                v.get_mut().0 = new_offset;
             }
             dashmap::mapref::entry::Entry::Vacant(v) => {
                 let offset = Self::insert(
                     &mut self.index_file,
+                    &lock,
                     delegate.clone(),
                     &ConcatenatedSecretKeys(secret_key.to_vec()),
                 )?;
                 v.insert((offset, HashSet::from([secret_key])));
             }
         }
+        drop(lock);
 
         fs::create_dir_all(&delegate_path)?;
         tracing::debug!("storing secret `{key}` at {secret_file_path:?}");
@@ -210,6 +607,28 @@ impl SecretsStore {
         delegate: &DelegateKey,
         key: &SecretsId,
     ) -> Result<(), SecretStoreError> {
+        // Update index, see `store_secret` for the lock ordering
+        let lock = IndexLock::acquire(&self.key_file)?;
+        if let dashmap::mapref::entry::Entry::Occupied(mut v) =
+            self.key_to_secret_part.entry(delegate.clone())
+        {
+            let (current_version_offset, secret_hashes) = v.get_mut();
+            if secret_hashes.remove(key.hash()) {
+                Self::remove(&self.key_file, &lock, *current_version_offset)?;
+                if secret_hashes.is_empty() {
+                    v.remove();
+                } else {
+                    *current_version_offset = Self::insert(
+                        &mut self.index_file,
+                        &lock,
+                        delegate.clone(),
+                        &ConcatenatedSecretKeys::from_hashes(secret_hashes),
+                    )?;
+                }
+            }
+        }
+        drop(lock);
+
         let secret_path = self.base_path.join(delegate.encode()).join(key.encode());
         match fs::remove_file(secret_path) {
             Ok(_) => Ok(()),
@@ -218,6 +637,41 @@ impl SecretsStore {
         }
     }
 
+    /// Live vs removed records in the key index file.
+    pub fn index_stats(&self) -> RuntimeResult<IndexStats> {
+        Ok(IndexStats::from_file(&self.key_file)?)
+    }
+
+    /// Returns the hashes of the ids of all the secrets stored for the given delegate.
+    pub fn list_secrets(&self, delegate: &DelegateKey) -> Vec<[u8; 32]> {
+        self.key_to_secret_part
+            .get(delegate)
+            .map(|entry| entry.1.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Erases every secret stored for the given delegate, alongside its encryption material.
+    pub fn remove_all_for_delegate(
+        &mut self,
+        delegate: &DelegateKey,
+    ) -> Result<(), SecretStoreError> {
+        let lock = IndexLock::acquire(&self.key_file)?;
+        if let Some((_, (offset, _))) = self.key_to_secret_part.remove(delegate) {
+            Self::remove(&self.key_file, &lock, offset)?;
+        }
+        drop(lock);
+        let delegate_path = self.base_path.join(delegate.encode());
+        match fs::remove_dir_all(delegate_path) {
+            Ok(_) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+        if self.ciphers.remove(delegate).is_some() {
+            fs::remove_file(self.ciphers_path.join(delegate.encode()))?;
+        }
+        Ok(())
+    }
+
     pub fn get_secret(
         &self,
         delegate: &DelegateKey,
@@ -228,17 +682,7 @@ impl SecretsStore {
 
         let ciphertext =
             fs::read(secret_path).map_err(|_| SecretStoreError::MissingSecret(key.clone()))?;
-        let plaintext = encryption
-            .cipher
-            .decrypt(&encryption.nonce, ciphertext.as_ref())
-            .map_err(|err| {
-                if encryption.nonce == *DEFAULT_NONCE {
-                    SecretStoreError::MissingCipher
-                } else {
-                    SecretStoreError::Encryption(err)
-                }
-            })?;
-        Ok(plaintext)
+        encryption.decrypt(&ciphertext)
     }
 }
 
@@ -254,20 +698,181 @@ mod test {
             .join("secrets-store-test");
         std::fs::create_dir_all(&secrets_dir)?;
 
-        let mut store = SecretsStore::new(secrets_dir)?;
+        let mut store = SecretsStore::new(secrets_dir, MasterKey::from_passphrase(b"test"))?;
 
         let delegate = Delegate::from((&vec![0, 1, 2].into(), &vec![].into()));
 
-        let cipher = XChaCha20Poly1305::new(&XChaCha20Poly1305::generate_key(&mut OsRng));
+        let key = XChaCha20Poly1305::generate_key(&mut OsRng);
         let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
         let secret_id = SecretsId::new(vec![0, 1, 2]);
         let text = vec![0, 1, 2];
 
-        store.register_delegate(delegate.key().clone(), cipher, nonce)?;
+        store.register_delegate(delegate.key().clone(), key, nonce)?;
         store.store_secret(delegate.key(), &secret_id, text)?;
         let f = store.get_secret(delegate.key(), &secret_id);
 
         assert!(f.is_ok());
         Ok(())
     }
+
+    #[test]
+    fn ciphers_survive_restart_and_rotation() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = crate::util::tests::get_temp_dir();
+        let secrets_dir = temp_dir.path().join("secrets");
+        let delegate = Delegate::from((&vec![3, 4, 5].into(), &vec![].into()));
+        let secret_id = SecretsId::new(vec![0, 1, 2]);
+        let text = vec![0, 1, 2];
+
+        {
+            let mut store =
+                SecretsStore::new(secrets_dir.clone(), MasterKey::from_passphrase(b"first"))?;
+            let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+            let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+            store.register_delegate(delegate.key().clone(), key, nonce)?;
+            store.store_secret(delegate.key(), &secret_id, text.clone())?;
+            store.rotate_master_key(MasterKey::from_passphrase(b"second"))?;
+        }
+
+        // reloading with the rotated master key restores the delegate cipher
+        let mut store =
+            SecretsStore::new(secrets_dir.clone(), MasterKey::from_passphrase(b"second"))?;
+        assert_eq!(store.get_secret(delegate.key(), &secret_id)?, text);
+
+        // registering new encryption material re-encrypts the existing secrets
+        let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        store.register_delegate(delegate.key().clone(), key, nonce)?;
+        assert_eq!(store.get_secret(delegate.key(), &secret_id)?, text);
+        drop(store);
+
+        // with the old master key the cipher can't be recovered
+        let store = SecretsStore::new(secrets_dir, MasterKey::from_passphrase(b"first"))?;
+        assert!(matches!(
+            store.get_secret(delegate.key(), &secret_id),
+            Err(SecretStoreError::MissingCipher)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn interrupted_rotation_is_finished_or_discarded() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = crate::util::tests::get_temp_dir();
+        let secrets_dir = temp_dir.path().join("secrets");
+        let delegate = Delegate::from((&vec![9, 10, 11].into(), &vec![].into()));
+        let secret_id = SecretsId::new(vec![0, 1, 2]);
+        let text = vec![0, 1, 2];
+        let first = MasterKey::from_passphrase(b"first");
+        let second = MasterKey::from_passphrase(b"second");
+        let staged_files = |dir: &Path| -> std::io::Result<usize> {
+            Ok(fs::read_dir(dir)?
+                .filter(|e| {
+                    e.as_ref()
+                        .map_or(false, |e| has_extension(&e.path(), ROTATING_EXT))
+                })
+                .count())
+        };
+
+        {
+            let mut store = SecretsStore::new(secrets_dir.clone(), first.clone())?;
+            let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+            let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+            store.register_delegate(delegate.key().clone(), key, nonce)?;
+            store.store_secret(delegate.key(), &secret_id, text.clone())?;
+            // stopped before the rotation was committed
+            store.stage_rotation(&second)?;
+        }
+        let store = SecretsStore::new(secrets_dir.clone(), first.clone())?;
+        assert_eq!(store.get_secret(delegate.key(), &secret_id)?, text);
+        assert_eq!(staged_files(&secrets_dir.join("ciphers"))?, 0);
+
+        // stopped after the rotation was committed but before replacing the ciphers
+        store.stage_rotation(&second)?;
+        write_synced(&secrets_dir.join(ROTATION_MARKER), &[])?;
+        drop(store);
+        let store = SecretsStore::new(secrets_dir.clone(), second)?;
+        assert_eq!(store.get_secret(delegate.key(), &secret_id)?, text);
+        assert_eq!(staged_files(&secrets_dir.join("ciphers"))?, 0);
+        assert!(!secrets_dir.join(ROTATION_MARKER).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn interrupted_reencryption_is_recovered() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = crate::util::tests::get_temp_dir();
+        let secrets_dir = temp_dir.path().join("secrets");
+        let delegate = Delegate::from((&vec![12, 13, 14].into(), &vec![].into()));
+        let secret_id = SecretsId::new(vec![0, 1, 2]);
+        let text = vec![0, 1, 2];
+        let master_key = MasterKey::from_passphrase(b"test");
+        let new_encryption = || {
+            Encryption::new(
+                XChaCha20Poly1305::generate_key(&mut OsRng),
+                XChaCha20Poly1305::generate_nonce(&mut OsRng),
+            )
+        };
+
+        let mut store = SecretsStore::new(secrets_dir.clone(), master_key.clone())?;
+        let current = new_encryption();
+        store.register_delegate(delegate.key().clone(), current.key, current.nonce)?;
+        store.store_secret(delegate.key(), &secret_id, text.clone())?;
+
+        // stopped before the new cipher was persisted, the staged secrets are discarded
+        let staged = store.stage_reencryption(delegate.key(), &current, &new_encryption())?;
+        assert_eq!(staged.len(), 1);
+        drop(store);
+        let store = SecretsStore::new(secrets_dir.clone(), master_key.clone())?;
+        assert_eq!(store.get_secret(delegate.key(), &secret_id)?, text);
+        assert!(!staged[0].0.exists());
+
+        // stopped after the new cipher was persisted, the staged secrets replace the old ones
+        let new = new_encryption();
+        let staged = store.stage_reencryption(delegate.key(), &current, &new)?;
+        store.persist_cipher(delegate.key(), &new)?;
+        drop(store);
+        let store = SecretsStore::new(secrets_dir, master_key)?;
+        assert_eq!(store.get_secret(delegate.key(), &secret_id)?, text);
+        assert!(!staged[0].0.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn remove_and_list() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = crate::util::tests::get_temp_dir();
+        let secrets_dir = temp_dir.path().join("secrets");
+        let mut store =
+            SecretsStore::new(secrets_dir.clone(), MasterKey::from_passphrase(b"test"))?;
+
+        let delegate = Delegate::from((&vec![6, 7, 8].into(), &vec![].into()));
+        let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        store.register_delegate(delegate.key().clone(), key, nonce)?;
+
+        let secret_1 = SecretsId::new(vec![1]);
+        let secret_2 = SecretsId::new(vec![2]);
+        store.store_secret(delegate.key(), &secret_1, vec![1])?;
+        store.store_secret(delegate.key(), &secret_2, vec![2])?;
+        let mut listed = store.list_secrets(delegate.key());
+        listed.sort();
+        let mut expected = vec![*secret_1.hash(), *secret_2.hash()];
+        expected.sort();
+        assert_eq!(listed, expected);
+
+        store.remove_secret(delegate.key(), &secret_1)?;
+        assert_eq!(store.list_secrets(delegate.key()), vec![*secret_2.hash()]);
+        assert!(store.get_secret(delegate.key(), &secret_1).is_err());
+
+        // the removal is persisted in the index
+        let mut key_to_secret_part = Arc::new(DashMap::new());
+        SecretsStore::load_from_file(&secrets_dir.join("KEY_DATA"), &mut key_to_secret_part)?;
+        assert_eq!(
+            key_to_secret_part.get(delegate.key()).unwrap().1,
+            HashSet::from([*secret_2.hash()])
+        );
+
+        store.remove_all_for_delegate(delegate.key())?;
+        assert!(store.list_secrets(delegate.key()).is_empty());
+        assert!(store.get_secret(delegate.key(), &secret_2).is_err());
+        assert!(!secrets_dir.join(delegate.key().encode()).exists());
+        Ok(())
+    }
 }
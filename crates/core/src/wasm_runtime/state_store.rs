@@ -1,3 +1,5 @@
+use std::time::SystemTime;
+
 use freenet_stdlib::prelude::*;
 use stretto::AsyncCache;
 
@@ -9,13 +11,27 @@ pub enum StateStoreError {
     Any(#[from] DynError),
     #[error("missing contract: {0}")]
     MissingContract(ContractKey),
+    #[error("missing version {version} of contract: {key}")]
+    MissingVersion { key: ContractKey, version: u64 },
+}
+
+/// A past state of a contract kept in its state history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateVersion {
+    /// Monotonically increasing version number, starting at 1.
+    pub version: u64,
+    /// When this state was stored.
+    pub timestamp: SystemTime,
+    /// Size in bytes of the state.
+    pub size: usize,
 }
 
 impl From<StateStoreError> for crate::wasm_runtime::ContractError {
     fn from(value: StateStoreError) -> Self {
         match value {
             StateStoreError::Any(err) => crate::wasm_runtime::ContractError::from(err),
-            err @ StateStoreError::MissingContract(_) => {
+            err
+            @ (StateStoreError::MissingContract(_) | StateStoreError::MissingVersion { .. }) => {
                 crate::wasm_runtime::ContractError::from(Into::<DynError>::into(format!("{err}")))
             }
         }
@@ -37,12 +53,29 @@ pub trait StateStorage {
         &'a self,
         key: &'a ContractKey,
     ) -> Result<Option<Parameters<'static>>, Self::Error>;
+    /// Appends a state to the history of the contract and returns its version number,
+    /// discarding the oldest versions so at most `max_versions` are kept.
+    async fn store_version(
+        &mut self,
+        key: ContractKey,
+        state: WrappedState,
+        max_versions: usize,
+    ) -> Result<u64, Self::Error>;
+    async fn get_version(
+        &self,
+        key: &ContractKey,
+        version: u64,
+    ) -> Result<Option<WrappedState>, Self::Error>;
+    /// Versions kept in the history of the contract, from oldest to newest.
+    async fn list_versions(&self, key: &ContractKey) -> Result<Vec<StateVersion>, Self::Error>;
 }
 
 pub struct StateStore<S: StateStorage> {
     state_mem_cache: AsyncCache<ContractKey, WrappedState>,
     // params_mem_cache: AsyncCache<ContractKey, Parameters<'static>>,
     store: S,
+    /// Number of past states kept per contract, history is disabled if not set.
+    max_versions: Option<usize>,
 }
 
 impl<S> StateStore<S>
@@ -62,9 +95,30 @@ where
             // params_mem_cache: AsyncCache::new(counters, max_size as i64)
             //     .map_err(|err| StateStoreError::Any(Box::new(err)))?,
             store,
+            max_versions: None,
         })
     }
 
+    /// Keep the last `max_versions` states of every contract, see [`Self::list_versions`].
+    pub fn with_history(mut self, max_versions: usize) -> Self {
+        self.max_versions = Some(max_versions).filter(|max| *max > 0);
+        self
+    }
+
+    async fn record_version(
+        &mut self,
+        key: &ContractKey,
+        state: &WrappedState,
+    ) -> Result<(), StateStoreError> {
+        if let Some(max_versions) = self.max_versions {
+            self.store
+                .store_version(key.clone(), state.clone(), max_versions)
+                .await
+                .map_err(Into::into)?;
+        }
+        Ok(())
+    }
+
     pub async fn update(
         &mut self,
         key: &ContractKey,
@@ -82,6 +136,7 @@ where
             .store(key.clone(), state.clone())
             .await
             .map_err(Into::into)?;
+        self.record_version(key, &state).await?;
         let cost = state.size() as i64;
         self.state_mem_cache.insert(key.clone(), state, cost).await;
         Ok(())
@@ -97,6 +152,7 @@ where
             .store(key.clone(), state.clone())
             .await
             .map_err(Into::into)?;
+        self.record_version(&key, &state).await?;
         let cost = state.size() as i64;
         self.state_mem_cache.insert(key.clone(), state, cost).await;
         self.store
@@ -126,4 +182,39 @@ where
         let r = self.store.get_params(key).await.map_err(Into::into)?;
         Ok(r)
     }
+
+    pub async fn get_version(
+        &self,
+        key: &ContractKey,
+        version: u64,
+    ) -> Result<WrappedState, StateStoreError> {
+        self.store
+            .get_version(key, version)
+            .await
+            .map_err(Into::into)?
+            .ok_or_else(|| StateStoreError::MissingVersion {
+                key: key.clone(),
+                version,
+            })
+    }
+
+    pub async fn list_versions(
+        &self,
+        key: &ContractKey,
+    ) -> Result<Vec<StateVersion>, StateStoreError> {
+        let versions = self.store.list_versions(key).await.map_err(Into::into)?;
+        Ok(versions)
+    }
+
+    /// Restores the state of a contract to a previous version, which is recorded as the newest
+    /// version of the history. The state is not validated against the contract.
+    pub async fn rollback(
+        &mut self,
+        key: &ContractKey,
+        version: u64,
+    ) -> Result<WrappedState, StateStoreError> {
+        let state = self.get_version(key, version).await?;
+        self.update(key, state.clone()).await?;
+        Ok(state)
+    }
 }
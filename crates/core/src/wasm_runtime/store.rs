@@ -1,48 +1,88 @@
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use crossbeam::channel::RecvTimeoutError;
+use dashmap::DashMap;
 use either::Either;
 use freenet_stdlib::prelude::{CodeHash, ContractInstanceId, DelegateKey};
 use notify::Watcher;
 use std::fs::{self, OpenOptions};
 use std::io::{self, BufReader, BufWriter, Seek, Write};
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
 use std::{fs::File, io::Read};
 
 use crate::DynError;
 
 const INTERNAL_KEY: usize = 32;
 const TOMBSTONE_MARKER: usize = 1;
+/// Ratio of removed records in an index file over which it gets compacted.
+const COMPACTION_THRESHOLD: f64 = 0.5;
+const COMPACTION_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// A lock file whose holder can't be determined is considered a leftover of an interrupted
+/// compaction once older than this.
+const STALE_LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Keeps the background maintenance of an index file running, it is stopped once dropped along
+/// with the store owning it.
+pub(super) struct WatchGuard {
+    _stop: crossbeam::channel::Sender<()>,
+}
 
 pub(super) struct SafeWriter<S> {
     file: BufWriter<File>,
-    lock_file_path: PathBuf,
+    path: PathBuf,
     compact: bool,
     _marker: std::marker::PhantomData<fn(S) -> S>,
 }
 
 impl<S: StoreFsManagement> SafeWriter<S> {
     pub fn new(path: &Path, compact: bool) -> Result<Self, io::Error> {
-        let file = if compact {
-            OpenOptions::new().create(true).write(true).open(path)?
-        } else {
-            OpenOptions::new()
-                .create(true)
-                .read(true)
-                .append(true)
-                .open(path)?
-        };
+        if !compact {
+            recover_interrupted_compaction(path, STALE_LOCK_TIMEOUT)?;
+        }
         let s = Self {
-            file: BufWriter::new(file),
+            file: BufWriter::new(Self::open(path, compact)?),
+            path: path.to_path_buf(),
             compact,
-            lock_file_path: path.with_extension("lock"),
             _marker: std::marker::PhantomData,
         };
         Ok(s)
     }
 
+    fn open(path: &Path, compact: bool) -> io::Result<File> {
+        if compact {
+            // a leftover from an interrupted compaction could be longer than the new contents
+            OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(path)
+        } else {
+            OpenOptions::new()
+                .create(true)
+                .read(true)
+                .append(true)
+                .open(path)
+        }
+    }
+
+    /// Reopens the file in case it was swapped by a compaction since it was opened,
+    /// otherwise records would be appended to the old, unlinked, file.
+    fn reopen_if_swapped(&mut self) -> io::Result<()> {
+        if self.compact {
+            return Ok(());
+        }
+        let current = self.file.get_ref().metadata()?;
+        let at_path = fs::metadata(&self.path)?;
+        if !is_same_file(&current, &at_path) {
+            self.file = BufWriter::new(Self::open(&self.path, false)?);
+        }
+        Ok(())
+    }
+
     /// Inserts a new record and returns the offset
     fn insert_record(&mut self, key: StoreKey, value: &[u8]) -> std::io::Result<u64> {
-        self.check_lock();
+        self.reopen_if_swapped()?;
         // The full key is the tombstone marker byte + kind + [internal key content]  + size of value
         self.file.write_u8(false as u8)?;
         let mut traversed = 1;
@@ -69,23 +109,170 @@ impl<S: StoreFsManagement> SafeWriter<S> {
         Ok(current_offset - traversed as u64)
     }
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        self.check_lock();
-        self.file.flush()
+    /// Flushes and makes sure the contents have reached the disk.
+    fn sync(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_all()
+    }
+}
+
+/// Whether both metadata belong to the same file, as opposed to one swapped in its place.
+fn is_same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        a.dev() == b.dev() && a.ino() == b.ino()
+    }
+    #[cfg(not(unix))]
+    {
+        // can't tell, a spurious reopen is harmless
+        let _ = (a, b);
+        false
     }
 }
 
-impl<S> SafeWriter<S> {
-    fn check_lock(&self) {
-        while !self.compact && self.lock_file_path.exists() {
+/// Exclusive access to an index file, shared by the writers and the compaction (also across
+/// processes) through a lock file holding the pid of its owner.
+///
+/// Offsets read from the in-memory containers are only valid while holding the lock, since
+/// a compaction rewrites them, so it must be acquired before reading them.
+pub(super) struct IndexLock {
+    lock_file_path: PathBuf,
+}
+
+impl IndexLock {
+    /// Blocks until the lock over the index file is acquired.
+    pub fn acquire(key_file_path: &Path) -> io::Result<Self> {
+        loop {
+            if let Some(lock) = Self::try_acquire(key_file_path)? {
+                return Ok(lock);
+            }
             std::thread::sleep(Duration::from_millis(10));
         }
     }
+
+    fn try_acquire(key_file_path: &Path) -> io::Result<Option<Self>> {
+        let lock_file_path = key_file_path.with_extension("lock");
+        match OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(&lock_file_path)
+        {
+            Ok(mut file) => {
+                let lock = Self { lock_file_path };
+                file.write_all(std::process::id().to_string().as_bytes())?;
+                Ok(Some(lock))
+            }
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                recover_interrupted_compaction(key_file_path, STALE_LOCK_TIMEOUT)?;
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
 }
 
-impl<S> Drop for SafeWriter<S> {
+impl Drop for IndexLock {
     fn drop(&mut self) {
-        self.check_lock();
+        if let Err(err) = fs::remove_file(&self.lock_file_path) {
+            tracing::error!("failed removing lock file {:?}: {err}", self.lock_file_path);
+        }
+    }
+}
+
+/// Whether the lock was left behind by a process which is not running anymore.
+///
+/// If the holder can't be determined (the lock is being created or there is no way to check
+/// the process), falls back to considering it stale once older than `stale_after`.
+fn is_stale_lock(lock_file_path: &Path, stale_after: Duration) -> bool {
+    let holder = fs::read_to_string(lock_file_path)
+        .ok()
+        .and_then(|pid| pid.trim().parse::<u32>().ok());
+    if let Some(alive) = holder.and_then(is_process_alive) {
+        return !alive;
+    }
+    fs::metadata(lock_file_path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|elapsed| elapsed >= stale_after)
+}
+
+fn is_process_alive(pid: u32) -> Option<bool> {
+    if pid == std::process::id() {
+        return Some(true);
+    }
+    if cfg!(target_os = "linux") {
+        Some(Path::new("/proc").join(pid.to_string()).exists())
+    } else {
+        None
+    }
+}
+
+/// Cleans up the leftovers of a compaction which did not finish (e.g. the process crashed).
+///
+/// The compacted file only replaces the original one through an atomic rename once complete,
+/// so the index file itself is always either the original or the fully compacted one.
+fn recover_interrupted_compaction(key_file_path: &Path, stale_after: Duration) -> io::Result<()> {
+    let lock_file_path = key_file_path.with_extension("lock");
+    if !lock_file_path.exists() || !is_stale_lock(&lock_file_path, stale_after) {
+        return Ok(());
+    }
+    tracing::warn!("found stale lock for {key_file_path:?}, cleaning interrupted compaction");
+    match fs::remove_file(key_file_path.with_extension("tmp")) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    match fs::remove_file(lock_file_path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Live vs removed (tombstoned) records in an index file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct IndexStats {
+    pub live_records: u64,
+    pub dead_records: u64,
+    pub live_bytes: u64,
+    pub dead_bytes: u64,
+}
+
+impl IndexStats {
+    /// Ratio of removed records over all the records in the index.
+    pub fn tombstone_ratio(&self) -> f64 {
+        let total = self.live_records + self.dead_records;
+        if total == 0 {
+            return 0.0;
+        }
+        self.dead_records as f64 / total as f64
+    }
+
+    fn needs_compaction(&self) -> bool {
+        self.dead_records > 0 && self.tombstone_ratio() >= COMPACTION_THRESHOLD
+    }
+
+    pub(super) fn from_file(key_file_path: &Path) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(key_file_path)?);
+        let mut stats = IndexStats::default();
+        let mut prev_pos = 0;
+        loop {
+            let record = match process_record(&mut reader) {
+                Ok(record) => record,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err),
+            };
+            let pos = reader.stream_position()?;
+            if record.is_some() {
+                stats.live_records += 1;
+                stats.live_bytes += pos - prev_pos;
+            } else {
+                stats.dead_records += 1;
+                stats.dead_bytes += pos - prev_pos;
+            }
+            prev_pos = pos;
+        }
+        Ok(stats)
     }
 }
 
@@ -138,7 +325,7 @@ enum KeyType {
 }
 
 pub(super) trait StoreFsManagement: Sized {
-    type MemContainer: Send + Sync + 'static;
+    type MemContainer: Clone + Send + Sync + 'static;
     type Key: Clone + From<StoreKey>;
     type Value: AsRef<[u8]> + for<'x> TryFrom<&'x [u8], Error = std::io::Error>;
 
@@ -148,18 +335,21 @@ pub(super) trait StoreFsManagement: Sized {
         value: Self::Value,
     );
 
+    /// Watches the index file, reloading the container on changes and compacting it
+    /// periodically until the returned guard is dropped.
     fn watch_changes(
-        mut container: Self::MemContainer,
+        container: Self::MemContainer,
         key_file_path: &Path,
-    ) -> Result<(), DynError> {
+    ) -> Result<WatchGuard, DynError> {
         let key_path = key_file_path.to_path_buf();
         let key_path_cp = key_path.clone();
+        let mut watched_container = container.clone();
         let mut watcher = notify::recommended_watcher(
             move |res: Result<notify::Event, notify::Error>| match res {
                 Ok(ev) => {
                     if let notify::EventKind::Modify(notify::event::ModifyKind::Data(_)) = ev.kind {
                         if let Err(err) =
-                            Self::load_from_file(key_path_cp.as_path(), &mut container)
+                            Self::load_from_file(key_path_cp.as_path(), &mut watched_container)
                         {
                             tracing::error!("{err}")
                         }
@@ -168,19 +358,33 @@ pub(super) trait StoreFsManagement: Sized {
                 Err(err) => tracing::error!("{err}"),
             },
         )?;
+        let (stop, stopped) = crossbeam::channel::bounded::<()>(0);
         std::thread::spawn(move || loop {
-            std::thread::sleep(Duration::from_secs(5 * 60));
-            if let Err(err) = compact_index_file::<Self>(&key_path) {
-                tracing::warn!("Failed index file ({key_path:?}) compaction: {err}");
+            // nothing is ever sent, so this only returns early once the guard is dropped
+            if let Err(RecvTimeoutError::Disconnected) =
+                stopped.recv_timeout(COMPACTION_CHECK_INTERVAL)
+            {
+                break;
+            }
+            match IndexStats::from_file(&key_path) {
+                Ok(stats) if stats.needs_compaction() => {
+                    tracing::debug!(?stats, "compacting index file {key_path:?}");
+                    if let Err(err) = compact_index_file::<Self>(&key_path, &mut container) {
+                        tracing::warn!("Failed index file ({key_path:?}) compaction: {err}");
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => tracing::warn!("Failed reading index file ({key_path:?}): {err}"),
             }
         });
         watcher.watch(key_file_path, notify::RecursiveMode::NonRecursive)?;
-        Ok(())
+        Ok(WatchGuard { _stop: stop })
     }
 
     /// Insert in index file and returns the offset at which this record resides.
     fn insert(
         file: &mut SafeWriter<Self>,
+        _lock: &IndexLock,
         key: Self::Key,
         value: &Self::Value,
     ) -> std::io::Result<u64>
@@ -193,7 +397,9 @@ pub(super) trait StoreFsManagement: Sized {
         Ok(offset)
     }
 
-    fn remove(key_file_path: &Path, key_offset: u64) -> std::io::Result<()> {
+    /// Marks the record at the given offset as removed, the offset must have been read while
+    /// holding the lock.
+    fn remove(key_file_path: &Path, _lock: &IndexLock, key_offset: u64) -> std::io::Result<()> {
         let mut file = std::fs::OpenOptions::new()
             .write(true)
             .read(true)
@@ -204,6 +410,15 @@ pub(super) trait StoreFsManagement: Sized {
         Ok(())
     }
 
+    /// Compacts the index file, dropping removed records, and reloads the offsets of the live
+    /// records into the container since they change after the compaction.
+    fn compact_index(
+        key_file_path: &Path,
+        container: &mut Self::MemContainer,
+    ) -> std::io::Result<()> {
+        compact_index_file::<Self>(key_file_path, container)
+    }
+
     fn load_from_file(
         key_file_path: &Path,
         container: &mut Self::MemContainer,
@@ -225,6 +440,81 @@ pub(super) trait StoreFsManagement: Sized {
     }
 }
 
+/// Tracks the disk space used by the code files of a store, and when each one was last used,
+/// so the least recently used can be evicted once the store goes over its quota.
+#[derive(Default)]
+pub(super) struct DiskUsage {
+    quota: Option<u64>,
+    used: AtomicU64,
+    last_access: DashMap<CodeHash, (SystemTime, u64)>,
+}
+
+impl DiskUsage {
+    pub fn with_quota(mut self, quota: u64) -> Self {
+        self.quota = Some(quota);
+        self
+    }
+
+    /// Registers an existing code file, using its modification time as last access time.
+    pub fn load(&self, code_hash: CodeHash, path: &Path) {
+        let Ok(metadata) = fs::metadata(path) else {
+            return;
+        };
+        let last_access = metadata.modified().unwrap_or_else(|_| SystemTime::now());
+        self.insert(code_hash, last_access, metadata.len());
+    }
+
+    pub fn record(&self, code_hash: CodeHash, size: u64) {
+        self.insert(code_hash, SystemTime::now(), size);
+    }
+
+    fn insert(&self, code_hash: CodeHash, last_access: SystemTime, size: u64) {
+        if let Some((_, prev_size)) = self.last_access.insert(code_hash, (last_access, size)) {
+            self.used.fetch_sub(prev_size, Ordering::SeqCst);
+        }
+        self.used.fetch_add(size, Ordering::SeqCst);
+    }
+
+    pub fn touch(&self, code_hash: &CodeHash) {
+        if let Some(mut entry) = self.last_access.get_mut(code_hash) {
+            entry.0 = SystemTime::now();
+        }
+    }
+
+    pub fn forget(&self, code_hash: &CodeHash) {
+        if let Some((_, (_, size))) = self.last_access.remove(code_hash) {
+            self.used.fetch_sub(size, Ordering::SeqCst);
+        }
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    /// Bytes over the quota, if any.
+    pub fn excess(&self) -> Option<u64> {
+        let quota = self.quota?;
+        self.used().checked_sub(quota).filter(|excess| *excess > 0)
+    }
+
+    /// Code files ordered from least to most recently used, alongside their size.
+    pub fn least_recently_used(&self) -> Vec<(CodeHash, u64)> {
+        let mut entries: Vec<_> = self
+            .last_access
+            .iter()
+            .map(|entry| {
+                let (last_access, size) = *entry.value();
+                (last_access, *entry.key(), size)
+            })
+            .collect();
+        entries.sort_by_key(|(last_access, _, _)| *last_access);
+        entries
+            .into_iter()
+            .map(|(_, code_hash, size)| (code_hash, size))
+            .collect()
+    }
+}
+
 #[allow(clippy::type_complexity)]
 fn process_record<T>(
     reader: &mut BufReader<T>,
@@ -291,26 +581,16 @@ where
     }
 }
 
-fn compact_index_file<S: StoreFsManagement>(key_file_path: &Path) -> std::io::Result<()> {
-    // Define the path to the lock file
-    let lock_file_path = key_file_path.with_extension("lock");
-
-    // Attempt to create the lock file
-    let mut opts = fs::OpenOptions::new();
-    opts.create_new(true).write(true);
-    match opts.open(&lock_file_path) {
-        Ok(_) => {
-            // The lock file was created successfully, so a compaction can proceed
-        }
-        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
-            // The lock file already exists, so a compaction is in progress
-            return Ok(());
-        }
-        Err(e) => {
-            // An unexpected error occurred
-            return Err(e);
-        }
-    }
+/// Rewrites the live records of the index into a fresh file which atomically replaces the
+/// original one, reloading the new offsets into the container while still holding the lock.
+fn compact_index_file<S: StoreFsManagement>(
+    key_file_path: &Path,
+    container: &mut S::MemContainer,
+) -> std::io::Result<()> {
+    // A compaction or a writer already holds the lock, it will be compacted next time
+    let Some(lock) = IndexLock::try_acquire(key_file_path)? else {
+        return Ok(());
+    };
 
     let original_file = OpenOptions::new()
         .truncate(false)
@@ -322,12 +602,7 @@ fn compact_index_file<S: StoreFsManagement>(key_file_path: &Path) -> std::io::Re
 
     // Read the original file and compact data into the temp file
     let mut original_reader = BufReader::new(original_file);
-    let mut temp_writer = SafeWriter::<S>::new(&temp_file_path, true).map_err(|e| {
-        if let Err(e) = fs::remove_file(&lock_file_path) {
-            eprintln!("{}:{}: Failed to remove lock file: {e}", file!(), line!());
-        }
-        e
-    })?;
+    let mut temp_writer = SafeWriter::<S>::new(&temp_file_path, true)?;
 
     let mut any_deleted = false; // Track if any deleted records were found
 
@@ -338,12 +613,7 @@ fn compact_index_file<S: StoreFsManagement>(key_file_path: &Path) -> std::io::Re
                     Either::Left(v) => v.as_slice(),
                     Either::Right(v) => v.as_slice(),
                 };
-                if let Err(err) = temp_writer.insert_record(store_key, value) {
-                    if let Err(e) = fs::remove_file(&lock_file_path) {
-                        eprintln!("{}:{}: Failed to remove lock file: {e}", file!(), line!());
-                    }
-                    return Err(err);
-                }
+                temp_writer.insert_record(store_key, value)?;
             }
             Ok(None) => {
                 // Skip record
@@ -353,47 +623,28 @@ fn compact_index_file<S: StoreFsManagement>(key_file_path: &Path) -> std::io::Re
                 // Done
                 break;
             }
-            Err(other) => {
-                // Handle other errors gracefully
-                if let Err(e) = fs::remove_file(&lock_file_path) {
-                    eprintln!("{}:{}: Failed to remove lock file: {e}", file!(), line!());
-                }
-                return Err(other);
-            }
+            Err(other) => return Err(other),
         }
     }
 
     // Check if any deleted records were found; if not, skip compaction
     if !any_deleted {
-        if let Err(e) = fs::remove_file(&lock_file_path) {
-            eprintln!("{}:{}: Failed to remove lock file: {e}", file!(), line!());
-        }
+        drop(temp_writer);
+        let _ = fs::remove_file(&temp_file_path);
         return Ok(());
     }
 
-    // Clean up and finalize the compaction process
-    if let Err(e) = temp_writer.flush() {
-        if let Err(e) = fs::remove_file(&lock_file_path) {
-            eprintln!("{}:{}: Failed to remove lock file: {e}", file!(), line!());
-        }
-        return Err(e);
-    }
+    // Finalize the compaction process, the contents must be on disk before the swap
+    temp_writer.sync()?;
 
     // Replace the original file with the temporary file
-    if let Err(e) = fs::rename(&temp_file_path, key_file_path) {
-        if let Err(e) = fs::remove_file(&lock_file_path) {
-            eprintln!("{}:{}: Failed to remove lock file: {e}", file!(), line!());
-        }
-        return Err(e);
-    }
+    fs::rename(&temp_file_path, key_file_path)?;
 
-    // Remove the lock file
-    fs::remove_file(&lock_file_path).map_err(|e| {
-        eprintln!("{}:{}: Failed to remove lock file: {e}", file!(), line!());
-        e
-    })?;
-
-    Ok(())
+    // Offsets changed, reload them before any other writer can make use of them,
+    // the lock is released once reloaded
+    let reloaded = S::load_from_file(key_file_path, container);
+    drop(lock);
+    reloaded
 }
 
 #[cfg(test)]
@@ -455,12 +706,16 @@ mod tests {
             let container_1 = <TestStore1 as StoreFsManagement>::MemContainer::default();
             let container_2 = <TestStore2 as StoreFsManagement>::MemContainer::default();
 
-            let offset = TestStore1::insert(&mut file_1, key_1, &expected_value_1)
+            let lock = IndexLock::acquire(&contract_keys_file_path).unwrap();
+            let offset = TestStore1::insert(&mut file_1, &lock, key_1, &expected_value_1)
                 .expect("Failed to update");
+            drop(lock);
             container_1.insert(key_1, (offset, expected_value_1));
 
-            let offset = TestStore2::insert(&mut file_2, key_2.clone(), &expected_value_2)
+            let lock = IndexLock::acquire(&delegate_keys_file_path).unwrap();
+            let offset = TestStore2::insert(&mut file_2, &lock, key_2.clone(), &expected_value_2)
                 .expect("Failed to update");
+            drop(lock);
             container_2.insert(key_2.clone(), (offset, expected_value_2));
         }
 
@@ -487,7 +742,9 @@ mod tests {
             let key_file_path = &contract_keys_file_path;
             let key_offset = 0;
 
-            TestStore1::remove(key_file_path, key_offset).expect("Failed to remove key");
+            let lock = IndexLock::acquire(key_file_path).unwrap();
+            TestStore1::remove(key_file_path, &lock, key_offset).expect("Failed to remove key");
+            drop(lock);
 
             // Reload the container from the key file and check if the key is removed
             let mut new_container_1 = <TestStore1 as StoreFsManagement>::MemContainer::default();
@@ -580,23 +837,26 @@ mod tests {
                 let key_file_path = key_file_path.clone();
                 let barrier = barrier.clone();
                 let shared_data = container.clone();
+                let mut compacted_data = container.clone();
                 let mut file = SafeWriter::new(&key_file_path, false).expect("failed");
                 std::thread::spawn(move || {
                     barrier.wait();
                     // concurrently creates/removes some data and compacts
                     if [10, 30].contains(&i) {
                         create_test_data(&mut file, &key_file_path, shared_data, i);
-                    } else if let Err(err) = super::compact_index_file::<TestStore1>(&key_file_path)
+                    } else if let Err(err) =
+                        super::compact_index_file::<TestStore1>(&key_file_path, &mut compacted_data)
                     {
                         eprintln!("Thread encountered an error during compaction: {err}");
                         return Err(err);
                     }
                     barrier.wait();
                     // compact a last time so we know what data to compare against
-                    super::compact_index_file::<TestStore1>(&key_file_path).map_err(|err| {
-                        eprintln!("Thread encountered an error during compaction: {err}");
-                        err
-                    })
+                    super::compact_index_file::<TestStore1>(&key_file_path, &mut compacted_data)
+                        .map_err(|err| {
+                            eprintln!("Thread encountered an error during compaction: {err}");
+                            err
+                        })
                 })
             })
             .collect();
@@ -647,13 +907,170 @@ mod tests {
         for j in 0..10 {
             let key = ContractInstanceId::new([thread + j as u8; 32]);
             let value = CodeHash::new([thread + j as u8; 32]);
-            let offset = TestStore1::insert(file, key, &value).expect("Failed to update");
+            let lock = IndexLock::acquire(test_path).unwrap();
+            let offset = TestStore1::insert(file, &lock, key, &value).expect("Failed to update");
             shared_data.insert(key, (offset, value));
         }
         for j in [3, 6, 9] {
             let key = ContractInstanceId::new([thread + j as u8; 32]);
+            // the offset must be read while holding the lock, a compaction could change it
+            let lock = IndexLock::acquire(test_path).unwrap();
             let key_offset = shared_data.remove(&key).unwrap().1 .0;
-            TestStore1::remove(test_path, key_offset).expect("Failed to remove key");
+            TestStore1::remove(test_path, &lock, key_offset).expect("Failed to remove key");
+        }
+    }
+
+    #[test]
+    fn compaction_stats() {
+        let temp_dir = get_temp_dir();
+        let key_file_path = temp_dir.path().join("data.dat");
+        let mut file = SafeWriter::new(&key_file_path, false).expect("failed");
+        let mut container = <TestStore1 as StoreFsManagement>::MemContainer::default();
+        create_test_data(&mut file, &key_file_path, container.clone(), 0);
+
+        let stats = IndexStats::from_file(&key_file_path).unwrap();
+        assert_eq!(stats.live_records, 7);
+        assert_eq!(stats.dead_records, 3);
+        let file_len = std::fs::metadata(&key_file_path).unwrap().len();
+        assert_eq!(stats.live_bytes + stats.dead_bytes, file_len);
+        assert!(!stats.needs_compaction());
+
+        for j in [0, 1, 2] {
+            let lock = IndexLock::acquire(&key_file_path).unwrap();
+            let offset = container
+                .remove(&ContractInstanceId::new([j; 32]))
+                .unwrap()
+                .1
+                 .0;
+            TestStore1::remove(&key_file_path, &lock, offset).unwrap();
+        }
+        let stats = IndexStats::from_file(&key_file_path).unwrap();
+        assert_eq!((stats.live_records, stats.dead_records), (4, 6));
+        assert!(stats.needs_compaction());
+
+        TestStore1::compact_index(&key_file_path, &mut container).unwrap();
+        let stats = IndexStats::from_file(&key_file_path).unwrap();
+        assert_eq!((stats.live_records, stats.dead_records), (4, 0));
+
+        // reloaded offsets and the writer are valid after the swap
+        let lock = IndexLock::acquire(&key_file_path).unwrap();
+        let offset = container
+            .remove(&ContractInstanceId::new([4; 32]))
+            .unwrap()
+            .1
+             .0;
+        TestStore1::remove(&key_file_path, &lock, offset).unwrap();
+        let key = ContractInstanceId::new([100; 32]);
+        TestStore1::insert(&mut file, &lock, key, &CodeHash::new([100; 32])).unwrap();
+        drop(lock);
+        let mut reloaded = <TestStore1 as StoreFsManagement>::MemContainer::default();
+        TestStore1::load_from_file(&key_file_path, &mut reloaded).unwrap();
+        assert!(!reloaded.contains_key(&ContractInstanceId::new([4; 32])));
+        assert!(reloaded.contains_key(&key));
+        assert_eq!(reloaded.len(), 4);
+    }
+
+    #[test]
+    fn recover_from_interrupted_compaction() {
+        let temp_dir = get_temp_dir();
+        let key_file_path = temp_dir.path().join("data.dat");
+        let lock_file_path = key_file_path.with_extension("lock");
+        let temp_file_path = key_file_path.with_extension("tmp");
+        {
+            let mut file = SafeWriter::new(&key_file_path, false).expect("failed");
+            let container = <TestStore1 as StoreFsManagement>::MemContainer::default();
+            create_test_data(&mut file, &key_file_path, container, 0);
         }
+
+        // simulate a crash in the middle of a compaction: the lock and a partially written
+        // compacted file are left behind, while the original index is untouched
+        std::fs::write(&lock_file_path, []).unwrap();
+        std::fs::write(&temp_file_path, vec![0xFF; 4096]).unwrap();
+        recover_interrupted_compaction(&key_file_path, Duration::from_secs(60)).unwrap();
+        assert!(lock_file_path.exists(), "a recent lock must be respected");
+        recover_interrupted_compaction(&key_file_path, Duration::ZERO).unwrap();
+        assert!(!lock_file_path.exists());
+        assert!(!temp_file_path.exists());
+
+        let mut container = <TestStore1 as StoreFsManagement>::MemContainer::default();
+        TestStore1::load_from_file(&key_file_path, &mut container).unwrap();
+        assert_eq!(container.len(), 7);
+
+        // a garbage leftover longer than the compacted contents doesn't corrupt the index
+        std::fs::write(&temp_file_path, vec![0xFF; 4096]).unwrap();
+        TestStore1::compact_index(&key_file_path, &mut container).unwrap();
+        let stats = IndexStats::from_file(&key_file_path).unwrap();
+        assert_eq!((stats.live_records, stats.dead_records), (7, 0));
+        let mut reloaded = <TestStore1 as StoreFsManagement>::MemContainer::default();
+        TestStore1::load_from_file(&key_file_path, &mut reloaded).unwrap();
+        assert_eq!(reloaded.len(), 7);
+    }
+
+    #[test]
+    fn stale_lock_is_detected_by_holder() {
+        let temp_dir = get_temp_dir();
+        let key_file_path = temp_dir.path().join("data.dat");
+        let lock_file_path = key_file_path.with_extension("lock");
+        std::fs::write(&key_file_path, []).unwrap();
+
+        // a lock held by a live process is respected regardless of its age
+        let lock = IndexLock::acquire(&key_file_path).unwrap();
+        recover_interrupted_compaction(&key_file_path, Duration::ZERO).unwrap();
+        assert!(lock_file_path.exists());
+        assert!(IndexLock::try_acquire(&key_file_path).unwrap().is_none());
+        drop(lock);
+        assert!(!lock_file_path.exists());
+
+        if cfg!(target_os = "linux") {
+            // a lock left by a process which is gone is recovered right away
+            let mut child = std::process::Command::new("true").spawn().unwrap();
+            let dead_pid = child.id();
+            child.wait().unwrap();
+            std::fs::write(&lock_file_path, dead_pid.to_string()).unwrap();
+            recover_interrupted_compaction(&key_file_path, Duration::from_secs(60)).unwrap();
+            assert!(!lock_file_path.exists());
+        }
+    }
+
+    #[test]
+    fn writer_follows_swapped_file_of_same_length() {
+        let temp_dir = get_temp_dir();
+        let key_file_path = temp_dir.path().join("data.dat");
+        let mut file = SafeWriter::<TestStore1>::new(&key_file_path, false).unwrap();
+        let key = ContractInstanceId::new([1; 32]);
+        let lock = IndexLock::acquire(&key_file_path).unwrap();
+        TestStore1::insert(&mut file, &lock, key, &CodeHash::new([1; 32])).unwrap();
+
+        // replace the file with a different one of the exact same length
+        let swapped_path = key_file_path.with_extension("tmp");
+        std::fs::copy(&key_file_path, &swapped_path).unwrap();
+        std::fs::rename(&swapped_path, &key_file_path).unwrap();
+
+        let other = ContractInstanceId::new([2; 32]);
+        TestStore1::insert(&mut file, &lock, other, &CodeHash::new([2; 32])).unwrap();
+        drop(lock);
+        let mut container = <TestStore1 as StoreFsManagement>::MemContainer::default();
+        TestStore1::load_from_file(&key_file_path, &mut container).unwrap();
+        assert!(container.contains_key(&key));
+        assert!(container.contains_key(&other));
+    }
+
+    #[test]
+    fn stops_watching_once_dropped() -> Result<(), DynError> {
+        let temp_dir = get_temp_dir();
+        let key_file_path = temp_dir.path().join("contract_keys");
+        File::create(&key_file_path)?;
+        let container = <TestStore1 as StoreFsManagement>::MemContainer::default();
+        let guard = TestStore1::watch_changes(container.clone(), &key_file_path)?;
+        assert!(Arc::strong_count(&container) > 1);
+
+        drop(guard);
+        // the maintenance thread exits right away, releasing its handle to the container
+        let started = std::time::Instant::now();
+        while Arc::strong_count(&container) > 1 {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,172 @@
+//! Dispatch over the versions of the contract and delegate container formats.
+//!
+//! Everything which depends on the version of a container goes through here: extracting the
+//! wasm code to compile from it (the module loader) and wrapping stored code back into a
+//! container of the same version. Versions the node doesn't know about are rejected with
+//! [`RuntimeInnerError::UnsupportedVersion`] instead of crashing it.
+//!
+//! To support a new version add it to [`ContractVersion`] (or [`DelegateVersion`]) and handle
+//! it in each match below. Code stored for previous versions is kept along with its version, so
+//! both keep running side by side.
+//!
+//! The interface exported by the code itself is versioned separately, see [`AbiVersion`]: every
+//! call from the runtime into a running instance goes through the [`Abi`] shim of the version of
+//! its code, so code built against different versions of the interface runs side by side too.
+use std::sync::Arc;
+
+use freenet_stdlib::prelude::*;
+use wasmer::{Function, Instance, Store, TypedFunction, Value};
+
+use super::{error::RuntimeInnerError, RuntimeResult};
+
+/// Versions of the contract container supported by the runtime.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum ContractVersion {
+    V1,
+}
+
+impl ContractVersion {
+    pub fn of(contract: &ContractContainer) -> Result<Self, RuntimeInnerError> {
+        match contract {
+            ContractContainer::Wasm(ContractWasmAPIVersion::V1(_)) => Ok(Self::V1),
+            _ => Err(RuntimeInnerError::UnsupportedVersion("contract")),
+        }
+    }
+
+    /// Returns the key and the code of the contract, if the version of the container is supported.
+    pub fn unwrap(
+        contract: &ContractContainer,
+    ) -> Result<(Self, &ContractKey, &Arc<ContractCode<'static>>), RuntimeInnerError> {
+        match contract {
+            ContractContainer::Wasm(ContractWasmAPIVersion::V1(contract)) => {
+                Ok((Self::V1, contract.key(), contract.code()))
+            }
+            _ => Err(RuntimeInnerError::UnsupportedVersion("contract")),
+        }
+    }
+
+    /// Wraps the code in a container of this version.
+    pub fn container(
+        self,
+        code: Arc<ContractCode<'static>>,
+        params: Parameters<'static>,
+    ) -> ContractContainer {
+        match self {
+            Self::V1 => ContractContainer::Wasm(ContractWasmAPIVersion::V1(WrappedContract::new(
+                code, params,
+            ))),
+        }
+    }
+}
+
+/// Versions of the delegate container supported by the runtime.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum DelegateVersion {
+    V1,
+}
+
+impl DelegateVersion {
+    pub fn of(delegate: &DelegateContainer) -> Result<Self, RuntimeInnerError> {
+        match delegate {
+            DelegateContainer::Wasm(DelegateWasmAPIVersion::V1(_)) => Ok(Self::V1),
+            _ => Err(RuntimeInnerError::UnsupportedVersion("delegate")),
+        }
+    }
+
+    /// Returns the delegate, if the version of the container is supported.
+    pub fn unwrap(delegate: DelegateContainer) -> Result<Delegate<'static>, RuntimeInnerError> {
+        match delegate {
+            DelegateContainer::Wasm(DelegateWasmAPIVersion::V1(delegate)) => Ok(delegate),
+            _ => Err(RuntimeInnerError::UnsupportedVersion("delegate")),
+        }
+    }
+}
+
+/// Versions of the interface between the runtime and the code of contracts and delegates.
+///
+/// The code declares the version it was built against with an exported `__frnt_abi_version`
+/// global, code which doesn't export it was built against the first version.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum AbiVersion {
+    V1,
+    /// Instances don't get an id since host functions only ever access the instance calling them,
+    /// so the code doesn't export `__frnt_set_id`.
+    V2,
+}
+
+impl AbiVersion {
+    const EXPORT: &'static str = "__frnt_abi_version";
+
+    pub fn of(store: &mut Store, instance: &Instance) -> Result<Self, RuntimeInnerError> {
+        let Ok(version) = instance.exports.get_global(Self::EXPORT) else {
+            return Ok(Self::V1);
+        };
+        match version.get(store) {
+            Value::I32(1) => Ok(Self::V1),
+            Value::I32(2) => Ok(Self::V2),
+            _ => Err(RuntimeInnerError::UnsupportedVersion("code interface")),
+        }
+    }
+
+    /// The shim adapting the calls of the runtime to this version of the interface.
+    pub fn abi(self) -> &'static dyn Abi {
+        match self {
+            Self::V1 => &AbiV1,
+            Self::V2 => &AbiV2,
+        }
+    }
+}
+
+/// Adapts the calls from the runtime into a running instance to the interface exported by its
+/// code.
+pub(super) trait Abi {
+    /// Prepares an instance, either fresh or taken from the pool, before it is called.
+    fn init(&self, store: &mut Store, instance: &Instance, id: i64) -> RuntimeResult<()>;
+
+    /// The function called for an entry point, like `validate_state` for contracts or
+    /// `process` for delegates. It takes the pointers to the buffers holding its arguments and
+    /// returns a pointer to its result.
+    fn entry_point(&self, instance: &Instance, name: &str) -> RuntimeResult<Function> {
+        Ok(instance.exports.get_function(name)?.clone())
+    }
+}
+
+struct AbiV1;
+
+impl Abi for AbiV1 {
+    fn init(&self, store: &mut Store, instance: &Instance, id: i64) -> RuntimeResult<()> {
+        let set_id: TypedFunction<i64, ()> = instance
+            .exports
+            .get_typed_function(store, "__frnt_set_id")?;
+        set_id.call(store, id)?;
+        Ok(())
+    }
+}
+
+struct AbiV2;
+
+impl Abi for AbiV2 {
+    fn init(&self, _: &mut Store, _: &Instance, _: i64) -> RuntimeResult<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn contract_round_trip() -> Result<(), RuntimeInnerError> {
+        let params = Parameters::from(vec![1]);
+        let contract = ContractContainer::Wasm(ContractWasmAPIVersion::V1(WrappedContract::new(
+            Arc::new(ContractCode::from(vec![0, 1, 2])),
+            params.clone(),
+        )));
+        let (version, key, code) = ContractVersion::unwrap(&contract)?;
+        assert_eq!(version, ContractVersion::of(&contract)?);
+        let rewrapped = version.container(code.clone(), params);
+        assert_eq!(&rewrapped.key(), key);
+        assert_eq!(ContractVersion::of(&rewrapped)?, ContractVersion::V1);
+        Ok(())
+    }
+}
@@ -1,8 +1,8 @@
 use std::{fs::File, io::Read, path::PathBuf};
 
 use freenet::dev_tool::{
-    ClientId, Config, ContractStore, DelegateStore, Executor, OperationMode, SecretsStore,
-    StateStore, Storage,
+    ClientId, Config, ContractStore, DelegateStore, Executor, MasterKey, OperationMode,
+    SecretsStore, StateStore, Storage,
 };
 use freenet_stdlib::{
     client_api::{ClientRequest, ContractRequest, DelegateRequest},
@@ -13,7 +13,9 @@ use freenet_stdlib::{
 //     Parameters, SecretsStore, StateStore,
 // };
 
-use crate::config::{BaseConfig, PutConfig, UpdateConfig};
+use crate::config::{
+    BaseConfig, HistoryConfig, PutConfig, RollbackConfig, RotateSecretsKeyConfig, UpdateConfig,
+};
 
 const MAX_MEM_CACHE: u32 = 10_000_000;
 const DEFAULT_MAX_CONTRACT_SIZE: i64 = 50 * 1024 * 1024;
@@ -146,6 +148,72 @@ pub async fn update(config: UpdateConfig, other: BaseConfig) -> Result<(), anyho
     execute_command(request, other).await
 }
 
+pub async fn history(config: HistoryConfig, other: BaseConfig) -> Result<(), anyhow::Error> {
+    let key = ContractInstanceId::try_from(config.key)?.into();
+    let state_store = state_store(&other).await?;
+    let versions = state_store.list_versions(&key).await?;
+    if versions.is_empty() {
+        println!("No past states kept for contract {key}");
+    }
+    for version in versions {
+        let timestamp = chrono::DateTime::<chrono::Utc>::from(version.timestamp);
+        println!(
+            "{}\t{}\t{} bytes",
+            version.version,
+            timestamp.to_rfc3339(),
+            version.size
+        );
+    }
+    Ok(())
+}
+
+pub async fn rollback(config: RollbackConfig, other: BaseConfig) -> Result<(), anyhow::Error> {
+    let key = ContractInstanceId::try_from(config.key)?.into();
+    println!("Rolling back contract {key} to version {}", config.version);
+    let mut state_store = state_store(&other).await?;
+    state_store.rollback(&key, config.version).await?;
+    Ok(())
+}
+
+/// Re-encrypts the secrets store under the master key derived from a new passphrase.
+///
+/// The node must not be running meanwhile, and must be started with the new passphrase as
+/// `FREENET_SECRETS_PASSPHRASE` afterwards.
+pub async fn rotate_secrets_key(
+    config: RotateSecretsKeyConfig,
+    other: BaseConfig,
+) -> Result<(), anyhow::Error> {
+    let secrets_data_path = other
+        .secret_data_dir
+        .unwrap_or_else(|| Config::conf().secrets_dir());
+    let mut secret_store =
+        SecretsStore::new(secrets_data_path, Config::conf().secrets_master_key())?;
+    secret_store.rotate_master_key(MasterKey::from_passphrase(config.new_passphrase.as_bytes()))?;
+    if let Some(key_file) = Config::conf().secrets_master_key_file() {
+        // the previous key was kept in plaintext and is of no use anymore
+        std::fs::remove_file(key_file)?;
+    }
+    println!("Rotated the secrets master key, start the node with the new passphrase");
+    Ok(())
+}
+
+async fn state_store(other: &BaseConfig) -> Result<StateStore<Storage>, anyhow::Error> {
+    let database_path = other
+        .database_dir
+        .clone()
+        .unwrap_or_else(|| Config::conf().db_dir());
+    let mut state_store = StateStore::new(
+        Storage::new(Config::conf().storage_backend, Some(&database_path))
+            .await
+            .map_err(|err| anyhow::anyhow!(err))?,
+        MAX_MEM_CACHE,
+    )?;
+    if let Some(max_versions) = other.state_history {
+        state_store = state_store.with_history(max_versions);
+    }
+    Ok(state_store)
+}
+
 async fn execute_command(
     request: ClientRequest<'static>,
     other: BaseConfig,
@@ -159,13 +227,10 @@ async fn execute_command(
     let secrets_data_path = other
         .secret_data_dir
         .unwrap_or_else(|| Config::conf().secrets_dir());
-    let database_path = other
-        .database_dir
-        .unwrap_or_else(|| Config::conf().db_dir());
+    let state_store = state_store(&other).await?;
     let contract_store = ContractStore::new(contracts_data_path, DEFAULT_MAX_CONTRACT_SIZE)?;
     let delegate_store = DelegateStore::new(delegates_data_path, DEFAULT_MAX_DELEGATE_SIZE)?;
-    let secret_store = SecretsStore::new(secrets_data_path)?;
-    let state_store = StateStore::new(Storage::new(Some(&database_path)).await?, MAX_MEM_CACHE)?;
+    let secret_store = SecretsStore::new(secrets_data_path, Config::conf().secrets_master_key())?;
     let rt =
         freenet::dev_tool::Runtime::build(contract_store, delegate_store, secret_store, false)?;
     let mut executor = Executor::new(state_store, || Ok(()), OperationMode::Local, rt, None)
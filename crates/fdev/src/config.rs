@@ -33,6 +33,9 @@ pub struct BaseConfig {
     /// Node operation mode.
     #[arg(value_enum, default_value_t=OperationMode::Local)]
     pub mode: OperationMode,
+    /// Number of past states kept for each contract when updating them, disabled by default.
+    #[arg(long)]
+    pub(crate) state_history: Option<usize>,
 }
 
 #[derive(clap::Subcommand, Clone)]
@@ -50,8 +53,14 @@ pub enum SubCommand {
 impl SubCommand {
     pub fn is_child(&self) -> bool {
         if let SubCommand::Test(config) = self {
-            if let crate::testing::TestMode::MultiProcess(config) = &config.command {
-                return matches!(config.mode, crate::testing::Process::Child);
+            match &config.command {
+                crate::testing::TestMode::MultiProcess(config) => {
+                    return matches!(config.mode, crate::testing::Process::Child);
+                }
+                crate::testing::TestMode::Network(config) => {
+                    return matches!(config.mode, crate::testing::NetworkProcess::Worker);
+                }
+                crate::testing::TestMode::SingleProcess => {}
             }
         }
         false
@@ -73,6 +82,36 @@ pub struct RunCliConfig {
 pub enum NodeCommand {
     Put(PutConfig),
     Update(UpdateConfig),
+    History(HistoryConfig),
+    Rollback(RollbackConfig),
+    RotateSecretsKey(RotateSecretsKeyConfig),
+}
+
+/// Lists the past states kept for a local contract.
+#[derive(clap::Parser, Clone)]
+pub struct HistoryConfig {
+    /// Contract id of the contract in Base58 format.
+    pub(crate) key: String,
+}
+
+/// Rolls back the state of a local contract to a past version.
+#[derive(clap::Parser, Clone)]
+pub struct RollbackConfig {
+    /// Contract id of the contract being rolled back in Base58 format.
+    pub(crate) key: String,
+    /// Version to restore, as listed by the `history` command.
+    pub(crate) version: u64,
+}
+
+/// Re-encrypts the local secrets store under a master key derived from a new passphrase.
+///
+/// The current key is the one the node would use, so the previous `FREENET_SECRETS_PASSPHRASE`
+/// (if any) must still be set. The node must be stopped meanwhile.
+#[derive(clap::Parser, Clone)]
+pub struct RotateSecretsKeyConfig {
+    /// Passphrase the new master key is derived from.
+    #[arg(long, env = "FREENET_NEW_SECRETS_PASSPHRASE")]
+    pub(crate) new_passphrase: String,
 }
 
 /// Updates a contract in the network.
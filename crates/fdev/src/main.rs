@@ -15,7 +15,7 @@ mod wasm_runtime;
 
 use crate::{
     build::build_package,
-    commands::{put, update},
+    commands::{history, put, rollback, rotate_secrets_key, update},
     config::{Config, SubCommand},
     inspect::inspect,
     new_package::create_new_package,
@@ -57,6 +57,15 @@ fn main() -> Result<(), anyhow::Error> {
                 config::NodeCommand::Update(update_config) => {
                     update(update_config, config.additional).await
                 }
+                config::NodeCommand::History(history_config) => {
+                    history(history_config, config.additional).await
+                }
+                config::NodeCommand::Rollback(rollback_config) => {
+                    rollback(rollback_config, config.additional).await
+                }
+                config::NodeCommand::RotateSecretsKey(rotate_config) => {
+                    rotate_secrets_key(rotate_config, config.additional).await
+                }
             },
             SubCommand::Test(test_config) => testing::test_framework(test_config).await,
             SubCommand::NetworkMetricsServer(server_config) => {
@@ -1,13 +1,15 @@
 use std::{path::PathBuf, time::Duration};
 
 use anyhow::Error;
-use freenet::dev_tool::SimNetwork;
+use freenet::dev_tool::{Property, SimNetwork};
 
 mod multiple_process;
 mod network;
+mod scenario;
 mod single_process;
 
 pub(crate) use multiple_process::Process;
+pub(crate) use network::NetworkProcess;
 
 use crate::network_metrics_server::{start_server, ServerConfig};
 
@@ -61,6 +63,10 @@ pub struct TestConfig {
     /// Don't start the metrics server for this test run.
     #[arg(long)]
     disable_metrics: bool,
+    /// A TOML or JSON file describing the churn and faults the network goes through,
+    /// and the assertions checked at the end of the run.
+    #[arg(long)]
+    scenario: Option<PathBuf>,
     #[clap(subcommand)]
     /// Execution mode for the test.
     pub command: TestMode,
@@ -84,6 +90,22 @@ impl TestConfig {
         (connectivity_timeout, conn_percent)
     }
 
+    fn scenario(&self) -> anyhow::Result<Option<scenario::Scenario>> {
+        self.scenario
+            .as_deref()
+            .map(|path| scenario::Scenario::load(path, self.gateways, self.nodes))
+            .transpose()
+    }
+
+    /// Invariants checked over the events of the simulation once it is over.
+    fn properties(&self) -> [Property; 3] {
+        [
+            Property::PutReachesCachingPeer,
+            Property::HopsWithin(self.ring_max_htl),
+            Property::SubscribersConverge,
+        ]
+    }
+
     fn seed(&self) -> u64 {
         use rand::RngCore;
         self.seed.unwrap_or_else(|| rand::rngs::OsRng.next_u64())
@@ -109,7 +131,9 @@ pub enum TestMode {
     /// Runs multiple simulated nodes in multiple processes.
     MultiProcess(multiple_process::MultiProcessConfig),
     /// Runs multiple simulated nodes in multiple processes and multiple machines.
-    Network,
+    ///
+    /// A coordinator waits for one worker per peer to connect and drives the simulation.
+    Network(network::NetworkConfig),
 }
 
 pub(crate) async fn test_framework(base_config: TestConfig) -> anyhow::Result<(), Error> {
@@ -125,7 +149,7 @@ pub(crate) async fn test_framework(base_config: TestConfig) -> anyhow::Result<()
     let res = match &base_config.command {
         TestMode::SingleProcess => single_process::run(&base_config).await,
         TestMode::MultiProcess(config) => multiple_process::run(&base_config, config).await,
-        TestMode::Network => network::run(&base_config).await,
+        TestMode::Network(config) => network::run(&base_config, config).await,
     };
     if let Some(server) = server {
         server.abort();
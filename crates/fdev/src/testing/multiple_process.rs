@@ -1,26 +1,31 @@
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     fmt::Display,
     process::Stdio,
+    sync::Arc,
     time::Duration,
 };
 
-use anyhow::anyhow;
 use freenet::{
     dev_tool::{
         EventChain, InterProcessConnManager, MemoryEventsGen, NodeConfig, NodeLabel, PeerId,
-        Runtime, SimPeer,
+        PeerOutput, PeerOutputMsg, Runtime, SimNetwork, SimPeer,
     },
     local_node::Executor,
 };
 use futures::{future::BoxFuture, stream::FuturesUnordered, FutureExt, StreamExt};
 use rand::Rng;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt, BufReader, Stdin},
-    process::{ChildStdout, Command},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    process::Command,
 };
 
-use super::Error;
+use super::{
+    scenario::{Action, Scenario},
+    Error,
+};
 
 impl super::TestConfig {
     fn subprocess_command(&self, seed: u64) -> Vec<String> {
@@ -107,42 +112,77 @@ async fn supervisor(config: &super::TestConfig) -> anyhow::Result<(), Error> {
     let mut simulated_network = super::config_sim_network(config).await?;
     simulated_network.debug(); // set to avoid deleting temp dirs created
     let peers = simulated_network.build_peers();
-
-    let (user_ev_controller, event_rx) = tokio::sync::mpsc::channel(1);
+    let scenario = config.scenario()?;
 
     let seed = config.seed();
-    let mut supervisor = Supervisor {
-        processes: HashMap::new(),
-        queued: HashMap::new(),
-        sending: FuturesUnordered::new(),
-        responses: FuturesUnordered::new(),
-        event_rx: Some(event_rx),
-    };
-    let cmd_args = config.subprocess_command(seed);
-    for (label, node) in &peers {
-        let mut subprocess = SubProcess::start(&cmd_args, label, node.peer_id)?;
-        subprocess.config(node).await?;
-        supervisor.processes.insert(node.peer_id, subprocess);
+    let mut supervisor = Supervisor::new(config.subprocess_command(seed), &peers);
+    let joining: HashSet<_> = scenario
+        .iter()
+        .flat_map(|scenario| scenario.joining())
+        .collect();
+    for (label, node) in peers {
+        if joining.contains(&label) {
+            supervisor.joining.insert(label, node);
+            continue;
+        }
+        let mut subprocess = SubProcess::start(&supervisor.cmd_args, &label, node.peer_id)?;
+        subprocess.config(&node).await?;
+        supervisor.add_process(subprocess);
     }
+    supervise(supervisor, config, simulated_network, scenario).await
+}
 
-    let peers = peers
-        .into_iter()
-        .map(|(label, config)| (label, config.peer_id))
+/// Drives the simulation once all the initial peers are running, until it finishes or,
+/// if there is a scenario, until the scenario is over.
+pub(super) async fn supervise(
+    mut supervisor: Supervisor,
+    config: &super::TestConfig,
+    simulated_network: SimNetwork,
+    scenario: Option<Scenario>,
+) -> anyhow::Result<(), Error> {
+    let (user_ev_controller, event_rx) = tokio::sync::mpsc::channel(1);
+    let (scenario_tx, scenario_rx) = tokio::sync::mpsc::unbounded_channel();
+    supervisor.event_rx = Some(event_rx);
+    supervisor.scenario_rx = Some(scenario_rx);
+    let mut peer_ids: Vec<_> = supervisor
+        .labels
+        .iter()
+        .map(|(label, peer)| (label.clone(), *peer))
         .collect();
-    let mut events = EventChain::new(peers, user_ev_controller, config.events, true);
+    peer_ids.sort();
+
+    let started = chrono::Utc::now();
+    if let Some(scenario) = &scenario {
+        let mut steps: Vec<_> = scenario.steps().cloned().collect();
+        steps.sort_by_key(|step| step.at_ms);
+        let start = tokio::time::Instant::now();
+        tokio::task::spawn(async move {
+            for step in steps {
+                tokio::time::sleep_until(start + Duration::from_millis(step.at_ms)).await;
+                if scenario_tx.send(step.action).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    let mut events = EventChain::new(peer_ids, user_ev_controller, config.events, true);
     let next_event_wait_time = config
         .event_wait_ms
         .map(Duration::from_millis)
         .unwrap_or(Duration::from_millis(200));
     let (connectivity_timeout, network_connection_percent) = config.get_connection_check_params();
+    let simulated_network = Arc::new(simulated_network);
+    // the events of the peers are forwarded by them, so the scenario is checked against them
+    supervisor.network = Some(simulated_network.clone());
+    let network = simulated_network.clone();
     let events_generated = tokio::task::spawn(async move {
         tracing::info!(
             "Waiting for network to be sufficiently connected ({}ms timeout, {}%)",
             connectivity_timeout.as_millis(),
             network_connection_percent * 100.0
         );
-        simulated_network
-            .check_partial_connectivity(connectivity_timeout, network_connection_percent)?;
+        network.check_partial_connectivity(connectivity_timeout, network_connection_percent)?;
         tracing::info!("Network is sufficiently connected, start sending events");
         while events.next().await.is_some() {
             tokio::time::sleep(next_event_wait_time).await;
@@ -154,15 +194,34 @@ async fn supervisor(config: &super::TestConfig) -> anyhow::Result<(), Error> {
 
     let supervisor_task = tokio::task::spawn(supervisor.start_simulation());
 
+    let scenario_finished = {
+        let duration = scenario.as_ref().map(Scenario::duration);
+        async move {
+            match duration {
+                Some(duration) => tokio::time::sleep(duration).await,
+                None => futures::future::pending().await,
+            }
+        }
+    };
+
     tokio::pin!(events_generated);
     tokio::pin!(supervisor_task);
     tokio::pin!(ctrl_c);
+    tokio::pin!(scenario_finished);
 
     loop {
         tokio::select! {
             _ = &mut ctrl_c  /* SIGINT handling */ => {
                 break;
             }
+            _ = &mut scenario_finished => {
+                let scenario = scenario.as_ref().expect("only set with a scenario");
+                tracing::info!("Scenario finished, checking assertions");
+                supervisor_task.abort();
+                let checked = scenario.check(&simulated_network, started).await;
+                simulated_network.check_properties(&config.properties()).await?;
+                return checked;
+            }
             res = &mut events_generated => {
                 match res? {
                     Ok(()) => {
@@ -190,35 +249,76 @@ async fn supervisor(config: &super::TestConfig) -> anyhow::Result<(), Error> {
         }
     }
 
-    Ok(())
+    simulated_network
+        .check_properties(&config.properties())
+        .await
 }
 
-type ChildResponses = Vec<(PeerId, Vec<u8>)>;
+type ChildResponses = Vec<PeerOutputMsg>;
+
+type PendingResponses = BoxFuture<'static, (PeerId, anyhow::Result<(ChildOutput, ChildResponses)>)>;
 
 /// Event driver for the supervisor process.
-struct Supervisor {
+pub(super) struct Supervisor {
     processes: HashMap<PeerId, SubProcess>,
     queued: HashMap<PeerId, VecDeque<IPCMessage>>,
     sending: FuturesUnordered<BoxFuture<'static, anyhow::Result<SubProcess>>>,
-    responses: FuturesUnordered<BoxFuture<'static, anyhow::Result<(ChildStdout, ChildResponses)>>>,
+    responses: FuturesUnordered<PendingResponses>,
     event_rx: Option<tokio::sync::mpsc::Receiver<(u32, PeerId)>>,
+    scenario_rx: Option<tokio::sync::mpsc::UnboundedReceiver<Action>>,
+    /// where the events forwarded by the peers are registered
+    network: Option<Arc<SimNetwork>>,
+    cmd_args: Vec<String>,
+    labels: HashMap<NodeLabel, PeerId>,
+    /// peers which are not started until they join the network
+    joining: HashMap<NodeLabel, NodeConfig>,
+    /// group of each peer while the network is partitioned
+    partition: HashMap<PeerId, usize>,
+    /// peers which left the network
+    gone: HashSet<PeerId>,
 }
 
 impl Supervisor {
+    pub fn new(cmd_args: Vec<String>, peers: &[(NodeLabel, NodeConfig)]) -> Self {
+        Self {
+            processes: HashMap::new(),
+            queued: HashMap::new(),
+            sending: FuturesUnordered::new(),
+            responses: FuturesUnordered::new(),
+            event_rx: None,
+            scenario_rx: None,
+            network: None,
+            cmd_args,
+            labels: peers
+                .iter()
+                .map(|(label, config)| (label.clone(), config.peer_id))
+                .collect(),
+            joining: HashMap::new(),
+            partition: HashMap::new(),
+            gone: HashSet::new(),
+        }
+    }
+
+    pub fn add_process(&mut self, subprocess: SubProcess) {
+        self.processes.insert(subprocess.id, subprocess);
+    }
+
     async fn start_simulation(mut self) -> anyhow::Result<()> {
         let ctrl_c = tokio::signal::ctrl_c();
         tokio::pin!(ctrl_c);
 
         let mut event_rx = self.event_rx.take().expect("should be set");
+        let mut scenario_rx = self.scenario_rx.take().expect("should be set");
         let mut finished_events = false;
+        let mut finished_scenario = false;
 
-        for child_stdout in self
+        for (id, child_stdout) in self
             .processes
             .values_mut()
-            .map(|sp| sp.child.stdout.take().expect("should be set"))
+            .map(|sp| (sp.id, sp.output.take().expect("should be set")))
         {
             self.responses
-                .push(SubProcess::get_child_responses(child_stdout).boxed());
+                .push(SubProcess::get_child_responses(id, child_stdout).boxed());
         }
 
         loop {
@@ -227,9 +327,12 @@ impl Supervisor {
                     break;
                 }
                 res = self.responses.next(), if !self.responses.is_empty() => {
-                    let (child_stdout, responses) = match res {
-                        Some(Ok(res)) => res,
-                        Some(Err(err)) => {
+                    let (origin, child_stdout, responses) = match res {
+                        Some((origin, Ok((child_stdout, responses)))) => (origin, child_stdout, responses),
+                        Some((origin, Err(_))) if self.gone.contains(&origin) => {
+                            continue;
+                        }
+                        Some((_, Err(err))) => {
                             tracing::error!("Error processing responses: {err}");
                             return Err(err);
                         }
@@ -237,11 +340,22 @@ impl Supervisor {
                             continue;
                         }
                     };
-                    self.responses.push(SubProcess::get_child_responses(child_stdout).boxed());
-                    self.process_responses(responses);
+                    self.responses.push(SubProcess::get_child_responses(origin, child_stdout).boxed());
+                    self.process_responses(origin, responses).await?;
+                }
+                action = scenario_rx.recv(), if !finished_scenario => {
+                    let Some(action) = action else {
+                        finished_scenario = true;
+                        continue;
+                    };
+                    self.apply_scenario_action(action).await?;
                 }
                 completed_send = self.sending.next(), if !self.sending.is_empty() => {
                     let mut subprocess = match completed_send {
+                        Some(Ok(res)) if self.gone.contains(&res.id) => {
+                            res.close().await;
+                            continue;
+                        }
                         Some(Ok(res)) => res,
                         Some(Err(err)) => {
                             tracing::error!("Error sending message: {err}");
@@ -256,10 +370,10 @@ impl Supervisor {
                         let n = rand::thread_rng().gen_range(0..=peer_queue.len());
                         let messages = peer_queue.drain(..n).collect::<Vec<_>>();
                         let task = async move {
-                            let stdin = subprocess.child.stdin.as_mut().expect("not taken");
+                            let input = &mut subprocess.input;
                             tracing::debug!(peer = %subprocess.id, "Draining {} messages from queue", n + 1);
                             for pending in messages {
-                                    pending.send(stdin).await?;
+                                    pending.send(input).await?;
                             }
                             Ok(subprocess)
                         }.boxed();
@@ -274,6 +388,9 @@ impl Supervisor {
                         finished_events = true;
                         continue;
                     };
+                    if self.gone.contains(&peer) {
+                        continue;
+                    }
                     let Some(mut subprocess) = self.processes.remove(&peer) else {
                         self.queued.entry(peer).or_default().push_back(IPCMessage::FiredEvent(event));
                         continue;
@@ -282,8 +399,7 @@ impl Supervisor {
                     pending.push_back(IPCMessage::FiredEvent(event));
                     let task = async move {
                         for msg in pending {
-                            msg.send(subprocess.child.stdin.as_mut().expect("not taken"))
-                            .await?;
+                            msg.send(&mut subprocess.input).await?;
                         }
                         Ok(subprocess)
                     }.boxed();
@@ -298,8 +414,25 @@ impl Supervisor {
         Ok(())
     }
 
-    fn process_responses(&mut self, responses: ChildResponses) {
-        for (target, data) in responses {
+    async fn process_responses(
+        &mut self,
+        origin: PeerId,
+        responses: ChildResponses,
+    ) -> anyhow::Result<()> {
+        for response in responses {
+            let (target, data) = match response {
+                PeerOutputMsg::Message { target, data } => (target, data),
+                PeerOutputMsg::Events(events) => {
+                    if let Some(network) = &self.network {
+                        network.register_forwarded_events(&events).await?;
+                    }
+                    continue;
+                }
+            };
+            if !self.reachable(&origin, &target) {
+                tracing::debug!(%origin, %target, "Message dropped, peers are unreachable");
+                continue;
+            }
             if let Some(mut target) = self.processes.remove(&target) {
                 tracing::debug!(to = %target.id, "Sending message");
                 let task = async move {
@@ -316,11 +449,75 @@ impl Supervisor {
                     .push_back(IPCMessage::Data(data));
             }
         }
+        Ok(())
+    }
+
+    fn reachable(&self, origin: &PeerId, target: &PeerId) -> bool {
+        const REST: usize = usize::MAX;
+        if self.gone.contains(origin) || self.gone.contains(target) {
+            return false;
+        }
+        if self.partition.is_empty() {
+            return true;
+        }
+        self.partition.get(origin).unwrap_or(&REST) == self.partition.get(target).unwrap_or(&REST)
+    }
+
+    async fn apply_scenario_action(&mut self, action: Action) -> anyhow::Result<()> {
+        tracing::info!(?action, "Applying scenario step");
+        let peer_id = |label: &str| self.labels.get(&NodeLabel::from(label)).copied();
+        match action {
+            Action::Join { peer } => {
+                let label = NodeLabel::from(peer.as_str());
+                let Some(node) = self.joining.remove(&label) else {
+                    return Ok(());
+                };
+                let mut subprocess = SubProcess::start(&self.cmd_args, &label, node.peer_id)?;
+                subprocess.config(&node).await?;
+                let stdout = subprocess.output.take().expect("should be set");
+                self.responses
+                    .push(SubProcess::get_child_responses(subprocess.id, stdout).boxed());
+                // deliver anything queued for the peer while it was not running
+                self.sending.push(async move { Ok(subprocess) }.boxed());
+            }
+            Action::Leave { peer: label } | Action::GatewayLoss { gateway: label } => {
+                let Some(peer) = peer_id(&label) else {
+                    return Ok(());
+                };
+                self.gone.insert(peer);
+                self.queued.remove(&peer);
+                // if the process is busy receiving messages it is closed once done
+                if let Some(subprocess) = self.processes.remove(&peer) {
+                    subprocess.close().await;
+                }
+            }
+            Action::Partition { groups } => {
+                self.partition = groups
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(group, peers)| {
+                        peers
+                            .iter()
+                            .filter_map(|label| peer_id(label))
+                            .map(move |peer| (peer, group))
+                    })
+                    .collect();
+            }
+            Action::Heal => self.partition.clear(),
+        }
+        Ok(())
     }
 }
 
-struct SubProcess {
-    child: tokio::process::Child,
+type ChildInput = Box<dyn AsyncWrite + Send + Unpin>;
+type ChildOutput = Box<dyn AsyncRead + Send + Unpin>;
+
+/// A peer running in a child process or in a remote worker.
+pub(super) struct SubProcess {
+    /// not set for peers running in a remote worker
+    child: Option<tokio::process::Child>,
+    input: ChildInput,
+    output: Option<ChildOutput>,
     id: PeerId,
 }
 
@@ -328,45 +525,71 @@ impl SubProcess {
     fn start(cmd_args: &[String], label: &NodeLabel, id: PeerId) -> anyhow::Result<Self, Error> {
         // the identifier used for multi-process tests is the peer id
         let data_dir = Executor::<Runtime>::test_data_dir(&id.to_string());
-        let child = Command::new("fdev")
+        let mut child = Command::new("fdev")
             .kill_on_drop(true)
             .args(cmd_args)
             .arg("--id")
             .arg(label.number().to_string())
             .arg("--data-dir")
             .arg(data_dir.to_str().expect("valid path"))
+            // write logs to stderr so stdout and stdin are free of unexpected data
+            .env("FREENET_LOG_TO_STDERR", "1")
+            .env("FREENET_PEER_ID", id.to_string())
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::inherit())
             .spawn()?;
-        Ok(Self { child, id })
+        let input = Box::new(child.stdin.take().expect("should be piped"));
+        let output = Box::new(child.stdout.take().expect("should be piped"));
+        Ok(Self {
+            child: Some(child),
+            input,
+            output: Some(output),
+            id,
+        })
+    }
+
+    /// A peer running in a remote worker connected through `stream`.
+    pub fn remote(id: PeerId, stream: TcpStream) -> Self {
+        let (output, input) = stream.into_split();
+        Self {
+            child: None,
+            input: Box::new(input),
+            output: Some(Box::new(output)),
+            id,
+        }
     }
 
-    async fn config(&mut self, config: &NodeConfig) -> anyhow::Result<(), Error> {
-        let input = self.get_input().ok_or_else(|| anyhow!("closed"))?;
-        let serialize = bincode::serialize(&config)?;
-        input
+    pub async fn config(&mut self, config: &impl Serialize) -> anyhow::Result<(), Error> {
+        let serialize = bincode::serialize(config)?;
+        self.input
             .write_all(&(serialize.len() as u32).to_le_bytes())
             .await?;
-        input.write_all(&serialize).await?;
+        self.input.write_all(&serialize).await?;
         Ok(())
     }
 
     #[inline]
     async fn send_msg(&mut self, msg: IPCMessage) -> anyhow::Result<()> {
-        msg.send(self.child.stdin.as_mut().expect("not taken"))
-            .await?;
+        msg.send(&mut self.input).await?;
         Ok(())
     }
 
     async fn get_child_responses(
-        mut stdout: ChildStdout,
-    ) -> anyhow::Result<(ChildStdout, Vec<(PeerId, Vec<u8>)>)> {
+        id: PeerId,
+        stdout: ChildOutput,
+    ) -> (PeerId, anyhow::Result<(ChildOutput, ChildResponses)>) {
+        (id, Self::pull_responses(stdout).await)
+    }
+
+    async fn pull_responses(
+        mut stdout: ChildOutput,
+    ) -> anyhow::Result<(ChildOutput, ChildResponses)> {
         let mut returned = vec![];
         loop {
             match InterProcessConnManager::pull_msg(&mut stdout).await {
-                Ok(Some((target, data))) => {
-                    returned.push((target, data));
+                Ok(Some(msg)) => {
+                    returned.push(msg);
                 }
                 Ok(None) if !returned.is_empty() => {
                     break;
@@ -382,12 +605,14 @@ impl SubProcess {
     }
 
     async fn close(mut self) {
-        let _ = self.child.kill().await;
-    }
-
-    #[inline]
-    fn get_input(&mut self) -> Option<&mut tokio::process::ChildStdin> {
-        self.child.stdin.as_mut()
+        match &mut self.child {
+            Some(child) => {
+                let _ = child.kill().await;
+            }
+            None => {
+                let _ = self.input.shutdown().await;
+            }
+        }
     }
 }
 
@@ -400,63 +625,108 @@ async fn child(
         .data_dir
         .as_ref()
         .expect("data_dir should be set for child process");
-    // write logs to stderr so stdout and stdin are free of unexpected data
-    std::env::set_var("FREENET_LOG_TO_STDERR", "1");
-    std::env::set_var("FREENET_DATA_DIR", data_dir);
 
+    let mut input = BufReader::new(tokio::io::stdin());
+    let mut node_config: NodeConfig = get_config(&mut input).await?;
+    node_config.with_data_dir(data_dir);
+    let params = PeerParams::new(
+        test_config,
+        test_config
+            .seed
+            .expect("seed should be set for child process"),
+    );
+    run_peer(
+        id,
+        &params,
+        node_config,
+        input,
+        Box::new(tokio::io::stdout()),
+    )
+    .await
+}
+
+/// Parameters shared by all the peers of a simulation.
+#[derive(Serialize, Deserialize)]
+pub(super) struct PeerParams {
+    seed: u64,
+    total_peers: usize,
+    max_contract_number: usize,
+    events: usize,
+    peer_start_backoff_ms: Option<u64>,
+}
+
+impl PeerParams {
+    pub fn new(config: &super::TestConfig, seed: u64) -> Self {
+        Self {
+            seed,
+            total_peers: config.gateways + config.nodes,
+            max_contract_number: config.max_contract_number.unwrap_or(config.nodes * 10),
+            events: config.events as usize,
+            peer_start_backoff_ms: config.peer_start_backoff_ms,
+        }
+    }
+}
+
+/// Reads a configuration sent by the supervisor.
+pub(super) async fn get_config<T: DeserializeOwned>(
+    input: &mut (impl AsyncRead + Unpin),
+) -> anyhow::Result<T> {
+    let mut buf = [0u8; 4];
+    input.read_exact(&mut buf).await?;
+    let config_len = u32::from_le_bytes(buf);
+    let mut config_buf = vec![0u8; config_len as usize];
+    input.read_exact(&mut config_buf).await?;
+    Ok(bincode::deserialize(&config_buf)?)
+}
+
+/// Runs a simulated peer which gets events and messages from the supervisor through `input`,
+/// and sends its messages for other peers through `output`.
+pub(super) async fn run_peer<R>(
+    id: usize,
+    params: &PeerParams,
+    node_config: NodeConfig,
+    input: BufReader<R>,
+    output: PeerOutput,
+) -> anyhow::Result<()>
+where
+    R: AsyncRead + Send + Unpin + 'static,
+{
     let (user_ev_controller, mut receiver_ch) = tokio::sync::watch::channel((0, PeerId::random()));
     receiver_ch.borrow_and_update();
-    let mut input = BufReader::new(tokio::io::stdin());
-    let node_config = Child::get_config(&mut input).await?;
     let this_child = Child {
         input,
         user_ev_controller,
         peer_id: node_config.peer_id,
     };
-    std::env::set_var("FREENET_PEER_ID", node_config.peer_id.to_string());
     freenet::config::set_logger();
     let mut event_generator = MemoryEventsGen::<fastrand::Rng>::new_with_seed(
         receiver_ch.clone(),
         node_config.peer_id,
-        test_config
-            .seed
-            .expect("seed should be set for child process"),
+        params.seed,
     );
     event_generator.rng_params(
         id,
-        test_config.gateways + test_config.nodes,
-        test_config
-            .max_contract_number
-            .unwrap_or(test_config.nodes * 10),
-        test_config.events as usize,
+        params.total_peers,
+        params.max_contract_number,
+        params.events,
     );
     let config = SimPeer::from(node_config);
-    if let Some(backoff) = test_config.peer_start_backoff_ms {
+    if let Some(backoff) = params.peer_start_backoff_ms {
         tokio::time::sleep(Duration::from_millis(backoff)).await;
     }
     tokio::task::spawn(this_child.event_loop());
-    config.start_child(event_generator).await?;
+    config.start_with_output(event_generator, output).await?;
     Ok(())
 }
 
 /// Controller for a child process.
-struct Child {
-    input: BufReader<Stdin>,
+struct Child<R> {
+    input: BufReader<R>,
     user_ev_controller: tokio::sync::watch::Sender<(u32, PeerId)>,
     peer_id: PeerId,
 }
 
-impl Child {
-    async fn get_config(input: &mut BufReader<Stdin>) -> anyhow::Result<NodeConfig> {
-        let mut buf = [0u8; 4];
-        input.read_exact(&mut buf).await?;
-        let config_len = u32::from_le_bytes(buf);
-        let mut config_buf = vec![0u8; config_len as usize];
-        input.read_exact(&mut config_buf).await?;
-        let node_config: NodeConfig = bincode::deserialize(&config_buf)?;
-        Ok(node_config)
-    }
-
+impl<R: AsyncRead + Unpin> Child<R> {
     async fn event_loop(mut self) -> anyhow::Result<()> {
         loop {
             match IPCMessage::recv(&mut self.input).await {
@@ -484,7 +754,7 @@ enum IPCMessage {
 }
 
 impl IPCMessage {
-    async fn send(self, out: &mut tokio::process::ChildStdin) -> anyhow::Result<()> {
+    async fn send(self, out: &mut (impl AsyncWrite + Unpin)) -> anyhow::Result<()> {
         match self {
             Self::FiredEvent(id) => {
                 out.write_u8(0).await?;
@@ -499,7 +769,7 @@ impl IPCMessage {
         Ok(())
     }
 
-    async fn recv(input: &mut BufReader<Stdin>) -> anyhow::Result<Self> {
+    async fn recv(input: &mut (impl AsyncRead + Unpin)) -> anyhow::Result<Self> {
         let marker = input.read_u8().await?;
         match marker {
             0 => {
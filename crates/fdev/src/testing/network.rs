@@ -1,3 +1,166 @@
-pub(super) async fn run(_config: &super::TestConfig) -> anyhow::Result<()> {
-    todo!()
+//! Simulations spanning multiple machines. A coordinator hands out the configuration of each
+//! peer to the workers connecting to it over TCP, and routes the messages between them the
+//! same way the multi-process supervisor does with its child processes.
+use std::{fmt::Display, net::SocketAddr, time::Duration};
+
+use freenet::{
+    dev_tool::{NodeConfig, Runtime},
+    local_node::Executor,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::BufReader,
+    net::{TcpListener, TcpStream},
+};
+
+use super::{
+    multiple_process::{self, PeerParams, SubProcess, Supervisor},
+    Error,
+};
+
+#[derive(clap::Parser, Clone)]
+pub struct NetworkConfig {
+    #[arg(long, default_value_t = NetworkProcess::Coordinator)]
+    pub mode: NetworkProcess,
+    /// Address at which the coordinator listens for workers.
+    #[arg(long, default_value = "127.0.0.1:50509")]
+    pub address: SocketAddr,
+}
+
+#[derive(Default, Clone, clap::ValueEnum)]
+pub enum NetworkProcess {
+    /// Hands out peers to the workers and drives the simulation.
+    #[default]
+    Coordinator,
+    /// Runs one of the peers of the simulation.
+    Worker,
+}
+
+impl Display for NetworkProcess {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Coordinator => write!(f, "coordinator"),
+            Self::Worker => write!(f, "worker"),
+        }
+    }
+}
+
+/// Everything a worker needs to run its peer, sent by the coordinator once connected.
+#[derive(Serialize, Deserialize)]
+struct WorkerSetup {
+    id: usize,
+    params: PeerParams,
+    config: NodeConfig,
+}
+
+pub(super) async fn run(
+    config: &super::TestConfig,
+    network_config: &NetworkConfig,
+) -> anyhow::Result<(), Error> {
+    match network_config.mode {
+        NetworkProcess::Coordinator => coordinator(config, network_config.address).await,
+        NetworkProcess::Worker => worker(network_config.address).await,
+    }
+}
+
+async fn coordinator(config: &super::TestConfig, address: SocketAddr) -> anyhow::Result<(), Error> {
+    let scenario = config.scenario()?;
+    if let Some(scenario) = &scenario {
+        if scenario.joining().next().is_some() {
+            anyhow::bail!("peers joining later are not supported when running over the network");
+        }
+    }
+    let mut simulated_network = super::config_sim_network(config).await?;
+    simulated_network.debug(); // set to avoid deleting temp dirs created
+    let peers = simulated_network.build_peers();
+
+    let seed = config.seed();
+    let mut supervisor = Supervisor::new(vec![], &peers);
+    let listener = TcpListener::bind(address).await?;
+    tracing::info!(
+        "Waiting for {} workers to connect at {address}",
+        peers.len()
+    );
+    for (label, node) in peers {
+        let (stream, worker) = listener.accept().await?;
+        stream.set_nodelay(true)?;
+        tracing::info!(%worker, peer = %label, "Worker connected");
+        let mut subprocess = SubProcess::remote(node.peer_id, stream);
+        subprocess
+            .config(&WorkerSetup {
+                id: label.number(),
+                params: PeerParams::new(config, seed),
+                config: node,
+            })
+            .await?;
+        supervisor.add_process(subprocess);
+    }
+    multiple_process::supervise(supervisor, config, simulated_network, scenario).await
+}
+
+async fn worker(address: SocketAddr) -> anyhow::Result<(), Error> {
+    let stream = connect(address).await?;
+    stream.set_nodelay(true)?;
+    let (input, output) = stream.into_split();
+    let mut input = BufReader::new(input);
+    let WorkerSetup {
+        id,
+        params,
+        mut config,
+    } = multiple_process::get_config(&mut input).await?;
+    config.with_data_dir(Executor::<Runtime>::test_data_dir(
+        &config.peer_id.to_string(),
+    ));
+    multiple_process::run_peer(id, &params, config, input, Box::new(output)).await
+}
+
+/// Connects to the coordinator, retrying for a while since workers may be started first.
+async fn connect(address: SocketAddr) -> anyhow::Result<TcpStream> {
+    const MAX_ATTEMPTS: usize = 60;
+    for _ in 1..MAX_ATTEMPTS {
+        match TcpStream::connect(address).await {
+            Ok(stream) => return Ok(stream),
+            Err(err) => {
+                tracing::debug!("Coordinator at {address} not available yet: {err}");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+    Ok(TcpStream::connect(address).await?)
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use super::*;
+    use crate::testing::TestConfig;
+
+    #[tokio::test]
+    async fn hands_out_peers_over_loopback() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let worker = tokio::spawn(async move {
+            let mut input = BufReader::new(connect(address).await?);
+            multiple_process::get_config::<WorkerSetup>(&mut input).await
+        });
+
+        let test_config = TestConfig::parse_from(["test", "--seed", "1", "network"]);
+        let config = NodeConfig::new();
+        let peer_id = config.peer_id;
+        let (stream, _) = listener.accept().await?;
+        let mut remote = SubProcess::remote(peer_id, stream);
+        remote
+            .config(&WorkerSetup {
+                id: 3,
+                params: PeerParams::new(&test_config, 1),
+                config,
+            })
+            .await?;
+
+        let setup = worker.await??;
+        assert_eq!(setup.id, 3);
+        assert_eq!(setup.config.peer_id, peer_id);
+        Ok(())
+    }
 }
@@ -0,0 +1,346 @@
+//! Declarative scenarios describing how the network changes during a test run (peers joining
+//! and leaving, partitions, gateways lost...) and what should hold once the run is over.
+//!
+//! Scenarios are written in TOML or JSON, for example:
+//!
+//! ```toml
+//! duration_ms = 60000
+//!
+//! [[steps]]
+//! at_ms = 10000
+//! action = "partition"
+//! groups = [["gateway-0", "node-2", "node-3"]]
+//!
+//! [[steps]]
+//! at_ms = 30000
+//! action = "heal"
+//!
+//! [[assertions]]
+//! check = "contract-availability"
+//! since_ms = 30000
+//! timeout_ms = 10000
+//! ```
+use std::{collections::HashSet, path::Path, time::Duration};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use freenet::dev_tool::{Fault, NodeLabel, SimNetwork};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Scenario {
+    /// Time in milliseconds the scenario runs for, once the network has started.
+    duration_ms: u64,
+    #[serde(default)]
+    steps: Vec<Step>,
+    #[serde(default)]
+    assertions: Vec<Assertion>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Step {
+    /// Time in milliseconds, since the network started, at which the action happens.
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub(crate) enum Action {
+    /// A node joins the network, it is not started until then.
+    Join { peer: String },
+    /// A node leaves the network.
+    Leave { peer: String },
+    /// Peers can only reach the ones in the same group, the peers not listed in any group
+    /// form one more group.
+    Partition { groups: Vec<Vec<String>> },
+    /// Joins all the partitions back.
+    Heal,
+    /// A gateway leaves the network.
+    GatewayLoss { gateway: String },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "check", rename_all = "kebab-case")]
+pub(crate) enum Assertion {
+    /// Every contract put before `since_ms` is retrieved by some peer after it.
+    ContractAvailability { since_ms: u64, timeout_ms: u64 },
+    /// Every contract with subscribers before `since_ms` is subscribed to again after it.
+    SubscriptionsRestored { since_ms: u64, timeout_ms: u64 },
+    /// No gap between the locations of two consecutive peers still in the network is
+    /// larger than `max_gap`.
+    RingDistribution { max_gap: f64 },
+}
+
+impl Scenario {
+    /// Loads a scenario from a JSON file, or from a TOML file for any other extension.
+    pub fn load(path: &Path, gateways: usize, nodes: usize) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed reading scenario {}", path.display()))?;
+        let scenario: Scenario = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&contents)?,
+            _ => toml::from_str(&contents)?,
+        };
+        scenario.validate(gateways, nodes)?;
+        Ok(scenario)
+    }
+
+    fn validate(&self, gateways: usize, nodes: usize) -> anyhow::Result<()> {
+        let check = |name: &str, gateway: bool| -> anyhow::Result<()> {
+            let (kind, range) = if gateway {
+                ("gateway", 0..gateways)
+            } else {
+                ("node", gateways..gateways + nodes)
+            };
+            match name.strip_prefix(kind).and_then(|n| n.strip_prefix('-')) {
+                Some(number) if number.parse().is_ok_and(|n: usize| range.contains(&n)) => Ok(()),
+                _ => anyhow::bail!("`{name}` is not a {kind} of this network"),
+            }
+        };
+        for step in &self.steps {
+            match &step.action {
+                Action::Join { peer } | Action::Leave { peer } => check(peer, false)?,
+                Action::GatewayLoss { gateway } => check(gateway, true)?,
+                Action::Partition { groups } => {
+                    for peer in groups.iter().flatten() {
+                        check(peer, false).or_else(|_| check(peer, true))?;
+                    }
+                }
+                Action::Heal => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.duration_ms)
+    }
+
+    pub fn steps(&self) -> impl Iterator<Item = &Step> {
+        self.steps.iter()
+    }
+
+    /// Peers held back until they join the network.
+    pub fn joining(&self) -> impl Iterator<Item = NodeLabel> + '_ {
+        self.steps.iter().filter_map(|step| match &step.action {
+            Action::Join { peer } => Some(peer.as_str().into()),
+            _ => None,
+        })
+    }
+
+    /// Peers out of the network once the scenario is over.
+    fn gone(&self) -> HashSet<NodeLabel> {
+        let mut steps: Vec<_> = self.steps.iter().collect();
+        steps.sort_by_key(|step| step.at_ms);
+        let mut gone = HashSet::new();
+        for step in steps {
+            match &step.action {
+                Action::Join { peer } => {
+                    gone.remove(&NodeLabel::from(peer.as_str()));
+                }
+                Action::Leave { peer: label } | Action::GatewayLoss { gateway: label } => {
+                    gone.insert(NodeLabel::from(label.as_str()));
+                }
+                Action::Partition { .. } | Action::Heal => {}
+            }
+        }
+        gone
+    }
+
+    /// Sets up the in-memory network so it goes through the steps of the scenario once started.
+    pub fn schedule(&self, network: &mut SimNetwork) {
+        for step in &self.steps {
+            let at = Duration::from_millis(step.at_ms);
+            match &step.action {
+                Action::Join { peer } => network.delay_start(peer.as_str().into(), at),
+                Action::Leave { peer: label } | Action::GatewayLoss { gateway: label } => {
+                    network.schedule_fault(at, Fault::Crash(label.as_str().into()))
+                }
+                Action::Partition { groups } => {
+                    let groups = groups
+                        .iter()
+                        .map(|group| group.iter().map(|peer| peer.as_str().into()).collect())
+                        .collect();
+                    network.schedule_fault(at, Fault::Partition(groups))
+                }
+                Action::Heal => network.schedule_fault(at, Fault::Heal),
+            }
+        }
+    }
+
+    /// Runs all the assertions of the scenario, `started` being the time at which the network
+    /// started.
+    pub async fn check(&self, network: &SimNetwork, started: DateTime<Utc>) -> anyhow::Result<()> {
+        let since = |ms: u64| started + chrono::Duration::milliseconds(ms as i64);
+        let gone = self.gone();
+        let mut failed = 0;
+        for assertion in &self.assertions {
+            let res = match assertion {
+                Assertion::ContractAvailability {
+                    since_ms,
+                    timeout_ms,
+                } => {
+                    network
+                        .check_contract_availability(
+                            since(*since_ms),
+                            Duration::from_millis(*timeout_ms),
+                        )
+                        .await
+                }
+                Assertion::SubscriptionsRestored {
+                    since_ms,
+                    timeout_ms,
+                } => {
+                    network
+                        .check_subscriptions(since(*since_ms), Duration::from_millis(*timeout_ms))
+                        .await
+                }
+                Assertion::RingDistribution { max_gap } => {
+                    network.check_ring_distribution(*max_gap, &gone).await
+                }
+            };
+            match res {
+                Ok(()) => tracing::info!(?assertion, "Scenario assertion holds"),
+                Err(err) => {
+                    tracing::error!(?assertion, "Scenario assertion failed: {err}");
+                    failed += 1;
+                }
+            }
+        }
+        if failed > 0 {
+            anyhow::bail!(
+                "{failed} out of {} scenario assertions failed",
+                self.assertions.len()
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use super::*;
+    use crate::testing::{single_process, TestConfig};
+
+    /// Configuration for running `scenario` in a single process over a small network.
+    fn scenario_config(name: &str, scenario: &str, events: u32) -> anyhow::Result<TestConfig> {
+        let path = std::env::temp_dir().join(format!("freenet-scenario-{name}.toml"));
+        std::fs::write(&path, scenario)?;
+        let events = events.to_string();
+        Ok(TestConfig::parse_from([
+            "test",
+            "--name",
+            name,
+            "--seed",
+            "1",
+            "--gateways",
+            "1",
+            "--nodes",
+            "4",
+            "--events",
+            &events,
+            "--disable-metrics",
+            "--scenario",
+            path.to_str().expect("valid path"),
+            "single-process",
+        ]))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn runs_scenario_end_to_end() -> anyhow::Result<()> {
+        let config = scenario_config(
+            "scenario-partition-heal",
+            r#"
+            duration_ms = 10000
+
+            [[steps]]
+            at_ms = 3000
+            action = "partition"
+            groups = [["gateway-0", "node-1", "node-2"], ["node-3", "node-4"]]
+
+            [[steps]]
+            at_ms = 6000
+            action = "heal"
+
+            [[assertions]]
+            check = "ring-distribution"
+            max_gap = 0.9
+            "#,
+            10,
+        )?;
+        single_process::run(&config).await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn assertions_without_events_fail() -> anyhow::Result<()> {
+        let config = scenario_config(
+            "scenario-no-events",
+            r#"
+            duration_ms = 3000
+
+            [[assertions]]
+            check = "contract-availability"
+            since_ms = 1000
+            timeout_ms = 500
+
+            [[assertions]]
+            check = "subscriptions-restored"
+            since_ms = 1000
+            timeout_ms = 500
+            "#,
+            0,
+        )?;
+        let err = single_process::run(&config)
+            .await
+            .expect_err("nothing was put nor subscribed to");
+        assert!(err.to_string().contains("2 out of 2"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_scenario() -> anyhow::Result<()> {
+        let scenario: Scenario = toml::from_str(
+            r#"
+            duration_ms = 1000
+
+            [[steps]]
+            at_ms = 100
+            action = "join"
+            peer = "node-3"
+
+            [[steps]]
+            at_ms = 200
+            action = "partition"
+            groups = [["gateway-0", "node-2"]]
+
+            [[steps]]
+            at_ms = 300
+            action = "gateway-loss"
+            gateway = "gateway-1"
+
+            [[assertions]]
+            check = "ring-distribution"
+            max_gap = 0.5
+            "#,
+        )?;
+        scenario.validate(2, 2)?;
+        assert_eq!(scenario.steps.len(), 3);
+        assert_eq!(
+            scenario.gone(),
+            HashSet::from([NodeLabel::from("gateway-1")])
+        );
+        assert!(matches!(
+            scenario.assertions[0],
+            Assertion::RingDistribution { max_gap } if max_gap == 0.5
+        ));
+
+        let json: Scenario = serde_json::from_str(
+            r#"{"duration_ms": 10, "steps": [{"at_ms": 1, "action": "leave", "peer": "node-9"}]}"#,
+        )?;
+        assert!(json.validate(2, 2).is_err());
+        Ok(())
+    }
+}
@@ -1,10 +1,18 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
+use freenet::dev_tool::SimNetwork;
 use futures::StreamExt;
-use tokio::signal;
+use tokio::{signal, task::JoinHandle};
+
+use super::scenario::Scenario;
 
 pub(super) async fn run(config: &super::TestConfig) -> anyhow::Result<(), super::Error> {
     let mut simulated_network = super::config_sim_network(config).await?;
+    let scenario = config.scenario()?;
+    if let Some(scenario) = &scenario {
+        scenario.schedule(&mut simulated_network);
+    }
+    let started = chrono::Utc::now();
 
     let join_handles = simulated_network
         .start_with_rand_gen::<fastrand::Rng>(
@@ -14,21 +22,26 @@ pub(super) async fn run(config: &super::TestConfig) -> anyhow::Result<(), super:
         )
         .await;
 
+    if let Some(scenario) = scenario {
+        return run_scenario(config, simulated_network, scenario, started, join_handles).await;
+    }
+
     let events = config.events;
     let next_event_wait_time = config
         .event_wait_ms
         .map(Duration::from_millis)
         .unwrap_or(Duration::from_millis(200));
     let (connectivity_timeout, network_connection_percent) = config.get_connection_check_params();
+    let mut stream = simulated_network.event_chain_by_ref(events);
+    let simulated_network = Arc::new(simulated_network);
+    let network = simulated_network.clone();
     let events_generated = tokio::task::spawn(async move {
         tracing::info!(
             "Waiting for network to be sufficiently connected ({}ms timeout, {}%)",
             connectivity_timeout.as_millis(),
             network_connection_percent * 100.0
         );
-        simulated_network
-            .check_partial_connectivity(connectivity_timeout, network_connection_percent)?;
-        let mut stream = simulated_network.event_chain(events, None);
+        network.check_partial_connectivity(connectivity_timeout, network_connection_percent)?;
         while stream.next().await.is_some() {
             tokio::time::sleep(next_event_wait_time).await;
         }
@@ -81,5 +94,67 @@ pub(super) async fn run(config: &super::TestConfig) -> anyhow::Result<(), super:
         }
     }
 
-    Ok(())
+    simulated_network
+        .check_properties(&config.properties())
+        .await
+}
+
+async fn run_scenario(
+    config: &super::TestConfig,
+    mut simulated_network: SimNetwork,
+    scenario: Scenario,
+    started: chrono::DateTime<chrono::Utc>,
+    join_handles: Vec<JoinHandle<anyhow::Result<()>>>,
+) -> anyhow::Result<(), super::Error> {
+    let next_event_wait_time = config
+        .event_wait_ms
+        .map(Duration::from_millis)
+        .unwrap_or(Duration::from_millis(200));
+    let (connectivity_timeout, network_connection_percent) = config.get_connection_check_params();
+    simulated_network
+        .check_partial_connectivity(connectivity_timeout, network_connection_percent)?;
+
+    let mut events = simulated_network.event_chain_by_ref(config.events);
+    let events_generated = async move {
+        while events.next().await.is_some() {
+            tokio::time::sleep(next_event_wait_time).await;
+        }
+        tracing::info!("Test events generated successfully");
+        futures::future::pending::<()>().await
+    };
+
+    let join_peer_tasks = async move {
+        let mut futs = futures::stream::FuturesUnordered::from_iter(join_handles);
+        while let Some(join_handle) = futs.next().await {
+            match join_handle {
+                Ok(res) => res?,
+                // peers leaving the network are aborted
+                Err(err) if err.is_cancelled() => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok::<_, super::Error>(())
+    };
+
+    let elapsed = (chrono::Utc::now() - started).to_std().unwrap_or_default();
+    let scenario_finished = tokio::time::sleep(scenario.duration().saturating_sub(elapsed));
+
+    tokio::select! {
+        _ = signal::ctrl_c() => return Ok(()),
+        _ = events_generated => {}
+        finalized = join_peer_tasks => {
+            if let Err(e) = finalized {
+                tracing::error!("Test finalized with error: {}", e);
+                return Err(e);
+            }
+        }
+        _ = scenario_finished => {}
+    }
+
+    tracing::info!("Scenario finished, checking assertions");
+    let checked = scenario.check(&simulated_network, started).await;
+    simulated_network
+        .check_properties(&config.properties())
+        .await?;
+    checked
 }
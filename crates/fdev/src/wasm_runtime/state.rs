@@ -29,9 +29,17 @@ impl AppState {
             Config::conf().delegates_dir(),
             Self::DEFAULT_MAX_DELEGATE_SIZE,
         )?;
-        let secrets_store = SecretsStore::new(Config::conf().secrets_dir())?;
+        let secrets_store = SecretsStore::new(
+            Config::conf().secrets_dir(),
+            Config::conf().secrets_master_key(),
+        )?;
         let state_store = StateStore::new(
-            Storage::new(Some(&Config::conf().db_dir())).await?,
+            Storage::new(
+                Config::conf().storage_backend,
+                Some(&Config::conf().db_dir()),
+            )
+            .await
+            .map_err(|err| anyhow::anyhow!(err))?,
             Self::MAX_MEM_CACHE,
         )?;
         let rt = freenet::dev_tool::Runtime::build(
@@ -0,0 +1,96 @@
+//! Runs a network simulation on this host, with the coordinator and every worker in their own
+//! process talking over loopback.
+use std::{
+    net::TcpListener,
+    process::{Child, Command},
+    time::{Duration, Instant},
+};
+
+const GATEWAYS: usize = 1;
+const NODES: usize = 3;
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+fn fdev(args: &[&str]) -> std::io::Result<Child> {
+    Command::new(env!("CARGO_BIN_EXE_fdev"))
+        .env("FREENET_LOG_TO_STDERR", "1")
+        .args(args)
+        .spawn()
+}
+
+#[test]
+fn simulation_over_loopback_workers() -> anyhow::Result<()> {
+    let address = TcpListener::bind("127.0.0.1:0")?.local_addr()?.to_string();
+    let scenario = std::env::temp_dir().join("freenet-network-loopback.toml");
+    std::fs::write(
+        &scenario,
+        r#"
+        duration_ms = 15000
+
+        [[assertions]]
+        check = "ring-distribution"
+        max_gap = 0.9
+        "#,
+    )?;
+    let gateways = GATEWAYS.to_string();
+    let nodes = NODES.to_string();
+    let test_args = [
+        "test",
+        "--seed",
+        "1",
+        "--gateways",
+        &gateways,
+        "--nodes",
+        &nodes,
+        "--events",
+        "20",
+        "--disable-metrics",
+    ];
+
+    let mut coordinator = fdev(
+        &[
+            &test_args[..],
+            &[
+                "--scenario",
+                scenario.to_str().expect("valid path"),
+                "network",
+                "--mode",
+                "coordinator",
+                "--address",
+                &address,
+            ],
+        ]
+        .concat(),
+    )?;
+    let mut workers = (0..GATEWAYS + NODES)
+        .map(|_| {
+            fdev(
+                &[
+                    &test_args[..],
+                    &["network", "--mode", "worker", "--address", &address],
+                ]
+                .concat(),
+            )
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = coordinator.try_wait()? {
+            break Some(status);
+        }
+        if started.elapsed() > TIMEOUT {
+            coordinator.kill()?;
+            break None;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    };
+    // workers keep running their peer until stopped
+    for worker in &mut workers {
+        let _ = worker.kill();
+        let _ = worker.wait();
+    }
+
+    let status = status.expect("the simulation should finish before timing out");
+    assert!(status.success(), "coordinator exited with {status}");
+    Ok(())
+}
@@ -0,0 +1,87 @@
+//! Delegate which reads and writes contracts on behalf of the application talking to it.
+use freenet_stdlib::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+enum InboundAppMessage {
+    GetContract(ContractInstanceId),
+    PutContract {
+        contract: ContractContainer,
+        state: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum OutboundAppMessage {
+    ContractState(Option<Vec<u8>>),
+    ContractPut(Result<(), String>),
+}
+
+struct Delegate;
+
+/// The application waiting for the contract operation, kept in the request context.
+fn app_context(app: ContractInstanceId) -> Result<DelegateContext, DelegateError> {
+    let context = bincode::serialize(&app).map_err(|err| DelegateError::Other(format!("{err}")))?;
+    Ok(DelegateContext::new(context))
+}
+
+fn respond(
+    context: DelegateContext,
+    response: OutboundAppMessage,
+) -> Result<Vec<OutboundDelegateMsg>, DelegateError> {
+    let app: ContractInstanceId = bincode::deserialize(context.as_ref())
+        .map_err(|err| DelegateError::Other(format!("{err}")))?;
+    let payload =
+        bincode::serialize(&response).map_err(|err| DelegateError::Other(format!("{err}")))?;
+    let msg = ApplicationMessage::new(app, payload)
+        .processed(true)
+        .with_context(context);
+    Ok(vec![OutboundDelegateMsg::ApplicationMessage(msg)])
+}
+
+#[delegate]
+impl DelegateInterface for Delegate {
+    fn process(
+        _params: Parameters<'static>,
+        _attested: Option<&'static [u8]>,
+        message: InboundDelegateMsg,
+    ) -> Result<Vec<OutboundDelegateMsg>, DelegateError> {
+        match message {
+            InboundDelegateMsg::ApplicationMessage(incoming_app) => {
+                let message: InboundAppMessage = bincode::deserialize(&incoming_app.payload)
+                    .map_err(|err| DelegateError::Other(format!("{err}")))?;
+                let context = app_context(incoming_app.app)?;
+                let request = match message {
+                    InboundAppMessage::GetContract(contract_id) => {
+                        OutboundDelegateMsg::GetContractRequest(GetContractRequest {
+                            contract_id,
+                            context,
+                            processed: false,
+                        })
+                    }
+                    InboundAppMessage::PutContract { contract, state } => {
+                        OutboundDelegateMsg::PutContractRequest(PutContractRequest {
+                            contract,
+                            state: WrappedState::new(state),
+                            related_contracts: RelatedContracts::default(),
+                            context,
+                            processed: false,
+                        })
+                    }
+                };
+                Ok(vec![request])
+            }
+            InboundDelegateMsg::GetContractResponse(response) => respond(
+                response.context,
+                OutboundAppMessage::ContractState(response.state.map(|s| s.as_ref().to_vec())),
+            ),
+            InboundDelegateMsg::PutContractResponse(response) => respond(
+                response.context,
+                OutboundAppMessage::ContractPut(response.result),
+            ),
+            _ => Err(DelegateError::Other(
+                "Unexpected app inbound message".to_string(),
+            )),
+        }
+    }
+}